@@ -1,6 +1,9 @@
 #![cfg(feature = "leveldb")]
 
-use crate::event::{proto, Event};
+use crate::{
+    event::{proto, Event},
+    metrics::{ComponentMetrics, Gauge},
+};
 use futures01::{
     task::{self, AtomicTask, Task},
     Async, AsyncSink, Poll, Sink, Stream,
@@ -68,11 +71,21 @@ pub struct Writer {
     batch_size: usize,
     max_size: usize,
     current_size: Arc<AtomicUsize>,
+    metrics: BufferMetrics,
 }
 
 // Writebatch isn't Send, but the leveldb docs explicitly say that it's okay to share across threads
 unsafe impl Send for Writer {}
 
+/// The fill level of the buffer, and the events read by the sink but not
+/// acknowledged yet.
+#[derive(Clone)]
+struct BufferMetrics {
+    events: Gauge,
+    byte_size: Gauge,
+    unacked_events: Gauge,
+}
+
 impl Clone for Writer {
     fn clone(&self) -> Self {
         Self {
@@ -84,6 +97,7 @@ impl Clone for Writer {
             batch_size: 0,
             max_size: self.max_size,
             current_size: Arc::clone(&self.current_size),
+            metrics: self.metrics.clone(),
         }
     }
 }
@@ -120,6 +134,10 @@ impl Sink for Writer {
 
         self.writebatch.put(Key(key), &value);
         self.batch_size += 1;
+        self.metrics.events.increment();
+        self.metrics
+            .byte_size
+            .set(self.current_size.load(Ordering::Relaxed) as i64);
 
         if self.batch_size >= 100 {
             self.poll_complete()?;
@@ -171,6 +189,7 @@ pub struct Reader {
     current_size: Arc<AtomicUsize>,
     ack_counter: Arc<AtomicUsize>,
     unacked_sizes: VecDeque<usize>,
+    metrics: BufferMetrics,
 }
 
 // Writebatch isn't Send, but the leveldb docs explicitly say that it's okay to share across threads
@@ -251,7 +270,15 @@ impl Reader {
 
             let size_deleted = self.unacked_sizes.drain(..num_to_delete).sum();
             self.current_size.fetch_sub(size_deleted, Ordering::Relaxed);
+
+            self.metrics.events.add(-(num_to_delete as i64));
+            self.metrics
+                .byte_size
+                .set(self.current_size.load(Ordering::Relaxed) as i64);
         }
+        self.metrics
+            .unacked_events
+            .set(self.unacked_sizes.len() as i64);
 
         for task in self.blocked_write_tasks.lock().unwrap().drain(..) {
             task.notify();
@@ -263,6 +290,7 @@ pub fn open(
     data_dir: &Path,
     buffer_dir: &Path,
     max_size: usize,
+    metrics: &ComponentMetrics,
 ) -> Result<(Writer, Reader, super::Acker), Error> {
     let path = data_dir.join(buffer_dir);
 
@@ -310,6 +338,14 @@ pub fn open(
     let initial_size = db.value_iter(ReadOptions::new()).map(|v| v.len()).sum();
     let current_size = Arc::new(AtomicUsize::new(initial_size));
 
+    let metrics = BufferMetrics {
+        events: metrics.gauge("buffer_events"),
+        byte_size: metrics.gauge("buffer_byte_size"),
+        unacked_events: metrics.gauge("buffer_unacked_events"),
+    };
+    metrics.events.set(tail.saturating_sub(head) as i64);
+    metrics.byte_size.set(initial_size as i64);
+
     let write_notifier = Arc::new(AtomicTask::new());
 
     let blocked_write_tasks = Arc::new(Mutex::new(Vec::new()));
@@ -326,6 +362,7 @@ pub fn open(
         batch_size: 0,
         max_size,
         current_size: Arc::clone(&current_size),
+        metrics: metrics.clone(),
     };
     let reader = Reader {
         db: Arc::clone(&db),
@@ -336,6 +373,7 @@ pub fn open(
         current_size,
         ack_counter,
         unacked_sizes: VecDeque::new(),
+        metrics,
     };
 
     Ok((writer, reader, acker))
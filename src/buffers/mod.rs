@@ -1,10 +1,14 @@
-use crate::Event;
+use crate::{
+    event::{finalization::PendingFinalizers, BatchStatus},
+    metrics::{ComponentMetrics, Gauge},
+    Event,
+};
 use futures01::{sync::mpsc, task::AtomicTask, AsyncSink, Poll, Sink, StartSend, Stream};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 use std::sync::{
     atomic::{AtomicUsize, Ordering},
-    Arc,
+    Arc, Mutex,
 };
 
 #[cfg(feature = "leveldb")]
@@ -47,8 +51,10 @@ impl Default for WhenFull {
     }
 }
 
+#[derive(Clone)]
 pub enum BufferInputCloner {
-    Memory(mpsc::Sender<Event>, WhenFull),
+    /// The gauge counts the events sent into the buffer, less those read.
+    Memory(mpsc::Sender<Event>, WhenFull, Gauge),
     #[cfg(feature = "leveldb")]
     Disk(disk::Writer, WhenFull),
 }
@@ -56,8 +62,11 @@ pub enum BufferInputCloner {
 impl BufferInputCloner {
     pub fn get(&self) -> Box<dyn Sink<SinkItem = Event, SinkError = ()> + Send> {
         match self {
-            BufferInputCloner::Memory(tx, when_full) => {
-                let inner = tx.clone().sink_map_err(|e| error!("sender error: {:?}", e));
+            BufferInputCloner::Memory(tx, when_full, buffered) => {
+                let inner = Counted {
+                    inner: tx.clone().sink_map_err(|e| error!("sender error: {:?}", e)),
+                    buffered: buffered.clone(),
+                };
                 if when_full == &WhenFull::DropNewest {
                     Box::new(DropWhenFull { inner })
                 } else {
@@ -85,6 +94,7 @@ impl BufferConfig {
         &self,
         data_dir: &Option<PathBuf>,
         sink_name: &str,
+        metrics: &ComponentMetrics,
     ) -> Result<
         (
             BufferInputCloner,
@@ -99,8 +109,9 @@ impl BufferConfig {
                 when_full,
             } => {
                 let (tx, rx) = mpsc::channel(*max_events);
-                let tx = BufferInputCloner::Memory(tx, *when_full);
-                let rx = Box::new(rx);
+                let buffered = metrics.gauge("buffer_events");
+                let tx = BufferInputCloner::Memory(tx, *when_full, buffered.clone());
+                let rx = Box::new(rx.inspect(move |_| buffered.decrement()));
                 Ok((tx, rx, Acker::Null))
             }
 
@@ -114,8 +125,9 @@ impl BufferConfig {
                     .ok_or_else(|| "Must set data_dir to use on-disk buffering.".to_string())?;
                 let buffer_dir = format!("{}_buffer", sink_name);
 
-                let (tx, rx, acker) = disk::open(&data_dir, buffer_dir.as_ref(), *max_size)
-                    .map_err(|err| err.to_string())?;
+                let (tx, rx, acker) =
+                    disk::open(&data_dir, buffer_dir.as_ref(), *max_size, metrics)
+                        .map_err(|err| err.to_string())?;
                 let tx = BufferInputCloner::Disk(tx, *when_full);
                 let rx = Box::new(rx);
                 Ok((tx, rx, acker))
@@ -128,6 +140,8 @@ impl BufferConfig {
 pub enum Acker {
     Disk(Arc<AtomicUsize>, Arc<AtomicTask>),
     Null,
+    /// Finalizes the events acked by a sink, then acks them in its buffer.
+    Finalizing(Arc<Mutex<PendingFinalizers>>, Box<Acker>),
 }
 
 impl Acker {
@@ -146,10 +160,36 @@ impl Acker {
                     counter.fetch_add(num, Ordering::Relaxed);
                     notifier.notify();
                 }
+                Acker::Finalizing(pending, buffer) => {
+                    pending.lock().unwrap().resolve(num, BatchStatus::Delivered);
+                    buffer.ack(num);
+                }
             }
         }
     }
 
+    // Like `ack`, but for events the sink gave up on. They're removed from the
+    // buffer all the same, so the events after them aren't held up, but their
+    // sources are told they failed.
+    pub fn fail(&self, num: usize) {
+        match self {
+            Acker::Finalizing(pending, buffer) if num > 0 => {
+                pending.lock().unwrap().resolve(num, BatchStatus::Failed);
+                buffer.ack(num);
+            }
+            _ => self.ack(num),
+        }
+    }
+
+    // Finalizes the event being sent to the sink, for a sink that dropped it
+    // instead of acking it, like when it couldn't be encoded. It counts as
+    // delivered.
+    pub fn discard(&self) {
+        if let Acker::Finalizing(pending, _) = self {
+            pending.lock().unwrap().discard_last();
+        }
+    }
+
     pub fn new_for_testing() -> (Self, Arc<AtomicUsize>) {
         let ack_counter = Arc::new(AtomicUsize::new(0));
         let notifier = Arc::new(AtomicTask::new());
@@ -159,6 +199,29 @@ impl Acker {
     }
 }
 
+/// Counts the events accepted by a memory buffer.
+pub struct Counted<S> {
+    inner: S,
+    buffered: Gauge,
+}
+
+impl<S: Sink> Sink for Counted<S> {
+    type SinkItem = S::SinkItem;
+    type SinkError = S::SinkError;
+
+    fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        let result = self.inner.start_send(item);
+        if let Ok(AsyncSink::Ready) = result {
+            self.buffered.increment();
+        }
+        result
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        self.inner.poll_complete()
+    }
+}
+
 pub struct DropWhenFull<S> {
     inner: S,
 }
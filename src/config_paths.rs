@@ -1,3 +1,4 @@
+use crate::topology::config::Format;
 use glob::glob;
 use lazy_static::lazy_static;
 use std::path::PathBuf;
@@ -7,13 +8,18 @@ lazy_static! {
 }
 
 /// Expand a list of paths (potentially containing glob patterns) into real
-/// config paths, replacing it with the default paths when empty.
-pub fn expand(config_paths: Vec<PathBuf>) -> Option<Vec<PathBuf>> {
+/// config paths, replacing it with the default paths when empty. Each path
+/// is paired with the format it was given in, or else the format of its
+/// extension.
+pub fn expand(config_paths: Vec<(PathBuf, Option<Format>)>) -> Option<Vec<(PathBuf, Format)>> {
     let mut paths = Vec::new();
-    for config_pattern in if !config_paths.is_empty() {
+    for (config_pattern, format) in if !config_paths.is_empty() {
         config_paths
     } else {
-        DEFAULT_CONFIG_PATHS.to_vec()
+        DEFAULT_CONFIG_PATHS
+            .iter()
+            .map(|path| (path.clone(), None))
+            .collect()
     } {
         let matches: Vec<PathBuf> = match glob(config_pattern.to_str().expect("No ability to glob"))
         {
@@ -30,8 +36,17 @@ pub fn expand(config_paths: Vec<PathBuf>) -> Option<Vec<PathBuf>> {
         }
 
         for path in matches {
-            paths.push(path);
+            let format = format.unwrap_or_else(|| Format::from_path(&path));
+            paths.push((path, format));
         }
     }
     Some(paths)
 }
+
+/// Pairs the paths of each list with the format they were given in, if any.
+pub fn with_formats(path_lists: &[(&[PathBuf], Option<Format>)]) -> Vec<(PathBuf, Option<Format>)> {
+    path_lists
+        .iter()
+        .flat_map(|(paths, format)| paths.iter().map(move |path| (path.clone(), *format)))
+        .collect()
+}
@@ -1,5 +1,7 @@
-use crate::runtime::TaskExecutor;
-use futures01::{future, Future};
+use futures01::{
+    future::{self, ExecuteErrorKind, Executor},
+    Future,
+};
 use hyper::client::connect::dns::{Name, Resolve};
 use snafu::{futures01::FutureExt, ResultExt};
 use std::{
@@ -29,7 +31,10 @@ pub enum LookupIp {
 }
 
 impl Resolver {
-    pub fn new(dns_servers: Vec<String>, exec: TaskExecutor) -> Result<Self, DnsError> {
+    pub fn new<E>(dns_servers: Vec<String>, exec: E) -> Result<Self, DnsError>
+    where
+        E: Executor<Box<dyn Future<Item = (), Error = ()> + Send>>,
+    {
         let (config, opt) = if !dns_servers.is_empty() {
             let mut config = ResolverConfig::new();
 
@@ -75,7 +80,8 @@ impl Resolver {
 
         let (inner, bg_task) = AsyncResolver::new(config, opt);
 
-        exec.spawn(bg_task);
+        exec.execute(Box::new(bg_task))
+            .map_err(|error| DnsError::SpawnBackground { kind: error.kind() })?;
 
         Ok(Self { inner })
     }
@@ -131,6 +137,8 @@ pub enum DnsError {
     #[cfg(unix)]
     #[snafu(display("Unable to read system dns config: {}", source))]
     ReadSystemConf { source: std::io::Error },
+    #[snafu(display("Unable to spawn dns resolver background task: {:?}", kind))]
+    SpawnBackground { kind: ExecuteErrorKind },
     #[snafu(display("Unable to resolve name: {}", source))]
     UnableLookup {
         #[snafu(source(from(trust_dns_resolver::error::ResolveError, ResolveError::from)))]
@@ -0,0 +1,250 @@
+use futures01::sync::oneshot;
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// Whether the events of a batch were delivered by every sink they were sent
+/// to, or failed permanently in at least one of them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BatchStatus {
+    Delivered,
+    Failed,
+}
+
+/// Notifies a source once every event it attached this notifier to has been
+/// dealt with.
+///
+/// Events are tracked until each sink they were sent to has acknowledged
+/// them. Events dropped along the way, like by a transform filtering them
+/// out, count as delivered.
+pub struct BatchNotifier {
+    failed: AtomicBool,
+    tx: Mutex<Option<oneshot::Sender<BatchStatus>>>,
+}
+
+impl BatchNotifier {
+    pub fn new_with_receiver() -> (Arc<Self>, oneshot::Receiver<BatchStatus>) {
+        let (tx, rx) = oneshot::channel();
+        let notifier = Self {
+            failed: AtomicBool::new(false),
+            tx: Mutex::new(Some(tx)),
+        };
+        (Arc::new(notifier), rx)
+    }
+}
+
+impl Drop for BatchNotifier {
+    fn drop(&mut self) {
+        let status = if self.failed.load(Ordering::Relaxed) {
+            BatchStatus::Failed
+        } else {
+            BatchStatus::Delivered
+        };
+        if let Some(tx) = self.tx.lock().unwrap().take() {
+            let _ = tx.send(status);
+        }
+    }
+}
+
+/// Tracks an event, and the copies of it sent to each sink, for its batch.
+struct EventFinalizer {
+    failed: AtomicBool,
+    batch: Arc<BatchNotifier>,
+}
+
+impl Drop for EventFinalizer {
+    fn drop(&mut self) {
+        if self.failed.load(Ordering::Relaxed) {
+            self.batch.failed.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// The finalizers carried by an event. Copies of an event share them, so
+/// the event is only finalized once every copy is.
+#[derive(Clone, Default)]
+pub struct EventFinalizers(Vec<Arc<EventFinalizer>>);
+
+impl EventFinalizers {
+    pub fn new(batch: Arc<BatchNotifier>) -> Self {
+        Self(vec![Arc::new(EventFinalizer {
+            failed: AtomicBool::new(false),
+            batch,
+        })])
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Adds the finalizers of another event, like one merged into this one.
+    pub fn merge(&mut self, other: Self) {
+        self.0.extend(other.0);
+    }
+
+    /// Records the outcome of this copy of the event. Dropping the finalizers
+    /// without a failure counts as delivered.
+    pub fn update_status(&self, status: BatchStatus) {
+        if status == BatchStatus::Failed {
+            for finalizer in &self.0 {
+                finalizer.failed.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+// Finalizers aren't part of an event's contents.
+impl PartialEq for EventFinalizers {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl fmt::Debug for EventFinalizers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "EventFinalizers({})", self.0.len())
+    }
+}
+
+/// The finalizers of the events received by a sink, in order, until the sink
+/// acknowledges them. Runs of events without finalizers are only counted.
+#[derive(Debug, Default)]
+pub struct PendingFinalizers {
+    /// The number of events without finalizers before each event with them.
+    events: VecDeque<(usize, EventFinalizers)>,
+    trailing: usize,
+}
+
+impl PendingFinalizers {
+    pub fn push(&mut self, finalizers: EventFinalizers) {
+        if finalizers.is_empty() {
+            self.trailing += 1;
+        } else {
+            self.events.push_back((self.trailing, finalizers));
+            self.trailing = 0;
+        }
+    }
+
+    /// The number of events not yet acknowledged.
+    pub fn len(&self) -> usize {
+        self.events
+            .iter()
+            .map(|(skipped, _)| skipped + 1)
+            .sum::<usize>()
+            + self.trailing
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Finalizes the next `num` events with `status`.
+    pub fn resolve(&mut self, mut num: usize, status: BatchStatus) {
+        while num > 0 {
+            match self.events.front_mut() {
+                Some((skipped, _)) if *skipped >= num => {
+                    *skipped -= num;
+                    num = 0;
+                }
+                Some((skipped, _)) => {
+                    num -= *skipped + 1;
+                    let (_, finalizers) = self.events.pop_front().unwrap();
+                    finalizers.update_status(status);
+                }
+                None => {
+                    self.trailing -= num.min(self.trailing);
+                    num = 0;
+                }
+            }
+        }
+    }
+
+    /// Finalizes the last event received as delivered, for one the sink
+    /// dropped instead of acking it.
+    pub fn discard_last(&mut self) {
+        if self.trailing > 0 {
+            self.trailing -= 1;
+        } else if let Some((skipped, _)) = self.events.pop_back() {
+            self.trailing = skipped;
+        }
+    }
+}
+
+// The events still pending when a sink goes away were never delivered.
+impl Drop for PendingFinalizers {
+    fn drop(&mut self) {
+        for (_, finalizers) in self.events.drain(..) {
+            finalizers.update_status(BatchStatus::Failed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures01::Future;
+
+    #[test]
+    fn finalization_waits_for_every_copy() {
+        let (batch, mut rx) = BatchNotifier::new_with_receiver();
+        let first = EventFinalizers::new(Arc::clone(&batch));
+        let second = EventFinalizers::new(batch);
+        let copy = first.clone();
+
+        drop(first);
+        drop(second);
+        assert_eq!(rx.try_recv(), Ok(None));
+
+        copy.update_status(BatchStatus::Failed);
+        drop(copy);
+        assert_eq!(rx.wait(), Ok(BatchStatus::Failed));
+    }
+
+    #[test]
+    fn finalization_resolves_pending_in_order() {
+        let (batch, rx) = BatchNotifier::new_with_receiver();
+        let (failed_batch, failed_rx) = BatchNotifier::new_with_receiver();
+
+        let mut pending = PendingFinalizers::default();
+        pending.push(EventFinalizers::default());
+        pending.push(EventFinalizers::new(batch));
+        pending.push(EventFinalizers::default());
+        pending.push(EventFinalizers::new(failed_batch));
+        pending.push(EventFinalizers::default());
+
+        pending.resolve(1, BatchStatus::Failed);
+        pending.resolve(2, BatchStatus::Delivered);
+        assert_eq!(rx.wait(), Ok(BatchStatus::Delivered));
+        assert_eq!(pending.events.len(), 1);
+        assert_eq!(pending.len(), 3);
+
+        drop(pending);
+        assert_eq!(failed_rx.wait(), Ok(BatchStatus::Failed));
+    }
+
+    #[test]
+    fn finalization_discards_last_event() {
+        let (batch, rx) = BatchNotifier::new_with_receiver();
+        let (failed_batch, failed_rx) = BatchNotifier::new_with_receiver();
+
+        let mut pending = PendingFinalizers::default();
+        pending.push(EventFinalizers::new(failed_batch));
+        pending.push(EventFinalizers::default());
+        pending.push(EventFinalizers::new(batch));
+        pending.discard_last();
+        assert_eq!(rx.wait(), Ok(BatchStatus::Delivered));
+        pending.push(EventFinalizers::default());
+        pending.discard_last();
+        assert_eq!(pending.len(), 2);
+
+        // The next events acked are still the ones before.
+        pending.resolve(1, BatchStatus::Failed);
+        assert_eq!(failed_rx.wait(), Ok(BatchStatus::Failed));
+        assert_eq!(pending.len(), 1);
+    }
+}
@@ -7,15 +7,17 @@ use metric::{MetricKind, MetricValue};
 use once_cell::sync::OnceCell;
 use serde::{Deserialize, Serialize, Serializer};
 use serde_json::Value as JsonValue;
-use std::{collections::BTreeMap, iter::FromIterator};
+use std::{collections::BTreeMap, iter::FromIterator, sync::Arc};
 use string_cache::DefaultAtom as Atom;
 
 pub mod discriminant;
+pub mod finalization;
 pub mod merge;
 pub mod merge_state;
 pub mod metric;
 mod util;
 
+pub use finalization::{BatchNotifier, BatchStatus, EventFinalizers};
 pub use metric::Metric;
 
 pub mod proto {
@@ -42,12 +44,14 @@ pub enum Event {
 #[derive(PartialEq, Debug, Clone)]
 pub struct LogEvent {
     fields: BTreeMap<Atom, Value>,
+    finalizers: EventFinalizers,
 }
 
 impl Event {
     pub fn new_empty_log() -> Self {
         Event::Log(LogEvent {
             fields: BTreeMap::new(),
+            finalizers: EventFinalizers::default(),
         })
     }
 
@@ -92,6 +96,22 @@ impl Event {
             _ => panic!("failed type coercion, {:?} is not a metric", self),
         }
     }
+
+    /// Tracks the delivery of this event for `batch`. Metrics aren't tracked,
+    /// so they count as delivered right away.
+    pub fn add_batch_notifier(&mut self, batch: Arc<BatchNotifier>) {
+        if let Event::Log(log) = self {
+            log.finalizers.merge(EventFinalizers::new(batch));
+        }
+    }
+
+    /// Takes the finalizers of this copy of the event, leaving it untracked.
+    pub fn take_finalizers(&mut self) -> EventFinalizers {
+        match self {
+            Event::Log(log) => std::mem::take(&mut log.finalizers),
+            Event::Metric(_) => EventFinalizers::default(),
+        }
+    }
 }
 
 impl LogEvent {
@@ -432,7 +452,10 @@ impl From<proto::EventWrapper> for Event {
                     .filter_map(|(k, v)| decode_value(v).map(|value| (Atom::from(k), value)))
                     .collect::<BTreeMap<_, _>>();
 
-                Event::Log(LogEvent { fields })
+                Event::Log(LogEvent {
+                    fields,
+                    finalizers: EventFinalizers::default(),
+                })
             }
             EventProto::Metric(proto) => {
                 let kind = match proto.kind() {
@@ -526,7 +549,7 @@ fn encode_array(items: Vec<Value>) -> proto::ValueArray {
 impl From<Event> for proto::EventWrapper {
     fn from(event: Event) -> Self {
         match event {
-            Event::Log(LogEvent { fields }) => {
+            Event::Log(LogEvent { fields, .. }) => {
                 let fields = fields
                     .into_iter()
                     .map(|(k, v)| (k.to_string(), encode_value(v)))
@@ -623,9 +646,7 @@ impl From<Event> for Vec<u8> {
 
 impl From<Bytes> for Event {
     fn from(message: Bytes) -> Self {
-        let mut event = Event::Log(LogEvent {
-            fields: BTreeMap::new(),
-        });
+        let mut event = Event::new_empty_log();
 
         event
             .as_mut_log()
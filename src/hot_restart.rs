@@ -0,0 +1,392 @@
+//! Hands a running Vector over to a newly started one, like when upgrading
+//! it, without refusing connections or losing buffered events.
+//!
+//! Both are started with `--hot-restart <path>`. The running Vector listens
+//! on the Unix socket at `path`, and the new one connects to it on startup:
+//!
+//! 1. The running Vector passes the sockets its sources listen on, as file
+//!    descriptors. The new Vector adopts them, so its sources share them
+//!    rather than binding their addresses again.
+//! 2. The new Vector builds its topology, with memory buffers since the disk
+//!    ones are still held, and once it's valid, sends a single byte to tell
+//!    the running Vector to go ahead. If it fails before that, it closes the
+//!    connection and the running Vector carries on.
+//! 3. The running Vector stops its sources, and sends the events left in the
+//!    memory buffers of its sinks once its transforms have drained, by sink
+//!    name. It closes the connection once its sinks have delivered the
+//!    events they already read, and exits.
+//!
+//! The new Vector then starts, sending the events it was handed to its sinks
+//! of the same name, and listens on `path` for the next restart.
+
+use crate::{
+    event::{proto, Event},
+    listeners::{self, Protocol},
+    topology::RunningTopology,
+};
+use futures01::{
+    stream::{self, Stream},
+    sync::{mpsc, oneshot},
+    Future,
+};
+use nix::sys::{
+    socket::{recvmsg, sendmsg, ControlMessage, ControlMessageOwned, MsgFlags},
+    uio::IoVec,
+};
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Read, Write},
+    net::SocketAddr,
+    os::unix::{
+        io::{AsRawFd, RawFd},
+        net::{UnixListener, UnixStream},
+    },
+    path::Path,
+    thread,
+};
+
+/// The most sockets handed over.
+const MAX_SOCKETS: usize = 128;
+
+/// Sent by the new Vector once its topology is built.
+const READY: u8 = 1;
+
+type Events = Box<dyn Stream<Item = (String, Event), Error = ()> + Send>;
+
+/// A socket passed along with the header, in the order of the descriptors.
+#[derive(Debug, Deserialize, Serialize)]
+struct HandedSocket {
+    protocol: Protocol,
+    address: SocketAddr,
+}
+
+/// The events a running Vector handed over, by sink name.
+#[derive(Debug, Default)]
+pub struct Handover {
+    pub events: HashMap<String, Vec<Event>>,
+}
+
+/// A connection to a running Vector whose sockets were adopted by
+/// `listeners`. Dropping it leaves that Vector running.
+#[derive(Debug)]
+pub struct Takeover {
+    stream: UnixStream,
+}
+
+/// Connects to the Vector listening on `path`, adopting the sockets it hands
+/// over. Returns `None` if no Vector listens there.
+pub fn connect(path: &Path) -> io::Result<Option<Takeover>> {
+    let mut stream = match UnixStream::connect(path) {
+        Ok(stream) => stream,
+        Err(error)
+            if error.kind() == io::ErrorKind::NotFound
+                || error.kind() == io::ErrorKind::ConnectionRefused =>
+        {
+            return Ok(None)
+        }
+        Err(error) => return Err(error),
+    };
+
+    for (socket, fd) in receive_sockets(&mut stream)? {
+        unsafe { listeners::inherit(socket.protocol, socket.address, fd) };
+    }
+
+    Ok(Some(Takeover { stream }))
+}
+
+impl Takeover {
+    /// Tells the running Vector to go ahead, waiting for it to stop and hand
+    /// its buffered events over.
+    pub fn receive(mut self) -> io::Result<Handover> {
+        self.stream.write_all(&[READY])?;
+
+        let mut handover = Handover::default();
+        while let Some(name) = read_frame(&mut self.stream)? {
+            let name = String::from_utf8(name).map_err(invalid_data)?;
+            let event = read_frame(&mut self.stream)?
+                .ok_or_else(|| invalid_data("Missing event after sink name."))?;
+            let event = proto::EventWrapper::decode(event).map_err(invalid_data)?;
+            handover
+                .events
+                .entry(name)
+                .or_insert_with(Vec::new)
+                .push(Event::from(event));
+        }
+        Ok(handover)
+    }
+}
+
+/// Listens on `path` for a new Vector to hand over to, yielding the
+/// connections of those ready to take over.
+pub fn listen(path: &Path) -> io::Result<mpsc::UnboundedReceiver<UnixStream>> {
+    // The socket of the Vector handing over to this one is left behind.
+    match fs::remove_file(path) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error),
+        _ => {}
+    }
+    let listener = UnixListener::bind(path)?;
+
+    let (tx, rx) = mpsc::unbounded();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let ready =
+                stream.and_then(|mut stream| offer(&mut stream).map(|ready| (stream, ready)));
+            match ready {
+                Ok((stream, true)) => {
+                    if tx.unbounded_send(stream).is_err() {
+                        break;
+                    }
+                }
+                Ok((_, false)) => warn!("The new Vector failed to start; keeping on running."),
+                Err(error) => error!(message = "Failed to accept hot restart.", %error),
+            }
+        }
+    });
+    Ok(rx)
+}
+
+/// Hands the sockets in use over to the Vector connected on `stream`, then
+/// waits for it to be ready to take over. It isn't if it closes the
+/// connection instead.
+fn offer(stream: &mut UnixStream) -> io::Result<bool> {
+    send_sockets(stream, listeners::bound()?)?;
+
+    let mut ready = [0u8; 1];
+    match stream.read(&mut ready)? {
+        0 => Ok(false),
+        _ if ready[0] == READY => Ok(true),
+        _ => Err(invalid_data("Unexpected reply to the sockets.")),
+    }
+}
+
+/// Hands the buffered events of the topology over to the Vector connected on
+/// `stream`, once it's ready, resolving once the topology has stopped.
+pub fn hand_over(
+    mut stream: UnixStream,
+    topology: RunningTopology,
+) -> impl Future<Item = (), Error = ()> {
+    let (shutdown, buffered) = topology.hand_over();
+    let events = buffered
+        .into_iter()
+        .map(|(name, events)| events.map(move |event| (name.clone(), event)))
+        .fold(
+            Box::new(stream::empty()) as Events,
+            |all, events| -> Events { Box::new(all.select(events)) },
+        );
+
+    // Events are read and written on their own thread, as writes block.
+    let (tx, rx) = oneshot::channel();
+    thread::spawn(move || {
+        let mut handed = 0;
+        for (name, event) in events.wait().filter_map(Result::ok) {
+            if let Err(error) = send_event(&mut stream, &name, event) {
+                error!(message = "Failed to hand events over.", %error, count = handed);
+                break;
+            }
+            handed += 1;
+        }
+        info!(message = "Handed buffered events over.", count = handed);
+        let _ = tx.send(stream);
+    });
+
+    // The connection is closed once the sinks have let go of disk buffers,
+    // which the new Vector opens.
+    shutdown
+        .join(rx.map_err(|_| ()))
+        .map(|((), stream)| drop(stream))
+}
+
+fn send_sockets(
+    stream: &mut UnixStream,
+    mut sockets: Vec<(Protocol, SocketAddr, listeners::Socket)>,
+) -> io::Result<()> {
+    sockets.truncate(MAX_SOCKETS);
+
+    let handed = sockets
+        .iter()
+        .map(|(protocol, address, _)| HandedSocket {
+            protocol: *protocol,
+            address: *address,
+        })
+        .collect::<Vec<_>>();
+    let header = serde_json::to_vec(&handed).map_err(invalid_data)?;
+    let fds = sockets
+        .iter()
+        .map(|(_, _, socket)| socket.as_raw_fd())
+        .collect::<Vec<_>>();
+
+    // The descriptors go along with the length of the header.
+    let len = (header.len() as u32).to_be_bytes();
+    let rights = [ControlMessage::ScmRights(&fds)];
+    let cmsgs = if fds.is_empty() { &[][..] } else { &rights[..] };
+    let sent = sendmsg(
+        stream.as_raw_fd(),
+        &[IoVec::from_slice(&len)],
+        cmsgs,
+        MsgFlags::empty(),
+        None,
+    )
+    .map_err(nix_error)?;
+    stream.write_all(&len[sent..])?;
+    stream.write_all(&header)?;
+
+    info!(message = "Handed sockets over.", count = fds.len());
+    Ok(())
+}
+
+fn receive_sockets(stream: &mut UnixStream) -> io::Result<Vec<(HandedSocket, RawFd)>> {
+    let mut len = [0u8; 4];
+    let mut cmsgs = nix::cmsg_space!([RawFd; MAX_SOCKETS]);
+    let (received, fds) = {
+        let msg = recvmsg(
+            stream.as_raw_fd(),
+            &[IoVec::from_mut_slice(&mut len)],
+            Some(&mut cmsgs),
+            MsgFlags::empty(),
+        )
+        .map_err(nix_error)?;
+        let fds = msg
+            .cmsgs()
+            .filter_map(|cmsg| match cmsg {
+                ControlMessageOwned::ScmRights(fds) => Some(fds),
+                _ => None,
+            })
+            .flatten()
+            .collect::<Vec<_>>();
+        (msg.bytes, fds)
+    };
+    if received == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    stream.read_exact(&mut len[received..])?;
+
+    let mut header = vec![0; u32::from_be_bytes(len) as usize];
+    stream.read_exact(&mut header)?;
+    let handed = serde_json::from_slice::<Vec<HandedSocket>>(&header).map_err(invalid_data)?;
+    if handed.len() != fds.len() {
+        return Err(invalid_data(format!(
+            "Received {} sockets, but {} descriptors.",
+            handed.len(),
+            fds.len()
+        )));
+    }
+
+    Ok(handed.into_iter().zip(fds).collect())
+}
+
+fn send_event(stream: &mut UnixStream, name: &str, event: Event) -> io::Result<()> {
+    let mut encoded = Vec::new();
+    proto::EventWrapper::from(event)
+        .encode(&mut encoded)
+        .map_err(invalid_data)?;
+    write_frame(stream, name.as_bytes())?;
+    write_frame(stream, &encoded)
+}
+
+fn write_frame(stream: &mut impl Write, frame: &[u8]) -> io::Result<()> {
+    stream.write_all(&(frame.len() as u32).to_be_bytes())?;
+    stream.write_all(frame)
+}
+
+/// Reads a frame, or `None` at the end of the stream.
+fn read_frame(stream: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 4];
+    match stream.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(error) => return Err(error),
+    }
+    let mut frame = vec![0; u32::from_be_bytes(len) as usize];
+    stream.read_exact(&mut frame)?;
+    Ok(Some(frame))
+}
+
+fn invalid_data(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+fn nix_error(error: nix::Error) -> io::Error {
+    match error.as_errno() {
+        Some(errno) => io::Error::from_raw_os_error(errno as i32),
+        None => io::Error::new(io::ErrorKind::Other, error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::next_addr;
+    use futures01::stream::iter_ok;
+    use std::net::UdpSocket;
+
+    #[test]
+    fn hot_restart_hands_sockets_and_events_over() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("vector.sock");
+        assert!(connect(&path).unwrap().is_none());
+
+        let addr = next_addr();
+        let socket = UdpSocket::bind(addr).unwrap();
+        let listener = UnixListener::bind(&path).unwrap();
+        let running = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let sockets = vec![(Protocol::Udp, addr, listeners::Socket::Udp(socket))];
+            send_sockets(&mut stream, sockets).unwrap();
+            let mut ready = [0u8; 1];
+            stream.read_exact(&mut ready).unwrap();
+            assert_eq!(ready[0], READY);
+
+            let events = iter_ok::<_, ()>(vec!["first", "second"]);
+            for message in events.wait() {
+                send_event(&mut stream, "out", Event::from(message.unwrap())).unwrap();
+            }
+        });
+
+        // The socket is adopted before the running Vector goes ahead, and
+        // shared with the sources binding it.
+        let takeover = connect(&path).unwrap().unwrap();
+        assert!(UdpSocket::bind(addr).is_err());
+        let mut socket_use = listeners::udp(addr).unwrap();
+        let socket = socket_use.bind_udp().unwrap();
+
+        let handover = takeover.receive().unwrap();
+        running.join().unwrap();
+
+        let messages = handover.events["out"]
+            .iter()
+            .map(|event| {
+                event
+                    .as_log()
+                    .get(&crate::event::log_schema().message_key())
+                    .unwrap()
+                    .to_string_lossy()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(messages, vec!["first", "second"]);
+
+        UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .send_to(b"datagram", addr)
+            .unwrap();
+        let mut buf = [0; 16];
+        assert_eq!(socket.recv(&mut buf).unwrap(), 8);
+    }
+
+    #[test]
+    fn hot_restart_keeps_running_if_not_taken_over() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("vector.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+        let running = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            offer(&mut stream).unwrap()
+        });
+
+        // Like a new Vector with an invalid config.
+        drop(connect(&path).unwrap().unwrap());
+        assert!(!running.join().unwrap());
+    }
+}
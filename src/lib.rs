@@ -9,24 +9,30 @@ extern crate derivative;
 #[global_allocator]
 static ALLOC: jemallocator::Jemalloc = jemallocator::Jemalloc;
 
+pub mod api;
 pub mod buffers;
 pub mod conditions;
 pub mod config_paths;
 pub mod dns;
 pub mod event;
 pub mod generate;
+#[cfg(unix)]
+pub mod hot_restart;
 #[cfg(feature = "rdkafka")]
 pub mod kafka;
 pub mod list;
+pub mod listeners;
 pub mod metrics;
 pub mod region;
 pub mod runtime;
 pub mod serde;
 pub mod sinks;
 pub mod sources;
+pub mod tap;
 pub mod template;
 pub mod test_util;
 pub mod tls;
+pub mod top;
 pub mod topology;
 pub mod trace;
 pub mod transforms;
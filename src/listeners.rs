@@ -0,0 +1,242 @@
+//! The sockets sources listen on, shared between the instances of a source.
+//!
+//! A reload builds the new instance of a changed source before the previous
+//! one stops. Rather than failing to bind its address, or closing the socket
+//! in between and refusing connections, the new instance shares the socket
+//! of the previous one. A socket is closed once no instance uses it anymore.
+//!
+//! On Unix, the sockets can also be handed over to another Vector process by
+//! a hot restart.
+
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::{
+    collections::HashMap,
+    io,
+    net::{SocketAddr, TcpListener, UdpSocket},
+    sync::Mutex,
+};
+
+lazy_static! {
+    static ref SOCKETS: Mutex<HashMap<(Protocol, SocketAddr), Shared>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+struct Shared {
+    socket: Socket,
+    users: usize,
+}
+
+#[derive(Debug)]
+pub enum Socket {
+    Tcp(TcpListener),
+    Udp(UdpSocket),
+}
+
+impl Socket {
+    fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Socket::Tcp(listener) => listener.try_clone().map(Socket::Tcp),
+            Socket::Udp(socket) => socket.try_clone().map(Socket::Udp),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl AsRawFd for Socket {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Socket::Tcp(listener) => listener.as_raw_fd(),
+            Socket::Udp(socket) => socket.as_raw_fd(),
+        }
+    }
+}
+
+/// A source's use of a shared socket, which ends when dropped.
+///
+/// A socket already bound by another instance of the source, or handed over
+/// by a hot restart, is shared as soon as the source is built. Otherwise it's
+/// only bound once the source runs, so validating a config binds nothing.
+#[derive(Debug)]
+pub struct Listener {
+    protocol: Protocol,
+    addr: SocketAddr,
+    socket: Option<Socket>,
+    key: Option<(Protocol, SocketAddr)>,
+}
+
+impl Listener {
+    /// Binds the TCP listener, unless it's already shared.
+    pub fn bind_tcp(&mut self) -> io::Result<TcpListener> {
+        match self.bind()? {
+            Socket::Tcp(listener) => Ok(listener),
+            Socket::Udp(_) => unreachable!(),
+        }
+    }
+
+    /// Binds the UDP socket, unless it's already shared.
+    pub fn bind_udp(&mut self) -> io::Result<UdpSocket> {
+        match self.bind()? {
+            Socket::Udp(socket) => Ok(socket),
+            Socket::Tcp(_) => unreachable!(),
+        }
+    }
+
+    fn bind(&mut self) -> io::Result<Socket> {
+        match self.socket.take() {
+            Some(socket) => Ok(socket),
+            None => {
+                let (socket, key) = share(self.protocol, self.addr)?;
+                self.key = key;
+                Ok(socket)
+            }
+        }
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Some(key) = self.key {
+            let mut sockets = SOCKETS.lock().unwrap();
+            let unused = sockets
+                .get_mut(&key)
+                .map(|shared| {
+                    shared.users -= 1;
+                    shared.users == 0
+                })
+                .unwrap_or(false);
+            if unused {
+                sockets.remove(&key);
+            }
+        }
+    }
+}
+
+/// A TCP listener for `addr`, sharing the one already bound to it.
+pub fn tcp(addr: SocketAddr) -> io::Result<Listener> {
+    listener(Protocol::Tcp, addr)
+}
+
+/// A UDP socket for `addr`, sharing the one already bound to it.
+pub fn udp(addr: SocketAddr) -> io::Result<Listener> {
+    listener(Protocol::Udp, addr)
+}
+
+fn listener(protocol: Protocol, addr: SocketAddr) -> io::Result<Listener> {
+    let mut listener = Listener {
+        protocol,
+        addr,
+        socket: None,
+        key: None,
+    };
+    let key = (protocol, addr);
+    if let Some(shared) = SOCKETS.lock().unwrap().get_mut(&key) {
+        listener.socket = Some(shared.socket.try_clone()?);
+        listener.key = Some(key);
+        shared.users += 1;
+    }
+    Ok(listener)
+}
+
+/// Binds a socket to `addr`, or shares the one already bound to it.
+fn share(
+    protocol: Protocol,
+    addr: SocketAddr,
+) -> io::Result<(Socket, Option<(Protocol, SocketAddr)>)> {
+    let bind = || match protocol {
+        Protocol::Tcp => TcpListener::bind(addr).map(Socket::Tcp),
+        Protocol::Udp => UdpSocket::bind(addr).map(Socket::Udp),
+    };
+
+    // Binding to any port gets a different one each time.
+    if addr.port() == 0 {
+        return Ok((bind()?, None));
+    }
+
+    let key = (protocol, addr);
+    let mut sockets = SOCKETS.lock().unwrap();
+    if let Some(shared) = sockets.get_mut(&key) {
+        let socket = shared.socket.try_clone()?;
+        shared.users += 1;
+        return Ok((socket, Some(key)));
+    }
+
+    let socket = bind()?;
+    let shared = Shared {
+        socket: socket.try_clone()?,
+        users: 1,
+    };
+    sockets.insert(key, shared);
+    Ok((socket, Some(key)))
+}
+
+/// The sockets in use, to hand over to another process.
+pub fn bound() -> io::Result<Vec<(Protocol, SocketAddr, Socket)>> {
+    let sockets = SOCKETS.lock().unwrap();
+    sockets
+        .iter()
+        .map(|(&(protocol, addr), shared)| Ok((protocol, addr, shared.socket.try_clone()?)))
+        .collect()
+}
+
+/// Adopts a socket handed over by another process, for the sources binding
+/// its address to share. It's closed by `release_unused` if none does.
+///
+/// The file descriptor must be a socket bound to `addr`, owned by the caller.
+#[cfg(unix)]
+pub unsafe fn inherit(protocol: Protocol, addr: SocketAddr, fd: RawFd) {
+    let socket = match protocol {
+        Protocol::Tcp => Socket::Tcp(TcpListener::from_raw_fd(fd)),
+        Protocol::Udp => Socket::Udp(UdpSocket::from_raw_fd(fd)),
+    };
+    // A socket already bound here is kept, closing the inherited one.
+    SOCKETS
+        .lock()
+        .unwrap()
+        .entry((protocol, addr))
+        .or_insert(Shared { socket, users: 0 });
+}
+
+/// Closes the inherited sockets no source uses.
+pub fn release_unused() {
+    SOCKETS.lock().unwrap().retain(|_, shared| shared.users > 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::next_addr;
+
+    #[test]
+    fn listeners_share_sockets_until_unused() {
+        let addr = next_addr();
+
+        // Nothing is bound until a source runs.
+        let mut first_use = tcp(addr).unwrap();
+        assert!(!SOCKETS.lock().unwrap().contains_key(&(Protocol::Tcp, addr)));
+
+        let first = first_use.bind_tcp().unwrap();
+        let mut second_use = tcp(addr).unwrap();
+        let second = second_use.bind_tcp().unwrap();
+        let mut udp_use = udp(addr).unwrap();
+        let _udp = udp_use.bind_udp().unwrap();
+        drop(first);
+        drop(first_use);
+
+        // Still listening through the second use.
+        assert!(std::net::TcpStream::connect(addr).is_ok());
+
+        drop(second);
+        drop(second_use);
+        assert!(!SOCKETS.lock().unwrap().contains_key(&(Protocol::Tcp, addr)));
+        assert!(TcpListener::bind(addr).is_ok());
+    }
+}
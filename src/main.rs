@@ -1,7 +1,7 @@
 #[macro_use]
 extern crate tracing;
 
-use futures01::{future, Future, Stream};
+use futures01::{future, sync::mpsc, Future, Stream};
 use std::{
     cmp::{max, min},
     fs::File,
@@ -11,9 +11,14 @@ use std::{
 use structopt::{clap::AppSettings, StructOpt};
 #[cfg(unix)]
 use tokio_signal::unix::{Signal, SIGHUP, SIGINT, SIGQUIT, SIGTERM};
-use topology::Config;
+use topology::{config::Format, Config};
 use tracing_futures::Instrument;
-use vector::{config_paths, event, generate, list, metrics, runtime, topology, trace, unit_test};
+use vector::{
+    api, config_paths, event, generate, list, metrics, runtime, tap, top, topology, trace,
+    unit_test,
+};
+#[cfg(unix)]
+use vector::{hot_restart, listeners};
 
 #[derive(StructOpt, Debug)]
 #[structopt(rename_all = "kebab-case")]
@@ -29,11 +34,27 @@ struct Opts {
 #[structopt(rename_all = "kebab-case")]
 struct RootOpts {
     /// Read configuration from one or more files. Wildcard paths are supported.
-    /// If zero files are specified the default config path
-    /// `/etc/vector/vector.toml` will be targeted.
+    /// The format of each file is detected by its extension: `.json`, `.yaml`
+    /// or `.yml`, and TOML otherwise. If zero files are specified the default
+    /// config path `/etc/vector/vector.toml` will be targeted.
     #[structopt(name = "config", short, long)]
     config_paths: Vec<PathBuf>,
 
+    /// Read configuration from one or more TOML files, whatever their
+    /// extension. Wildcard paths are supported.
+    #[structopt(name = "config-toml", long)]
+    config_paths_toml: Vec<PathBuf>,
+
+    /// Read configuration from one or more JSON files, whatever their
+    /// extension. Wildcard paths are supported.
+    #[structopt(name = "config-json", long)]
+    config_paths_json: Vec<PathBuf>,
+
+    /// Read configuration from one or more YAML files, whatever their
+    /// extension. Wildcard paths are supported.
+    #[structopt(name = "config-yaml", long)]
+    config_paths_yaml: Vec<PathBuf>,
+
     /// Exit on startup if any sinks fail healthchecks
     #[structopt(short, long)]
     require_healthy: bool,
@@ -46,6 +67,11 @@ struct RootOpts {
     #[structopt(short, long)]
     metrics_addr: Option<SocketAddr>,
 
+    /// Serve the management API, reporting health and component status and
+    /// accepting reloads, from the given address
+    #[structopt(long)]
+    api_addr: Option<SocketAddr>,
+
     /// Number of threads to use for processing (default is number of available cores)
     #[structopt(short, long)]
     threads: Option<usize>,
@@ -77,6 +103,11 @@ struct RootOpts {
     /// Watch for changes in configuration file, and reload accordingly.
     #[structopt(short, long)]
     watch_config: bool,
+
+    /// Take over from the Vector listening on this Unix socket, if any, then
+    /// listen on it for the next Vector to hand over to. (Unix only)
+    #[structopt(long)]
+    hot_restart: Option<PathBuf>,
 }
 
 #[derive(StructOpt, Debug)]
@@ -94,6 +125,14 @@ enum SubCommand {
     /// Run Vector config unit tests, then exit. This command is experimental and therefore subject to change.
     /// For guidance on how to write unit tests check out: https://vector.dev/docs/setup/guides/unit-testing/
     Test(unit_test::Opts),
+
+    /// Stream the events sent out of a source or transform of a running
+    /// Vector, through its API.
+    Tap(tap::Opts),
+
+    /// Show the throughput, errors and buffer usage of each component of a
+    /// running Vector, through its API.
+    Top(top::Opts),
 }
 
 #[derive(StructOpt, Debug)]
@@ -107,9 +146,22 @@ struct Validate {
     #[structopt(short, long)]
     deny_warnings: bool,
 
-    /// Any number of Vector config files to validate. If none are specified the
-    /// default config path `/etc/vector/vector.toml` will be targeted.
+    /// Any number of Vector config files to validate, in the format of their
+    /// extension. If none are specified the default config path
+    /// `/etc/vector/vector.toml` will be targeted.
     paths: Vec<PathBuf>,
+
+    /// Any number of Vector config files to validate as TOML.
+    #[structopt(name = "config-toml", long)]
+    paths_toml: Vec<PathBuf>,
+
+    /// Any number of Vector config files to validate as JSON.
+    #[structopt(name = "config-json", long)]
+    paths_json: Vec<PathBuf>,
+
+    /// Any number of Vector config files to validate as YAML.
+    #[structopt(name = "config-yaml", long)]
+    paths_yaml: Vec<PathBuf>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -239,6 +291,8 @@ fn main() {
             SubCommand::List(l) => list::cmd(&l),
             SubCommand::Test(t) => unit_test::cmd(&t),
             SubCommand::Generate(g) => generate::cmd(&g),
+            SubCommand::Tap(t) => tap::cmd(&t),
+            SubCommand::Top(t) => top::cmd(&t),
         })
     });
 
@@ -251,7 +305,13 @@ fn main() {
         }
     }
 
-    let mut config_paths = config_paths::expand(opts.config_paths.clone()).unwrap_or_else(|| {
+    let paths = config_paths::with_formats(&[
+        (&opts.config_paths, None),
+        (&opts.config_paths_toml, Some(Format::Toml)),
+        (&opts.config_paths_json, Some(Format::Json)),
+        (&opts.config_paths_yaml, Some(Format::Yaml)),
+    ]);
+    let mut config_paths = config_paths::expand(paths).unwrap_or_else(|| {
         std::process::exit(exitcode::CONFIG);
     });
     config_paths.sort();
@@ -260,7 +320,7 @@ fn main() {
     if opts.watch_config {
         // Start listening for config changes immediately.
         vector::topology::config::watcher::config_watcher(
-            config_paths.clone(),
+            config_paths.iter().map(|(path, _)| path.clone()).collect(),
             vector::topology::config::watcher::CONFIG_WATCH_DELAY,
         )
         .unwrap_or_else(|error| {
@@ -276,7 +336,8 @@ fn main() {
 
     let config = read_configs(&config_paths);
     let config = handle_config_errors(config);
-    let config = config.unwrap_or_else(|| {
+    #[allow(unused_mut)]
+    let mut config = config.unwrap_or_else(|| {
         std::process::exit(exitcode::CONFIG);
     });
     event::LOG_SCHEMA
@@ -297,7 +358,7 @@ fn main() {
         rt.spawn(
             metrics::serve(&metrics_addr, metrics_controller)
                 .instrument(info_span!("metrics", addr = ?metrics_addr))
-                .select(metrics_tripwire)
+                .select(metrics_tripwire.clone())
                 .map(|_| ())
                 .map_err(|_| ()),
         );
@@ -315,9 +376,57 @@ fn main() {
         info!("Dry run enabled, exiting after config validation.");
     }
 
+    // The sockets handed over are adopted before the sources are built, so
+    // they share them.
+    #[cfg(unix)]
+    let takeover = match opts.hot_restart.as_deref().filter(|_| !opts.dry_run) {
+        Some(path) => hot_restart::connect(path).unwrap_or_else(|error| {
+            error!(message = "Unable to take over from the running Vector.", %error);
+            std::process::exit(exitcode::UNAVAILABLE);
+        }),
+        None => None,
+    };
+    #[cfg(windows)]
+    {
+        if opts.hot_restart.is_some() {
+            error!("Hot restart isn't supported on Windows.");
+            std::process::exit(exitcode::USAGE);
+        }
+    }
+
+    // The running Vector only stops once the config is known to be valid.
+    // Its disk buffers stay locked until then, so the config is first
+    // validated with memory buffers, and those pieces are kept until the
+    // real ones are built so the adopted sockets stay shared.
+    #[cfg(unix)]
+    let (handover, _validated) = match takeover {
+        Some(takeover) => {
+            let buffers = config
+                .sinks
+                .values_mut()
+                .map(|sink| std::mem::take(&mut sink.buffer))
+                .collect::<Vec<_>>();
+            let validated = topology::validate(&config, rt.executor()).unwrap_or_else(|| {
+                std::process::exit(exitcode::CONFIG);
+            });
+            for (sink, buffer) in config.sinks.values_mut().zip(buffers) {
+                sink.buffer = buffer;
+            }
+
+            let handover = takeover.receive().unwrap_or_else(|error| {
+                error!(message = "Unable to receive the events handed over.", %error);
+                hot_restart::Handover::default()
+            });
+            (Some(handover), Some(validated))
+        }
+        None => (None, None),
+    };
+
     let pieces = topology::validate(&config, rt.executor()).unwrap_or_else(|| {
         std::process::exit(exitcode::CONFIG);
     });
+    #[cfg(unix)]
+    drop(_validated);
 
     if opts.dry_run && !opts.require_healthy {
         info!("Config validated, exiting.");
@@ -334,9 +443,59 @@ fn main() {
         std::process::exit(exitcode::OK);
     }
 
+    #[cfg(unix)]
+    let hot_restarts = {
+        use futures01::{stream::iter_ok, Sink};
+
+        if let Some(handover) = handover {
+            info!("Took over from the running Vector.");
+            for (name, events) in handover.events {
+                match topology.sink_input(&name) {
+                    Some(input) => {
+                        rt.spawn(input.send_all(iter_ok::<_, ()>(events)).map(|_| ()));
+                    }
+                    None => warn!(
+                        message = "Sink was removed; dropping the events handed over to it.",
+                        %name,
+                        count = events.len(),
+                    ),
+                }
+            }
+        }
+        listeners::release_unused();
+
+        match opts.hot_restart.as_deref().map(hot_restart::listen) {
+            Some(Ok(hot_restarts)) => Some(hot_restarts),
+            Some(Err(error)) => {
+                error!(message = "Unable to listen for hot restarts.", %error);
+                std::process::exit(exitcode::UNAVAILABLE);
+            }
+            None => None,
+        }
+    };
+
+    // The sender is held until Vector exits, so reload requests never end.
+    let (reload_tx, reload_rx) = mpsc::unbounded::<api::ReloadRequest>();
+
+    if let Some(api_addr) = opts.api_addr {
+        debug!("Starting API server");
+
+        rt.spawn(
+            api::serve(&api_addr, topology.status(), reload_tx.clone())
+                .instrument(info_span!("api", addr = ?api_addr))
+                .select(metrics_tripwire)
+                .map(|_| ())
+                .map_err(|_| ()),
+        );
+    }
+
     #[cfg(unix)]
     {
         let mut topology = topology;
+        let mut reload_rx = reload_rx;
+        // Like reload_tx, the sender is held so that the stream never ends.
+        let (_hot_restart_tx, no_hot_restarts) = mpsc::unbounded();
+        let mut hot_restarts = hot_restarts.unwrap_or(no_hot_restarts);
         let sigint = Signal::new(SIGINT).flatten_stream();
         let sigterm = Signal::new(SIGTERM).flatten_stream();
         let sigquit = Signal::new(SIGQUIT).flatten_stream();
@@ -344,50 +503,66 @@ fn main() {
 
         let mut signals = sigint.select(sigterm.select(sigquit.select(sighup)));
 
-        let signal = loop {
-            let signal = future::poll_fn(|| signals.poll());
-            let crash = future::poll_fn(|| graceful_crash.poll());
+        let exit = loop {
+            let signal = future::poll_fn(|| signals.poll())
+                .map(|signal| Next::Signal(signal.expect("Signal streams never end")));
+            let crash = future::poll_fn(|| graceful_crash.poll()).map(|_| Next::Crash);
+            let reload = future::poll_fn(|| reload_rx.poll()).map(Next::Reload);
+            let hot_restart = future::poll_fn(|| hot_restarts.poll()).map(Next::HotRestart);
 
             let next = signal
-                .select2(crash)
+                .map_err(|_| ())
+                .select(crash)
+                .map_err(|_| ())
+                .select(reload)
+                .map_err(|_| ())
+                .select(hot_restart)
                 .wait()
+                .map(|(next, _)| next)
                 .map_err(|_| ())
                 .expect("Neither stream errors");
 
             let signal = match next {
-                future::Either::A((signal, _)) => signal.expect("Signal streams never end"),
-                future::Either::B((_crash, _)) => SIGINT, // Trigger graceful shutdown if a component crashed
+                Next::Signal(signal) => signal,
+                Next::Crash => SIGINT, // Trigger graceful shutdown if a component crashed
+                Next::Reload(Some(reply)) => {
+                    let result =
+                        reload_configs(&mut topology, &config_paths, &mut rt, opts.require_healthy);
+                    let _ = reply.send(result);
+                    continue;
+                }
+                Next::Reload(None) => unreachable!("reload_tx is kept"),
+                Next::HotRestart(Some(stream)) => break Exit::HotRestart(stream),
+                Next::HotRestart(None) => unreachable!("_hot_restart_tx is kept"),
             };
 
             if signal != SIGHUP {
-                break signal;
+                break Exit::Signal(signal);
             }
 
-            // Reload config
-            info!(
-                message = "Reloading configs.",
-                path = ?config_paths
-            );
-            let config = read_configs(&config_paths);
-
-            trace!("Parsing config");
-            let config = handle_config_errors(config);
-            if let Some(config) = config {
-                let success =
-                    topology.reload_config_and_respawn(config, &mut rt, opts.require_healthy);
-                if !success {
-                    error!("Reload was not successful.");
-                }
-            } else {
-                error!("Reload aborted.");
-            }
+            let _ = reload_configs(&mut topology, &config_paths, &mut rt, opts.require_healthy);
         };
 
-        if signal == SIGINT || signal == SIGTERM {
-            use futures01::future::Either;
+        use futures01::future::Either;
+
+        let shutdown = match exit {
+            Exit::HotRestart(stream) => {
+                info!("Handing over to the new Vector.");
+                Some(Either::A(hot_restart::hand_over(stream, topology)))
+            }
+            Exit::Signal(signal) if signal == SIGINT || signal == SIGTERM => {
+                info!("Shutting down.");
+                Some(Either::B(topology.stop()))
+            }
+            Exit::Signal(signal) if signal == SIGQUIT => {
+                info!("Shutting down immediately");
+                drop(topology);
+                None
+            }
+            Exit::Signal(_) => unreachable!(),
+        };
 
-            info!("Shutting down.");
-            let shutdown = topology.stop();
+        if let Some(shutdown) = shutdown {
             metrics_trigger.cancel();
 
             match rt.block_on(shutdown.select2(signals.into_future())) {
@@ -398,15 +573,17 @@ fn main() {
                 }
                 Err(_) => unreachable!(),
             }
-        } else if signal == SIGQUIT {
-            info!("Shutting down immediately");
-            drop(topology);
-        } else {
-            unreachable!();
         }
     }
     #[cfg(windows)]
     {
+        // Reloading relies on SIGHUP's handling, which Windows lacks.
+        rt.spawn(reload_rx.for_each(|reply| {
+            let error = "Reloading isn't supported on Windows.".to_owned();
+            let _ = reply.send(Err(vec![error]));
+            Ok(())
+        }));
+
         let ctrl_c = tokio_signal::ctrl_c().flatten_stream().into_future();
         let crash = future::poll_fn(move || graceful_crash.poll());
 
@@ -439,6 +616,45 @@ fn main() {
     rt.shutdown_now().wait().unwrap();
 }
 
+#[cfg(unix)]
+enum Next {
+    Signal(i32),
+    Crash,
+    Reload(Option<api::ReloadRequest>),
+    HotRestart(Option<std::os::unix::net::UnixStream>),
+}
+
+#[cfg(unix)]
+enum Exit {
+    Signal(i32),
+    HotRestart(std::os::unix::net::UnixStream),
+}
+
+/// Reloads the configs, logging the reasons the reload was aborted and
+/// returning them.
+#[cfg(unix)]
+fn reload_configs(
+    topology: &mut topology::RunningTopology,
+    config_paths: &Vec<(PathBuf, Format)>,
+    rt: &mut runtime::Runtime,
+    require_healthy: bool,
+) -> api::ReloadResult {
+    info!(
+        message = "Reloading configs.",
+        path = ?config_paths
+    );
+    let result = read_configs(config_paths)
+        .and_then(|config| topology.try_reload_config_and_respawn(config, rt, require_healthy));
+
+    if let Err(errors) = &result {
+        for error in errors {
+            error!("Configuration error: {}", error);
+        }
+        error!("Reload was not successful.");
+    }
+    result
+}
+
 fn handle_config_errors(config: Result<Config, Vec<String>>) -> Option<Config> {
     match config {
         Err(errors) => {
@@ -451,11 +667,11 @@ fn handle_config_errors(config: Result<Config, Vec<String>>) -> Option<Config> {
     }
 }
 
-fn read_configs(config_paths: &Vec<PathBuf>) -> Result<Config, Vec<String>> {
+fn read_configs(config_paths: &Vec<(PathBuf, Format)>) -> Result<Config, Vec<String>> {
     let mut config = vector::topology::Config::empty();
     let mut errors = Vec::new();
 
-    config_paths.iter().for_each(|p| {
+    config_paths.iter().for_each(|(p, format)| {
         let file = if let Some(file) = open_config(&p) {
             file
         } else {
@@ -468,7 +684,7 @@ fn read_configs(config_paths: &Vec<PathBuf>) -> Result<Config, Vec<String>> {
             path = ?p
         );
 
-        match Config::load(file).and_then(|n| config.append(n)) {
+        match Config::load_from(file, *format).and_then(|n| config.append(n)) {
             Err(errs) => errors.extend(errs.iter().map(|e| format!("{:?}: {}", p, e))),
             _ => (),
         };
@@ -501,11 +717,17 @@ fn open_config(path: &Path) -> Option<File> {
 }
 
 fn validate(opts: &Validate) -> exitcode::ExitCode {
-    let paths = config_paths::expand(opts.paths.clone()).unwrap_or_else(|| {
+    let paths = config_paths::with_formats(&[
+        (&opts.paths, None),
+        (&opts.paths_toml, Some(Format::Toml)),
+        (&opts.paths_json, Some(Format::Json)),
+        (&opts.paths_yaml, Some(Format::Yaml)),
+    ]);
+    let paths = config_paths::expand(paths).unwrap_or_else(|| {
         std::process::exit(exitcode::CONFIG);
     });
 
-    for config_path in paths {
+    for (config_path, format) in paths {
         let file = if let Some(file) = open_config(&config_path) {
             file
         } else {
@@ -521,7 +743,7 @@ fn validate(opts: &Validate) -> exitcode::ExitCode {
             path = ?config_path
         );
 
-        let config = vector::topology::Config::load(file);
+        let config = vector::topology::Config::load_from(file, format);
         let config = handle_config_errors(config);
         let mut config = config.unwrap_or_else(|| {
             error!(
@@ -0,0 +1,175 @@
+use super::registry::{registry, Counter, Gauge, Histogram, Labels};
+use futures01::{Future, Poll, Stream};
+use std::cell::RefCell;
+
+/// Upper bounds, in seconds, of the request duration buckets.
+pub const DURATION_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+];
+
+/// Upper bounds of the batch size buckets, in events.
+pub const BATCH_BUCKETS: &[f64] = &[1.0, 10.0, 100.0, 1_000.0, 10_000.0, 100_000.0];
+
+thread_local! {
+    static CURRENT: RefCell<Option<ComponentMetrics>> = RefCell::new(None);
+}
+
+/// The metrics of a source, transform or sink, labeled by its name, kind
+/// and type.
+#[derive(Clone, Debug)]
+pub struct ComponentMetrics {
+    labels: Labels,
+    events_in: Counter,
+    events_out: Counter,
+}
+
+impl ComponentMetrics {
+    pub fn new(kind: &'static str, name: &str, typetag: &str) -> Self {
+        let labels = vec![
+            ("component_kind", kind.to_owned()),
+            ("component_name", name.to_owned()),
+            ("component_type", typetag.to_owned()),
+        ];
+        Self {
+            events_in: registry().counter("events_in_total", labels.clone()),
+            events_out: registry().counter("events_out_total", labels.clone()),
+            labels,
+        }
+    }
+
+    pub fn labels(&self) -> &Labels {
+        &self.labels
+    }
+
+    pub fn name(&self) -> &str {
+        &self.labels[1].1
+    }
+
+    pub fn events_in(&self) -> &Counter {
+        &self.events_in
+    }
+
+    pub fn events_out(&self) -> &Counter {
+        &self.events_out
+    }
+
+    pub fn counter(&self, name: &'static str) -> Counter {
+        registry().counter(name, self.labels.clone())
+    }
+
+    pub fn gauge(&self, name: &'static str) -> Gauge {
+        registry().gauge(name, self.labels.clone())
+    }
+
+    pub fn histogram(&self, name: &'static str, buckets: &'static [f64]) -> Histogram {
+        registry().histogram(name, self.labels.clone(), buckets)
+    }
+
+    /// Counts an error, by kind, in `errors_total`.
+    pub fn error(&self, error_type: &'static str) {
+        let mut labels = self.labels.clone();
+        labels.push(("error_type", error_type.to_owned()));
+        registry().counter("errors_total", labels).increment();
+    }
+
+    /// Runs a future or stream with these metrics as the current ones.
+    pub fn instrument<T>(self, inner: T) -> Instrumented<T> {
+        Instrumented {
+            inner,
+            metrics: Some(self),
+        }
+    }
+}
+
+/// Calls `f` with the metrics of the component running on this thread.
+/// Code shared by components, like sink requests and retries, uses this to
+/// report metrics without being handed the component's name and type.
+pub fn with_current<F: FnOnce(&ComponentMetrics)>(f: F) {
+    CURRENT.with(|current| {
+        if let Some(metrics) = &*current.borrow() {
+            f(metrics)
+        }
+    })
+}
+
+/// Keeps the current component's metrics for a future spawned onto its own
+/// task, like a connection accepted by a source.
+pub fn in_current<T>(inner: T) -> Instrumented<T> {
+    Instrumented {
+        inner,
+        metrics: CURRENT.with(|current| current.borrow().clone()),
+    }
+}
+
+/// A future or stream polled with its component's metrics as the current
+/// ones.
+pub struct Instrumented<T> {
+    inner: T,
+    metrics: Option<ComponentMetrics>,
+}
+
+impl<T> Instrumented<T> {
+    fn enter<R>(&mut self, f: impl FnOnce(&mut T) -> R) -> R {
+        let previous = CURRENT.with(|current| current.replace(self.metrics.clone()));
+        let result = f(&mut self.inner);
+        CURRENT.with(|current| current.replace(previous));
+        result
+    }
+}
+
+impl<T: Future> Future for Instrumented<T> {
+    type Item = T::Item;
+    type Error = T::Error;
+
+    fn poll(&mut self) -> Poll<T::Item, T::Error> {
+        self.enter(|inner| inner.poll())
+    }
+}
+
+impl<T: Stream> Stream for Instrumented<T> {
+    type Item = T::Item;
+    type Error = T::Error;
+
+    fn poll(&mut self) -> Poll<Option<T::Item>, T::Error> {
+        self.enter(|inner| inner.poll())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::registry::Value;
+    use futures01::future;
+
+    #[test]
+    fn component_metrics_are_current_while_polled() {
+        let metrics = ComponentMetrics::new("sink", "component_test", "console");
+
+        let mut instrumented = metrics.clone().instrument(future::lazy(|| {
+            with_current(|metrics| {
+                metrics.events_out().add(2);
+                metrics.error("request_failed");
+            });
+            future::ok::<_, ()>(())
+        }));
+        instrumented.poll().unwrap();
+        with_current(|_| panic!("No component should be current"));
+
+        assert_eq!(metrics.events_out().get(), 2);
+        let errors = registry()
+            .snapshot()
+            .into_iter()
+            .find(|(key, _)| {
+                key.name == "errors_total"
+                    && key
+                        .labels
+                        .contains(&("component_name", "component_test".to_owned()))
+            })
+            .unwrap();
+        assert_eq!(errors.1, Value::Counter(1));
+        assert!(errors
+            .0
+            .labels
+            .contains(&("error_type", "request_failed".to_owned())));
+    }
+}
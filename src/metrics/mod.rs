@@ -1,3 +1,11 @@
+mod component;
+mod registry;
+
+pub use component::{
+    in_current, with_current, ComponentMetrics, Instrumented, BATCH_BUCKETS, DURATION_BUCKETS,
+};
+pub use registry::{registry, Counter, Gauge, Histogram, HistogramValue, Key, Labels, Value};
+
 use futures01::Future;
 use hotmic::{
     snapshot::{Snapshot, TypedMeasurement},
@@ -8,16 +16,22 @@ use hyper::{
     service::{make_service_fn, service_fn_ok},
     Body, Request, Response, Server,
 };
-use std::net::SocketAddr;
+use lazy_static::lazy_static;
+use std::{net::SocketAddr, sync::Mutex};
 use tracing::field;
 
 pub type Sink = hotmic::Sink<&'static str>;
 
+lazy_static! {
+    static ref CONTROLLER: Mutex<Option<Controller>> = Mutex::new(None);
+}
+
 /// Build the metrics receiver, controller and sink
 pub fn build() -> (Controller, Sink) {
     let mut receiver = Receiver::builder().build();
     let controller = receiver.get_controller();
     let sink = receiver.get_sink();
+    *CONTROLLER.lock().unwrap() = Some(controller.clone());
 
     std::thread::spawn(move || {
         receiver.run();
@@ -26,6 +40,11 @@ pub fn build() -> (Controller, Sink) {
     (controller, sink)
 }
 
+/// The controller of the metrics receiver, once it's built.
+pub fn controller() -> Option<Controller> {
+    CONTROLLER.lock().unwrap().clone()
+}
+
 /// Serve the metrics server via the address from the metrics controller
 pub fn serve(addr: &SocketAddr, controller: Controller) -> impl Future<Item = (), Error = ()> {
     let make_svc = make_service_fn(move |addr_stream: &AddrStream| {
@@ -37,7 +56,8 @@ pub fn serve(addr: &SocketAddr, controller: Controller) -> impl Future<Item = ()
             connection_span.in_scope(|| {
                 debug!(message = "snapshotting metrics.");
                 let snapshot = controller.get_snapshot().unwrap();
-                let output = process_snapshot(snapshot).unwrap();
+                let mut output = process_snapshot(snapshot).unwrap();
+                process_registry(&mut output);
 
                 trace!(
                     message = "sending metrics snapshot output.",
@@ -130,3 +150,87 @@ fn process_snapshot(snapshot: Snapshot) -> Result<String, ()> {
 
     Ok(output)
 }
+
+/// Appends the labeled metrics of the registry in the Prometheus text format.
+fn process_registry(output: &mut String) {
+    let mut previous = None;
+    for (key, value) in registry().snapshot() {
+        if previous != Some(key.name) {
+            let kind = match value {
+                Value::Counter(_) => "counter",
+                Value::Gauge(_) => "gauge",
+                Value::Histogram(_) => "histogram",
+            };
+            output.push_str(&format!("\n# TYPE {} {}\n", key.name, kind));
+            previous = Some(key.name);
+        }
+
+        match value {
+            Value::Counter(value) => output.push_str(&format!(
+                "{}{} {}\n",
+                key.name,
+                labels(&key.labels, None),
+                value
+            )),
+            Value::Gauge(value) => output.push_str(&format!(
+                "{}{} {}\n",
+                key.name,
+                labels(&key.labels, None),
+                value
+            )),
+            Value::Histogram(histogram) => {
+                let mut cumulative = 0;
+                for (bound, count) in &histogram.buckets {
+                    cumulative += count;
+                    output.push_str(&format!(
+                        "{}_bucket{} {}\n",
+                        key.name,
+                        labels(&key.labels, Some(&bound.to_string())),
+                        cumulative
+                    ));
+                }
+                output.push_str(&format!(
+                    "{}_bucket{} {}\n",
+                    key.name,
+                    labels(&key.labels, Some("+Inf")),
+                    histogram.count
+                ));
+                output.push_str(&format!(
+                    "{}_sum{} {}\n",
+                    key.name,
+                    labels(&key.labels, None),
+                    histogram.sum
+                ));
+                output.push_str(&format!(
+                    "{}_count{} {}\n",
+                    key.name,
+                    labels(&key.labels, None),
+                    histogram.count
+                ));
+            }
+        }
+    }
+}
+
+fn labels(labels: &Labels, bucket: Option<&str>) -> String {
+    let mut pairs = labels
+        .iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+        .collect::<Vec<_>>();
+    if let Some(bound) = bucket {
+        pairs.push(format!("le=\"{}\"", bound));
+    }
+
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
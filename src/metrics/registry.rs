@@ -0,0 +1,247 @@
+use lazy_static::lazy_static;
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+};
+
+lazy_static! {
+    static ref REGISTRY: Registry = Registry::default();
+}
+
+/// The registry shared by the whole process.
+pub fn registry() -> &'static Registry {
+    &REGISTRY
+}
+
+pub type Labels = Vec<(&'static str, String)>;
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Key {
+    pub name: &'static str,
+    pub labels: Labels,
+}
+
+/// Labeled counters, gauges and histograms. Unlike `hotmic`, metrics are
+/// kept for as long as the process runs, so they can be read at any time by
+/// the metrics server and the `internal_metrics` source.
+#[derive(Default)]
+pub struct Registry {
+    metrics: RwLock<BTreeMap<Key, Metric>>,
+}
+
+#[derive(Clone)]
+enum Metric {
+    Counter(Counter),
+    Gauge(Gauge),
+    Histogram(Histogram),
+}
+
+impl Registry {
+    pub fn counter(&self, name: &'static str, labels: Labels) -> Counter {
+        match self.get_or_insert(name, labels, || Metric::Counter(Counter::default())) {
+            Metric::Counter(counter) => counter,
+            _ => Counter::default(),
+        }
+    }
+
+    pub fn gauge(&self, name: &'static str, labels: Labels) -> Gauge {
+        match self.get_or_insert(name, labels, || Metric::Gauge(Gauge::default())) {
+            Metric::Gauge(gauge) => gauge,
+            _ => Gauge::default(),
+        }
+    }
+
+    pub fn histogram(
+        &self,
+        name: &'static str,
+        labels: Labels,
+        buckets: &'static [f64],
+    ) -> Histogram {
+        match self.get_or_insert(name, labels, || Metric::Histogram(Histogram::new(buckets))) {
+            Metric::Histogram(histogram) => histogram,
+            _ => Histogram::new(buckets),
+        }
+    }
+
+    /// A metric registered twice with different kinds is only kept the
+    /// first time, the later handles are detached from the registry.
+    fn get_or_insert(
+        &self,
+        name: &'static str,
+        mut labels: Labels,
+        metric: impl FnOnce() -> Metric,
+    ) -> Metric {
+        labels.sort();
+        let key = Key { name, labels };
+
+        if let Some(metric) = self.metrics.read().unwrap().get(&key) {
+            return metric.clone();
+        }
+        self.metrics
+            .write()
+            .unwrap()
+            .entry(key)
+            .or_insert_with(metric)
+            .clone()
+    }
+
+    /// Forgets the metrics having a label, like those of a component removed
+    /// from the topology.
+    pub fn remove(&self, label: &str, value: &str) {
+        self.metrics.write().unwrap().retain(|key, _| {
+            !key.labels
+                .iter()
+                .any(|(name, label_value)| *name == label && label_value == value)
+        });
+    }
+
+    pub fn snapshot(&self) -> Vec<(Key, Value)> {
+        self.metrics
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(key, metric)| {
+                let value = match metric {
+                    Metric::Counter(counter) => Value::Counter(counter.get()),
+                    Metric::Gauge(gauge) => Value::Gauge(gauge.get()),
+                    Metric::Histogram(histogram) => Value::Histogram(histogram.get()),
+                };
+                (key.clone(), value)
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Counter(u64),
+    Gauge(i64),
+    Histogram(HistogramValue),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Counter(Arc<AtomicU64>);
+
+impl Counter {
+    pub fn increment(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, value: u64) {
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Gauge(Arc<AtomicI64>);
+
+impl Gauge {
+    pub fn set(&self, value: i64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    pub fn add(&self, value: i64) {
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn increment(&self) {
+        self.add(1);
+    }
+
+    pub fn decrement(&self) {
+        self.add(-1);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Counts values into buckets with fixed upper bounds, like Prometheus.
+#[derive(Clone, Debug)]
+pub struct Histogram(Arc<Mutex<HistogramValue>>);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct HistogramValue {
+    /// The upper bound of each bucket and the number of values within it,
+    /// not including those of the previous buckets.
+    pub buckets: Vec<(f64, u64)>,
+    pub count: u64,
+    pub sum: f64,
+}
+
+impl Histogram {
+    fn new(bounds: &[f64]) -> Self {
+        Self(Arc::new(Mutex::new(HistogramValue {
+            buckets: bounds.iter().map(|bound| (*bound, 0)).collect(),
+            count: 0,
+            sum: 0.0,
+        })))
+    }
+
+    pub fn record(&self, value: f64) {
+        let mut histogram = self.0.lock().unwrap();
+        if let Some(bucket) = histogram
+            .buckets
+            .iter_mut()
+            .find(|(bound, _)| value <= *bound)
+        {
+            bucket.1 += 1;
+        }
+        histogram.count += 1;
+        histogram.sum += value;
+    }
+
+    pub fn get(&self) -> HistogramValue {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registry_reuses_metrics() {
+        let registry = Registry::default();
+        let labels = || {
+            vec![
+                ("component_name", "in".to_owned()),
+                ("error_type", "io".to_owned()),
+            ]
+        };
+
+        registry.counter("errors_total", labels()).increment();
+        registry
+            .counter("errors_total", labels().into_iter().rev().collect())
+            .add(2);
+        registry.gauge("buffer_events", labels()).set(-3);
+        let histogram = registry.histogram("batch_events", labels(), &[1.0, 10.0]);
+        histogram.record(5.0);
+        histogram.record(50.0);
+
+        let snapshot = registry.snapshot();
+        assert_eq!(snapshot.len(), 3);
+        assert_eq!(snapshot[0].0.name, "batch_events");
+        assert_eq!(
+            snapshot[0].1,
+            Value::Histogram(HistogramValue {
+                buckets: vec![(1.0, 0), (10.0, 1)],
+                count: 2,
+                sum: 55.0,
+            })
+        );
+        assert_eq!(snapshot[1].1, Value::Gauge(-3));
+        assert_eq!(snapshot[2].1, Value::Counter(3));
+
+        registry.remove("component_name", "in");
+        assert!(registry.snapshot().is_empty());
+    }
+}
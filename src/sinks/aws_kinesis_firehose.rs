@@ -4,16 +4,17 @@ use crate::{
     region::RegionOrEndpoint,
     sinks::util::{
         encoding::{EncodingConfig, EncodingConfiguration},
+        finalize_dropped,
         retries::RetryLogic,
         rusoto::{self, AwsCredentialsProvider},
-        BatchEventsConfig, SinkExt, TowerRequestConfig,
+        BatchEventsConfig, SharedBatch, SharedRequest, SinkExt, TowerRequestConfig,
     },
     topology::config::{DataType, SinkConfig, SinkContext, SinkDescription},
 };
 use bytes::Bytes;
-use futures01::{stream::iter_ok, Future, Poll, Sink};
+use futures01::{future, Future, Poll, Sink};
 use lazy_static::lazy_static;
-use rusoto_core::{Region, RusotoError, RusotoFuture};
+use rusoto_core::{Region, RusotoError};
 use rusoto_firehose::{
     DescribeDeliveryStreamInput, KinesisFirehose, KinesisFirehoseClient, PutRecordBatchError,
     PutRecordBatchInput, PutRecordBatchOutput, Record,
@@ -24,6 +25,11 @@ use std::{convert::TryInto, fmt};
 use tower::Service;
 use tracing_futures::{Instrument, Instrumented};
 
+// https://docs.aws.amazon.com/firehose/latest/APIReference/API_PutRecordBatch.html
+const MAX_RECORD_BYTES: usize = 1000 * 1024;
+const MAX_REQUEST_BYTES: usize = 4 * 1024 * 1024;
+const MAX_REQUEST_RECORDS: usize = 500;
+
 #[derive(Clone)]
 pub struct KinesisFirehoseService {
     client: KinesisFirehoseClient,
@@ -99,39 +105,106 @@ impl KinesisFirehoseService {
 
         let sink = request
             .batch_sink(KinesisFirehoseRetryLogic, kinesis, cx.acker())
-            .batched_with_min(Vec::new(), &batch)
-            .with_flat_map(move |e| iter_ok(encode_event(e, &encoding)));
+            .batched_with_min(SharedBatch::new(Vec::new()), &batch)
+            .with_flat_map(finalize_dropped(cx.acker(), move |e| {
+                encode_event(e, &encoding)
+            }));
 
         Ok(sink)
     }
 }
 
-impl Service<Vec<Record>> for KinesisFirehoseService {
+impl Service<SharedRequest<Vec<Record>>> for KinesisFirehoseService {
     type Response = PutRecordBatchOutput;
-    type Error = RusotoError<PutRecordBatchError>;
-    type Future = Instrumented<RusotoFuture<PutRecordBatchOutput, PutRecordBatchError>>;
+    type Error = KinesisFirehoseError;
+    type Future = Instrumented<Box<dyn Future<Item = Self::Response, Error = Self::Error> + Send>>;
 
     fn poll_ready(&mut self) -> Poll<(), Self::Error> {
         Ok(().into())
     }
 
-    fn call(&mut self, records: Vec<Record>) -> Self::Future {
+    // Records which failed are left in the request for the retry layer to resend.
+    fn call(&mut self, request: SharedRequest<Vec<Record>>) -> Self::Future {
+        let records = request.get();
         debug!(
             message = "sending records.",
             events = %records.len(),
         );
 
-        let request = PutRecordBatchInput {
-            records,
-            delivery_stream_name: self.config.stream_name.clone(),
-        };
+        let fut = put_record_batch(&self.client, &self.config.stream_name, records).and_then(
+            move |(output, failed)| {
+                if failed.is_empty() {
+                    Ok(output)
+                } else {
+                    let count = failed.len();
+                    request.set(failed);
+                    Err(KinesisFirehoseError::FailedRecords { count })
+                }
+            },
+        );
 
-        self.client
-            .put_record_batch(request)
-            .instrument(info_span!("request"))
+        let fut: Box<dyn Future<Item = _, Error = _> + Send> = Box::new(fut);
+        fut.instrument(info_span!("request"))
     }
 }
 
+/// Puts the records with as many requests as the request limits require,
+/// returning the merged output and the records which failed. The records of
+/// requests which failed with a retriable error count as failed.
+fn put_record_batch(
+    client: &KinesisFirehoseClient,
+    stream_name: &str,
+    records: Vec<Record>,
+) -> impl Future<Item = (PutRecordBatchOutput, Vec<Record>), Error = KinesisFirehoseError> {
+    let requests =
+        rusoto::chunk_records(records, MAX_REQUEST_RECORDS, MAX_REQUEST_BYTES, |record| {
+            record.data.len()
+        })
+        .into_iter()
+        .map(|records| {
+            let request = PutRecordBatchInput {
+                records: records.clone(),
+                delivery_stream_name: stream_name.to_owned(),
+            };
+            client
+                .put_record_batch(request)
+                .then(move |result| Ok((records, result)))
+        })
+        .collect::<Vec<_>>();
+
+    future::join_all(requests).and_then(|responses| {
+        let mut merged = PutRecordBatchOutput::default();
+        let mut failed = Vec::new();
+        for (records, result) in responses {
+            let output = match result {
+                Ok(output) => output,
+                Err(source) => {
+                    let error = KinesisFirehoseError::PutRecordBatch { source };
+                    if !KinesisFirehoseRetryLogic.is_retriable_error(&error) {
+                        return Err(error);
+                    }
+                    warn!(
+                        message = "PutRecordBatch request failed; Retrying its records.",
+                        %error,
+                        rate_limit_secs = 30,
+                    );
+                    failed.extend(records);
+                    continue;
+                }
+            };
+            for (record, result) in records.into_iter().zip(&output.request_responses) {
+                if result.error_code.is_some() {
+                    failed.push(record);
+                }
+            }
+            merged.failed_put_count += output.failed_put_count;
+            merged.encrypted = output.encrypted;
+            merged.request_responses.extend(output.request_responses);
+        }
+        Ok((merged, failed))
+    })
+}
+
 impl fmt::Debug for KinesisFirehoseService {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("KinesisFirehoseService")
@@ -140,19 +213,32 @@ impl fmt::Debug for KinesisFirehoseService {
     }
 }
 
+#[derive(Debug, Snafu)]
+pub enum KinesisFirehoseError {
+    #[snafu(display("PutRecordBatch failed: {}", source))]
+    PutRecordBatch {
+        source: RusotoError<PutRecordBatchError>,
+    },
+    #[snafu(display("{} records failed to be put", count))]
+    FailedRecords { count: usize },
+}
+
 #[derive(Debug, Clone)]
 struct KinesisFirehoseRetryLogic;
 
 impl RetryLogic for KinesisFirehoseRetryLogic {
-    type Error = RusotoError<PutRecordBatchError>;
+    type Error = KinesisFirehoseError;
     type Response = PutRecordBatchOutput;
 
     fn is_retriable_error(&self, error: &Self::Error) -> bool {
         match error {
-            RusotoError::HttpDispatch(_) => true,
-            RusotoError::Service(PutRecordBatchError::ServiceUnavailable(_)) => true,
-            RusotoError::Unknown(res) if res.status.is_server_error() => true,
-            _ => false,
+            KinesisFirehoseError::PutRecordBatch { source } => match source {
+                RusotoError::HttpDispatch(_) => true,
+                RusotoError::Service(PutRecordBatchError::ServiceUnavailable(_)) => true,
+                RusotoError::Unknown(res) if res.status.is_server_error() => true,
+                _ => false,
+            },
+            KinesisFirehoseError::FailedRecords { .. } => true,
         }
     }
 }
@@ -216,6 +302,16 @@ fn encode_event(mut event: Event, encoding: &EncodingConfig<Encoding>) -> Option
             .unwrap_or_default(),
     };
 
+    if data.len() > MAX_RECORD_BYTES {
+        warn!(
+            message = "Record is larger than the maximum record size; Dropping event.",
+            size = data.len(),
+            max_size = MAX_RECORD_BYTES,
+            rate_limit_secs = 30,
+        );
+        return None;
+    }
+
     let data = Bytes::from(data);
 
     Some(Record { data })
@@ -224,8 +320,15 @@ fn encode_event(mut event: Event, encoding: &EncodingConfig<Encoding>) -> Option
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::event::{self, Event};
+    use crate::{
+        event::{self, Event},
+        region::RegionOrEndpoint,
+        sinks::util::test::build_test_server_with_responses,
+        test_util::{next_addr, random_string, runtime},
+    };
+    use futures01::Stream;
     use std::collections::BTreeMap;
+    use tower::ServiceBuilder;
 
     #[test]
     fn firehose_encode_event_text() {
@@ -247,6 +350,82 @@ mod tests {
         assert_eq!(map[&event::log_schema().message_key().to_string()], message);
         assert_eq!(map["key"], "value".to_string());
     }
+
+    #[test]
+    fn firehose_encode_event_record_limit() {
+        let event = Event::from(random_string(MAX_RECORD_BYTES + 1));
+        assert!(encode_event(event, &Encoding::Text.into()).is_none());
+    }
+
+    #[test]
+    fn firehose_resends_failed_records() {
+        let mut rt = runtime();
+        let addr = next_addr();
+        let responses = vec![
+            r#"{"FailedPutCount":1,"RequestResponses":[
+                {"RecordId":"1"},
+                {"ErrorCode":"ServiceUnavailableException","ErrorMessage":"Slow down."},
+                {"RecordId":"2"}
+            ]}"#
+            .to_owned(),
+            r#"{"FailedPutCount":0,"RequestResponses":[{"RecordId":"3"}]}"#.to_owned(),
+        ];
+        let (rx, _trigger, server) = build_test_server_with_responses(&addr, responses);
+        rt.spawn(server);
+
+        let endpoint = format!("http://{}", addr);
+        let resolver = Resolver::new(Vec::new(), rt.executor()).unwrap();
+        let client = KinesisFirehoseClient::new_with(
+            rusoto::client(resolver).unwrap(),
+            AwsCredentialsProvider::new_minimal("foo", "bar"),
+            Region::Custom {
+                name: "local".into(),
+                endpoint: endpoint.clone(),
+            },
+        );
+        let request = TowerRequestConfig {
+            retry_initial_backoff_secs: Some(0),
+            ..Default::default()
+        }
+        .unwrap_with(&REQUEST_DEFAULTS);
+        let service = KinesisFirehoseService {
+            client,
+            config: KinesisFirehoseSinkConfig {
+                stream_name: "test".into(),
+                region: RegionOrEndpoint::with_endpoint(endpoint),
+                encoding: Encoding::Text.into(),
+                batch: Default::default(),
+                request: Default::default(),
+                assume_role: None,
+            },
+        };
+        let mut service = ServiceBuilder::new()
+            .retry(request.retry_policy(KinesisFirehoseRetryLogic))
+            .timeout(request.timeout)
+            .service(service);
+
+        let records = vec!["a", "b", "c"]
+            .into_iter()
+            .map(|data| Record {
+                data: Bytes::from(data),
+            })
+            .collect();
+        let output = rt
+            .block_on(service.call(SharedRequest::new(records)))
+            .unwrap();
+        assert_eq!(output.failed_put_count, 0);
+
+        let counts = rt
+            .block_on(rx.take(2).collect())
+            .unwrap()
+            .into_iter()
+            .map(|(_, body)| {
+                let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                body["Records"].as_array().unwrap().len()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(counts, vec![3, 1]);
+    }
 }
 
 #[cfg(feature = "firehose-integration-tests")]
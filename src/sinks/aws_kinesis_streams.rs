@@ -4,17 +4,19 @@ use crate::{
     region::RegionOrEndpoint,
     sinks::util::{
         encoding::{EncodingConfig, EncodingConfiguration},
+        finalize_dropped,
         retries::RetryLogic,
         rusoto::{self, AwsCredentialsProvider},
-        BatchEventsConfig, SinkExt, TowerRequestConfig,
+        BatchEventsConfig, SharedBatch, SharedRequest, SinkExt, TowerRequestConfig,
     },
+    template::Template,
     topology::config::{DataType, SinkConfig, SinkContext, SinkDescription},
 };
 use bytes::Bytes;
-use futures01::{stream::iter_ok, Future, Poll, Sink};
+use futures01::{future, Future, Poll, Sink};
 use lazy_static::lazy_static;
 use rand::random;
-use rusoto_core::{Region, RusotoError, RusotoFuture};
+use rusoto_core::{Region, RusotoError};
 use rusoto_kinesis::{
     Kinesis, KinesisClient, ListStreamsInput, PutRecordsError, PutRecordsInput, PutRecordsOutput,
     PutRecordsRequestEntry,
@@ -26,6 +28,11 @@ use string_cache::DefaultAtom as Atom;
 use tower::Service;
 use tracing_futures::{Instrument, Instrumented};
 
+// https://docs.aws.amazon.com/kinesis/latest/APIReference/API_PutRecords.html
+const MAX_RECORD_BYTES: usize = 1024 * 1024;
+const MAX_REQUEST_BYTES: usize = 5 * 1024 * 1024;
+const MAX_REQUEST_RECORDS: usize = 500;
+
 #[derive(Clone)]
 pub struct KinesisService {
     client: Arc<KinesisClient>,
@@ -36,6 +43,8 @@ pub struct KinesisService {
 #[serde(deny_unknown_fields)]
 pub struct KinesisSinkConfig {
     pub stream_name: String,
+    pub partition_key: Option<Template>,
+    /// Deprecated, use `partition_key` instead.
     pub partition_key_field: Option<Atom>,
     #[serde(flatten)]
     pub region: RegionOrEndpoint,
@@ -97,45 +106,121 @@ impl KinesisService {
         let batch = config.batch.unwrap_or(500, 1);
         let request = config.request.unwrap_with(&REQUEST_DEFAULTS);
         let encoding = config.encoding.clone();
-        let partition_key_field = config.partition_key_field.clone();
+        let partition_key = config.partition_key.clone().or_else(|| {
+            config
+                .partition_key_field
+                .as_ref()
+                .map(|field| Template::from(format!("{{{{ {} }}}}", field)))
+        });
 
         let kinesis = KinesisService { client, config };
 
         let sink = request
             .batch_sink(KinesisRetryLogic, kinesis, cx.acker())
-            .batched_with_min(Vec::new(), &batch)
-            .with_flat_map(move |e| iter_ok(encode_event(e, &partition_key_field, &encoding)));
+            .batched_with_min(SharedBatch::new(Vec::new()), &batch)
+            .with_flat_map(finalize_dropped(cx.acker(), move |e| {
+                encode_event(e, &partition_key, &encoding)
+            }));
 
         Ok(sink)
     }
 }
 
-impl Service<Vec<PutRecordsRequestEntry>> for KinesisService {
+impl Service<SharedRequest<Vec<PutRecordsRequestEntry>>> for KinesisService {
     type Response = PutRecordsOutput;
-    type Error = RusotoError<PutRecordsError>;
-    type Future = Instrumented<RusotoFuture<PutRecordsOutput, PutRecordsError>>;
+    type Error = KinesisError;
+    type Future = Instrumented<Box<dyn Future<Item = Self::Response, Error = Self::Error> + Send>>;
 
     fn poll_ready(&mut self) -> Poll<(), Self::Error> {
         Ok(().into())
     }
 
-    fn call(&mut self, records: Vec<PutRecordsRequestEntry>) -> Self::Future {
+    // Records which failed are left in the request for the retry layer to resend.
+    fn call(&mut self, request: SharedRequest<Vec<PutRecordsRequestEntry>>) -> Self::Future {
+        let records = request.get();
         debug!(
             message = "sending records.",
             events = %records.len(),
         );
 
-        let request = PutRecordsInput {
-            records,
-            stream_name: self.config.stream_name.clone(),
-        };
+        let fut = put_records(&self.client, &self.config.stream_name, records).and_then(
+            move |(output, failed)| {
+                if failed.is_empty() {
+                    Ok(output)
+                } else {
+                    let count = failed.len();
+                    request.set(failed);
+                    Err(KinesisError::FailedRecords { count })
+                }
+            },
+        );
 
-        self.client
-            .put_records(request)
-            .instrument(info_span!("request"))
+        let fut: Box<dyn Future<Item = _, Error = _> + Send> = Box::new(fut);
+        fut.instrument(info_span!("request"))
     }
 }
 
+/// Puts the records with as many requests as the request limits require,
+/// returning the merged output and the records which failed. The records of
+/// requests which failed with a retriable error count as failed.
+fn put_records(
+    client: &KinesisClient,
+    stream_name: &str,
+    records: Vec<PutRecordsRequestEntry>,
+) -> impl Future<Item = (PutRecordsOutput, Vec<PutRecordsRequestEntry>), Error = KinesisError> {
+    let requests =
+        rusoto::chunk_records(records, MAX_REQUEST_RECORDS, MAX_REQUEST_BYTES, record_size)
+            .into_iter()
+            .map(|records| {
+                let request = PutRecordsInput {
+                    records: records.clone(),
+                    stream_name: stream_name.to_owned(),
+                };
+                client
+                    .put_records(request)
+                    .then(move |result| Ok((records, result)))
+            })
+            .collect::<Vec<_>>();
+
+    future::join_all(requests).and_then(|responses| {
+        let mut merged = PutRecordsOutput::default();
+        let mut failed = Vec::new();
+        for (records, result) in responses {
+            let output = match result {
+                Ok(output) => output,
+                Err(source) => {
+                    let error = KinesisError::PutRecords { source };
+                    if !KinesisRetryLogic.is_retriable_error(&error) {
+                        return Err(error);
+                    }
+                    warn!(
+                        message = "PutRecords request failed; Retrying its records.",
+                        %error,
+                        rate_limit_secs = 30,
+                    );
+                    failed.extend(records);
+                    continue;
+                }
+            };
+            for (record, result) in records.into_iter().zip(&output.records) {
+                if result.error_code.is_some() {
+                    failed.push(record);
+                }
+            }
+            merged.failed_record_count = Some(
+                merged.failed_record_count.unwrap_or(0) + output.failed_record_count.unwrap_or(0),
+            );
+            merged.encryption_type = output.encryption_type;
+            merged.records.extend(output.records);
+        }
+        Ok((merged, failed))
+    })
+}
+
+fn record_size(record: &PutRecordsRequestEntry) -> usize {
+    record.data.len() + record.partition_key.len()
+}
+
 impl fmt::Debug for KinesisService {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("KinesisService")
@@ -144,19 +229,32 @@ impl fmt::Debug for KinesisService {
     }
 }
 
+#[derive(Debug, Snafu)]
+pub enum KinesisError {
+    #[snafu(display("PutRecords failed: {}", source))]
+    PutRecords {
+        source: RusotoError<PutRecordsError>,
+    },
+    #[snafu(display("{} records failed to be put", count))]
+    FailedRecords { count: usize },
+}
+
 #[derive(Debug, Clone)]
 struct KinesisRetryLogic;
 
 impl RetryLogic for KinesisRetryLogic {
-    type Error = RusotoError<PutRecordsError>;
+    type Error = KinesisError;
     type Response = PutRecordsOutput;
 
     fn is_retriable_error(&self, error: &Self::Error) -> bool {
         match error {
-            RusotoError::HttpDispatch(_) => true,
-            RusotoError::Service(PutRecordsError::ProvisionedThroughputExceeded(_)) => true,
-            RusotoError::Unknown(res) if res.status.is_server_error() => true,
-            _ => false,
+            KinesisError::PutRecords { source } => match source {
+                RusotoError::HttpDispatch(_) => true,
+                RusotoError::Service(PutRecordsError::ProvisionedThroughputExceeded(_)) => true,
+                RusotoError::Unknown(res) if res.status.is_server_error() => true,
+                _ => false,
+            },
+            KinesisError::FailedRecords { .. } => true,
         }
     }
 }
@@ -218,27 +316,36 @@ fn create_client(
 
 fn encode_event(
     mut event: Event,
-    partition_key_field: &Option<Atom>,
+    partition_key: &Option<Template>,
     encoding: &EncodingConfig<Encoding>,
 ) -> Option<PutRecordsRequestEntry> {
     encoding.apply_rules(&mut event);
-    let partition_key = if let Some(partition_key_field) = partition_key_field {
-        if let Some(v) = event.as_log().get(&partition_key_field) {
-            v.to_string_lossy()
-        } else {
-            warn!(
-                message = "Partition key does not exist; Dropping event.",
-                %partition_key_field,
-                rate_limit_secs = 30,
-            );
-            return None;
+    let partition_key = if let Some(partition_key) = partition_key {
+        match partition_key.render_string(&event) {
+            Ok(partition_key) if !partition_key.is_empty() => partition_key,
+            Ok(_) => {
+                warn!(
+                    message = "Partition key is empty; Dropping event.",
+                    rate_limit_secs = 30,
+                );
+                return None;
+            }
+            Err(missing_keys) => {
+                warn!(
+                    message = "Partition key does not exist; Dropping event.",
+                    ?missing_keys,
+                    rate_limit_secs = 30,
+                );
+                return None;
+            }
         }
     } else {
         gen_partition_key()
     };
 
-    let partition_key = if partition_key.len() >= 256 {
-        partition_key[..256].to_string()
+    // The limit is in characters, not bytes.
+    let partition_key = if partition_key.len() > 256 {
+        partition_key.chars().take(256).collect()
     } else {
         partition_key
     };
@@ -252,6 +359,16 @@ fn encode_event(
             .unwrap_or_default(),
     };
 
+    if data.len() + partition_key.len() > MAX_RECORD_BYTES {
+        warn!(
+            message = "Record is larger than the maximum record size; Dropping event.",
+            size = data.len() + partition_key.len(),
+            max_size = MAX_RECORD_BYTES,
+            rate_limit_secs = 30,
+        );
+        return None;
+    }
+
     let data = Bytes::from(data);
 
     Some(PutRecordsRequestEntry {
@@ -275,9 +392,12 @@ mod tests {
     use super::*;
     use crate::{
         event::{self, Event},
-        test_util::random_string,
+        sinks::util::test::build_test_server_with_responses,
+        test_util::{next_addr, random_string, runtime},
     };
+    use futures01::Stream;
     use std::collections::BTreeMap;
+    use tower::ServiceBuilder;
 
     #[test]
     fn kinesis_encode_event_text() {
@@ -304,7 +424,7 @@ mod tests {
     fn kinesis_encode_event_custom_partition_key() {
         let mut event = Event::from("hello world");
         event.as_mut_log().insert("key", "some_key");
-        let event = encode_event(event, &Some("key".into()), &Encoding::Text.into()).unwrap();
+        let event = encode_event(event, &Some("{{ key }}".into()), &Encoding::Text.into()).unwrap();
 
         assert_eq!(&event.data[..], "hello world".as_bytes());
         assert_eq!(&event.partition_key, &"some_key".to_string());
@@ -314,11 +434,109 @@ mod tests {
     fn kinesis_encode_event_custom_partition_key_limit() {
         let mut event = Event::from("hello world");
         event.as_mut_log().insert("key", random_string(300));
-        let event = encode_event(event, &Some("key".into()), &Encoding::Text.into()).unwrap();
+        let event = encode_event(event, &Some("{{ key }}".into()), &Encoding::Text.into()).unwrap();
 
         assert_eq!(&event.data[..], "hello world".as_bytes());
         assert_eq!(event.partition_key.len(), 256);
     }
+
+    #[test]
+    fn kinesis_encode_event_custom_partition_key_limit_multibyte() {
+        let mut event = Event::from("hello world");
+        event
+            .as_mut_log()
+            .insert("key", format!("a{}", "é".repeat(300)));
+        let event = encode_event(event, &Some("{{ key }}".into()), &Encoding::Text.into()).unwrap();
+
+        assert_eq!(event.partition_key.chars().count(), 256);
+        assert_eq!(event.partition_key, format!("a{}", "é".repeat(255)));
+    }
+
+    #[test]
+    fn kinesis_encode_event_record_limit() {
+        let event = Event::from(random_string(MAX_RECORD_BYTES));
+        assert!(encode_event(event, &Some("key".into()), &Encoding::Text.into()).is_none());
+    }
+
+    #[test]
+    fn kinesis_resends_failed_records() {
+        let mut rt = runtime();
+        let addr = next_addr();
+        let responses = vec![
+            r#"{"FailedRecordCount":1,"Records":[
+                {"SequenceNumber":"1","ShardId":"shardId-0"},
+                {"ErrorCode":"ProvisionedThroughputExceededException","ErrorMessage":"Rate exceeded"},
+                {"SequenceNumber":"2","ShardId":"shardId-0"}
+            ]}"#
+            .to_owned(),
+            r#"{"FailedRecordCount":0,"Records":[{"SequenceNumber":"3","ShardId":"shardId-0"}]}"#
+                .to_owned(),
+        ];
+        let (rx, _trigger, server) = build_test_server_with_responses(&addr, responses);
+        rt.spawn(server);
+
+        let endpoint = format!("http://{}", addr);
+        let resolver = Resolver::new(Vec::new(), rt.executor()).unwrap();
+        let client = KinesisClient::new_with(
+            rusoto::client(resolver).unwrap(),
+            AwsCredentialsProvider::new_minimal("foo", "bar"),
+            Region::Custom {
+                name: "local".into(),
+                endpoint: endpoint.clone(),
+            },
+        );
+        let request = TowerRequestConfig {
+            retry_initial_backoff_secs: Some(0),
+            ..Default::default()
+        }
+        .unwrap_with(&REQUEST_DEFAULTS);
+        let service = KinesisService {
+            client: Arc::new(client),
+            config: KinesisSinkConfig {
+                stream_name: "test".into(),
+                partition_key: None,
+                partition_key_field: None,
+                region: RegionOrEndpoint::with_endpoint(endpoint),
+                encoding: Encoding::Text.into(),
+                batch: Default::default(),
+                request: Default::default(),
+                assume_role: None,
+            },
+        };
+        let mut service = ServiceBuilder::new()
+            .retry(request.retry_policy(KinesisRetryLogic))
+            .timeout(request.timeout)
+            .service(service);
+
+        let records = vec!["a", "b", "c"]
+            .into_iter()
+            .map(|key| PutRecordsRequestEntry {
+                data: Bytes::from(key),
+                partition_key: key.into(),
+                ..Default::default()
+            })
+            .collect();
+        let output = rt
+            .block_on(service.call(SharedRequest::new(records)))
+            .unwrap();
+        assert_eq!(output.failed_record_count, Some(0));
+
+        let keys = rt
+            .block_on(rx.take(2).collect())
+            .unwrap()
+            .into_iter()
+            .map(|(_, body)| {
+                let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                body["Records"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|record| record["PartitionKey"].as_str().unwrap().to_owned())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec![vec!["a", "b", "c"], vec!["b"]]);
+    }
 }
 
 #[cfg(feature = "kinesis-integration-tests")]
@@ -349,6 +567,7 @@ mod integration_tests {
 
         let config = KinesisSinkConfig {
             stream_name: stream.clone(),
+            partition_key: None,
             partition_key_field: None,
             region: RegionOrEndpoint::with_endpoint("http://localhost:4568".into()),
             encoding: Encoding::Text.into(),
@@ -1,9 +1,14 @@
+mod multipart;
+
 use crate::{
     dns::Resolver,
-    event::{self, Event},
+    event::{self, Event, LogEvent},
     region::RegionOrEndpoint,
     sinks::util::{
+        buffer::columnar::{ColumnarBuffer, ColumnarFormat, SchemaConfig},
         encoding::{skip_serializing_if_default, EncodingConfigWithDefault, EncodingConfiguration},
+        finalize_dropped,
+        multipart::{MultipartConfig, MultipartSink, MIN_PART_SIZE},
         retries::RetryLogic,
         rusoto, BatchBytesConfig, Buffer, PartitionBuffer, PartitionInnerBuffer, ServiceBuilderExt,
         SinkExt, TowerRequestConfig,
@@ -13,8 +18,9 @@ use crate::{
 };
 use bytes::Bytes;
 use chrono::Utc;
-use futures01::{stream::iter_ok, Future, Poll, Sink};
+use futures01::{Future, Poll, Sink};
 use lazy_static::lazy_static;
+use multipart::S3Uploader;
 use rusoto_core::{Region, RusotoError, RusotoFuture};
 use rusoto_s3::{
     HeadBucketRequest, PutObjectError, PutObjectOutput, PutObjectRequest, S3Client, S3,
@@ -47,12 +53,14 @@ pub struct S3SinkConfig {
     pub region: RegionOrEndpoint,
     #[serde(skip_serializing_if = "skip_serializing_if_default", default)]
     pub encoding: EncodingConfigWithDefault<Encoding>,
+    pub schema: Option<SchemaConfig>,
     pub compression: Compression,
     #[serde(default)]
     pub batch: BatchBytesConfig,
     #[serde(default)]
     pub request: TowerRequestConfig,
     pub assume_role: Option<String>,
+    pub multipart_upload: Option<MultipartConfig>,
 }
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
@@ -118,6 +126,18 @@ pub enum Encoding {
     #[derivative(Default)]
     Text,
     Ndjson,
+    Parquet,
+    Avro,
+}
+
+impl Encoding {
+    fn columnar_format(&self) -> Option<ColumnarFormat> {
+        match self {
+            Encoding::Parquet => Some(ColumnarFormat::Parquet),
+            Encoding::Avro => Some(ColumnarFormat::Avro),
+            Encoding::Text | Encoding::Ndjson => None,
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone, Derivative)]
@@ -151,6 +171,14 @@ impl SinkConfig for S3SinkConfig {
     }
 }
 
+#[derive(Debug, Snafu)]
+enum BuildError {
+    #[snafu(display("Multipart uploads are not supported with the {:?} encoding", encoding))]
+    MultipartColumnar { encoding: Encoding },
+    #[snafu(display("Multipart upload parts must be at least {} bytes", min))]
+    PartSizeTooSmall { min: usize },
+}
+
 #[derive(Debug, Snafu)]
 enum HealthcheckError {
     #[snafu(display("Invalid credentials"))]
@@ -170,9 +198,12 @@ impl S3Sink {
             Compression::Gzip => true,
             Compression::None => false,
         };
+        let format = encoding.codec.columnar_format();
+        // Columnar files compress their contents internally, so they are
+        // neither gzipped as a whole nor sent with a content encoding.
+        let content_gzip = compression && format.is_none();
         let filename_time_format = config.filename_time_format.clone().unwrap_or("%s".into());
         let filename_append_uuid = config.filename_append_uuid.unwrap_or(true);
-        let batch = config.batch.unwrap_or(bytesize::mib(10u64), 300);
 
         let key_prefix = if let Some(kp) = &config.key_prefix {
             Template::from(kp.as_str())
@@ -186,10 +217,59 @@ impl S3Sink {
             client: Self::create_client(region, config.assume_role.clone(), cx.resolver())?,
         };
 
-        let filename_extension = config.filename_extension.clone();
+        let filename_extension = config
+            .filename_extension
+            .clone()
+            .or_else(|| format.map(|format| format.extension().into()));
         let bucket = config.bucket.clone();
         let options = config.options.clone();
 
+        if let Some(multipart) = &config.multipart_upload {
+            if format.is_some() {
+                return Err(BuildError::MultipartColumnar {
+                    encoding: encoding.codec.clone(),
+                }
+                .into());
+            }
+            let part_size = multipart.part_size();
+            if part_size < MIN_PART_SIZE {
+                return Err(BuildError::PartSizeTooSmall { min: MIN_PART_SIZE }.into());
+            }
+
+            let batch = config.batch.unwrap_or(bytesize::mib(100u64), 300);
+            let dangling_prefix = if multipart.abort_dangling_uploads() {
+                let template = config
+                    .key_prefix
+                    .as_ref()
+                    .map_or("date=%F/", String::as_str);
+                Some(static_prefix(template).to_string())
+            } else {
+                None
+            };
+            let uploader = S3Uploader {
+                client: s3.client,
+                bucket,
+                options,
+                content_encoding: content_encoding(compression),
+                time_format: filename_time_format,
+                append_uuid: filename_append_uuid,
+                extension: filename_extension.unwrap_or_else(|| default_extension(compression)),
+                request,
+                dangling_prefix,
+                // Uploads still in progress by another instance are finalized
+                // within one batch timeout, so only older ones are discarded.
+                dangling_age: batch.timeout * 2,
+            };
+
+            let sink = MultipartSink::new(uploader, part_size, &batch, compression, cx.acker())
+                .with_flat_map(finalize_dropped(cx.acker(), move |e| {
+                    encode_event(e, &key_prefix, &encoding)
+                }));
+            return Ok(Box::new(sink));
+        }
+
+        let batch = config.batch.unwrap_or(bytesize::mib(10u64), 300);
+
         let svc = ServiceBuilder::new()
             .map(move |req| {
                 build_request(
@@ -197,7 +277,7 @@ impl S3Sink {
                     filename_time_format.clone(),
                     filename_extension.clone(),
                     filename_append_uuid,
-                    compression,
+                    content_gzip,
                     bucket.clone(),
                     options.clone(),
                 )
@@ -205,11 +285,30 @@ impl S3Sink {
             .settings(request, S3RetryLogic)
             .service(s3);
 
-        let sink = crate::sinks::util::BatchServiceSink::new(svc, cx.acker())
-            .partitioned_batched_with_min(PartitionBuffer::new(Buffer::new(compression)), &batch)
-            .with_flat_map(move |e| iter_ok(encode_event(e, &key_prefix, &encoding)));
+        let sink = crate::sinks::util::BatchServiceSink::new(svc, cx.acker());
+        let sink: super::RouterSink = match format {
+            Some(format) => {
+                let schema = config.schema.clone().map(Into::into);
+                let buffer = ColumnarBuffer::new(format, schema, compression);
+                Box::new(
+                    sink.partitioned_batched_with_min(PartitionBuffer::new(buffer), &batch)
+                        .with_flat_map(finalize_dropped(cx.acker(), move |e| {
+                            partition_event(e, &key_prefix, &encoding)
+                        })),
+                )
+            }
+            None => Box::new(
+                sink.partitioned_batched_with_min(
+                    PartitionBuffer::new(Buffer::new(compression)),
+                    &batch,
+                )
+                .with_flat_map(finalize_dropped(cx.acker(), move |e| {
+                    encode_event(e, &key_prefix, &encoding)
+                })),
+            ),
+        };
 
-        Ok(Box::new(sink))
+        Ok(sink)
     }
 
     pub fn healthcheck(
@@ -275,13 +374,6 @@ impl Service<Request> for S3Sink {
 
     fn call(&mut self, request: Request) -> Self::Future {
         let options = request.options;
-        let mut tagging = url::form_urlencoded::Serializer::new(String::new());
-        if let Some(tags) = options.tags {
-            for (p, v) in tags {
-                tagging.append_pair(&p, &v);
-            }
-        }
-        let tagging = tagging.finish();
         self.client
             .put_object(PutObjectRequest {
                 body: Some(request.body.into()),
@@ -296,7 +388,7 @@ impl Service<Request> for S3Sink {
                 server_side_encryption: options.server_side_encryption.map(to_string),
                 ssekms_key_id: options.ssekms_key_id,
                 storage_class: options.storage_class.map(to_string),
-                tagging: Some(tagging),
+                tagging: Some(tagging(options.tags)),
                 ..Default::default()
             })
             .instrument(info_span!("request"))
@@ -308,20 +400,34 @@ fn to_string(value: impl Serialize) -> String {
     value.as_str().unwrap().into()
 }
 
-fn build_request(
-    req: PartitionInnerBuffer<Vec<u8>, Bytes>,
-    time_format: String,
-    extension: Option<String>,
-    uuid: bool,
-    gzip: bool,
-    bucket: String,
-    options: S3Options,
-) -> Request {
-    let (inner, key) = req.into_parts();
+fn tagging(tags: Option<BTreeMap<String, String>>) -> String {
+    let mut tagging = url::form_urlencoded::Serializer::new(String::new());
+    for (p, v) in tags.into_iter().flatten() {
+        tagging.append_pair(&p, &v);
+    }
+    tagging.finish()
+}
+
+fn default_extension(gzip: bool) -> String {
+    if gzip {
+        "log.gz".into()
+    } else {
+        "log".into()
+    }
+}
+
+fn content_encoding(gzip: bool) -> Option<String> {
+    if gzip {
+        Some("gzip".to_string())
+    } else {
+        None
+    }
+}
 
+fn object_key(prefix: &[u8], time_format: &str, uuid: bool, extension: &str) -> String {
     // TODO: pull the seconds from the last event
     let filename = {
-        let seconds = Utc::now().format(&time_format);
+        let seconds = Utc::now().format(time_format);
 
         if uuid {
             let uuid = Uuid::new_v4();
@@ -331,11 +437,32 @@ fn build_request(
         }
     };
 
-    let extension = extension.unwrap_or_else(|| if gzip { "log.gz".into() } else { "log".into() });
+    let prefix = String::from_utf8_lossy(prefix);
 
-    let key = String::from_utf8_lossy(&key[..]).into_owned();
+    format!("{}{}.{}", prefix, filename, extension)
+}
+
+/// The part of a key prefix template which is the same for every event.
+fn static_prefix(template: &str) -> &str {
+    let end = template
+        .find(|c| c == '{' || c == '%')
+        .unwrap_or(template.len());
+    &template[..end]
+}
+
+fn build_request(
+    req: PartitionInnerBuffer<Vec<u8>, Bytes>,
+    time_format: String,
+    extension: Option<String>,
+    uuid: bool,
+    gzip: bool,
+    bucket: String,
+    options: S3Options,
+) -> Request {
+    let (inner, key) = req.into_parts();
 
-    let key = format!("{}{}.{}", key, filename, extension);
+    let extension = extension.unwrap_or_else(|| default_extension(gzip));
+    let key = object_key(&key, &time_format, uuid, &extension);
 
     debug!(
         message = "sending events.",
@@ -348,7 +475,7 @@ fn build_request(
         body: inner,
         bucket,
         key,
-        content_encoding: if gzip { Some("gzip".to_string()) } else { None },
+        content_encoding: content_encoding(gzip),
         options,
     }
 }
@@ -370,19 +497,23 @@ impl RetryLogic for S3RetryLogic {
     type Response = PutObjectOutput;
 
     fn is_retriable_error(&self, error: &Self::Error) -> bool {
-        match error {
-            RusotoError::HttpDispatch(_) => true,
-            RusotoError::Unknown(res) if res.status.is_server_error() => true,
-            _ => false,
-        }
+        is_retriable_error(error)
     }
 }
 
-fn encode_event(
+fn is_retriable_error<E>(error: &RusotoError<E>) -> bool {
+    match error {
+        RusotoError::HttpDispatch(_) => true,
+        RusotoError::Unknown(res) if res.status.is_server_error() => true,
+        _ => false,
+    }
+}
+
+fn partition_event(
     mut event: Event,
     key_prefix: &Template,
     encoding: &EncodingConfigWithDefault<Encoding>,
-) -> Option<PartitionInnerBuffer<Vec<u8>, Bytes>> {
+) -> Option<PartitionInnerBuffer<LogEvent, Bytes>> {
     encoding.apply_rules(&mut event);
     let key = key_prefix
         .render_string(&event)
@@ -395,7 +526,15 @@ fn encode_event(
         })
         .ok()?;
 
-    let log = event.into_log();
+    Some(PartitionInnerBuffer::new(event.into_log(), key.into()))
+}
+
+fn encode_event(
+    event: Event,
+    key_prefix: &Template,
+    encoding: &EncodingConfigWithDefault<Encoding>,
+) -> Option<PartitionInnerBuffer<Vec<u8>, Bytes>> {
+    let (log, key) = partition_event(event, key_prefix, encoding)?.into_parts();
     let bytes = match encoding.codec {
         Encoding::Ndjson => serde_json::to_vec(&log)
             .map(|mut b| {
@@ -411,9 +550,12 @@ fn encode_event(
             bytes.push(b'\n');
             bytes
         }
+        Encoding::Parquet | Encoding::Avro => {
+            unreachable!("Columnar encodings are written by `ColumnarBuffer`")
+        }
     };
 
-    Some(PartitionInnerBuffer::new(bytes, key.into()))
+    Some(PartitionInnerBuffer::new(bytes, key))
 }
 
 #[cfg(test)]
@@ -503,6 +645,14 @@ mod tests {
         );
         assert_ne!(req.key, "key/date.log.gz".to_string());
     }
+
+    #[test]
+    fn s3_static_prefix() {
+        assert_eq!(static_prefix("date=%F/"), "date=");
+        assert_eq!(static_prefix("logs/{{ app }}/%F/"), "logs/");
+        assert_eq!(static_prefix("logs/"), "logs/");
+        assert_eq!(static_prefix(""), "");
+    }
 }
 
 #[cfg(feature = "s3-integration-tests")]
@@ -524,7 +674,7 @@ mod integration_tests {
     use pretty_assertions::assert_eq;
     use rusoto_core::region::Region;
     use rusoto_s3::{S3Client, S3};
-    use std::io::{BufRead, BufReader};
+    use std::io::{BufRead, BufReader, Read};
 
     const BUCKET: &str = "router-tests";
 
@@ -706,6 +856,113 @@ mod integration_tests {
         assert_eq!(lines, response_lines);
     }
 
+    #[test]
+    fn s3_parquet() {
+        let mut rt = runtime();
+        let cx = SinkContext::new_test(rt.executor());
+
+        ensure_bucket(&client());
+
+        let config = S3SinkConfig {
+            encoding: Encoding::Parquet.into(),
+            compression: Compression::Gzip,
+            ..config(1000)
+        };
+
+        let prefix = config.key_prefix.clone();
+        let sink = S3Sink::new(&config, cx).unwrap();
+
+        let (_lines, events) = random_lines_with_stream(100, 10);
+
+        let pump = sink.send_all(events);
+        let _ = rt.block_on(pump).unwrap();
+
+        let keys = get_keys(prefix.unwrap());
+        assert_eq!(keys.len(), 1);
+
+        let key = keys.into_iter().next().unwrap();
+        assert!(key.ends_with(".parquet"));
+
+        let obj = get_object(key);
+        assert_eq!(obj.content_encoding, None);
+
+        let mut body = Vec::new();
+        obj.body
+            .unwrap()
+            .into_blocking_read()
+            .read_to_end(&mut body)
+            .unwrap();
+        assert_eq!(&body[..4], b"PAR1");
+        assert_eq!(&body[body.len() - 4..], b"PAR1");
+    }
+
+    #[test]
+    fn s3_multipart_upload() {
+        let mut rt = runtime();
+        let cx = SinkContext::new_test(rt.executor());
+
+        let config = S3SinkConfig {
+            multipart_upload: Some(Default::default()),
+            filename_time_format: Some("%s%f".into()),
+            ..config(12_000_000)
+        };
+        let prefix = config.key_prefix.clone();
+        let sink = S3Sink::new(&config, cx).unwrap();
+
+        // Enough for one object of three parts and a second one of a single part.
+        let (lines, events) = random_lines_with_stream(1000, 15_000);
+
+        let pump = sink.send_all(events);
+        let _ = rt.block_on(pump).unwrap();
+
+        let keys = get_keys(prefix.unwrap());
+        assert_eq!(keys.len(), 2);
+
+        let response_lines = keys
+            .into_iter()
+            .map(|key| {
+                assert!(key.ends_with(".log"));
+                get_lines(get_object(key))
+            })
+            .flatten()
+            .collect::<Vec<_>>();
+
+        assert_eq!(lines, response_lines);
+    }
+
+    #[test]
+    fn s3_abort_dangling_uploads() {
+        let mut rt = runtime();
+        let cx = SinkContext::new_test(rt.executor());
+
+        let config = S3SinkConfig {
+            multipart_upload: Some(Default::default()),
+            batch: BatchBytesConfig {
+                max_size: None,
+                timeout_secs: Some(0),
+            },
+            ..config(1)
+        };
+        let prefix = config.key_prefix.clone().unwrap();
+        let key = format!("{}dangling.log", static_prefix(&prefix));
+
+        client()
+            .create_multipart_upload(rusoto_s3::CreateMultipartUploadRequest {
+                bucket: BUCKET.to_string(),
+                key: key.clone(),
+                ..Default::default()
+            })
+            .sync()
+            .unwrap();
+        assert_eq!(list_uploads(&key), 1);
+
+        let sink = S3Sink::new(&config, cx).unwrap();
+        let pump = sink.send_all(futures01::stream::iter_ok(vec![]));
+        let _ = rt.block_on(pump).unwrap();
+
+        assert_eq!(list_uploads(&key), 0);
+    }
+
     #[test]
     fn s3_healthchecks() {
         let mut rt = Runtime::new().unwrap();
@@ -805,6 +1062,19 @@ mod integration_tests {
             .collect()
     }
 
+    fn list_uploads(prefix: &str) -> usize {
+        client()
+            .list_multipart_uploads(rusoto_s3::ListMultipartUploadsRequest {
+                bucket: BUCKET.to_string(),
+                prefix: Some(prefix.to_string()),
+                ..Default::default()
+            })
+            .sync()
+            .unwrap()
+            .uploads
+            .map_or(0, |uploads| uploads.len())
+    }
+
     fn get_object(key: String) -> rusoto_s3::GetObjectOutput {
         client()
             .get_object(rusoto_s3::GetObjectRequest {
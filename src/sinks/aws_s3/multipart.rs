@@ -0,0 +1,284 @@
+use super::{is_retriable_error, object_key, tagging, to_string, S3Options};
+use crate::sinks::util::{
+    multipart::{MultipartUploader, Part, UploadFuture},
+    retries::RetryLogic,
+    TowerRequestSettings,
+};
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures01::{
+    future::{self, Loop},
+    Future,
+};
+use rusoto_core::RusotoError;
+use rusoto_s3::{
+    AbortMultipartUploadRequest, CompleteMultipartUploadRequest, CompletedMultipartUpload,
+    CompletedPart, CreateMultipartUploadRequest, ListMultipartUploadsRequest, S3Client,
+    UploadPartRequest, S3,
+};
+use snafu::Snafu;
+use std::{marker::PhantomData, time::Duration};
+
+#[derive(Debug, Snafu)]
+enum MultipartError {
+    #[snafu(display("No upload id returned for {:?}", key))]
+    MissingUploadId { key: String },
+}
+
+/// Streams objects to S3 with the multipart upload API.
+#[derive(Clone)]
+pub(super) struct S3Uploader {
+    pub(super) client: S3Client,
+    pub(super) bucket: String,
+    pub(super) options: S3Options,
+    pub(super) content_encoding: Option<String>,
+    pub(super) time_format: String,
+    pub(super) append_uuid: bool,
+    pub(super) extension: String,
+    pub(super) request: TowerRequestSettings,
+    /// Uploads under this prefix which were started more than
+    /// `dangling_age` ago are aborted on startup.
+    pub(super) dangling_prefix: Option<String>,
+    pub(super) dangling_age: Duration,
+}
+
+#[derive(Clone, Debug)]
+pub(super) struct S3Upload {
+    key: String,
+    upload_id: String,
+    parts: Vec<CompletedPart>,
+}
+
+#[derive(Debug)]
+struct MultipartRetryLogic<E>(PhantomData<fn() -> E>);
+
+impl<E> Clone for MultipartRetryLogic<E> {
+    fn clone(&self) -> Self {
+        MultipartRetryLogic(PhantomData)
+    }
+}
+
+impl<E> RetryLogic for MultipartRetryLogic<E>
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    type Error = RusotoError<E>;
+    type Response = ();
+
+    fn is_retriable_error(&self, error: &Self::Error) -> bool {
+        is_retriable_error(error)
+    }
+}
+
+fn retry<E, F, R>(
+    settings: &TowerRequestSettings,
+    request: F,
+) -> impl Future<Item = R::Item, Error = crate::Error>
+where
+    E: std::error::Error + Send + Sync + 'static,
+    F: FnMut() -> R,
+    R: Future<Error = RusotoError<E>>,
+{
+    settings
+        .retry_policy(MultipartRetryLogic(PhantomData))
+        .retry_request(request)
+        .map_err(Into::into)
+}
+
+impl MultipartUploader for S3Uploader {
+    type Upload = S3Upload;
+
+    fn create(&self, prefix: Bytes) -> UploadFuture<S3Upload> {
+        let key = object_key(
+            &prefix,
+            &self.time_format,
+            self.append_uuid,
+            &self.extension,
+        );
+        debug!(
+            message = "starting multipart upload.",
+            bucket = %self.bucket,
+            %key,
+        );
+
+        let options = self.options.clone();
+        let request = CreateMultipartUploadRequest {
+            bucket: self.bucket.clone(),
+            key: key.clone(),
+            content_encoding: self.content_encoding.clone(),
+            acl: options.acl.map(to_string),
+            grant_full_control: options.grant_full_control,
+            grant_read: options.grant_read,
+            grant_read_acp: options.grant_read_acp,
+            grant_write_acp: options.grant_write_acp,
+            server_side_encryption: options.server_side_encryption.map(to_string),
+            ssekms_key_id: options.ssekms_key_id,
+            storage_class: options.storage_class.map(to_string),
+            tagging: Some(tagging(options.tags)),
+            ..Default::default()
+        };
+
+        let client = self.client.clone();
+        let fut = retry(&self.request, move || {
+            client.create_multipart_upload(request.clone())
+        })
+        .and_then(move |output| match output.upload_id {
+            Some(upload_id) => Ok(S3Upload {
+                key,
+                upload_id,
+                parts: Vec::new(),
+            }),
+            None => Err(MultipartError::MissingUploadId { key }.into()),
+        });
+
+        Box::new(fut)
+    }
+
+    fn upload_part(&self, mut upload: S3Upload, part: Part) -> UploadFuture<S3Upload> {
+        let client = self.client.clone();
+        let bucket = self.bucket.clone();
+        let key = upload.key.clone();
+        let upload_id = upload.upload_id.clone();
+        let number = part.number as i64;
+        let body = part.body;
+
+        let fut = retry(&self.request, move || {
+            client.upload_part(UploadPartRequest {
+                bucket: bucket.clone(),
+                key: key.clone(),
+                upload_id: upload_id.clone(),
+                part_number: number,
+                content_length: Some(body.len() as i64),
+                body: Some(body.clone().into()),
+                ..Default::default()
+            })
+        })
+        .map(move |output| {
+            upload.parts.push(CompletedPart {
+                e_tag: output.e_tag,
+                part_number: Some(number),
+            });
+            upload
+        });
+
+        Box::new(fut)
+    }
+
+    fn complete(&self, upload: S3Upload, part: Part) -> UploadFuture<()> {
+        // S3 only accepts an empty part if it's the only one.
+        let upload: UploadFuture<S3Upload> = if part.body.is_empty() && !upload.parts.is_empty() {
+            Box::new(future::ok(upload))
+        } else {
+            self.upload_part(upload, part)
+        };
+
+        let client = self.client.clone();
+        let bucket = self.bucket.clone();
+        let settings = self.request.clone();
+        let fut = upload.and_then(move |upload| {
+            retry(&settings, move || {
+                client.complete_multipart_upload(CompleteMultipartUploadRequest {
+                    bucket: bucket.clone(),
+                    key: upload.key.clone(),
+                    upload_id: upload.upload_id.clone(),
+                    multipart_upload: Some(CompletedMultipartUpload {
+                        parts: Some(upload.parts.clone()),
+                    }),
+                    ..Default::default()
+                })
+            })
+            .map(|_| ())
+        });
+
+        Box::new(fut)
+    }
+
+    fn abort(&self, upload: S3Upload) -> UploadFuture<()> {
+        let client = self.client.clone();
+        let request = AbortMultipartUploadRequest {
+            bucket: self.bucket.clone(),
+            key: upload.key,
+            upload_id: upload.upload_id,
+            ..Default::default()
+        };
+
+        let fut = retry(&self.request, move || {
+            client.abort_multipart_upload(request.clone())
+        })
+        .map(|_| ());
+
+        Box::new(fut)
+    }
+
+    fn abort_dangling(&self) -> Option<UploadFuture<()>> {
+        let prefix = self.dangling_prefix.clone()?;
+        let started_before = Utc::now() - chrono::Duration::from_std(self.dangling_age).ok()?;
+
+        let client = self.client.clone();
+        let bucket = self.bucket.clone();
+        let list = future::loop_fn(
+            (Vec::new(), None, None),
+            move |(mut dangling, key_marker, upload_id_marker)| {
+                client
+                    .list_multipart_uploads(ListMultipartUploadsRequest {
+                        bucket: bucket.clone(),
+                        prefix: Some(prefix.clone()),
+                        key_marker,
+                        upload_id_marker,
+                        ..Default::default()
+                    })
+                    .map(move |output| {
+                        for upload in output.uploads.unwrap_or_default() {
+                            let initiated = upload
+                                .initiated
+                                .and_then(|ts| DateTime::parse_from_rfc3339(&ts).ok())
+                                .map(|ts| ts.with_timezone(&Utc));
+                            if let (Some(key), Some(upload_id), Some(initiated)) =
+                                (upload.key, upload.upload_id, initiated)
+                            {
+                                if initiated < started_before {
+                                    dangling.push((key, upload_id));
+                                }
+                            }
+                        }
+
+                        if output.is_truncated == Some(true) {
+                            Loop::Continue((
+                                dangling,
+                                output.next_key_marker,
+                                output.next_upload_id_marker,
+                            ))
+                        } else {
+                            Loop::Break(dangling)
+                        }
+                    })
+            },
+        );
+
+        let client = self.client.clone();
+        let bucket = self.bucket.clone();
+        let fut: UploadFuture<()> =
+            Box::new(list.map_err(crate::Error::from).and_then(move |dangling| {
+                if !dangling.is_empty() {
+                    info!(
+                        message = "aborting dangling multipart uploads.",
+                        count = dangling.len(),
+                    );
+                }
+
+                future::join_all(dangling.into_iter().map(move |(key, upload_id)| {
+                    client
+                        .abort_multipart_upload(AbortMultipartUploadRequest {
+                            bucket: bucket.clone(),
+                            key,
+                            upload_id,
+                            ..Default::default()
+                        })
+                        .map_err(crate::Error::from)
+                }))
+                .map(|_| ())
+            }));
+
+        Some(fut)
+    }
+}
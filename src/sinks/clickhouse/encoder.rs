@@ -0,0 +1,522 @@
+use super::{
+    schema::{Column, ColumnType, Schema, SchemaLoader},
+    Encoding, UnknownFields,
+};
+use crate::{
+    event::{Event, LogEvent, Value},
+    sinks::util::encoding::{EncodingConfigWithDefault, EncodingConfiguration},
+};
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use futures01::{try_ready, Async, AsyncSink, Poll, Sink, StartSend};
+use serde_json::{Map, Number, Value as JsonValue};
+use std::convert::TryFrom;
+
+/// Turns events into rows of the table's columns.
+pub(super) struct Encoder {
+    pub(super) encoding: EncodingConfigWithDefault<Encoding>,
+    pub(super) unknown_fields: UnknownFields,
+}
+
+impl Encoder {
+    pub(super) fn encode(&self, schema: &Schema, mut event: Event) -> Option<Vec<u8>> {
+        self.encoding.apply_rules(&mut event);
+        let log = event.as_log();
+
+        if self.unknown_fields == UnknownFields::Reject {
+            if let Some(field) = log.keys().find(|field| !schema.is_known(field)) {
+                warn!(
+                    message = "Field has no matching column; dropping event.",
+                    %field,
+                    rate_limit_secs = 30,
+                );
+                return None;
+            }
+        }
+
+        let mut row = Vec::new();
+        let result = match self.encoding.codec() {
+            Encoding::Default => encode_json(schema, log, &mut row),
+            Encoding::RowBinary => encode_row_binary(schema, log, &mut row),
+        };
+
+        match result {
+            Ok(()) => Some(row),
+            Err(column) => {
+                warn!(
+                    message = "Value can't be converted to its column's type; dropping event.",
+                    column = %column.name,
+                    column_type = ?column.ty,
+                    rate_limit_secs = 30,
+                );
+                None
+            }
+        }
+    }
+}
+
+/// Fields set to null are left out, so non-nullable columns get their
+/// default instead.
+fn column_value<'a>(log: &'a LogEvent, column: &Column) -> Option<&'a Value> {
+    let value = log.get(&column.key);
+    match (value, &column.ty) {
+        (Some(Value::Null), ColumnType::Nullable(_)) => value,
+        (Some(Value::Null), _) => None,
+        _ => value,
+    }
+}
+
+fn encode_json<'a>(
+    schema: &'a Schema,
+    log: &LogEvent,
+    row: &mut Vec<u8>,
+) -> Result<(), &'a Column> {
+    let mut map = Map::new();
+    for column in &schema.columns {
+        if let Some(value) = column_value(log, column) {
+            let value = to_json(value, &column.ty).ok_or(column)?;
+            map.insert(column.name.clone(), value);
+        }
+    }
+
+    serde_json::to_writer(&mut *row, &map).expect("Rows should be valid json!");
+    row.push(b'\n');
+    Ok(())
+}
+
+fn to_json(value: &Value, ty: &ColumnType) -> Option<JsonValue> {
+    match (ty, value) {
+        (ColumnType::Nullable(_), Value::Null) => Some(JsonValue::Null),
+        (ColumnType::Nullable(inner), value) => to_json(value, inner),
+        (_, Value::Null) => None,
+        (ColumnType::Array(inner), Value::Array(values)) => values
+            .iter()
+            .map(|value| to_json(value, inner))
+            .collect::<Option<_>>()
+            .map(JsonValue::Array),
+        (ColumnType::Array(inner), value) => to_json(value, inner).map(|value| vec![value].into()),
+        (ColumnType::String, value) | (ColumnType::FixedString(_), value) => {
+            Some(value.to_string_lossy().into())
+        }
+        (ColumnType::Int(bits), value) => to_integer(value)
+            .filter(|n| in_range(*n, true, *bits))
+            .map(integer_json),
+        (ColumnType::UInt(bits), value) => to_integer(value)
+            .filter(|n| in_range(*n, false, *bits))
+            .map(integer_json),
+        (ColumnType::Float32, value) | (ColumnType::Float64, value) => to_float(value)
+            .and_then(Number::from_f64)
+            .map(JsonValue::Number),
+        // Text that isn't a known date format is left for ClickHouse to parse.
+        (ColumnType::Date, value) => match to_days(value) {
+            Some(days) => Some(
+                (NaiveDate::from_ymd(1970, 1, 1) + Duration::days(days))
+                    .format("%Y-%m-%d")
+                    .to_string()
+                    .into(),
+            ),
+            None => text_json(value),
+        },
+        (ColumnType::DateTime, value) => match to_timestamp(value) {
+            Some(timestamp) => Some(timestamp.timestamp().into()),
+            None => text_json(value),
+        },
+        (ColumnType::DateTime64(precision), value) => match to_timestamp(value) {
+            Some(timestamp) => Some(format_ticks(ticks(&timestamp, *precision), *precision).into()),
+            None => text_json(value),
+        },
+        (ColumnType::Other(_), value) => serde_json::to_value(value).ok(),
+    }
+}
+
+fn text_json(value: &Value) -> Option<JsonValue> {
+    match value {
+        Value::Bytes(_) => Some(value.to_string_lossy().into()),
+        _ => None,
+    }
+}
+
+fn integer_json(n: i128) -> JsonValue {
+    if n <= i128::from(i64::max_value()) {
+        (n as i64).into()
+    } else {
+        (n as u64).into()
+    }
+}
+
+fn encode_row_binary<'a>(
+    schema: &'a Schema,
+    log: &LogEvent,
+    row: &mut Vec<u8>,
+) -> Result<(), &'a Column> {
+    for column in &schema.columns {
+        write_binary(row, column_value(log, column), &column.ty).ok_or(column)?;
+    }
+    Ok(())
+}
+
+/// Writes a value in ClickHouse's `RowBinary` format. Missing values are
+/// written as the type's default, since `RowBinary` rows hold every column.
+fn write_binary(row: &mut Vec<u8>, value: Option<&Value>, ty: &ColumnType) -> Option<()> {
+    if let ColumnType::Nullable(inner) = ty {
+        return match value {
+            None | Some(Value::Null) => {
+                row.push(1);
+                Some(())
+            }
+            Some(value) => {
+                row.push(0);
+                write_binary(row, Some(value), inner)
+            }
+        };
+    }
+
+    let value = match value {
+        None | Some(Value::Null) => {
+            write_default(row, ty);
+            return Some(());
+        }
+        Some(value) => value,
+    };
+
+    match ty {
+        ColumnType::String => write_bytes(row, &value.as_bytes()),
+        ColumnType::FixedString(length) => {
+            let mut bytes = value.as_bytes().to_vec();
+            bytes.resize(*length, 0);
+            row.extend_from_slice(&bytes);
+        }
+        ColumnType::Int(bits) => {
+            let n = to_integer(value).filter(|n| in_range(*n, true, *bits))?;
+            row.extend_from_slice(&(n as i64).to_le_bytes()[..*bits as usize / 8]);
+        }
+        ColumnType::UInt(bits) => {
+            let n = to_integer(value).filter(|n| in_range(*n, false, *bits))?;
+            row.extend_from_slice(&(n as u64).to_le_bytes()[..*bits as usize / 8]);
+        }
+        ColumnType::Float32 => row.extend_from_slice(&(to_float(value)? as f32).to_le_bytes()),
+        ColumnType::Float64 => row.extend_from_slice(&to_float(value)?.to_le_bytes()),
+        ColumnType::Date => {
+            let days = u16::try_from(to_days(value)?).ok()?;
+            row.extend_from_slice(&days.to_le_bytes());
+        }
+        ColumnType::DateTime => {
+            let seconds = u32::try_from(to_timestamp(value)?.timestamp()).ok()?;
+            row.extend_from_slice(&seconds.to_le_bytes());
+        }
+        ColumnType::DateTime64(precision) => {
+            let ticks = ticks(&to_timestamp(value)?, *precision);
+            row.extend_from_slice(&ticks.to_le_bytes());
+        }
+        ColumnType::Array(inner) => match value {
+            Value::Array(values) => {
+                write_varint(row, values.len() as u64);
+                for value in values {
+                    write_binary(row, Some(value), inner)?;
+                }
+            }
+            value => {
+                write_varint(row, 1);
+                write_binary(row, Some(value), inner)?;
+            }
+        },
+        ColumnType::Nullable(_) => unreachable!("Nullable is handled above"),
+        ColumnType::Other(_) => return None,
+    }
+    Some(())
+}
+
+fn write_default(row: &mut Vec<u8>, ty: &ColumnType) {
+    let size = match ty {
+        // An empty string or array is just its zero length.
+        ColumnType::String | ColumnType::Array(_) => 1,
+        ColumnType::FixedString(length) => *length,
+        ColumnType::Int(bits) | ColumnType::UInt(bits) => *bits as usize / 8,
+        ColumnType::Float32 | ColumnType::DateTime => 4,
+        ColumnType::Float64 | ColumnType::DateTime64(_) => 8,
+        ColumnType::Date => 2,
+        ColumnType::Nullable(_) => {
+            row.push(1);
+            return;
+        }
+        ColumnType::Other(_) => 0,
+    };
+    row.resize(row.len() + size, 0);
+}
+
+fn write_bytes(row: &mut Vec<u8>, bytes: &[u8]) {
+    write_varint(row, bytes.len() as u64);
+    row.extend_from_slice(bytes);
+}
+
+fn write_varint(row: &mut Vec<u8>, mut n: u64) {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            row.push(byte);
+            return;
+        }
+        row.push(byte | 0x80);
+    }
+}
+
+fn to_integer(value: &Value) -> Option<i128> {
+    match value {
+        Value::Integer(n) => Some(i128::from(*n)),
+        Value::Float(f) if f.is_finite() && f.fract() == 0.0 => Some(*f as i128),
+        Value::Boolean(b) => Some(i128::from(*b)),
+        Value::Bytes(bytes) => std::str::from_utf8(bytes).ok()?.trim().parse().ok(),
+        Value::Timestamp(timestamp) => Some(i128::from(timestamp.timestamp())),
+        _ => None,
+    }
+}
+
+fn in_range(n: i128, signed: bool, bits: u32) -> bool {
+    if signed {
+        -(1 << (bits - 1)) <= n && n < (1 << (bits - 1))
+    } else {
+        0 <= n && n < (1 << bits)
+    }
+}
+
+fn to_float(value: &Value) -> Option<f64> {
+    match value {
+        Value::Integer(n) => Some(*n as f64),
+        Value::Float(f) => Some(*f),
+        Value::Boolean(b) => Some(if *b { 1.0 } else { 0.0 }),
+        Value::Bytes(bytes) => std::str::from_utf8(bytes).ok()?.trim().parse().ok(),
+        Value::Timestamp(timestamp) => {
+            Some(timestamp.timestamp() as f64 + f64::from(timestamp.timestamp_subsec_nanos()) / 1e9)
+        }
+        _ => None,
+    }
+}
+
+/// Integers and floats are taken as unix timestamps, and text as RFC 3339.
+fn to_timestamp(value: &Value) -> Option<DateTime<Utc>> {
+    match value {
+        Value::Timestamp(timestamp) => Some(*timestamp),
+        Value::Integer(seconds) => Utc.timestamp_opt(*seconds, 0).single(),
+        Value::Float(seconds) if seconds.is_finite() => {
+            let whole = seconds.floor();
+            let nanos = ((seconds - whole) * 1e9) as u32;
+            Utc.timestamp_opt(whole as i64, nanos).single()
+        }
+        Value::Bytes(bytes) => DateTime::parse_from_rfc3339(std::str::from_utf8(bytes).ok()?)
+            .ok()
+            .map(|timestamp| timestamp.with_timezone(&Utc)),
+        _ => None,
+    }
+}
+
+/// Days since the unix epoch. Integers are taken as days already.
+fn to_days(value: &Value) -> Option<i64> {
+    let epoch = NaiveDate::from_ymd(1970, 1, 1);
+    match value {
+        Value::Integer(days) => Some(*days),
+        Value::Bytes(bytes) => {
+            let text = std::str::from_utf8(bytes).ok()?;
+            match NaiveDate::parse_from_str(text, "%Y-%m-%d") {
+                Ok(date) => Some((date - epoch).num_days()),
+                Err(_) => {
+                    to_timestamp(value).map(|timestamp| timestamp.timestamp().div_euclid(86400))
+                }
+            }
+        }
+        value => to_timestamp(value).map(|timestamp| timestamp.timestamp().div_euclid(86400)),
+    }
+}
+
+/// The value of a `DateTime64` column, in units of `10^-precision` seconds.
+fn ticks(timestamp: &DateTime<Utc>, precision: u32) -> i64 {
+    let precision = precision.min(9);
+    timestamp.timestamp() * 10i64.pow(precision)
+        + i64::from(timestamp.timestamp_subsec_nanos()) / 10i64.pow(9 - precision)
+}
+
+fn format_ticks(ticks: i64, precision: u32) -> String {
+    let precision = precision.min(9);
+    let scale = 10i64.pow(precision);
+    if precision == 0 {
+        ticks.to_string()
+    } else {
+        format!(
+            "{}.{:0width$}",
+            ticks.div_euclid(scale),
+            ticks.rem_euclid(scale),
+            width = precision as usize
+        )
+    }
+}
+
+/// Encodes events once the table's columns are known, then forwards the rows
+/// to the batching sink.
+pub(super) struct EncodingSink<S> {
+    inner: S,
+    encoder: Encoder,
+    schema: SchemaLoader,
+    pending: Option<Vec<u8>>,
+}
+
+impl<S> EncodingSink<S>
+where
+    S: Sink<SinkItem = Vec<u8>>,
+{
+    pub(super) fn new(inner: S, encoder: Encoder, schema: SchemaLoader) -> Self {
+        Self {
+            inner,
+            encoder,
+            schema,
+            pending: None,
+        }
+    }
+
+    fn poll_pending(&mut self) -> Poll<(), S::SinkError> {
+        if let Some(row) = self.pending.take() {
+            if let AsyncSink::NotReady(row) = self.inner.start_send(row)? {
+                self.pending = Some(row);
+                return Ok(Async::NotReady);
+            }
+        }
+        Ok(Async::Ready(()))
+    }
+}
+
+impl<S> Sink for EncodingSink<S>
+where
+    S: Sink<SinkItem = Vec<u8>>,
+{
+    type SinkItem = Event;
+    type SinkError = S::SinkError;
+
+    fn start_send(&mut self, event: Event) -> StartSend<Event, S::SinkError> {
+        if self.poll_pending()?.is_not_ready() {
+            return Ok(AsyncSink::NotReady(event));
+        }
+        let schema = match self.schema.poll_schema() {
+            Async::Ready(schema) => schema,
+            Async::NotReady => return Ok(AsyncSink::NotReady(event)),
+        };
+
+        if let Some(row) = self.encoder.encode(&schema, event) {
+            if let AsyncSink::NotReady(row) = self.inner.start_send(row)? {
+                self.pending = Some(row);
+            }
+        }
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), S::SinkError> {
+        try_ready!(self.poll_pending());
+        self.inner.poll_complete()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use string_cache::DefaultAtom as Atom;
+
+    fn schema(columns: &[(&str, &str)]) -> Schema {
+        Schema::new(
+            columns
+                .iter()
+                .map(|(name, ty)| Column {
+                    name: (*name).to_owned(),
+                    key: Atom::from(*name),
+                    ty: ColumnType::parse(ty),
+                })
+                .collect(),
+        )
+    }
+
+    fn encoder(codec: Encoding, unknown_fields: UnknownFields) -> Encoder {
+        Encoder {
+            encoding: EncodingConfigWithDefault {
+                codec,
+                ..Default::default()
+            },
+            unknown_fields,
+        }
+    }
+
+    fn event() -> Event {
+        let mut event = Event::from("raw log line");
+        let log = event.as_mut_log();
+        log.insert("timestamp", Utc.timestamp(1583020800, 123_456_789));
+        log.insert("status", "200");
+        log.insert("tags", vec![Value::from("a"), Value::from("b")]);
+        log.insert("extra", true);
+        event
+    }
+
+    #[test]
+    fn clickhouse_encodes_json_rows() {
+        let schema = schema(&[
+            ("message", "LowCardinality(String)"),
+            ("timestamp", "DateTime64(3, 'UTC')"),
+            ("status", "UInt16"),
+            ("tags", "Array(String)"),
+            ("host", "Nullable(String)"),
+        ]);
+        let row = encoder(Encoding::Default, UnknownFields::Skip)
+            .encode(&schema, event())
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(row).unwrap(),
+            "{\"message\":\"raw log line\",\"timestamp\":\"1583020800.123\",\"status\":200,\"tags\":[\"a\",\"b\"]}\n"
+        );
+    }
+
+    #[test]
+    fn clickhouse_encodes_row_binary_rows() {
+        let schema = schema(&[
+            ("message", "String"),
+            ("timestamp", "DateTime"),
+            ("status", "Int16"),
+            ("tags", "Array(FixedString(2))"),
+            ("host", "Nullable(String)"),
+            ("date", "Date"),
+        ]);
+        let row = encoder(Encoding::RowBinary, UnknownFields::Skip)
+            .encode(&schema, event())
+            .unwrap();
+
+        let mut expected = vec![12];
+        expected.extend_from_slice(b"raw log line");
+        expected.extend_from_slice(&1583020800u32.to_le_bytes());
+        expected.extend_from_slice(&200i16.to_le_bytes());
+        expected.extend_from_slice(&[2, b'a', 0, b'b', 0]);
+        expected.push(1);
+        expected.extend_from_slice(&[0, 0]);
+        assert_eq!(row, expected);
+    }
+
+    #[test]
+    fn clickhouse_rejects_unknown_fields_and_invalid_values() {
+        let schema = schema(&[
+            ("message", "String"),
+            ("timestamp", "DateTime"),
+            ("status", "UInt8"),
+            ("tags", "Array(String)"),
+        ]);
+        assert!(encoder(Encoding::Default, UnknownFields::Reject)
+            .encode(&schema, event())
+            .is_none());
+
+        let mut event = event();
+        event.as_mut_log().insert("status", 300);
+        assert!(encoder(Encoding::Default, UnknownFields::Skip)
+            .encode(&schema, event)
+            .is_none());
+    }
+
+    #[test]
+    fn clickhouse_encodes_varints() {
+        let mut row = Vec::new();
+        write_varint(&mut row, 0);
+        write_varint(&mut row, 300);
+        assert_eq!(row, vec![0, 0xac, 0x02]);
+    }
+}
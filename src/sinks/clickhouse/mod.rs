@@ -1,6 +1,7 @@
+mod encoder;
+mod schema;
+
 use crate::{
-    dns::Resolver,
-    event::Event,
     sinks::util::{
         encoding::{skip_serializing_if_default, EncodingConfigWithDefault, EncodingConfiguration},
         http::{https_client, Auth, HttpRetryLogic, HttpService, Response},
@@ -10,13 +11,15 @@ use crate::{
     tls::{TlsOptions, TlsSettings},
     topology::config::{DataType, SinkConfig, SinkContext, SinkDescription},
 };
-use futures01::{stream::iter_ok, Future, Sink};
+use encoder::{Encoder, EncodingSink};
+use futures01::Future;
 use http::StatusCode;
 use http::{Method, Uri};
-use hyper::{Body, Request};
 use lazy_static::lazy_static;
+use schema::{SchemaFetcher, SchemaLoader, SharedSchema};
 use serde::{Deserialize, Serialize};
-use snafu::ResultExt;
+use snafu::{ResultExt, Snafu};
+use std::sync::{Arc, Mutex};
 
 #[derive(Deserialize, Serialize, Debug, Clone, Default)]
 #[serde(deny_unknown_fields)]
@@ -28,6 +31,10 @@ pub struct ClickhouseConfig {
     #[serde(skip_serializing_if = "skip_serializing_if_default", default)]
     pub encoding: EncodingConfigWithDefault<Encoding>,
     #[serde(default)]
+    pub unknown_fields: UnknownFields,
+    pub allowed_errors_num: Option<u64>,
+    pub allowed_errors_ratio: Option<f64>,
+    #[serde(default)]
     pub batch: BatchBytesConfig,
     pub auth: Option<Auth>,
     #[serde(default)]
@@ -50,14 +57,58 @@ inventory::submit! {
 #[derivative(Default)]
 pub enum Encoding {
     #[derivative(Default)]
+    #[serde(alias = "json_each_row")]
     Default,
+    RowBinary,
+}
+
+/// What to do with events having fields the table has no column for.
+#[derive(Deserialize, Serialize, Debug, Eq, PartialEq, Clone, Copy, Derivative)]
+#[serde(rename_all = "snake_case")]
+#[derivative(Default)]
+pub enum UnknownFields {
+    #[derivative(Default)]
+    Reject,
+    Skip,
+}
+
+#[derive(Debug, Snafu)]
+enum BuildError {
+    #[snafu(display(
+        "allowed_errors_num and allowed_errors_ratio can't be used with the row_binary encoding"
+    ))]
+    AllowedErrorsWithRowBinary,
+    #[snafu(display("allowed_errors_ratio must be between 0 and 1"))]
+    InvalidAllowedErrorsRatio,
 }
 
 #[typetag::serde(name = "clickhouse")]
 impl SinkConfig for ClickhouseConfig {
     fn build(&self, cx: SinkContext) -> crate::Result<(super::RouterSink, super::Healthcheck)> {
-        let healthcheck = healthcheck(cx.resolver(), &self)?;
-        let sink = clickhouse(self.clone(), cx)?;
+        let row_binary = self.encoding.codec() == &Encoding::RowBinary;
+        if row_binary && (self.allowed_errors_num.is_some() || self.allowed_errors_ratio.is_some())
+        {
+            return Err(BuildError::AllowedErrorsWithRowBinary.into());
+        }
+        if let Some(ratio) = self.allowed_errors_ratio {
+            if ratio < 0.0 || ratio > 1.0 {
+                return Err(BuildError::InvalidAllowedErrorsRatio.into());
+            }
+        }
+
+        let tls = TlsSettings::from_options(&self.tls)?;
+        let fetcher = SchemaFetcher {
+            client: https_client(cx.resolver(), tls)?,
+            host: self.host.clone(),
+            auth: self.auth.clone(),
+            database: self.database.clone().unwrap_or("default".into()),
+            table: self.table.clone(),
+            row_binary,
+        };
+        let shared = Arc::new(Mutex::new(None));
+
+        let healthcheck = healthcheck(fetcher.clone(), Arc::clone(&shared));
+        let sink = clickhouse(self.clone(), SchemaLoader::new(fetcher, shared), cx)?;
 
         Ok((sink, healthcheck))
     }
@@ -71,7 +122,11 @@ impl SinkConfig for ClickhouseConfig {
     }
 }
 
-fn clickhouse(config: ClickhouseConfig, cx: SinkContext) -> crate::Result<super::RouterSink> {
+fn clickhouse(
+    config: ClickhouseConfig,
+    schema: SchemaLoader,
+    cx: SinkContext,
+) -> crate::Result<super::RouterSink> {
     let host = config.host.clone();
     let database = config.database.clone().unwrap_or("default".into());
     let table = config.table.clone();
@@ -86,7 +141,11 @@ fn clickhouse(config: ClickhouseConfig, cx: SinkContext) -> crate::Result<super:
 
     let auth = config.auth.clone();
 
-    let uri = encode_uri(&host, &database, &table)?;
+    let uri = encode_uri(&host, &database, &table, &config)?;
+    let content_type = match config.encoding.codec() {
+        Encoding::Default => "application/x-ndjson",
+        Encoding::RowBinary => "application/octet-stream",
+    };
     let tls_settings = TlsSettings::from_options(&config.tls)?;
 
     let http_service = HttpService::builder(cx.resolver())
@@ -96,7 +155,7 @@ fn clickhouse(config: ClickhouseConfig, cx: SinkContext) -> crate::Result<super:
             builder.method(Method::POST);
             builder.uri(uri.clone());
 
-            builder.header("Content-Type", "application/x-ndjson");
+            builder.header("Content-Type", content_type);
 
             if gzip {
                 builder.header("Content-Encoding", "gzip");
@@ -119,57 +178,54 @@ fn clickhouse(config: ClickhouseConfig, cx: SinkContext) -> crate::Result<super:
             http_service,
             cx.acker(),
         )
-        .batched_with_min(Buffer::new(gzip), &batch)
-        .with_flat_map(move |event: Event| iter_ok(encode_event(event, &config.encoding)));
-
-    Ok(Box::new(sink))
-}
+        .batched_with_min(Buffer::new(gzip), &batch);
 
-fn encode_event(
-    mut event: Event,
-    encoding: &EncodingConfigWithDefault<Encoding>,
-) -> Option<Vec<u8>> {
-    encoding.apply_rules(&mut event);
-    let mut body =
-        serde_json::to_vec(&event.as_log().all_fields()).expect("Events should be valid json!");
-    body.push(b'\n');
-    Some(body)
+    let encoder = Encoder {
+        encoding: config.encoding,
+        unknown_fields: config.unknown_fields,
+    };
+    Ok(Box::new(EncodingSink::new(sink, encoder, schema)))
 }
 
-fn healthcheck(resolver: Resolver, config: &ClickhouseConfig) -> crate::Result<super::Healthcheck> {
-    // TODO: check if table exists?
-    let uri = format!("{}/?query=SELECT%201", config.host);
-    let mut request = Request::get(uri).body(Body::empty()).unwrap();
-
-    if let Some(auth) = &config.auth {
-        auth.apply(&mut request);
-    }
-
-    let tls = TlsSettings::from_options(&config.tls)?;
-    let client = https_client(resolver, tls)?;
-    let healthcheck = client
-        .request(request)
-        .map_err(|err| err.into())
-        .and_then(|response| match response.status() {
-            hyper::StatusCode::OK => Ok(()),
-            status => Err(super::HealthcheckError::UnexpectedStatus { status }.into()),
-        });
+/// Fetches the table's columns, which also checks that it exists.
+fn healthcheck(fetcher: SchemaFetcher, shared: SharedSchema) -> super::Healthcheck {
+    let healthcheck = fetcher.fetch().map(move |schema| {
+        *shared.lock().unwrap() = Some(Arc::new(schema));
+    });
 
-    Ok(Box::new(healthcheck))
+    Box::new(healthcheck)
 }
 
-fn encode_uri(host: &str, database: &str, table: &str) -> crate::Result<Uri> {
-    let query = url::form_urlencoded::Serializer::new(String::new())
-        .append_pair(
-            "query",
-            format!(
-                "INSERT INTO \"{}\".\"{}\" FORMAT JSONEachRow",
-                database,
-                table.replace("\"", "\\\"")
-            )
-            .as_str(),
+fn encode_uri(
+    host: &str,
+    database: &str,
+    table: &str,
+    config: &ClickhouseConfig,
+) -> crate::Result<Uri> {
+    let format = match config.encoding.codec() {
+        Encoding::Default => "JSONEachRow",
+        Encoding::RowBinary => "RowBinary",
+    };
+
+    let mut query = url::form_urlencoded::Serializer::new(String::new());
+    query.append_pair(
+        "query",
+        format!(
+            "INSERT INTO \"{}\".\"{}\" FORMAT {}",
+            database,
+            table.replace("\"", "\\\""),
+            format
         )
-        .finish();
+        .as_str(),
+    );
+    // Rows ClickHouse fails to parse are skipped, up to these limits.
+    if let Some(num) = config.allowed_errors_num {
+        query.append_pair("input_format_allow_errors_num", &num.to_string());
+    }
+    if let Some(ratio) = config.allowed_errors_ratio {
+        query.append_pair("input_format_allow_errors_ratio", &ratio.to_string());
+    }
+    let query = query.finish();
 
     let url = if host.ends_with('/') {
         format!("{}?{}", host, query)
@@ -223,16 +279,46 @@ mod tests {
 
     #[test]
     fn encode_valid() {
-        let uri = encode_uri("http://localhost:80", "my_database", "my_table").unwrap();
+        let config = ClickhouseConfig::default();
+        let uri = encode_uri("http://localhost:80", "my_database", "my_table", &config).unwrap();
         assert_eq!(uri, "http://localhost:80/?query=INSERT+INTO+%22my_database%22.%22my_table%22+FORMAT+JSONEachRow");
 
-        let uri = encode_uri("http://localhost:80", "my_database", "my_\"table\"").unwrap();
+        let uri = encode_uri(
+            "http://localhost:80",
+            "my_database",
+            "my_\"table\"",
+            &config,
+        )
+        .unwrap();
         assert_eq!(uri, "http://localhost:80/?query=INSERT+INTO+%22my_database%22.%22my_%5C%22table%5C%22%22+FORMAT+JSONEachRow");
     }
 
+    #[test]
+    fn encode_formats_and_allowed_errors() {
+        let config: ClickhouseConfig = toml::from_str(
+            r#"
+host = "http://localhost:80"
+table = "my_table"
+encoding = "row_binary"
+"#,
+        )
+        .unwrap();
+        let uri = encode_uri("http://localhost:80", "my_database", "my_table", &config).unwrap();
+        assert_eq!(uri, "http://localhost:80/?query=INSERT+INTO+%22my_database%22.%22my_table%22+FORMAT+RowBinary");
+
+        let config = ClickhouseConfig {
+            allowed_errors_num: Some(10),
+            allowed_errors_ratio: Some(0.5),
+            ..Default::default()
+        };
+        let uri = encode_uri("http://localhost:80", "my_database", "my_table", &config).unwrap();
+        assert_eq!(uri, "http://localhost:80/?query=INSERT+INTO+%22my_database%22.%22my_table%22+FORMAT+JSONEachRow&input_format_allow_errors_num=10&input_format_allow_errors_ratio=0.5");
+    }
+
     #[test]
     fn encode_invalid() {
-        encode_uri("localhost:80", "my_database", "my_table").unwrap_err();
+        let config = ClickhouseConfig::default();
+        encode_uri("localhost:80", "my_database", "my_table", &config).unwrap_err();
     }
 }
 
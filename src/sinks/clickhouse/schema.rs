@@ -0,0 +1,362 @@
+use crate::sinks::util::http::{Auth, HttpsClient};
+use futures01::{Async, Future, Stream};
+use hyper::{Body, Request};
+use serde::Deserialize;
+use snafu::{ResultExt, Snafu};
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use string_cache::DefaultAtom as Atom;
+use tokio::timer::Delay;
+
+/// The columns fetched by the healthcheck, reused by the sink.
+pub(super) type SharedSchema = Arc<Mutex<Option<Arc<Schema>>>>;
+
+type SchemaFuture = Box<dyn Future<Item = Schema, Error = crate::Error> + Send>;
+
+#[derive(Debug, Snafu)]
+pub(super) enum SchemaError {
+    #[snafu(display("Table \"{}\".\"{}\" doesn't exist or has no columns", database, table))]
+    NoColumns { database: String, table: String },
+    #[snafu(display("Unexpected status fetching the table's columns: {}", status))]
+    FetchStatus { status: http::StatusCode },
+    #[snafu(display("Invalid columns returned by ClickHouse: {}", source))]
+    ParseColumns { source: serde_json::Error },
+    #[snafu(display(
+        "Column {:?} has type {}, which the row_binary encoding doesn't support",
+        name,
+        ty
+    ))]
+    UnsupportedType { name: String, ty: String },
+}
+
+/// The types values are coerced to before being inserted. `LowCardinality`
+/// is encoded exactly like the type it wraps, so it's parsed as that type.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum ColumnType {
+    String,
+    FixedString(usize),
+    Int(u32),
+    UInt(u32),
+    Float32,
+    Float64,
+    Date,
+    DateTime,
+    DateTime64(u32),
+    Array(Box<ColumnType>),
+    Nullable(Box<ColumnType>),
+    /// Types without a coercion, sent as they are with `JSONEachRow`.
+    Other(String),
+}
+
+impl ColumnType {
+    pub(super) fn parse(ty: &str) -> Self {
+        let ty = ty.trim();
+        if let Some(inner) = arguments(ty, "LowCardinality") {
+            return Self::parse(inner);
+        }
+        if let Some(inner) = arguments(ty, "Nullable") {
+            return ColumnType::Nullable(Box::new(Self::parse(inner)));
+        }
+        if let Some(inner) = arguments(ty, "Array") {
+            return ColumnType::Array(Box::new(Self::parse(inner)));
+        }
+        if let Some(length) = arguments(ty, "FixedString").and_then(|n| n.trim().parse().ok()) {
+            return ColumnType::FixedString(length);
+        }
+        if let Some(precision) = arguments(ty, "DateTime64")
+            .and_then(|args| args.split(',').next())
+            .and_then(|precision| precision.trim().parse().ok())
+        {
+            return ColumnType::DateTime64(precision);
+        }
+        // The time zone only matters to how ClickHouse displays values.
+        if arguments(ty, "DateTime").is_some() {
+            return ColumnType::DateTime;
+        }
+
+        match ty {
+            "String" => ColumnType::String,
+            "Int8" => ColumnType::Int(8),
+            "Int16" => ColumnType::Int(16),
+            "Int32" => ColumnType::Int(32),
+            "Int64" => ColumnType::Int(64),
+            "UInt8" => ColumnType::UInt(8),
+            "UInt16" => ColumnType::UInt(16),
+            "UInt32" => ColumnType::UInt(32),
+            "UInt64" => ColumnType::UInt(64),
+            "Float32" => ColumnType::Float32,
+            "Float64" => ColumnType::Float64,
+            "Date" => ColumnType::Date,
+            "DateTime" => ColumnType::DateTime,
+            other => ColumnType::Other(other.to_owned()),
+        }
+    }
+
+    /// The type `RowBinary` can't encode, if any.
+    fn row_binary_unsupported(&self) -> Option<&str> {
+        match self {
+            ColumnType::Other(ty) => Some(ty),
+            ColumnType::Array(inner) | ColumnType::Nullable(inner) => {
+                inner.row_binary_unsupported()
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Returns the arguments of a parametric type like `Nullable(String)`.
+fn arguments<'a>(ty: &'a str, name: &str) -> Option<&'a str> {
+    if ty.starts_with(name) && ty[name.len()..].starts_with('(') && ty.ends_with(')') {
+        Some(&ty[name.len() + 1..ty.len() - 1])
+    } else {
+        None
+    }
+}
+
+#[derive(Debug)]
+pub(super) struct Column {
+    pub(super) name: String,
+    pub(super) key: Atom,
+    pub(super) ty: ColumnType,
+}
+
+/// The columns values can be inserted into, in the table's order.
+#[derive(Debug)]
+pub(super) struct Schema {
+    pub(super) columns: Vec<Column>,
+    names: HashSet<String>,
+}
+
+#[derive(Deserialize)]
+struct ColumnRow {
+    name: String,
+    #[serde(rename = "type")]
+    ty: String,
+    default_kind: String,
+}
+
+impl Schema {
+    pub(super) fn new(columns: Vec<Column>) -> Self {
+        let names = columns.iter().map(|column| column.name.clone()).collect();
+        Self { columns, names }
+    }
+
+    /// Parses the `JSONEachRow` output of the columns query. Materialized
+    /// and alias columns are computed by ClickHouse and can't be inserted.
+    fn parse(body: &[u8]) -> Result<Self, SchemaError> {
+        let mut columns = Vec::new();
+        for row in serde_json::Deserializer::from_slice(body).into_iter::<ColumnRow>() {
+            let row = row.context(ParseColumns)?;
+            if row.default_kind == "MATERIALIZED" || row.default_kind == "ALIAS" {
+                continue;
+            }
+            columns.push(Column {
+                key: Atom::from(row.name.as_str()),
+                ty: ColumnType::parse(&row.ty),
+                name: row.name,
+            });
+        }
+        Ok(Self::new(columns))
+    }
+
+    /// Whether a flattened field path is stored in a column, either as is or
+    /// within a map or array column.
+    pub(super) fn is_known(&self, path: &str) -> bool {
+        self.names.contains(path)
+            || path
+                .char_indices()
+                .filter(|(_, c)| *c == '.' || *c == '[')
+                .any(|(i, _)| self.names.contains(&path[..i]))
+    }
+
+    pub(super) fn check_row_binary(&self) -> Result<(), SchemaError> {
+        for column in &self.columns {
+            if let Some(ty) = column.ty.row_binary_unsupported() {
+                return Err(SchemaError::UnsupportedType {
+                    name: column.name.clone(),
+                    ty: ty.to_owned(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Everything needed to fetch a table's columns from `system.columns`.
+#[derive(Clone)]
+pub(super) struct SchemaFetcher {
+    pub(super) client: HttpsClient,
+    pub(super) host: String,
+    pub(super) auth: Option<Auth>,
+    pub(super) database: String,
+    pub(super) table: String,
+    pub(super) row_binary: bool,
+}
+
+impl SchemaFetcher {
+    pub(super) fn fetch(&self) -> impl Future<Item = Schema, Error = crate::Error> {
+        let query = format!(
+            "SELECT name, type, default_kind FROM system.columns \
+             WHERE database = '{}' AND table = '{}' FORMAT JSONEachRow",
+            escape(&self.database),
+            escape(&self.table)
+        );
+        let query = url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("query", &query)
+            .finish();
+        let uri = if self.host.ends_with('/') {
+            format!("{}?{}", self.host, query)
+        } else {
+            format!("{}/?{}", self.host, query)
+        };
+
+        let mut request = Request::get(uri).body(Body::empty()).unwrap();
+        if let Some(auth) = &self.auth {
+            auth.apply(&mut request);
+        }
+
+        let database = self.database.clone();
+        let table = self.table.clone();
+        let row_binary = self.row_binary;
+        self.client
+            .request(request)
+            .map_err(Into::into)
+            .and_then(|response| {
+                let status = response.status();
+                response
+                    .into_body()
+                    .concat2()
+                    .map_err(Into::into)
+                    .map(move |body| (status, body))
+            })
+            .and_then(move |(status, body)| {
+                if status != http::StatusCode::OK {
+                    return Err(SchemaError::FetchStatus { status }.into());
+                }
+                let schema = Schema::parse(&body)?;
+                if schema.columns.is_empty() {
+                    return Err(SchemaError::NoColumns { database, table }.into());
+                }
+                if row_binary {
+                    schema.check_row_binary()?;
+                }
+                Ok(schema)
+            })
+    }
+}
+
+/// Fetches the columns when the sink first needs them, waiting between
+/// attempts until ClickHouse answers.
+pub(super) struct SchemaLoader {
+    fetcher: SchemaFetcher,
+    shared: SharedSchema,
+    state: LoaderState,
+}
+
+enum LoaderState {
+    Idle,
+    Fetching(SchemaFuture),
+    Waiting(Delay),
+    Loaded(Arc<Schema>),
+}
+
+impl SchemaLoader {
+    pub(super) fn new(fetcher: SchemaFetcher, shared: SharedSchema) -> Self {
+        Self {
+            fetcher,
+            shared,
+            state: LoaderState::Idle,
+        }
+    }
+
+    pub(super) fn poll_schema(&mut self) -> Async<Arc<Schema>> {
+        loop {
+            if let LoaderState::Loaded(schema) = &self.state {
+                return Async::Ready(Arc::clone(schema));
+            }
+            if let Some(schema) = self.shared.lock().unwrap().clone() {
+                self.state = LoaderState::Loaded(schema);
+                continue;
+            }
+
+            self.state = match &mut self.state {
+                LoaderState::Idle => LoaderState::Fetching(Box::new(self.fetcher.fetch())),
+                LoaderState::Fetching(future) => match future.poll() {
+                    Ok(Async::Ready(schema)) => {
+                        debug!(
+                            message = "Fetched the table's columns.",
+                            count = schema.columns.len()
+                        );
+                        *self.shared.lock().unwrap() = Some(Arc::new(schema));
+                        continue;
+                    }
+                    Ok(Async::NotReady) => return Async::NotReady,
+                    Err(error) => {
+                        error!(message = "Unable to fetch the table's columns; retrying.", %error);
+                        LoaderState::Waiting(Delay::new(Instant::now() + Duration::from_secs(10)))
+                    }
+                },
+                LoaderState::Waiting(delay) => match delay.poll() {
+                    Ok(Async::NotReady) => return Async::NotReady,
+                    _ => LoaderState::Idle,
+                },
+                LoaderState::Loaded(_) => unreachable!("Loaded is handled above"),
+            };
+        }
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\'', "\\'")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clickhouse_parses_column_types() {
+        assert_eq!(ColumnType::parse("String"), ColumnType::String);
+        assert_eq!(ColumnType::parse("UInt16"), ColumnType::UInt(16));
+        assert_eq!(
+            ColumnType::parse("FixedString(4)"),
+            ColumnType::FixedString(4)
+        );
+        assert_eq!(
+            ColumnType::parse("DateTime('Europe/London')"),
+            ColumnType::DateTime
+        );
+        assert_eq!(
+            ColumnType::parse("DateTime64(3, 'UTC')"),
+            ColumnType::DateTime64(3)
+        );
+        assert_eq!(
+            ColumnType::parse("Array(LowCardinality(Nullable(String)))"),
+            ColumnType::Array(Box::new(ColumnType::Nullable(Box::new(ColumnType::String))))
+        );
+        assert_eq!(
+            ColumnType::parse("Decimal(9, 2)"),
+            ColumnType::Other("Decimal(9, 2)".into())
+        );
+    }
+
+    #[test]
+    fn clickhouse_parses_columns() {
+        let body = br#"{"name":"host","type":"LowCardinality(String)","default_kind":""}
+{"name":"date","type":"Date","default_kind":"MATERIALIZED"}
+{"name":"tags","type":"Map(String, String)","default_kind":""}
+"#;
+        let schema = Schema::parse(body).unwrap();
+        assert_eq!(schema.columns.len(), 2);
+        assert_eq!(schema.columns[0].ty, ColumnType::String);
+
+        assert!(schema.is_known("host"));
+        assert!(schema.is_known("tags.env"));
+        assert!(!schema.is_known("date"));
+        assert!(!schema.is_known("hostname"));
+        assert!(schema.check_row_binary().is_err());
+    }
+}
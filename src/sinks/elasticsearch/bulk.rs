@@ -0,0 +1,404 @@
+use crate::sinks::util::{
+    http::{HttpRetryLogic, HttpService, Response},
+    retries::{RetryAction, RetryLogic},
+    SharedRequest,
+};
+use flate2::{read::MultiGzDecoder, write::GzEncoder};
+use futures01::{Future, Poll};
+use serde::Deserialize;
+use snafu::Snafu;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Write};
+use tower::Service;
+
+#[derive(Debug, Snafu)]
+pub enum ElasticSearchError {
+    #[snafu(display("HTTP request failed: {}", source))]
+    Http { source: hyper::Error },
+    #[snafu(display("{} bulk actions failed with a retryable status", count))]
+    FailedActions { count: usize },
+    #[snafu(display("Could not encode retried bulk actions: {}", source))]
+    Encode { source: std::io::Error },
+}
+
+/// Sends bulk requests. A request with actions which failed with a
+/// retryable status fails with `FailedActions`, after narrowing its body
+/// down to those actions.
+#[derive(Clone)]
+pub struct ElasticSearchService {
+    inner: HttpService,
+    gzip: bool,
+}
+
+impl ElasticSearchService {
+    pub fn new(inner: HttpService, gzip: bool) -> Self {
+        Self { inner, gzip }
+    }
+}
+
+impl Service<SharedRequest<Vec<u8>>> for ElasticSearchService {
+    type Response = Response;
+    type Error = ElasticSearchError;
+    type Future = Box<dyn Future<Item = Self::Response, Error = Self::Error> + Send + 'static>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner
+            .poll_ready()
+            .map_err(|source| ElasticSearchError::Http { source })
+    }
+
+    fn call(&mut self, request: SharedRequest<Vec<u8>>) -> Self::Future {
+        let gzip = self.gzip;
+        let body = request.get();
+
+        let fut = self
+            .inner
+            .call(body.clone())
+            .map_err(|source| ElasticSearchError::Http { source })
+            .and_then(move |response| {
+                // Failed requests are handled by the retry logic.
+                if !response.status().is_success() {
+                    return Ok(response);
+                }
+
+                match retry_body(&body, response.body(), gzip) {
+                    Ok(Some(retry)) => {
+                        request.set(retry.body);
+                        Err(ElasticSearchError::FailedActions { count: retry.count })
+                    }
+                    Ok(None) => Ok(response),
+                    Err(source) => Err(ElasticSearchError::Encode { source }),
+                }
+            });
+
+        Box::new(fut)
+    }
+}
+
+#[derive(Clone)]
+pub struct ElasticSearchRetryLogic;
+
+impl RetryLogic for ElasticSearchRetryLogic {
+    type Error = ElasticSearchError;
+    type Response = Response;
+
+    fn is_retriable_error(&self, error: &Self::Error) -> bool {
+        match error {
+            ElasticSearchError::Http { source } => HttpRetryLogic.is_retriable_error(source),
+            ElasticSearchError::FailedActions { .. } => true,
+            ElasticSearchError::Encode { .. } => false,
+        }
+    }
+
+    fn should_retry_response(&self, response: &Self::Response) -> RetryAction {
+        HttpRetryLogic.should_retry_response(response)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct BulkResponse {
+    errors: bool,
+    #[serde(default)]
+    items: Vec<HashMap<String, BulkItem>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct BulkItem {
+    status: u16,
+    #[serde(default)]
+    error: Option<serde_json::Value>,
+}
+
+impl BulkItem {
+    fn is_retryable(&self) -> bool {
+        self.status == 429 || self.status >= 500
+    }
+
+    /// The type of the error, and its reason.
+    fn error_reason(&self) -> (String, String) {
+        match &self.error {
+            Some(serde_json::Value::Object(error)) => {
+                let field = |name: &str| {
+                    error
+                        .get(name)
+                        .and_then(serde_json::Value::as_str)
+                        .map(str::to_owned)
+                };
+                (
+                    field("type").unwrap_or_else(|| "unknown".into()),
+                    field("reason").unwrap_or_default(),
+                )
+            }
+            Some(serde_json::Value::String(reason)) => ("unknown".into(), reason.clone()),
+            _ => ("unknown".into(), format!("status {}", self.status)),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct RetryBody {
+    body: Vec<u8>,
+    count: usize,
+}
+
+/// Matches the bulk response items to the actions of the request. Rejected
+/// actions are dropped, and the actions which failed with a retryable status
+/// are returned as a new request body.
+fn retry_body(
+    request: &[u8],
+    response: &[u8],
+    gzip: bool,
+) -> Result<Option<RetryBody>, std::io::Error> {
+    let response = match serde_json::from_slice::<BulkResponse>(response) {
+        Ok(response) => response,
+        Err(error) => {
+            warn!(
+                message = "Unable to parse bulk response; assuming it succeeded.",
+                %error,
+                rate_limit_secs = 30,
+            );
+            return Ok(None);
+        }
+    };
+    if !response.errors {
+        return Ok(None);
+    }
+
+    let request = if gzip {
+        let mut decoded = Vec::new();
+        MultiGzDecoder::new(request).read_to_end(&mut decoded)?;
+        decoded
+    } else {
+        request.to_vec()
+    };
+    let actions = split_actions(&request);
+    if actions.len() != response.items.len() {
+        warn!(
+            message = "Bulk response doesn't match the request; not retrying failed actions.",
+            actions = actions.len(),
+            items = response.items.len(),
+            rate_limit_secs = 30,
+        );
+        return Ok(None);
+    }
+
+    let mut body = Vec::new();
+    let mut count = 0;
+    // Rejected actions by error type, with an example reason.
+    let mut rejected = BTreeMap::<String, (usize, String)>::new();
+    for (action, item) in actions.into_iter().zip(response.items) {
+        let item = match item.into_iter().next() {
+            Some((_, item)) => item,
+            None => continue,
+        };
+        if item.status < 300 {
+            continue;
+        } else if item.is_retryable() {
+            body.extend_from_slice(action);
+            count += 1;
+        } else {
+            let (kind, reason) = item.error_reason();
+            rejected.entry(kind).or_insert((0, reason)).0 += 1;
+        }
+    }
+
+    for (kind, (count, reason)) in rejected {
+        error!(
+            message = "Elasticsearch rejected documents; dropping them.",
+            error_type = %kind,
+            %reason,
+            rejected_counter = count,
+        );
+    }
+
+    if count == 0 {
+        return Ok(None);
+    }
+
+    if gzip {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&body)?;
+        body = encoder.finish()?;
+    }
+
+    Ok(Some(RetryBody { body, count }))
+}
+
+/// Splits a bulk request body into its actions, each with its source line.
+/// Only the `delete` action doesn't have one.
+fn split_actions(body: &[u8]) -> Vec<&[u8]> {
+    let mut actions = Vec::new();
+    let mut start = 0;
+    let mut lines = body
+        .split(|&b| b == b'\n')
+        .filter(|line| !line.is_empty())
+        .peekable();
+
+    while let Some(line) = lines.next() {
+        let action_end = line_end(body, line);
+        let has_source = serde_json::from_slice::<HashMap<String, serde_json::Value>>(line)
+            .map(|action| !action.contains_key("delete"))
+            .unwrap_or(true);
+
+        let end = match (has_source, lines.peek()) {
+            (true, Some(_)) => line_end(body, lines.next().unwrap()),
+            _ => action_end,
+        };
+        actions.push(&body[start..end]);
+        start = end;
+    }
+
+    actions
+}
+
+/// Offset in `body` right after the newline ending `line`.
+fn line_end(body: &[u8], line: &[u8]) -> usize {
+    let end = line.as_ptr() as usize - body.as_ptr() as usize + line.len();
+    (end + 1).min(body.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{dns::Resolver, sinks::util::TowerRequestConfig, test_util};
+    use futures01::{sync::mpsc, Sink, Stream};
+    use hyper::{service::service_fn_ok, Body, Request, Server};
+    use std::sync::{Arc, Mutex};
+    use tower::ServiceBuilder;
+
+    const REQUEST: &[u8] = b"{\"index\":{\"_index\":\"a\"}}\n{\"message\":\"one\"}\n\
+        {\"delete\":{\"_index\":\"a\",\"_id\":\"1\"}}\n\
+        {\"index\":{\"_index\":\"a\"}}\n{\"message\":\"three\"}\n";
+
+    #[test]
+    fn elasticsearch_split_actions() {
+        let actions = split_actions(REQUEST);
+        assert_eq!(
+            actions,
+            vec![
+                &b"{\"index\":{\"_index\":\"a\"}}\n{\"message\":\"one\"}\n"[..],
+                &b"{\"delete\":{\"_index\":\"a\",\"_id\":\"1\"}}\n"[..],
+                &b"{\"index\":{\"_index\":\"a\"}}\n{\"message\":\"three\"}\n"[..],
+            ]
+        );
+    }
+
+    #[test]
+    fn elasticsearch_retry_body() {
+        let response = br#"{"took":3,"errors":true,"items":[
+            {"index":{"_index":"a","status":429,"error":{"type":"es_rejected_execution_exception","reason":"queue full"}}},
+            {"delete":{"_index":"a","status":200}},
+            {"index":{"_index":"a","status":400,"error":{"type":"mapper_parsing_exception","reason":"failed to parse"}}}
+        ]}"#;
+        let retry = retry_body(REQUEST, response, false).unwrap().unwrap();
+        assert_eq!(retry.count, 1);
+        assert_eq!(
+            retry.body,
+            b"{\"index\":{\"_index\":\"a\"}}\n{\"message\":\"one\"}\n".to_vec()
+        );
+
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(REQUEST).unwrap();
+        let retry = retry_body(&encoder.finish().unwrap(), response, true)
+            .unwrap()
+            .unwrap();
+        let mut decoded = Vec::new();
+        MultiGzDecoder::new(&retry.body[..])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(
+            decoded,
+            b"{\"index\":{\"_index\":\"a\"}}\n{\"message\":\"one\"}\n".to_vec()
+        );
+
+        let rejected = br#"{"errors":true,"items":[
+            {"index":{"status":400,"error":{"type":"mapper_parsing_exception"}}},
+            {"delete":{"status":404}},
+            {"index":{"status":201}}
+        ]}"#;
+        assert!(retry_body(REQUEST, rejected, false).unwrap().is_none());
+        assert!(
+            retry_body(REQUEST, br#"{"errors":false,"items":[]}"#, false)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn elasticsearch_resends_retryable_actions() {
+        let mut rt = test_util::runtime();
+        let addr = test_util::next_addr();
+
+        let mut responses = vec![
+            r#"{"errors":true,"items":[
+                {"index":{"status":201}},
+                {"delete":{"status":503,"error":{"type":"unavailable_shards_exception"}}},
+                {"index":{"status":429,"error":{"type":"es_rejected_execution_exception"}}}
+            ]}"#,
+            r#"{"errors":true,"items":[
+                {"delete":{"status":200}},
+                {"index":{"status":429,"error":{"type":"es_rejected_execution_exception"}}}
+            ]}"#,
+            r#"{"errors":false,"items":[{"index":{"status":201}}]}"#,
+        ];
+        responses.reverse();
+        let responses = Arc::new(Mutex::new(responses));
+
+        let (tx, rx) = mpsc::channel(10);
+        let service = move || {
+            let tx = tx.clone();
+            let responses = responses.clone();
+            service_fn_ok(move |req: Request<Body>| {
+                let tx = tx.clone();
+                tokio::spawn(
+                    req.into_body()
+                        .concat2()
+                        .map_err(|e| panic!(e))
+                        .and_then(|body| tx.send(body.to_vec()))
+                        .map(|_| ())
+                        .map_err(|e| panic!(e)),
+                );
+                hyper::Response::new(Body::from(responses.lock().unwrap().pop().unwrap()))
+            })
+        };
+        rt.spawn(
+            Server::bind(&addr)
+                .serve(service)
+                .map_err(|e| panic!("server error: {}", e)),
+        );
+
+        let resolver = Resolver::new(Vec::new(), rt.executor()).unwrap();
+        let http = HttpService::new(resolver, move |body| {
+            hyper::Request::post(format!("http://{}/_bulk", addr))
+                .body(body)
+                .unwrap()
+        });
+        let settings = TowerRequestConfig {
+            retry_initial_backoff_secs: Some(0),
+            ..Default::default()
+        }
+        .unwrap_with(&Default::default());
+        let mut service = ServiceBuilder::new()
+            .retry(settings.retry_policy(ElasticSearchRetryLogic))
+            .timeout(settings.timeout)
+            .service(ElasticSearchService::new(http, false));
+
+        let response = rt
+            .block_on(service.call(SharedRequest::new(REQUEST.to_vec())))
+            .unwrap();
+        assert!(response.status().is_success());
+
+        let requests = rt.block_on(rx.take(3).collect()).unwrap();
+        assert_eq!(requests[0], REQUEST.to_vec());
+        assert_eq!(
+            requests[1],
+            b"{\"delete\":{\"_index\":\"a\",\"_id\":\"1\"}}\n\
+              {\"index\":{\"_index\":\"a\"}}\n{\"message\":\"three\"}\n"
+                .to_vec()
+        );
+        assert_eq!(
+            requests[2],
+            b"{\"index\":{\"_index\":\"a\"}}\n{\"message\":\"three\"}\n".to_vec()
+        );
+    }
+}
@@ -1,16 +1,20 @@
+mod bulk;
+
+use self::bulk::{ElasticSearchRetryLogic, ElasticSearchService};
 use crate::{
     dns::Resolver,
-    event::Event,
+    event::{self, Event},
     sinks::util::{
         encoding::{skip_serializing_if_default, EncodingConfigWithDefault, EncodingConfiguration},
-        http::{https_client, HttpRetryLogic, HttpService},
-        BatchBytesConfig, Buffer, Compression, SinkExt, TowerRequestConfig,
+        finalize_dropped,
+        http::{https_client, HttpService},
+        BatchBytesConfig, Buffer, Compression, SharedBatch, SinkExt, TowerRequestConfig,
     },
     template::Template,
     tls::{TlsOptions, TlsSettings},
     topology::config::{DataType, SinkConfig, SinkContext, SinkDescription},
 };
-use futures01::{stream::iter_ok, Future, Sink};
+use futures01::{Future, Sink};
 use http::{uri::InvalidUri, Method, Uri};
 use hyper::{
     header::{HeaderName, HeaderValue},
@@ -32,6 +36,12 @@ pub struct ElasticSearchConfig {
     pub index: Option<String>,
     pub doc_type: Option<String>,
     pub id_key: Option<String>,
+    pub bulk_action: Option<String>,
+    pub pipeline: Option<String>,
+    pub routing_key: Option<String>,
+    #[serde(default)]
+    pub mode: ElasticSearchMode,
+    pub data_stream: Option<DataStreamConfig>,
     pub compression: Option<Compression>,
     #[serde(skip_serializing_if = "skip_serializing_if_default", default)]
     pub encoding: EncodingConfigWithDefault<Encoding>,
@@ -61,6 +71,64 @@ pub enum Encoding {
     Default,
 }
 
+#[derive(Deserialize, Serialize, Debug, Eq, PartialEq, Clone, Copy, Derivative)]
+#[serde(rename_all = "snake_case")]
+#[derivative(Default)]
+pub enum ElasticSearchMode {
+    #[derivative(Default)]
+    Normal,
+    /// Events are created in the data stream `<type>-<dataset>-<namespace>`.
+    DataStream,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields, default)]
+pub struct DataStreamConfig {
+    #[serde(rename = "type")]
+    pub dtype: String,
+    pub dataset: String,
+    pub namespace: String,
+}
+
+impl Default for DataStreamConfig {
+    fn default() -> Self {
+        Self {
+            dtype: "logs".into(),
+            dataset: "generic".into(),
+            namespace: "default".into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum BulkAction {
+    Index,
+    Create,
+    Update,
+    Delete,
+}
+
+impl BulkAction {
+    fn parse(action: &str) -> Option<Self> {
+        match action {
+            "index" => Some(BulkAction::Index),
+            "create" => Some(BulkAction::Create),
+            "update" => Some(BulkAction::Update),
+            "delete" => Some(BulkAction::Delete),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            BulkAction::Index => "index",
+            BulkAction::Create => "create",
+            BulkAction::Update => "update",
+            BulkAction::Delete => "delete",
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize, Clone, Debug)]
 #[serde(deny_unknown_fields, rename_all = "snake_case", tag = "strategy")]
 pub enum ElasticSearchAuth {
@@ -87,7 +155,7 @@ impl SinkConfig for ElasticSearchConfig {
     fn build(&self, cx: SinkContext) -> crate::Result<(super::RouterSink, super::Healthcheck)> {
         let common = ElasticSearchCommon::parse_config(&self)?;
         let healthcheck = healthcheck(cx.resolver(), &common)?;
-        let sink = es(self, common, cx);
+        let sink = es(self, common, cx)?;
 
         Ok((sink, healthcheck))
     }
@@ -119,6 +187,10 @@ enum ParseError {
     AWSCredentialsProviderFailed { source: CredentialsError },
     #[snafu(display("Could not generate AWS credentials: {:?}", source))]
     AWSCredentialsGenerateFailed { source: CredentialsError },
+    #[snafu(display("Invalid bulk action {:?}", action))]
+    InvalidBulkAction { action: String },
+    #[snafu(display("Data streams only support the \"create\" bulk action"))]
+    DataStreamBulkAction,
 }
 
 impl ElasticSearchCommon {
@@ -187,23 +259,15 @@ fn es(
     config: &ElasticSearchConfig,
     common: ElasticSearchCommon,
     cx: SinkContext,
-) -> super::RouterSink {
-    let id_key = config.id_key.clone();
+) -> crate::Result<super::RouterSink> {
+    let encoder = EventEncoder::new(config)?;
     let mut gzip = match config.compression.unwrap_or(Compression::Gzip) {
         Compression::None => false,
         Compression::Gzip => true,
     };
-    let encoding = config.encoding.clone();
     let batch = config.batch.unwrap_or(bytesize::mib(10u64), 1);
     let request = config.request.unwrap_with(&REQUEST_DEFAULTS);
 
-    let index = if let Some(idx) = &config.index {
-        Template::from(idx.as_str())
-    } else {
-        Template::from("vector-%Y.%m.%d")
-    };
-    let doc_type = config.doc_type.clone().unwrap_or("_doc".into());
-
     let headers = config
         .headers
         .as_ref()
@@ -268,24 +332,164 @@ fn es(
             }
         });
 
+    let service = ElasticSearchService::new(http_service, gzip);
+
     let sink = request
-        .batch_sink(HttpRetryLogic, http_service, cx.acker())
-        .batched_with_min(Buffer::new(gzip), &batch)
-        .with_flat_map(move |e| iter_ok(encode_event(e, &index, &doc_type, &id_key, &encoding)));
+        .batch_sink(ElasticSearchRetryLogic, service, cx.acker())
+        .batched_with_min(SharedBatch::new(Buffer::new(gzip)), &batch)
+        .with_flat_map(finalize_dropped(cx.acker(), move |e| {
+            encoder.encode_event(e)
+        }));
 
-    Box::new(sink)
+    Ok(Box::new(sink))
 }
 
-fn encode_event(
-    mut event: Event,
-    index: &Template,
-    doc_type: &str,
-    id_key: &Option<String>,
-    encoding: &EncodingConfigWithDefault<Encoding>,
-) -> Option<Vec<u8>> {
-    encoding.apply_rules(&mut event);
-    let index = index
-        .render_string(&event)
+struct DataStreamTemplates {
+    dtype: Template,
+    dataset: Template,
+    namespace: Template,
+}
+
+/// Encodes events into bulk actions.
+struct EventEncoder {
+    index: Template,
+    doc_type: String,
+    id_key: Option<String>,
+    routing_key: Option<String>,
+    bulk_action: Option<Template>,
+    pipeline: Option<Template>,
+    data_stream: Option<DataStreamTemplates>,
+    encoding: EncodingConfigWithDefault<Encoding>,
+}
+
+impl EventEncoder {
+    fn new(config: &ElasticSearchConfig) -> crate::Result<Self> {
+        let bulk_action = config.bulk_action.as_ref().map(|action| {
+            let template = Template::from(action.as_str());
+            if !template.is_dynamic() && BulkAction::parse(action).is_none() {
+                return Err(ParseError::InvalidBulkAction {
+                    action: action.clone(),
+                });
+            }
+            Ok(template)
+        });
+        let bulk_action = bulk_action.transpose()?;
+
+        let data_stream = match config.mode {
+            ElasticSearchMode::Normal => None,
+            ElasticSearchMode::DataStream => {
+                if config.bulk_action.as_ref().map_or(false, |a| a != "create") {
+                    return Err(ParseError::DataStreamBulkAction.into());
+                }
+                let data_stream = config.data_stream.clone().unwrap_or_default();
+                Some(DataStreamTemplates {
+                    dtype: Template::from(data_stream.dtype),
+                    dataset: Template::from(data_stream.dataset),
+                    namespace: Template::from(data_stream.namespace),
+                })
+            }
+        };
+
+        Ok(Self {
+            index: Template::from(config.index.as_deref().unwrap_or("vector-%Y.%m.%d")),
+            doc_type: config.doc_type.clone().unwrap_or_else(|| "_doc".into()),
+            id_key: config.id_key.clone(),
+            routing_key: config.routing_key.clone(),
+            bulk_action,
+            pipeline: config.pipeline.as_deref().map(Template::from),
+            data_stream,
+            encoding: config.encoding.clone(),
+        })
+    }
+
+    fn encode_event(&self, mut event: Event) -> Option<Vec<u8>> {
+        self.encoding.apply_rules(&mut event);
+
+        let (index, action) = match &self.data_stream {
+            Some(data_stream) => {
+                let dtype = render(&data_stream.dtype, &event)?;
+                let dataset = render(&data_stream.dataset, &event)?;
+                let namespace = render(&data_stream.namespace, &event)?;
+                let index = format!("{}-{}-{}", dtype, dataset, namespace);
+                prepare_data_stream_event(&mut event, dtype, dataset, namespace);
+                (index, BulkAction::Create)
+            }
+            None => {
+                let index = render(&self.index, &event)?;
+                let action = match &self.bulk_action {
+                    Some(template) => {
+                        let action = render(template, &event)?;
+                        match BulkAction::parse(&action) {
+                            Some(action) => action,
+                            None => {
+                                warn!(
+                                    message = "Invalid bulk action; Dropping event.",
+                                    %action,
+                                    rate_limit_secs = 30,
+                                );
+                                return None;
+                            }
+                        }
+                    }
+                    None => BulkAction::Index,
+                };
+                (index, action)
+            }
+        };
+
+        let mut metadata = json!({ "_index": index });
+        // Data streams are only supported by versions without mapping types.
+        if self.data_stream.is_none() {
+            metadata["_type"] = json!(self.doc_type);
+        }
+        maybe_set_id(self.id_key.as_ref(), &mut metadata, &event);
+        maybe_set_field(self.routing_key.as_ref(), "routing", &mut metadata, &event);
+        // Only indexed documents go through ingest pipelines.
+        match (&self.pipeline, action) {
+            (Some(pipeline), BulkAction::Index) | (Some(pipeline), BulkAction::Create) => {
+                match pipeline.render_string(&event) {
+                    Ok(pipeline) if !pipeline.is_empty() => metadata["pipeline"] = json!(pipeline),
+                    Ok(_) => (),
+                    Err(missing_keys) => warn!(
+                        message = "Keys do not exist on the event; Sending it without a pipeline.",
+                        ?missing_keys,
+                        rate_limit_secs = 30,
+                    ),
+                }
+            }
+            _ => (),
+        }
+
+        if action == BulkAction::Delete && metadata.get("_id").is_none() {
+            warn!(
+                message = "Delete action requires an id; Dropping event.",
+                rate_limit_secs = 30,
+            );
+            return None;
+        }
+
+        let mut body = serde_json::to_vec(&json!({ action.as_str(): metadata })).unwrap();
+        body.push(b'\n');
+
+        match action {
+            BulkAction::Delete => return Some(body),
+            BulkAction::Update => serde_json::to_writer(
+                &mut body,
+                &json!({ "doc": event.into_log(), "doc_as_upsert": true }),
+            ),
+            BulkAction::Index | BulkAction::Create => {
+                serde_json::to_writer(&mut body, &event.into_log())
+            }
+        }
+        .unwrap();
+        body.push(b'\n');
+        Some(body)
+    }
+}
+
+fn render(template: &Template, event: &Event) -> Option<String> {
+    template
+        .render_string(event)
         .map_err(|missing_keys| {
             warn!(
                 message = "Keys do not exist on the event; Dropping event.",
@@ -293,26 +497,21 @@ fn encode_event(
                 rate_limit_secs = 30,
             );
         })
-        .ok()?;
+        .ok()
+}
 
-    let mut action = json!({
-        "index": {
-            "_index": index,
-            "_type": doc_type,
+/// Data streams require an `@timestamp` field, and the `data_stream` fields
+/// are expected to match the name of the stream.
+fn prepare_data_stream_event(event: &mut Event, dtype: String, dataset: String, namespace: String) {
+    let log = event.as_mut_log();
+    if log.get(&"@timestamp".into()).is_none() {
+        if let Some(timestamp) = log.remove(&event::log_schema().timestamp_key()) {
+            log.insert("@timestamp", timestamp);
         }
-    });
-    maybe_set_id(
-        id_key.as_ref(),
-        action.pointer_mut("/index").unwrap(),
-        &event,
-    );
-
-    let mut body = serde_json::to_vec(&action).unwrap();
-    body.push(b'\n');
-
-    serde_json::to_writer(&mut body, &event.into_log()).unwrap();
-    body.push(b'\n');
-    Some(body)
+    }
+    log.insert("data_stream.type", dtype);
+    log.insert("data_stream.dataset", dataset);
+    log.insert("data_stream.namespace", namespace);
 }
 
 fn healthcheck(
@@ -372,12 +571,19 @@ fn finish_signer(
 }
 
 fn maybe_set_id(key: Option<impl AsRef<str>>, doc: &mut serde_json::Value, event: &Event) {
+    maybe_set_field(key, "_id", doc, event)
+}
+
+fn maybe_set_field(
+    key: Option<impl AsRef<str>>,
+    name: &str,
+    doc: &mut serde_json::Value,
+    event: &Event,
+) {
     if let Some(val) = key.and_then(|k| event.as_log().get(&k.as_ref().into())) {
         let val = val.to_string_lossy();
 
-        doc.as_object_mut()
-            .unwrap()
-            .insert("_id".into(), json!(val));
+        doc.as_object_mut().unwrap().insert(name.into(), json!(val));
     }
 }
 
@@ -422,6 +628,151 @@ mod tests {
 
         assert_eq!(json!({}), action);
     }
+
+    fn encode(config: ElasticSearchConfig, event: Event) -> Option<Vec<serde_json::Value>> {
+        let encoder = EventEncoder::new(&config).unwrap();
+        encoder.encode_event(event).map(|body| {
+            serde_json::Deserializer::from_slice(&body)
+                .into_iter::<serde_json::Value>()
+                .map(Result::unwrap)
+                .collect()
+        })
+    }
+
+    #[test]
+    fn encodes_index_action_by_default() {
+        let config = ElasticSearchConfig {
+            index: Some("vector".into()),
+            ..Default::default()
+        };
+        let lines = encode(config, Event::from("hello")).unwrap();
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0],
+            json!({"index": {"_index": "vector", "_type": "_doc"}})
+        );
+        assert_eq!(lines[1]["message"], json!("hello"));
+    }
+
+    #[test]
+    fn encodes_templated_bulk_actions() {
+        let config = ElasticSearchConfig {
+            index: Some("vector".into()),
+            bulk_action: Some("{{ action }}".into()),
+            id_key: Some("id".into()),
+            routing_key: Some("user".into()),
+            pipeline: Some("{{ pipeline }}".into()),
+            ..Default::default()
+        };
+
+        let mut event = Event::from("hello");
+        event.as_mut_log().insert("action", "create");
+        event.as_mut_log().insert("id", "42");
+        event.as_mut_log().insert("user", "alice");
+        event.as_mut_log().insert("pipeline", "geoip");
+        let lines = encode(config.clone(), event).unwrap();
+        assert_eq!(
+            lines[0],
+            json!({"create": {
+                "_index": "vector",
+                "_type": "_doc",
+                "_id": "42",
+                "routing": "alice",
+                "pipeline": "geoip",
+            }})
+        );
+        assert_eq!(lines[1]["message"], json!("hello"));
+
+        // Updates don't go through pipelines.
+        let mut event = Event::from("hello");
+        event.as_mut_log().insert("action", "update");
+        event.as_mut_log().insert("id", "42");
+        event.as_mut_log().insert("pipeline", "geoip");
+        let lines = encode(config.clone(), event).unwrap();
+        assert_eq!(
+            lines[0],
+            json!({"update": {"_index": "vector", "_type": "_doc", "_id": "42"}})
+        );
+        assert_eq!(lines[1]["doc"]["message"], json!("hello"));
+        assert_eq!(lines[1]["doc_as_upsert"], json!(true));
+
+        let mut event = Event::from("hello");
+        event.as_mut_log().insert("action", "delete");
+        event.as_mut_log().insert("id", "42");
+        let lines = encode(config.clone(), event).unwrap();
+        assert_eq!(
+            lines,
+            vec![json!({"delete": {"_index": "vector", "_type": "_doc", "_id": "42"}})]
+        );
+
+        // Events without a pipeline are indexed without one.
+        let mut event = Event::from("hello");
+        event.as_mut_log().insert("action", "index");
+        let lines = encode(config.clone(), event).unwrap();
+        assert_eq!(
+            lines[0],
+            json!({"index": {"_index": "vector", "_type": "_doc"}})
+        );
+
+        // Deleting requires an id, and the action must be valid.
+        let mut event = Event::from("hello");
+        event.as_mut_log().insert("action", "delete");
+        assert!(encode(config.clone(), event).is_none());
+
+        let mut event = Event::from("hello");
+        event.as_mut_log().insert("action", "upsert");
+        assert!(encode(config, event).is_none());
+    }
+
+    #[test]
+    fn encodes_data_stream_events() {
+        let config = ElasticSearchConfig {
+            mode: ElasticSearchMode::DataStream,
+            data_stream: Some(DataStreamConfig {
+                dataset: "{{ service }}".into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let mut event = Event::from("hello");
+        event.as_mut_log().insert("service", "nginx");
+        let timestamp = event.as_log()[&event::log_schema().timestamp_key()].clone();
+        let lines = encode(config, event).unwrap();
+
+        assert_eq!(
+            lines[0],
+            json!({"create": {"_index": "logs-nginx-default"}})
+        );
+        assert_eq!(lines[1]["@timestamp"], json!(timestamp));
+        assert!(lines[1].get("timestamp").is_none());
+        assert_eq!(
+            lines[1]["data_stream"],
+            json!({"type": "logs", "dataset": "nginx", "namespace": "default"})
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_bulk_actions() {
+        assert!(EventEncoder::new(&ElasticSearchConfig {
+            bulk_action: Some("upsert".into()),
+            ..Default::default()
+        })
+        .is_err());
+        assert!(EventEncoder::new(&ElasticSearchConfig {
+            mode: ElasticSearchMode::DataStream,
+            bulk_action: Some("index".into()),
+            ..Default::default()
+        })
+        .is_err());
+        assert!(EventEncoder::new(&ElasticSearchConfig {
+            mode: ElasticSearchMode::DataStream,
+            bulk_action: Some("create".into()),
+            ..Default::default()
+        })
+        .is_ok());
+    }
 }
 
 #[cfg(test)]
@@ -1,13 +1,16 @@
 use super::{healthcheck_response, GcpAuthConfig, GcpCredentials, Scope};
 use crate::{
-    event::{self, Event},
+    event::{self, Event, LogEvent},
     sinks::{
         util::{
+            buffer::columnar::{ColumnarBuffer, ColumnarFormat, SchemaConfig},
             encoding::{EncodingConfig, EncodingConfiguration},
+            finalize_dropped,
             http::{https_client, HttpsClient},
+            multipart::{MultipartConfig, MultipartSink, MultipartUploader, Part, UploadFuture},
             retries::{RetryAction, RetryLogic},
             BatchBytesConfig, Buffer, PartitionBuffer, PartitionInnerBuffer, ServiceBuilderExt,
-            SinkExt, TowerRequestConfig,
+            SinkExt, TowerRequestConfig, TowerRequestSettings,
         },
         Healthcheck, RouterSink,
     },
@@ -17,8 +20,8 @@ use crate::{
 };
 use bytes::Bytes;
 use chrono::Utc;
-use futures01::{stream::iter_ok, Future, Poll, Sink};
-use http::{Method, StatusCode, Uri};
+use futures01::{Future, Poll, Sink};
+use http::{HeaderMap, Method, StatusCode, Uri};
 use hyper::{
     header::{HeaderName, HeaderValue},
     Body, Request,
@@ -33,6 +36,8 @@ use uuid::Uuid;
 
 const NAME: &str = "gcp_cloud_storage";
 const BASE_URL: &str = "https://storage.googleapis.com/";
+// Resumable uploads must be sent in chunks of a multiple of this size.
+const CHUNK_SIZE: usize = 256 * 1024;
 
 #[derive(Clone)]
 struct GcsSink {
@@ -49,6 +54,14 @@ enum GcsError {
     BucketNotFound { bucket: String },
 }
 
+#[derive(Debug, Snafu)]
+enum BuildError {
+    #[snafu(display("Multipart uploads are not supported with the {:?} encoding", encoding))]
+    MultipartColumnar { encoding: Encoding },
+    #[snafu(display("Multipart upload parts must be a multiple of {} bytes", multiple))]
+    InvalidPartSize { multiple: usize },
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct GcsSinkConfig {
@@ -61,6 +74,7 @@ pub struct GcsSinkConfig {
     filename_append_uuid: Option<bool>,
     filename_extension: Option<String>,
     encoding: EncodingConfig<Encoding>,
+    schema: Option<SchemaConfig>,
     compression: Compression,
     #[serde(default)]
     batch: BatchBytesConfig,
@@ -69,6 +83,7 @@ pub struct GcsSinkConfig {
     #[serde(flatten)]
     auth: GcpAuthConfig,
     tls: Option<TlsOptions>,
+    multipart_upload: Option<MultipartConfig>,
 }
 
 #[cfg(test)]
@@ -83,11 +98,13 @@ fn default_config(e: Encoding) -> GcsSinkConfig {
         filename_append_uuid: Default::default(),
         filename_extension: Default::default(),
         encoding: e.into(),
+        schema: Default::default(),
         compression: Default::default(),
         batch: Default::default(),
         request: Default::default(),
         auth: Default::default(),
         tls: Default::default(),
+        multipart_upload: Default::default(),
     }
 }
 
@@ -128,6 +145,8 @@ lazy_static! {
 enum Encoding {
     Text,
     Ndjson,
+    Parquet,
+    Avro,
 }
 
 impl Encoding {
@@ -135,6 +154,15 @@ impl Encoding {
         match self {
             Self::Text => "text/plain",
             Self::Ndjson => "application/x-ndjson",
+            Self::Parquet | Self::Avro => self.columnar_format().unwrap().content_type(),
+        }
+    }
+
+    fn columnar_format(&self) -> Option<ColumnarFormat> {
+        match self {
+            Self::Parquet => Some(ColumnarFormat::Parquet),
+            Self::Avro => Some(ColumnarFormat::Avro),
+            Self::Text | Self::Ndjson => None,
         }
     }
 }
@@ -221,7 +249,6 @@ impl GcsSink {
             Compression::Gzip => true,
             Compression::None => false,
         };
-        let batch = config.batch.unwrap_or(bytesize::mib(10u64), 300);
 
         let key_prefix = if let Some(kp) = &config.key_prefix {
             Template::from(kp.as_str())
@@ -229,6 +256,38 @@ impl GcsSink {
             Template::from("date=%F/")
         };
 
+        if let Some(multipart) = &config.multipart_upload {
+            if encoding.codec.columnar_format().is_some() {
+                return Err(BuildError::MultipartColumnar {
+                    encoding: encoding.codec,
+                }
+                .into());
+            }
+            let part_size = multipart.part_size();
+            if part_size == 0 || part_size % CHUNK_SIZE != 0 {
+                return Err(BuildError::InvalidPartSize {
+                    multiple: CHUNK_SIZE,
+                }
+                .into());
+            }
+
+            let batch = config.batch.unwrap_or(bytesize::mib(100u64), 300);
+            let uploader = GcsUploader {
+                client: self.client,
+                creds: self.creds,
+                base_url: self.base_url,
+                settings: self.settings,
+                request,
+            };
+
+            let sink = MultipartSink::new(uploader, part_size, &batch, compression, cx.acker())
+                .with_flat_map(finalize_dropped(cx.acker(), move |e| {
+                    encode_event(e, &key_prefix, &encoding)
+                }));
+            return Ok(Box::new(sink));
+        }
+
+        let batch = config.batch.unwrap_or(bytesize::mib(10u64), 300);
         let settings = self.settings.clone();
 
         let svc = ServiceBuilder::new()
@@ -236,11 +295,30 @@ impl GcsSink {
             .settings(request, GcsRetryLogic)
             .service(self);
 
-        let sink = crate::sinks::util::BatchServiceSink::new(svc, cx.acker())
-            .partitioned_batched_with_min(PartitionBuffer::new(Buffer::new(compression)), &batch)
-            .with_flat_map(move |e| iter_ok(encode_event(e, &key_prefix, &encoding)));
+        let sink = crate::sinks::util::BatchServiceSink::new(svc, cx.acker());
+        let sink: RouterSink = match encoding.codec.columnar_format() {
+            Some(format) => {
+                let schema = config.schema.clone().map(Into::into);
+                let buffer = ColumnarBuffer::new(format, schema, compression);
+                Box::new(
+                    sink.partitioned_batched_with_min(PartitionBuffer::new(buffer), &batch)
+                        .with_flat_map(finalize_dropped(cx.acker(), move |e| {
+                            partition_event(e, &key_prefix, &encoding)
+                        })),
+                )
+            }
+            None => Box::new(
+                sink.partitioned_batched_with_min(
+                    PartitionBuffer::new(Buffer::new(compression)),
+                    &batch,
+                )
+                .with_flat_map(finalize_dropped(cx.acker(), move |e| {
+                    encode_event(e, &key_prefix, &encoding)
+                })),
+            ),
+        };
 
-        Ok(Box::new(sink))
+        Ok(sink)
     }
 
     fn healthcheck(&self) -> crate::Result<Healthcheck> {
@@ -288,19 +366,11 @@ impl Service<RequestWrapper> for GcsSink {
         builder.method(Method::PUT);
         builder.uri(uri);
         let headers = builder.headers_mut().unwrap();
-        headers.insert("content-type", settings.content_type);
+        settings.insert_headers(headers);
         headers.insert(
             "content-length",
             HeaderValue::from_str(&format!("{}", request.body.len())).unwrap(),
         );
-        settings
-            .content_encoding
-            .map(|ce| headers.insert("content-encoding", ce));
-        headers.insert("x-goog-acl", settings.acl);
-        headers.insert("x-goog-storage-class", settings.storage_class);
-        for (p, v) in settings.metadata {
-            headers.insert(p, v);
-        }
 
         let mut request = builder.body(Body::from(request.body)).unwrap();
         if let Some(creds) = &self.creds {
@@ -326,25 +396,7 @@ struct RequestWrapper {
 impl RequestWrapper {
     fn new(req: PartitionInnerBuffer<Vec<u8>, Bytes>, settings: RequestSettings) -> Self {
         let (body, key) = req.into_parts();
-
-        // TODO: pull the seconds from the last event
-        let filename = {
-            let seconds = Utc::now().format(&settings.time_format);
-
-            if settings.append_uuid {
-                let uuid = Uuid::new_v4();
-                format!("{}-{}", seconds, uuid.to_hyphenated())
-            } else {
-                seconds.to_string()
-            }
-        };
-
-        let key = format!(
-            "{}{}.{}",
-            String::from_utf8_lossy(&key[..]),
-            filename,
-            settings.extension
-        );
+        let key = object_key(&key, &settings);
 
         debug!(
             message = "sending events.",
@@ -360,6 +412,27 @@ impl RequestWrapper {
     }
 }
 
+fn object_key(prefix: &[u8], settings: &RequestSettings) -> String {
+    // TODO: pull the seconds from the last event
+    let filename = {
+        let seconds = Utc::now().format(&settings.time_format);
+
+        if settings.append_uuid {
+            let uuid = Uuid::new_v4();
+            format!("{}-{}", seconds, uuid.to_hyphenated())
+        } else {
+            seconds.to_string()
+        }
+    };
+
+    format!(
+        "{}{}.{}",
+        String::from_utf8_lossy(prefix),
+        filename,
+        settings.extension
+    )
+}
+
 // Settings required to produce a request that do not change per
 // request. All possible values are pre-computed for direct use in
 // producing a request.
@@ -380,9 +453,13 @@ impl RequestSettings {
         let acl = config.acl.unwrap_or(GcsPredefinedAcl::default());
         let acl = HeaderValue::from_str(&to_string(acl)).unwrap();
         let content_type = HeaderValue::from_str(config.encoding.codec.content_type()).unwrap();
+        let format = config.encoding.codec.columnar_format();
+        // Columnar files compress their contents internally, so they are
+        // sent without a content encoding.
         let content_encoding = config
             .compression
             .content_encoding()
+            .filter(|_| format.is_none())
             .map(|ce| HeaderValue::from_str(&to_string(ce)).unwrap());
         let storage_class = config.storage_class.unwrap_or(GcsStorageClass::default());
         let storage_class = HeaderValue::from_str(&to_string(storage_class)).unwrap();
@@ -396,10 +473,12 @@ impl RequestSettings {
                     .collect::<Result<Vec<_>, _>>()
             })
             .unwrap_or(Ok(vec![]))?;
-        let extension = config
-            .filename_extension
-            .clone()
-            .unwrap_or_else(|| config.compression.extension().into());
+        let extension = config.filename_extension.clone().unwrap_or_else(|| {
+            format
+                .map(ColumnarFormat::extension)
+                .unwrap_or_else(|| config.compression.extension())
+                .into()
+        });
         let time_format = config.filename_time_format.clone().unwrap_or("%s".into());
         let append_uuid = config.filename_append_uuid.unwrap_or(true);
         Ok(Self {
@@ -413,6 +492,18 @@ impl RequestSettings {
             append_uuid,
         })
     }
+
+    fn insert_headers(&self, headers: &mut HeaderMap) {
+        headers.insert("content-type", self.content_type.clone());
+        if let Some(ce) = &self.content_encoding {
+            headers.insert("content-encoding", ce.clone());
+        }
+        headers.insert("x-goog-acl", self.acl.clone());
+        headers.insert("x-goog-storage-class", self.storage_class.clone());
+        for (p, v) in &self.metadata {
+            headers.insert(p, v.clone());
+        }
+    }
 }
 
 // Make a header pair from a key-value string pair
@@ -423,11 +514,11 @@ fn make_header((name, value): (&String, &String)) -> crate::Result<(HeaderName,
     ))
 }
 
-fn encode_event(
+fn partition_event(
     mut event: Event,
     key_prefix: &Template,
     encoding: &EncodingConfig<Encoding>,
-) -> Option<PartitionInnerBuffer<Vec<u8>, Bytes>> {
+) -> Option<PartitionInnerBuffer<LogEvent, Bytes>> {
     encoding.apply_rules(&mut event);
     let key = key_prefix
         .render_string(&event)
@@ -439,7 +530,16 @@ fn encode_event(
             );
         })
         .ok()?;
-    let log = event.into_log();
+
+    Some(PartitionInnerBuffer::new(event.into_log(), key.into()))
+}
+
+fn encode_event(
+    event: Event,
+    key_prefix: &Template,
+    encoding: &EncodingConfig<Encoding>,
+) -> Option<PartitionInnerBuffer<Vec<u8>, Bytes>> {
+    let (log, key) = partition_event(event, key_prefix, encoding)?.into_parts();
     let bytes = match encoding.codec {
         Encoding::Ndjson => serde_json::to_vec(&log)
             .map(|mut b| {
@@ -455,9 +555,12 @@ fn encode_event(
             bytes.push(b'\n');
             bytes
         }
+        Encoding::Parquet | Encoding::Avro => {
+            unreachable!("Columnar encodings are written by `ColumnarBuffer`")
+        }
     };
 
-    Some(PartitionInnerBuffer::new(bytes, key.into()))
+    Some(PartitionInnerBuffer::new(bytes, key))
 }
 
 #[derive(Clone)]
@@ -487,6 +590,184 @@ impl RetryLogic for GcsRetryLogic {
     }
 }
 
+#[derive(Debug, Snafu)]
+enum UploadError {
+    #[snafu(display("Request failed: {}", source))]
+    Http { source: hyper::Error },
+    #[snafu(display("Unexpected response status: {}", status))]
+    Status { status: StatusCode },
+    #[snafu(display("No session URI returned for {:?}", key))]
+    MissingSession { key: String },
+}
+
+#[derive(Clone)]
+struct UploadRetryLogic;
+
+impl RetryLogic for UploadRetryLogic {
+    type Error = UploadError;
+    type Response = ();
+
+    fn is_retriable_error(&self, error: &Self::Error) -> bool {
+        match error {
+            UploadError::Http { source } => GcsRetryLogic.is_retriable_error(source),
+            UploadError::Status { status } => {
+                *status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+            }
+            UploadError::MissingSession { .. } => false,
+        }
+    }
+}
+
+/// Streams objects to GCS with the XML API's resumable uploads, sending
+/// each part as a chunk of the upload session.
+struct GcsUploader {
+    client: HttpsClient,
+    creds: Option<GcpCredentials>,
+    base_url: String,
+    settings: RequestSettings,
+    request: TowerRequestSettings,
+}
+
+#[derive(Clone, Debug)]
+struct GcsUpload {
+    session: Uri,
+}
+
+impl GcsUploader {
+    fn send<F>(
+        &self,
+        build: F,
+        accept: fn(StatusCode) -> bool,
+    ) -> impl Future<Item = hyper::Response<Body>, Error = crate::Error>
+    where
+        F: Fn() -> Request<Body>,
+    {
+        let client = self.client.clone();
+        let creds = self.creds.clone();
+        self.request
+            .retry_policy(UploadRetryLogic)
+            .retry_request(move || {
+                let mut request = build();
+                if let Some(creds) = &creds {
+                    creds.apply(&mut request);
+                }
+                client
+                    .request(request)
+                    .map_err(|source| UploadError::Http { source })
+                    .and_then(move |response| match response.status() {
+                        status if accept(status) => Ok(response),
+                        status => Err(UploadError::Status { status }),
+                    })
+            })
+            .map_err(Into::into)
+    }
+
+    fn put(&self, upload: &GcsUpload, range: String, body: Vec<u8>) -> UploadFuture<()> {
+        let session = upload.session.clone();
+        let body = Bytes::from(body);
+        let build = move || {
+            let mut builder = Request::builder();
+            builder.method(Method::PUT);
+            builder.uri(session.clone());
+            builder.header("content-range", range.as_str());
+            builder.header("content-length", body.len().to_string().as_str());
+            builder.body(Body::from(body.clone())).unwrap()
+        };
+
+        // Chunks before the last one are acknowledged with a 308.
+        Box::new(
+            self.send(build, |status| {
+                status == StatusCode::PERMANENT_REDIRECT || status.is_success()
+            })
+            .map(|_| ()),
+        )
+    }
+}
+
+// The `Content-Range` of a chunk, where the final chunk also sets the size
+// of the whole object.
+fn content_range(part: &Part, last: bool) -> String {
+    let end = part.offset + part.body.len();
+    let total = if last {
+        end.to_string()
+    } else {
+        "*".to_string()
+    };
+    if part.body.is_empty() {
+        format!("bytes */{}", total)
+    } else {
+        format!("bytes {}-{}/{}", part.offset, end - 1, total)
+    }
+}
+
+impl MultipartUploader for GcsUploader {
+    type Upload = GcsUpload;
+
+    fn create(&self, prefix: Bytes) -> UploadFuture<GcsUpload> {
+        let key = object_key(&prefix, &self.settings);
+        debug!(
+            message = "starting resumable upload.",
+            key = &field::debug(&key)
+        );
+
+        let uri = format!("{}{}", self.base_url, key).parse::<Uri>().unwrap();
+        let settings = self.settings.clone();
+        let build = move || {
+            let mut builder = Request::builder();
+            builder.method(Method::POST);
+            builder.uri(uri.clone());
+            let headers = builder.headers_mut().unwrap();
+            settings.insert_headers(headers);
+            headers.insert("x-goog-resumable", HeaderValue::from_static("start"));
+            headers.insert("content-length", HeaderValue::from_static("0"));
+            builder.body(Body::empty()).unwrap()
+        };
+
+        let fut = self
+            .send(build, |status| status == StatusCode::CREATED)
+            .and_then(move |response| {
+                response
+                    .headers()
+                    .get("location")
+                    .and_then(|location| location.to_str().ok())
+                    .and_then(|location| location.parse::<Uri>().ok())
+                    .map(|session| GcsUpload { session })
+                    .ok_or_else(|| UploadError::MissingSession { key }.into())
+            });
+
+        Box::new(fut)
+    }
+
+    fn upload_part(&self, upload: GcsUpload, part: Part) -> UploadFuture<GcsUpload> {
+        let range = content_range(&part, false);
+        Box::new(self.put(&upload, range, part.body).map(move |()| upload))
+    }
+
+    fn complete(&self, upload: GcsUpload, part: Part) -> UploadFuture<()> {
+        let range = content_range(&part, true);
+        self.put(&upload, range, part.body)
+    }
+
+    fn abort(&self, upload: GcsUpload) -> UploadFuture<()> {
+        let session = upload.session;
+        let build = move || {
+            let mut builder = Request::builder();
+            builder.method(Method::DELETE);
+            builder.uri(session.clone());
+            builder.header("content-length", "0");
+            builder.body(Body::empty()).unwrap()
+        };
+
+        // A cancelled session is acknowledged with a 499.
+        Box::new(
+            self.send(build, |status| {
+                status.as_u16() == 499 || status.is_success()
+            })
+            .map(|_| ()),
+        )
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -570,4 +851,35 @@ mod tests {
         let req = RequestWrapper::new(buf.clone(), request_settings(None, true, Compression::Gzip));
         assert_ne!(req.key, "key/date.log.gz".to_string());
     }
+    #[test]
+    fn gcs_columnar_request_settings() {
+        let settings = RequestSettings::new(&GcsSinkConfig {
+            compression: Compression::Gzip,
+            ..default_config(Encoding::Parquet)
+        })
+        .expect("Could not create request settings");
+
+        assert_eq!(settings.extension, "parquet");
+        assert_eq!(settings.content_encoding, None);
+        assert_eq!(settings.content_type, "application/octet-stream");
+
+        let settings = RequestSettings::new(&default_config(Encoding::Avro))
+            .expect("Could not create request settings");
+        assert_eq!(settings.extension, "avro");
+        assert_eq!(settings.content_type, "avro/binary");
+    }
+
+    #[test]
+    fn gcs_multipart_content_range() {
+        let part = |offset, len| Part {
+            number: 1,
+            offset,
+            body: vec![0; len],
+        };
+
+        assert_eq!(content_range(&part(0, 10), false), "bytes 0-9/*");
+        assert_eq!(content_range(&part(10, 5), false), "bytes 10-14/*");
+        assert_eq!(content_range(&part(10, 5), true), "bytes 10-14/15");
+        assert_eq!(content_range(&part(15, 0), true), "bytes */15");
+    }
 }
@@ -1,7 +1,7 @@
 //! Loki sink
 //!
 //! This sink provides downstream support for `Loki` via
-//! the v1 http json endpoint.
+//! the v1 http push endpoint, as json or snappy compressed protobuf.
 //!
 //! https://github.com/grafana/loki/blob/master/docs/api.md
 //!
@@ -11,6 +11,10 @@
 //!
 //! If an event produces no labels, this can happen if the template
 //! does not match, we will add a default label `{agent="vector"}`.
+//!
+//! Loki rejects entries older than the latest one of their stream, so
+//! the latest timestamp sent to each stream is tracked as requests are
+//! built, and events older than it are dropped or moved forward.
 
 use crate::{
     dns::Resolver,
@@ -27,9 +31,18 @@ use crate::{
 };
 use derivative::Derivative;
 use futures::compat::Future01CompatExt;
+use prost::Message;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+mod proto {
+    include!(concat!(env!("OUT_DIR"), "/logproto.rs"));
+}
 
 type Labels = Vec<(String, String)>;
 
@@ -47,6 +60,12 @@ pub struct LokiConfig {
     #[serde(default = "crate::serde::default_true")]
     remove_timestamp: bool,
 
+    #[serde(default)]
+    out_of_order_action: OutOfOrderAction,
+    stream_limit: Option<StreamLimitConfig>,
+    #[serde(default)]
+    push_format: PushFormat,
+
     auth: Option<Auth>,
 
     #[serde(default)]
@@ -67,6 +86,37 @@ enum Encoding {
     Text,
 }
 
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Derivative)]
+#[serde(rename_all = "snake_case")]
+#[derivative(Default)]
+enum OutOfOrderAction {
+    #[derivative(Default)]
+    Drop,
+    RewriteTimestamp,
+    Accept,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Derivative)]
+#[serde(rename_all = "snake_case")]
+#[derivative(Default)]
+enum PushFormat {
+    #[derivative(Default)]
+    Json,
+    Protobuf,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct StreamLimitConfig {
+    max_streams: usize,
+    #[serde(default = "default_window_secs")]
+    window_secs: u64,
+}
+
+fn default_window_secs() -> u64 {
+    60
+}
+
 inventory::submit! {
     SinkDescription::new_without_default::<LokiConfig>("loki")
 }
@@ -77,13 +127,18 @@ impl SinkConfig for LokiConfig {
         if self.labels.is_empty() {
             return Err(format!("`labels` must include at least one label.").into());
         }
+        if let Some(limit) = &self.stream_limit {
+            if limit.max_streams == 0 {
+                return Err("`stream_limit.max_streams` must be at least 1.".into());
+            }
+        }
 
         let request_settings = self.request.unwrap_with(&TowerRequestConfig::default());
         let batch_settings = self.batch.unwrap_or(bytesize::mib(10u64), 1);
         let tls = TlsSettings::from_options(&self.tls)?;
 
         let sink = BatchedHttpSink::new(
-            self.clone(),
+            LokiSink::new(self.clone()),
             Vec::new(),
             request_settings,
             batch_settings,
@@ -105,20 +160,125 @@ impl SinkConfig for LokiConfig {
     }
 }
 
-impl HttpSink for LokiConfig {
+struct LokiSink {
+    config: LokiConfig,
+    streams: Arc<Mutex<Streams>>,
+}
+
+impl LokiSink {
+    fn new(config: LokiConfig) -> Self {
+        let window = config
+            .stream_limit
+            .as_ref()
+            .map_or_else(default_window_secs, |limit| limit.window_secs);
+        let streams = Streams::new(
+            config.stream_limit.as_ref().map(|limit| limit.max_streams),
+            Duration::from_secs(window),
+        );
+        Self {
+            config,
+            streams: Arc::new(Mutex::new(streams)),
+        }
+    }
+}
+
+/// The streams events were recently sent to, to bound their number and
+/// keep the entries of each in order.
+struct Streams {
+    max_streams: Option<usize>,
+    window: Duration,
+    window_start: Instant,
+    /// The streams seen in the current window.
+    active: HashSet<Labels>,
+    /// The latest timestamp sent to each stream.
+    latest: HashMap<Labels, i64>,
+}
+
+impl Streams {
+    fn new(max_streams: Option<usize>, window: Duration) -> Self {
+        Self {
+            max_streams,
+            window,
+            window_start: Instant::now(),
+            active: HashSet::new(),
+            latest: HashMap::new(),
+        }
+    }
+
+    /// Returns the labels of the stream to send an event to, falling back
+    /// to the catch-all stream once `max_streams` are active.
+    fn admit(&mut self, labels: Labels, now: Instant) -> Labels {
+        if now.saturating_duration_since(self.window_start) >= self.window {
+            // Streams idle for a whole window are forgotten.
+            let active = &self.active;
+            self.latest.retain(|labels, _| active.contains(labels));
+            self.active.clear();
+            self.window_start = now;
+        }
+
+        if self.active.contains(&labels) {
+            return labels;
+        }
+        match self.max_streams {
+            Some(max) if self.active.len() >= max => {
+                warn!(
+                    message = "Too many streams; sending event to the catch-all stream.",
+                    %max,
+                    rate_limit_secs = 30,
+                );
+                let labels = default_labels();
+                self.active.insert(labels.clone());
+                labels
+            }
+            _ => {
+                self.active.insert(labels.clone());
+                labels
+            }
+        }
+    }
+
+    /// Returns the timestamp to send an event of the stream with, if any.
+    /// The events of a request must be ordered by their timestamp.
+    fn order(&mut self, labels: &Labels, timestamp: i64, action: OutOfOrderAction) -> Option<i64> {
+        let latest = self.latest.entry(labels.clone()).or_insert(timestamp);
+        if timestamp >= *latest {
+            *latest = timestamp;
+            return Some(timestamp);
+        }
+
+        match action {
+            OutOfOrderAction::Drop => {
+                warn!(
+                    message = "Dropping out of order event.",
+                    rate_limit_secs = 30,
+                );
+                None
+            }
+            OutOfOrderAction::RewriteTimestamp => Some(*latest),
+            OutOfOrderAction::Accept => Some(timestamp),
+        }
+    }
+}
+
+fn default_labels() -> Labels {
+    vec![("agent".to_string(), "vector".to_string())]
+}
+
+impl HttpSink for LokiSink {
     type Input = (Labels, (i64, String));
     type Output = Vec<(Labels, (i64, String))>;
 
     fn encode_event(&self, mut event: Event) -> Option<Self::Input> {
-        self.encoding.apply_rules(&mut event);
+        let config = &self.config;
+        config.encoding.apply_rules(&mut event);
         let mut labels = Vec::new();
 
-        for (key, template) in &self.labels {
+        for (key, template) in &config.labels {
             if let Ok(value) = template.render_string(&event) {
                 labels.push((key.clone(), value));
             }
 
-            if self.remove_label_fields {
+            if config.remove_label_fields {
                 if let Some(fields) = template.get_fields() {
                     for field in fields {
                         event.as_mut_log().remove(&field);
@@ -135,13 +295,13 @@ impl HttpSink for LokiConfig {
             chrono::Utc::now().timestamp_nanos()
         };
 
-        if self.remove_timestamp {
+        if config.remove_timestamp {
             event
                 .as_mut_log()
                 .remove(&event::log_schema().timestamp_key());
         }
 
-        let event = match &self.encoding.codec {
+        let event = match &config.encoding.codec {
             Encoding::Json => serde_json::to_string(&event.as_log().all_fields())
                 .expect("json encoding should never fail"),
 
@@ -156,65 +316,61 @@ impl HttpSink for LokiConfig {
         // `{agent="vector"}` label. This can happen if the only
         // label is a templatable one but the event doesn't match.
         if labels.is_empty() {
-            labels = vec![("agent".to_string(), "vector".to_string())]
+            labels = default_labels();
         }
 
+        // We must sort here to ensure it hashes to the same stream
+        // if the label set matches.
+        labels.sort();
+
+        let labels = self.streams.lock().unwrap().admit(labels, Instant::now());
+
         Some((labels, (ts, event)))
     }
 
     fn build_request(&self, events: Self::Output) -> http::Request<Vec<u8>> {
+        let config = &self.config;
         let mut streams: HashMap<Labels, Vec<(i64, String)>> = HashMap::new();
 
-        for (mut labels, event) in events {
-            // We must sort here to ensure it hashes to the same stream
-            // if the label set matches.
-            labels.sort();
-
-            if let Some(stream) = streams.get_mut(&labels) {
-                stream.push(event);
-            } else {
-                streams.insert(labels, vec![event]);
-            }
+        for (labels, event) in events {
+            streams.entry(labels).or_default().push(event);
         }
 
-        // Construct the json body
-        let mut streams_json: Vec<serde_json::Value> = Vec::new();
-
-        for (stream, mut events) in streams {
-            // Sort by timestamp
+        let mut tracked = self.streams.lock().unwrap();
+        for (labels, events) in streams.iter_mut() {
+            // Sort by timestamp, keeping the order of equal ones, so only
+            // events older than the ones already sent are out of order.
             events.sort_by_key(|e| e.0);
-
-            let stream = stream.into_iter().collect::<HashMap<_, _>>();
-            let events = events
+            *events = std::mem::take(events)
                 .into_iter()
-                // The final json output should be: `[ts, line]`
-                .map(|e| json!([format!("{}", e.0), e.1]))
-                .collect::<Vec<_>>();
-
-            streams_json.push(json!({
-                "stream": stream,
-                "values": events,
-            }));
+                .filter_map(|(ts, line)| {
+                    tracked
+                        .order(labels, ts, config.out_of_order_action)
+                        .map(|ts| (ts, line))
+                })
+                .collect();
         }
+        drop(tracked);
+        streams.retain(|_, events| !events.is_empty());
 
-        let body = serde_json::to_vec(&json!({
-            "streams": streams_json,
-        }))
-        .unwrap();
+        let (body, content_type) = match config.push_format {
+            PushFormat::Json => (encode_json(streams), "application/json"),
+            PushFormat::Protobuf => (encode_protobuf(streams), "application/x-protobuf"),
+        };
 
-        let uri = format!("{}loki/api/v1/push", self.endpoint);
+        let uri = format!("{}loki/api/v1/push", config.endpoint);
 
         let mut req = http::Request::post(uri);
 
-        req.header("Content-Type", "application/json");
+        req.header("Content-Type", content_type);
 
-        if let Some(tenant_id) = &self.tenant_id {
+        if let Some(tenant_id) = &config.tenant_id {
             req.header("X-Scope-OrigID", tenant_id);
         }
 
         let mut req = req.body(body).unwrap();
 
-        if let Some(auth) = &self.auth {
+        if let Some(auth) = &config.auth {
             auth.apply(&mut req);
         }
 
@@ -222,6 +378,74 @@ impl HttpSink for LokiConfig {
     }
 }
 
+fn encode_json(streams: HashMap<Labels, Vec<(i64, String)>>) -> Vec<u8> {
+    // Construct the json body
+    let mut streams_json: Vec<serde_json::Value> = Vec::new();
+
+    for (stream, events) in streams {
+        let stream = stream.into_iter().collect::<HashMap<_, _>>();
+        let events = events
+            .into_iter()
+            // The final json output should be: `[ts, line]`
+            .map(|e| json!([format!("{}", e.0), e.1]))
+            .collect::<Vec<_>>();
+
+        streams_json.push(json!({
+            "stream": stream,
+            "values": events,
+        }));
+    }
+
+    serde_json::to_vec(&json!({
+        "streams": streams_json,
+    }))
+    .unwrap()
+}
+
+/// Loki expects protobuf bodies to be compressed with snappy's raw format.
+fn encode_protobuf(streams: HashMap<Labels, Vec<(i64, String)>>) -> Vec<u8> {
+    let streams = streams
+        .into_iter()
+        .map(|(labels, events)| proto::Stream {
+            labels: format_labels(&labels),
+            entries: events
+                .into_iter()
+                .map(|(ts, line)| proto::Entry {
+                    timestamp: Some(prost_types::Timestamp {
+                        seconds: ts.div_euclid(1_000_000_000),
+                        nanos: ts.rem_euclid(1_000_000_000) as i32,
+                    }),
+                    line,
+                })
+                .collect(),
+        })
+        .collect();
+
+    let mut buf = Vec::new();
+    proto::PushRequest { streams }
+        .encode(&mut buf)
+        .expect("protobuf encoding should never fail");
+
+    snap::raw::Encoder::new()
+        .compress_vec(&buf)
+        .expect("snappy compression should never fail")
+}
+
+/// Formats labels as a Prometheus style label set, `{key="value"}`.
+fn format_labels(labels: &[(String, String)]) -> String {
+    let labels = labels
+        .iter()
+        .map(|(key, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", key, value)
+        })
+        .collect::<Vec<_>>();
+    format!("{{{}}}", labels.join(", "))
+}
+
 async fn healthcheck(config: LokiConfig, resolver: Resolver) -> Result<(), crate::Error> {
     let uri = format!("{}ready", config.endpoint);
 
@@ -244,6 +468,7 @@ mod tests {
     use crate::sinks::util::http::HttpSink;
     use crate::sinks::util::test::load_sink;
     use crate::Event;
+    use chrono::TimeZone;
 
     #[test]
     fn interpolate_labels() {
@@ -261,7 +486,7 @@ mod tests {
 
         e1.as_mut_log().insert("foo", "bar");
 
-        let (mut labels, (_, line)) = config.encode_event(e1).unwrap();
+        let (mut labels, (_, line)) = LokiSink::new(config).encode_event(e1).unwrap();
 
         // HashMap -> Vec doesn't like keeping ordering
         labels.sort();
@@ -280,6 +505,119 @@ mod tests {
             ("label2".to_string(), "some-static-label".to_string())
         );
     }
+
+    #[test]
+    fn loki_out_of_order_actions() {
+        let labels = default_labels();
+        let mut streams = Streams::new(None, Duration::from_secs(60));
+
+        assert_eq!(streams.order(&labels, 10, OutOfOrderAction::Drop), Some(10));
+        assert_eq!(streams.order(&labels, 5, OutOfOrderAction::Drop), None);
+        assert_eq!(
+            streams.order(&labels, 5, OutOfOrderAction::RewriteTimestamp),
+            Some(10)
+        );
+        assert_eq!(streams.order(&labels, 5, OutOfOrderAction::Accept), Some(5));
+        assert_eq!(streams.order(&labels, 10, OutOfOrderAction::Drop), Some(10));
+        assert_eq!(streams.order(&labels, 11, OutOfOrderAction::Drop), Some(11));
+
+        // Streams are tracked separately.
+        let other = vec![("app".to_string(), "web".to_string())];
+        assert_eq!(streams.order(&other, 1, OutOfOrderAction::Drop), Some(1));
+    }
+
+    #[test]
+    fn loki_stream_limit_falls_back_to_catch_all() {
+        let stream = |name: &str| vec![("app".to_string(), name.to_string())];
+        let start = Instant::now();
+        let mut streams = Streams::new(Some(2), Duration::from_secs(60));
+
+        assert_eq!(streams.admit(stream("a"), start), stream("a"));
+        assert_eq!(streams.admit(stream("b"), start), stream("b"));
+        assert_eq!(streams.admit(stream("c"), start), default_labels());
+        assert_eq!(streams.admit(stream("a"), start), stream("a"));
+
+        // A new window admits new streams again.
+        streams.order(&stream("a"), 10, OutOfOrderAction::Drop);
+        let later = start + Duration::from_secs(61);
+        assert_eq!(streams.admit(stream("c"), later), stream("c"));
+        assert!(streams.latest.contains_key(&stream("a")));
+
+        // Streams idle for a whole window are forgotten.
+        let idle = later + Duration::from_secs(61);
+        assert_eq!(streams.admit(stream("c"), idle), stream("c"));
+        assert!(streams.latest.is_empty());
+    }
+
+    #[test]
+    fn loki_protobuf_request() {
+        let (config, _cx, _rt) = load_sink::<LokiConfig>(
+            r#"
+            endpoint = "http://localhost:3100"
+            labels = {app = "{{ app }}"}
+            encoding = "text"
+            push_format = "protobuf"
+            out_of_order_action = "rewrite_timestamp"
+        "#,
+        )
+        .unwrap();
+        let sink = LokiSink::new(config);
+
+        let encode = |events: Vec<(i64, &str)>| {
+            events
+                .into_iter()
+                .map(|(secs, app)| {
+                    let mut event = Event::from(format!("line {}", secs));
+                    event.as_mut_log().insert("app", app);
+                    event.as_mut_log().insert(
+                        event::log_schema().timestamp_key().clone(),
+                        chrono::Utc.timestamp(secs, 0),
+                    );
+                    sink.encode_event(event).unwrap()
+                })
+                .collect::<Vec<_>>()
+        };
+        let decode = |request: http::Request<Vec<u8>>| {
+            assert_eq!(request.headers()["Content-Type"], "application/x-protobuf");
+            let body = snap::raw::Decoder::new()
+                .decompress_vec(request.body())
+                .unwrap();
+            let mut push = proto::PushRequest::decode(body).unwrap();
+            push.streams.sort_by(|a, b| a.labels.cmp(&b.labels));
+            push
+        };
+        let entries = |stream: &proto::Stream| {
+            stream
+                .entries
+                .iter()
+                .map(|entry| {
+                    (
+                        entry.timestamp.as_ref().unwrap().seconds,
+                        entry.line.clone(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let events = encode(vec![(3, "web"), (1, "web"), (2, "db\"quoted\"")]);
+        let push = decode(sink.build_request(events));
+        assert_eq!(push.streams.len(), 2);
+        assert_eq!(push.streams[0].labels, r#"{app="db\"quoted\""}"#);
+        assert_eq!(push.streams[1].labels, r#"{app="web"}"#);
+
+        // Events of a batch are sorted, so they are all in order.
+        assert_eq!(
+            entries(&push.streams[1]),
+            vec![(1, "line 1".to_string()), (3, "line 3".to_string())]
+        );
+
+        // The late line of the web stream was moved to its latest timestamp.
+        let push = decode(sink.build_request(encode(vec![(2, "web"), (4, "web")])));
+        assert_eq!(
+            entries(&push.streams[0]),
+            vec![(3, "line 2".to_string()), (4, "line 4".to_string())]
+        );
+    }
 }
 
 #[cfg(feature = "docker")]
@@ -3,6 +3,7 @@ use crate::{
     event::{self, Event, LogEvent, Value},
     sinks::util::{
         encoding::{skip_serializing_if_default, EncodingConfigWithDefault, EncodingConfiguration},
+        finalize_dropped,
         http::{https_client, HttpRetryLogic, HttpService},
         BatchBytesConfig, Buffer, Compression, SinkExt, TowerRequestConfig,
     },
@@ -10,7 +11,7 @@ use crate::{
     topology::config::{DataType, SinkConfig, SinkContext, SinkDescription},
 };
 use bytes::Bytes;
-use futures01::{stream::iter_ok, Future, Sink};
+use futures01::{Future, Sink};
 use http::{HttpTryFrom, Method, Request, StatusCode, Uri};
 use hyper::Body;
 use lazy_static::lazy_static;
@@ -131,7 +132,9 @@ pub fn hec(config: HecSinkConfig, cx: SinkContext) -> crate::Result<super::Route
     let sink = request
         .batch_sink(HttpRetryLogic, http_service, cx.acker())
         .batched_with_min(Buffer::new(gzip), &batch)
-        .with_flat_map(move |e| iter_ok(encode_event(&host_field, e, &indexed_fields, &encoding)));
+        .with_flat_map(finalize_dropped(cx.acker(), move |e| {
+            encode_event(&host_field, e, &indexed_fields, &encoding)
+        }));
 
     Ok(Box::new(sink))
 }
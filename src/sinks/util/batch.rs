@@ -1,5 +1,6 @@
 use futures01::{try_ready, Async, AsyncSink, Future, Poll, Sink, StartSend};
 use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use tokio::timer::Delay;
 
@@ -78,6 +79,73 @@ impl<T> Batch for Vec<T> {
     }
 }
 
+/// A request whose clones share its contents. The retry layer resends
+/// clones of a request, so a service can narrow a request down to the parts
+/// which failed before it's retried.
+#[derive(Debug)]
+pub struct SharedRequest<T>(Arc<Mutex<T>>);
+
+impl<T> SharedRequest<T> {
+    pub fn new(inner: T) -> Self {
+        SharedRequest(Arc::new(Mutex::new(inner)))
+    }
+
+    /// Replaces the contents sent when the request is retried.
+    pub fn set(&self, inner: T) {
+        *self.0.lock().unwrap() = inner;
+    }
+}
+
+impl<T: Clone> SharedRequest<T> {
+    pub fn get(&self) -> T {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+impl<T> Clone for SharedRequest<T> {
+    fn clone(&self) -> Self {
+        SharedRequest(Arc::clone(&self.0))
+    }
+}
+
+/// Wraps a batch, so its output is a `SharedRequest`.
+pub struct SharedBatch<B>(B);
+
+impl<B> SharedBatch<B> {
+    pub fn new(batch: B) -> Self {
+        SharedBatch(batch)
+    }
+}
+
+impl<B: Batch> Batch for SharedBatch<B> {
+    type Input = B::Input;
+    type Output = SharedRequest<B::Output>;
+
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn push(&mut self, item: Self::Input) {
+        self.0.push(item)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn fresh(&self) -> Self {
+        SharedBatch(self.0.fresh())
+    }
+
+    fn finish(self) -> Self::Output {
+        SharedRequest::new(self.0.finish())
+    }
+
+    fn num_items(&self) -> usize {
+        self.0.num_items()
+    }
+}
+
 pub struct BatchSink<B, S> {
     batch: B,
     inner: S,
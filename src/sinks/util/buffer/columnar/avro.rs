@@ -0,0 +1,220 @@
+//! A minimal Avro object container file writer.
+//!
+//! Events are written as records of nullable fields in a single data block,
+//! optionally compressed with the `deflate` codec.
+
+use super::{Cell, ColumnType, Schema};
+use crate::event::LogEvent;
+use flate2::write::DeflateEncoder;
+use serde_json::{json, Value as JsonValue};
+use std::collections::HashSet;
+use std::io::Write;
+
+const MAGIC: &[u8] = b"Obj\x01";
+
+/// Writes `events` as an Avro container file laid out according to
+/// `schema`, compressing the data block if `deflate` is set.
+pub fn encode(schema: &Schema, events: &[LogEvent], deflate: bool) -> Vec<u8> {
+    let sync: [u8; 16] = rand::random();
+    let codec: &[u8] = if deflate { b"deflate" } else { b"null" };
+
+    let mut file = MAGIC.to_vec();
+    write_long(&mut file, 2);
+    write_bytes(&mut file, b"avro.schema");
+    write_bytes(&mut file, record_schema(schema).to_string().as_bytes());
+    write_bytes(&mut file, b"avro.codec");
+    write_bytes(&mut file, codec);
+    write_long(&mut file, 0);
+    file.extend_from_slice(&sync);
+
+    let mut block = Vec::new();
+    for log in events {
+        for column in schema.columns() {
+            match column.cell(log) {
+                None => write_long(&mut block, 0),
+                Some(cell) => {
+                    write_long(&mut block, 1);
+                    write_cell(&mut block, cell, column.ty());
+                }
+            }
+        }
+    }
+    if deflate {
+        let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+        block = encoder
+            .write_all(&block)
+            .and_then(|_| encoder.finish())
+            .expect("This can't fail because the inner writer is a Vec");
+    }
+
+    write_long(&mut file, events.len() as i64);
+    write_long(&mut file, block.len() as i64);
+    file.extend_from_slice(&block);
+    file.extend_from_slice(&sync);
+    file
+}
+
+fn record_schema(schema: &Schema) -> JsonValue {
+    let fields = schema
+        .columns()
+        .iter()
+        .zip(field_names(schema))
+        .map(|(column, name)| {
+            let ty = match column.ty() {
+                ColumnType::String | ColumnType::Json => json!("string"),
+                ColumnType::Integer => json!("long"),
+                ColumnType::Float => json!("double"),
+                ColumnType::Boolean => json!("boolean"),
+                ColumnType::Timestamp => json!({"type": "long", "logicalType": "timestamp-micros"}),
+            };
+            json!({
+                "name": name,
+                "type": ["null", ty],
+                "default": null,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "type": "record",
+        "name": "event",
+        "fields": fields,
+    })
+}
+
+/// The names of the fields of each column, which have to be unique. Columns
+/// whose names are the same once sanitized, like `a.b` and `a-b`, are told
+/// apart by a numeric suffix.
+fn field_names(schema: &Schema) -> Vec<String> {
+    let mut seen = HashSet::new();
+    schema
+        .columns()
+        .iter()
+        .map(|column| {
+            let name = field_name(column.name());
+            let mut field = name.clone();
+            let mut suffix = 1;
+            while !seen.insert(field.clone()) {
+                suffix += 1;
+                field = format!("{}_{}", name, suffix);
+            }
+            field
+        })
+        .collect()
+}
+
+// Avro names may only contain `[A-Za-z0-9_]` and must not start with a
+// digit, so anything else (such as the dots of flattened fields) is replaced
+// with an underscore.
+fn field_name(name: &str) -> String {
+    let mut field = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    if field.chars().next().map_or(true, |c| c.is_ascii_digit()) {
+        field.insert(0, '_');
+    }
+    field
+}
+
+fn write_cell(buf: &mut Vec<u8>, cell: Cell, ty: ColumnType) {
+    match cell {
+        // Avro strings have to be valid UTF-8.
+        Cell::Bytes(bytes) if ty == ColumnType::String || ty == ColumnType::Json => {
+            write_bytes(buf, String::from_utf8_lossy(&bytes).as_bytes())
+        }
+        Cell::Bytes(bytes) => write_bytes(buf, &bytes),
+        Cell::Integer(i) => write_long(buf, i),
+        Cell::Float(f) => buf.extend_from_slice(&f.to_bits().to_le_bytes()),
+        Cell::Boolean(b) => buf.push(b as u8),
+    }
+}
+
+fn write_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    write_long(buf, bytes.len() as i64);
+    buf.extend_from_slice(bytes);
+}
+
+fn write_long(buf: &mut Vec<u8>, value: i64) {
+    let mut value = ((value << 1) ^ (value >> 63)) as u64;
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{Event, Value};
+    use crate::sinks::util::buffer::columnar::Column;
+
+    #[test]
+    fn avro_field_names() {
+        assert_eq!(field_name("message"), "message");
+        assert_eq!(field_name("nested.field"), "nested_field");
+        assert_eq!(field_name("@timestamp"), "_timestamp");
+        assert_eq!(field_name("1st"), "_1st");
+
+        let schema = Schema::new(vec![
+            Column::new("a.b", ColumnType::String),
+            Column::new("a-b", ColumnType::String),
+            Column::new("a_b", ColumnType::String),
+            Column::new("c", ColumnType::String),
+        ]);
+        assert_eq!(field_names(&schema), vec!["a_b", "a_b_2", "a_b_3", "c"]);
+    }
+
+    #[test]
+    fn avro_encode_invalid_utf8() {
+        let schema = Schema::new(vec![Column::new("message", ColumnType::String)]);
+        let mut event = Event::from("");
+        event
+            .as_mut_log()
+            .insert("message", Value::Bytes(vec![b'h', 0xff].into()));
+
+        let file = encode(&schema, &[event.into_log()], false);
+        let mut expected = Vec::new();
+        write_bytes(&mut expected, "h\u{fffd}".as_bytes());
+        assert_eq!(
+            &file[file.len() - 16 - expected.len()..file.len() - 16],
+            &expected[..]
+        );
+    }
+
+    #[test]
+    fn avro_encode() {
+        let schema = Schema::new(vec![
+            Column::new("message", ColumnType::String),
+            Column::new("count", ColumnType::Integer),
+            Column::new("ok", ColumnType::Boolean),
+        ]);
+        let mut event = Event::from("hi");
+        event.as_mut_log().insert("count", -3);
+
+        let file = encode(&schema, &[event.into_log()], false);
+        assert_eq!(&file[..4], MAGIC);
+
+        let schema_json = record_schema(&schema).to_string();
+        let header_end = file
+            .windows(schema_json.len())
+            .position(|w| w == schema_json.as_bytes())
+            .expect("schema missing from header")
+            + schema_json.len();
+        let body = &file[header_end..];
+
+        // codec metadata, end of the metadata map, sync marker
+        let sync = &body[body.len() - 16..];
+        let mut expected = Vec::new();
+        write_bytes(&mut expected, b"avro.codec");
+        write_bytes(&mut expected, b"null");
+        write_long(&mut expected, 0);
+        expected.extend_from_slice(sync);
+        // a block of one record taking up 7 bytes
+        expected.extend_from_slice(&[2, 14]);
+        expected.extend_from_slice(&[2, 4, b'h', b'i', 2, 5, 0]);
+        expected.extend_from_slice(sync);
+        assert_eq!(body, &expected[..]);
+    }
+}
@@ -0,0 +1,414 @@
+//! A `Batch` implementation that collects log events and writes each batch
+//! out as a single self-describing columnar file (Parquet or Avro).
+//!
+//! The columns written are either declared by the user or inferred from the
+//! first batch the buffer sees. Inferred schemas are shared between every
+//! buffer created through `fresh`, so all files written by a sink agree on
+//! their layout.
+
+use crate::{
+    event::{LogEvent, Value},
+    sinks::util::Batch,
+};
+use chrono::DateTime;
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use string_cache::DefaultAtom as Atom;
+
+pub mod avro;
+pub mod parquet;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ColumnarFormat {
+    Parquet,
+    Avro,
+}
+
+impl ColumnarFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ColumnarFormat::Parquet => "parquet",
+            ColumnarFormat::Avro => "avro",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ColumnarFormat::Parquet => "application/octet-stream",
+            ColumnarFormat::Avro => "avro/binary",
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ColumnType {
+    String,
+    Integer,
+    Float,
+    Boolean,
+    Timestamp,
+    Json,
+}
+
+impl ColumnType {
+    fn infer(value: &Value) -> Option<Self> {
+        match value {
+            Value::Bytes(_) => Some(ColumnType::String),
+            Value::Integer(_) => Some(ColumnType::Integer),
+            Value::Float(_) => Some(ColumnType::Float),
+            Value::Boolean(_) => Some(ColumnType::Boolean),
+            Value::Timestamp(_) => Some(ColumnType::Timestamp),
+            Value::Map(_) | Value::Array(_) => Some(ColumnType::Json),
+            Value::Null => None,
+        }
+    }
+
+    // Picks a type able to hold values of both `self` and `other`.
+    fn widen(self, other: Self) -> Self {
+        match (self, other) {
+            (a, b) if a == b => a,
+            (ColumnType::Integer, ColumnType::Float) | (ColumnType::Float, ColumnType::Integer) => {
+                ColumnType::Float
+            }
+            _ => ColumnType::String,
+        }
+    }
+}
+
+/// User facing schema configuration, mapping field names to column types.
+/// Columns are written in the order they are declared.
+pub type SchemaConfig = IndexMap<String, ColumnType>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Column {
+    name: String,
+    path: Atom,
+    ty: ColumnType,
+}
+
+impl Column {
+    pub fn new(name: impl Into<String>, ty: ColumnType) -> Self {
+        let name = name.into();
+        let path = Atom::from(name.as_str());
+        Self { name, path, ty }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn ty(&self) -> ColumnType {
+        self.ty
+    }
+
+    fn cell(&self, log: &LogEvent) -> Option<Cell> {
+        log.get(&self.path)
+            .and_then(|value| Cell::coerce(value, self.ty))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schema {
+    columns: Vec<Column>,
+}
+
+impl Schema {
+    pub fn new(columns: Vec<Column>) -> Self {
+        Self { columns }
+    }
+
+    pub fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    /// Builds a schema covering every non-null field seen in `events`.
+    /// Nested maps are flattened into dotted column names while arrays are
+    /// stored whole as JSON. Fields whose values disagree on their type are
+    /// widened, falling back to strings.
+    pub fn infer(events: &[LogEvent]) -> Self {
+        let mut types: IndexMap<String, ColumnType> = IndexMap::new();
+        for log in events {
+            for (path, value) in log.all_fields() {
+                let (name, ty) = match path.find('[') {
+                    Some(index) => (path[..index].to_string(), Some(ColumnType::Json)),
+                    None => (path.to_string(), ColumnType::infer(value)),
+                };
+                if let Some(ty) = ty {
+                    let entry = types.entry(name).or_insert(ty);
+                    *entry = entry.widen(ty);
+                }
+            }
+        }
+        types.into()
+    }
+}
+
+impl From<SchemaConfig> for Schema {
+    fn from(config: SchemaConfig) -> Self {
+        Schema::new(
+            config
+                .into_iter()
+                .map(|(name, ty)| Column::new(name, ty))
+                .collect(),
+        )
+    }
+}
+
+/// A single value coerced to the physical representation of its column.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cell {
+    Bytes(Vec<u8>),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+}
+
+impl Cell {
+    /// Converts `value` to fit a column of type `ty`, returning `None` (which
+    /// is written as null) if it can't be represented.
+    fn coerce(value: &Value, ty: ColumnType) -> Option<Self> {
+        match (ty, value) {
+            (_, Value::Null) => None,
+            (ColumnType::String, value) => Some(Cell::Bytes(value.as_bytes().to_vec())),
+            (ColumnType::Json, Value::Bytes(bytes)) => {
+                serde_json::to_vec(&String::from_utf8_lossy(bytes))
+                    .ok()
+                    .map(Cell::Bytes)
+            }
+            (ColumnType::Json, value) => serde_json::to_vec(value).ok().map(Cell::Bytes),
+            (ColumnType::Integer, Value::Integer(i)) => Some(Cell::Integer(*i)),
+            (ColumnType::Integer, Value::Float(f)) => Some(Cell::Integer(*f as i64)),
+            (ColumnType::Integer, Value::Bytes(bytes)) => {
+                parse(bytes).and_then(|s| s.parse().ok()).map(Cell::Integer)
+            }
+            (ColumnType::Float, Value::Float(f)) => Some(Cell::Float(*f)),
+            (ColumnType::Float, Value::Integer(i)) => Some(Cell::Float(*i as f64)),
+            (ColumnType::Float, Value::Bytes(bytes)) => {
+                parse(bytes).and_then(|s| s.parse().ok()).map(Cell::Float)
+            }
+            (ColumnType::Boolean, Value::Boolean(b)) => Some(Cell::Boolean(*b)),
+            (ColumnType::Boolean, Value::Bytes(bytes)) => {
+                parse(bytes).and_then(|s| s.parse().ok()).map(Cell::Boolean)
+            }
+            (ColumnType::Timestamp, Value::Timestamp(ts)) => Some(Cell::Integer(micros(ts))),
+            (ColumnType::Timestamp, Value::Bytes(bytes)) => parse(bytes)
+                .and_then(|s| DateTime::parse_from_rfc3339(s).ok())
+                .map(|ts| Cell::Integer(micros(&ts))),
+            _ => None,
+        }
+    }
+}
+
+fn parse(bytes: &[u8]) -> Option<&str> {
+    std::str::from_utf8(bytes).ok().map(str::trim)
+}
+
+fn micros<Tz: chrono::TimeZone>(ts: &DateTime<Tz>) -> i64 {
+    ts.timestamp() * 1_000_000 + i64::from(ts.timestamp_subsec_micros())
+}
+
+// A rough estimate of how much space `value` will take up once written,
+// used to decide when a batch is full.
+fn estimated_size(value: &Value) -> usize {
+    match value {
+        Value::Bytes(bytes) => bytes.len(),
+        Value::Map(map) => map
+            .iter()
+            .map(|(key, value)| key.len() + estimated_size(value))
+            .sum(),
+        Value::Array(array) => array.iter().map(estimated_size).sum(),
+        Value::Boolean(_) | Value::Null => 1,
+        Value::Integer(_) | Value::Float(_) | Value::Timestamp(_) => 8,
+    }
+}
+
+#[derive(Debug)]
+pub struct ColumnarBuffer {
+    format: ColumnarFormat,
+    compression: bool,
+    schema: Arc<Mutex<Option<Schema>>>,
+    events: Vec<LogEvent>,
+    size: usize,
+}
+
+impl ColumnarBuffer {
+    /// Creates a buffer writing files in `format`. If no `schema` is given,
+    /// it is inferred from the first batch to be finished. `compression`
+    /// enables the format's own compression (gzip pages for Parquet,
+    /// deflate blocks for Avro) rather than compressing the whole file.
+    pub fn new(format: ColumnarFormat, schema: Option<Schema>, compression: bool) -> Self {
+        Self {
+            format,
+            compression,
+            schema: Arc::new(Mutex::new(schema)),
+            events: Vec::new(),
+            size: 0,
+        }
+    }
+}
+
+impl Batch for ColumnarBuffer {
+    type Input = LogEvent;
+    type Output = Vec<u8>;
+
+    fn len(&self) -> usize {
+        self.size
+    }
+
+    fn push(&mut self, item: Self::Input) {
+        self.size += item
+            .all_fields()
+            .map(|(key, value)| key.len() + estimated_size(value))
+            .sum::<usize>();
+        self.events.push(item);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    fn fresh(&self) -> Self {
+        Self {
+            format: self.format,
+            compression: self.compression,
+            schema: Arc::clone(&self.schema),
+            events: Vec::new(),
+            size: 0,
+        }
+    }
+
+    fn finish(self) -> Self::Output {
+        let schema = self
+            .schema
+            .lock()
+            .expect("Schema lock poisoned")
+            .get_or_insert_with(|| Schema::infer(&self.events))
+            .clone();
+
+        match self.format {
+            ColumnarFormat::Parquet => parquet::encode(&schema, &self.events, self.compression),
+            ColumnarFormat::Avro => avro::encode(&schema, &self.events, self.compression),
+        }
+    }
+
+    fn num_items(&self) -> usize {
+        self.events.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::Event;
+    use chrono::{TimeZone, Utc};
+
+    fn log(fields: Vec<(&str, Value)>) -> LogEvent {
+        let mut event = Event::new_empty_log();
+        for (key, value) in fields {
+            event.as_mut_log().insert(key, value);
+        }
+        event.into_log()
+    }
+
+    #[test]
+    fn columnar_infer_schema() {
+        let events = vec![
+            log(vec![
+                ("message", "hello".into()),
+                ("count", 1.into()),
+                ("nested.flag", true.into()),
+                ("tags", Value::Array(vec!["a".into(), "b".into()])),
+                ("missing", Value::Null),
+            ]),
+            log(vec![("count", 1.5.into()), ("status", 200.into())]),
+            log(vec![("status", "ok".into())]),
+        ];
+
+        let schema = Schema::infer(&events);
+        let columns: Vec<_> = schema
+            .columns()
+            .iter()
+            .map(|column| (column.name(), column.ty()))
+            .collect();
+
+        assert_eq!(
+            columns,
+            vec![
+                ("count", ColumnType::Float),
+                ("message", ColumnType::String),
+                ("nested.flag", ColumnType::Boolean),
+                ("tags", ColumnType::Json),
+                ("status", ColumnType::String),
+            ]
+        );
+    }
+
+    #[test]
+    fn columnar_coerce_values() {
+        let ts = Utc.ymd(2020, 4, 1).and_hms_micro(10, 30, 0, 250);
+        let cases = vec![
+            (
+                Value::from(5),
+                ColumnType::String,
+                Some(Cell::Bytes(b"5".to_vec())),
+            ),
+            (
+                Value::from("12"),
+                ColumnType::Integer,
+                Some(Cell::Integer(12)),
+            ),
+            (Value::from("nope"), ColumnType::Integer, None),
+            (Value::from(3), ColumnType::Float, Some(Cell::Float(3.0))),
+            (
+                Value::from("true"),
+                ColumnType::Boolean,
+                Some(Cell::Boolean(true)),
+            ),
+            (
+                Value::from(ts),
+                ColumnType::Timestamp,
+                Some(Cell::Integer(1_585_737_000_000_250)),
+            ),
+            (
+                Value::from("2020-04-01T10:30:00.000250Z"),
+                ColumnType::Timestamp,
+                Some(Cell::Integer(1_585_737_000_000_250)),
+            ),
+            (
+                Value::Array(vec![1.into(), "a".into()]),
+                ColumnType::Json,
+                Some(Cell::Bytes(br#"[1,"a"]"#.to_vec())),
+            ),
+            (Value::Null, ColumnType::String, None),
+        ];
+
+        for (value, ty, expected) in cases {
+            assert_eq!(
+                Cell::coerce(&value, ty),
+                expected,
+                "{:?} as {:?}",
+                value,
+                ty
+            );
+        }
+    }
+
+    #[test]
+    fn columnar_buffer_shares_inferred_schema() {
+        let mut buffer = ColumnarBuffer::new(ColumnarFormat::Avro, None, false);
+        let fresh = buffer.fresh();
+
+        buffer.push(log(vec![("message", "hello".into())]));
+        assert_eq!(buffer.num_items(), 1);
+        assert_eq!(buffer.len(), "message".len() + "hello".len());
+        buffer.finish();
+
+        let schema = fresh.schema.lock().unwrap().clone().unwrap();
+        assert_eq!(
+            schema,
+            Schema::new(vec![Column::new("message", ColumnType::String)])
+        );
+    }
+}
@@ -0,0 +1,372 @@
+//! A minimal Parquet writer.
+//!
+//! Every batch is written as a single row group with one PLAIN encoded data
+//! page per column. All columns are optional, with their definition levels
+//! stored as bit-packed runs. The file metadata is serialized with the Thrift
+//! compact protocol as described by the Parquet format specification.
+
+use super::{Cell, ColumnType, Schema};
+use crate::event::LogEvent;
+use flate2::write::GzEncoder;
+use std::io::Write;
+
+const MAGIC: &[u8] = b"PAR1";
+const CREATED_BY: &str = "vector";
+
+// parquet.thrift enum values.
+const TYPE_BOOLEAN: i32 = 0;
+const TYPE_INT64: i32 = 2;
+const TYPE_DOUBLE: i32 = 5;
+const TYPE_BYTE_ARRAY: i32 = 6;
+const CONVERTED_UTF8: i32 = 0;
+const CONVERTED_TIMESTAMP_MICROS: i32 = 10;
+const CONVERTED_JSON: i32 = 19;
+const REPETITION_OPTIONAL: i32 = 1;
+const ENCODING_PLAIN: i32 = 0;
+const ENCODING_RLE: i32 = 3;
+const CODEC_UNCOMPRESSED: i32 = 0;
+const CODEC_GZIP: i32 = 2;
+const PAGE_DATA: i32 = 0;
+
+fn physical_type(ty: ColumnType) -> i32 {
+    match ty {
+        ColumnType::String | ColumnType::Json => TYPE_BYTE_ARRAY,
+        ColumnType::Integer | ColumnType::Timestamp => TYPE_INT64,
+        ColumnType::Float => TYPE_DOUBLE,
+        ColumnType::Boolean => TYPE_BOOLEAN,
+    }
+}
+
+fn converted_type(ty: ColumnType) -> Option<i32> {
+    match ty {
+        ColumnType::String => Some(CONVERTED_UTF8),
+        ColumnType::Json => Some(CONVERTED_JSON),
+        ColumnType::Timestamp => Some(CONVERTED_TIMESTAMP_MICROS),
+        ColumnType::Integer | ColumnType::Float | ColumnType::Boolean => None,
+    }
+}
+
+struct ChunkInfo {
+    offset: usize,
+    uncompressed_size: usize,
+    compressed_size: usize,
+}
+
+/// Writes `events` as a Parquet file laid out according to `schema`,
+/// compressing each page with gzip if `gzip` is set.
+pub fn encode(schema: &Schema, events: &[LogEvent], gzip: bool) -> Vec<u8> {
+    let mut file = MAGIC.to_vec();
+    let mut chunks = Vec::with_capacity(schema.columns().len());
+
+    for column in schema.columns() {
+        let cells = events
+            .iter()
+            .map(|log| column.cell(log))
+            .collect::<Vec<_>>();
+        let page = encode_page(&cells);
+        let data = if gzip { compress(&page) } else { page.clone() };
+        let header = page_header(cells.len(), page.len(), data.len());
+
+        chunks.push(ChunkInfo {
+            offset: file.len(),
+            uncompressed_size: header.len() + page.len(),
+            compressed_size: header.len() + data.len(),
+        });
+        file.extend_from_slice(&header);
+        file.extend_from_slice(&data);
+    }
+
+    let metadata = file_metadata(schema, events.len(), &chunks, gzip);
+    file.extend_from_slice(&metadata);
+    file.extend_from_slice(&(metadata.len() as u32).to_le_bytes());
+    file.extend_from_slice(MAGIC);
+    file
+}
+
+fn compress(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder
+        .write_all(data)
+        .and_then(|_| encoder.finish())
+        .expect("This can't fail because the inner writer is a Vec")
+}
+
+fn encode_page(cells: &[Option<Cell>]) -> Vec<u8> {
+    // Definition levels, length prefixed.
+    let levels = bit_packed_run(cells.iter().map(Option::is_some));
+    let mut page = (levels.len() as u32).to_le_bytes().to_vec();
+    page.extend_from_slice(&levels);
+
+    // Values, skipping nulls.
+    let values = cells.iter().filter_map(Option::as_ref);
+    match cells.iter().find_map(Option::as_ref) {
+        Some(Cell::Boolean(_)) => {
+            let bits = values.map(|cell| cell == &Cell::Boolean(true));
+            page.extend_from_slice(&bit_pack(bits));
+        }
+        _ => {
+            for cell in values {
+                match cell {
+                    Cell::Bytes(bytes) => {
+                        page.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+                        page.extend_from_slice(bytes);
+                    }
+                    Cell::Integer(i) => page.extend_from_slice(&i.to_le_bytes()),
+                    Cell::Float(f) => page.extend_from_slice(&f.to_bits().to_le_bytes()),
+                    Cell::Boolean(_) => unreachable!("boolean pages are bit packed"),
+                }
+            }
+        }
+    }
+    page
+}
+
+// Packs bits LSB first, padding the final byte with zeros.
+fn bit_pack(bits: impl Iterator<Item = bool>) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for (i, bit) in bits.enumerate() {
+        if i % 8 == 0 {
+            bytes.push(0);
+        }
+        if bit {
+            *bytes.last_mut().unwrap() |= 1 << (i % 8);
+        }
+    }
+    bytes
+}
+
+// A single bit-packed run of the RLE/bit-packing hybrid encoding with a bit
+// width of one. The run covers a whole number of groups of eight values.
+fn bit_packed_run(bits: impl Iterator<Item = bool>) -> Vec<u8> {
+    let packed = bit_pack(bits);
+    let mut run = Vec::with_capacity(packed.len() + 5);
+    write_varint(&mut run, ((packed.len() as u64) << 1) | 1);
+    run.extend_from_slice(&packed);
+    run
+}
+
+fn page_header(num_values: usize, uncompressed_size: usize, compressed_size: usize) -> Vec<u8> {
+    let mut w = CompactWriter::default();
+    w.begin();
+    w.i32(1, PAGE_DATA);
+    w.i32(2, uncompressed_size as i32);
+    w.i32(3, compressed_size as i32);
+    w.begin_struct(5);
+    w.i32(1, num_values as i32);
+    w.i32(2, ENCODING_PLAIN);
+    w.i32(3, ENCODING_RLE);
+    w.i32(4, ENCODING_RLE);
+    w.end();
+    w.end();
+    w.buf
+}
+
+fn file_metadata(schema: &Schema, num_rows: usize, chunks: &[ChunkInfo], gzip: bool) -> Vec<u8> {
+    let columns = schema.columns();
+    let mut w = CompactWriter::default();
+    w.begin();
+    w.i32(1, 1);
+
+    w.list(2, STRUCT, columns.len() + 1);
+    w.begin();
+    w.binary(4, b"schema");
+    w.i32(5, columns.len() as i32);
+    w.end();
+    for column in columns {
+        w.begin();
+        w.i32(1, physical_type(column.ty()));
+        w.i32(3, REPETITION_OPTIONAL);
+        w.binary(4, column.name().as_bytes());
+        if let Some(converted) = converted_type(column.ty()) {
+            w.i32(6, converted);
+        }
+        w.end();
+    }
+
+    w.i64(3, num_rows as i64);
+
+    w.list(4, STRUCT, 1);
+    w.begin();
+    w.list(1, STRUCT, columns.len());
+    for (column, chunk) in columns.iter().zip(chunks) {
+        w.begin();
+        w.i64(2, chunk.offset as i64);
+        w.begin_struct(3);
+        w.i32(1, physical_type(column.ty()));
+        w.list(2, I32, 2);
+        w.varint_i64(ENCODING_PLAIN.into());
+        w.varint_i64(ENCODING_RLE.into());
+        w.list(3, BINARY, 1);
+        w.raw_binary(column.name().as_bytes());
+        w.i32(4, if gzip { CODEC_GZIP } else { CODEC_UNCOMPRESSED });
+        w.i64(5, num_rows as i64);
+        w.i64(6, chunk.uncompressed_size as i64);
+        w.i64(7, chunk.compressed_size as i64);
+        w.i64(9, chunk.offset as i64);
+        w.end();
+        w.end();
+    }
+    let total_size = chunks
+        .iter()
+        .map(|chunk| chunk.uncompressed_size)
+        .sum::<usize>();
+    w.i64(2, total_size as i64);
+    w.i64(3, num_rows as i64);
+    w.end();
+
+    w.binary(6, CREATED_BY.as_bytes());
+    w.end();
+    w.buf
+}
+
+// Thrift compact protocol type ids.
+const I32: u8 = 5;
+const I64: u8 = 6;
+const BINARY: u8 = 8;
+const LIST: u8 = 9;
+const STRUCT: u8 = 12;
+
+#[derive(Default)]
+struct CompactWriter {
+    buf: Vec<u8>,
+    last_field: i16,
+    parents: Vec<i16>,
+}
+
+impl CompactWriter {
+    /// Starts a struct, either at the top level or as a list element.
+    fn begin(&mut self) {
+        self.parents.push(self.last_field);
+        self.last_field = 0;
+    }
+
+    /// Starts a struct nested in a field of the current struct.
+    fn begin_struct(&mut self, id: i16) {
+        self.field(id, STRUCT);
+        self.begin();
+    }
+
+    fn end(&mut self) {
+        self.buf.push(0);
+        self.last_field = self.parents.pop().unwrap_or(0);
+    }
+
+    fn field(&mut self, id: i16, ty: u8) {
+        let delta = id - self.last_field;
+        if delta > 0 && delta <= 15 {
+            self.buf.push((delta as u8) << 4 | ty);
+        } else {
+            self.buf.push(ty);
+            self.varint_i64(id.into());
+        }
+        self.last_field = id;
+    }
+
+    fn i32(&mut self, id: i16, value: i32) {
+        self.field(id, I32);
+        self.varint_i64(value.into());
+    }
+
+    fn i64(&mut self, id: i16, value: i64) {
+        self.field(id, I64);
+        self.varint_i64(value);
+    }
+
+    fn binary(&mut self, id: i16, value: &[u8]) {
+        self.field(id, BINARY);
+        self.raw_binary(value);
+    }
+
+    fn list(&mut self, id: i16, element: u8, len: usize) {
+        self.field(id, LIST);
+        if len < 15 {
+            self.buf.push((len as u8) << 4 | element);
+        } else {
+            self.buf.push(0xf0 | element);
+            write_varint(&mut self.buf, len as u64);
+        }
+    }
+
+    fn raw_binary(&mut self, value: &[u8]) {
+        write_varint(&mut self.buf, value.len() as u64);
+        self.buf.extend_from_slice(value);
+    }
+
+    fn varint_i64(&mut self, value: i64) {
+        write_varint(&mut self.buf, ((value << 1) ^ (value >> 63)) as u64);
+    }
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::Event;
+    use crate::sinks::util::buffer::columnar::Column;
+
+    #[test]
+    fn parquet_encode_page() {
+        let cells = vec![Some(Cell::Integer(1)), None, Some(Cell::Integer(-2))];
+        let page = encode_page(&cells);
+
+        let mut expected = vec![2, 0, 0, 0, 0b11, 0b101];
+        expected.extend_from_slice(&1i64.to_le_bytes());
+        expected.extend_from_slice(&(-2i64).to_le_bytes());
+        assert_eq!(page, expected);
+
+        let cells = vec![Some(Cell::Boolean(true)), Some(Cell::Boolean(false)), None];
+        assert_eq!(encode_page(&cells), vec![2, 0, 0, 0, 0b11, 0b011, 0b01]);
+    }
+
+    #[test]
+    fn parquet_compact_protocol() {
+        let mut w = CompactWriter::default();
+        w.begin();
+        w.i32(1, -1);
+        w.begin_struct(20);
+        w.i64(2, 300);
+        w.end();
+        w.binary(21, b"ab");
+        w.end();
+
+        assert_eq!(
+            w.buf,
+            vec![0x15, 0x01, 0x0c, 0x28, 0x26, 0xd8, 0x04, 0x00, 0x18, 0x02, b'a', b'b', 0x00]
+        );
+    }
+
+    #[test]
+    fn parquet_encode_file_layout() {
+        let schema = Schema::new(vec![
+            Column::new("message", ColumnType::String),
+            Column::new("count", ColumnType::Integer),
+        ]);
+        let mut event = Event::from("hello");
+        event.as_mut_log().insert("count", 3);
+
+        for gzip in vec![false, true] {
+            let file = encode(&schema, &[event.clone().into_log()], gzip);
+
+            assert_eq!(&file[..4], MAGIC);
+            assert_eq!(&file[file.len() - 4..], MAGIC);
+            let footer = file.len() - 8;
+            let mut len = [0; 4];
+            len.copy_from_slice(&file[footer..footer + 4]);
+            let metadata_start = footer - u32::from_le_bytes(len) as usize;
+            let metadata = &file[metadata_start..footer];
+
+            let contains = |needle: &[u8]| metadata.windows(needle.len()).any(|w| w == needle);
+            assert!(contains(b"message"));
+            assert!(contains(b"count"));
+            assert!(contains(CREATED_BY.as_bytes()));
+            assert_eq!(metadata.last(), Some(&0));
+        }
+    }
+}
@@ -4,6 +4,7 @@ use partition::Partition;
 use serde::{Deserialize, Serialize};
 use std::io::Write;
 
+pub mod columnar;
 pub mod json;
 pub mod metrics;
 pub mod partition;
@@ -67,6 +68,20 @@ impl Buffer {
             InnerBuffer::Gzip(inner) => inner.get_ref().is_empty(),
         }
     }
+
+    /// Removes the first `len` bytes of output if at least that many have
+    /// been written so far, which allows streaming a buffer out in pieces.
+    pub fn take_output(&mut self, len: usize) -> Option<Vec<u8>> {
+        let inner = match &mut self.inner {
+            InnerBuffer::Plain(inner) => inner,
+            InnerBuffer::Gzip(inner) => inner.get_mut(),
+        };
+        if inner.len() < len {
+            return None;
+        }
+        let rest = inner.split_off(len);
+        Some(std::mem::replace(inner, rest))
+    }
 }
 
 impl Batch for Buffer {
@@ -4,8 +4,10 @@ use super::{
     Batch, BatchSettings, BatchSink,
 };
 use crate::{
+    buffers::Acker,
     dns::Resolver,
     event::Event,
+    metrics,
     tls::{TlsConnectorExt, TlsSettings},
     topology::config::SinkContext,
 };
@@ -44,6 +46,7 @@ where
     // the inner sink is applying back pressure. This trick is used in the `WithFlatMap`
     // sink combinator. https://docs.rs/futures/0.1.29/src/futures/sink/with_flat_map.rs.html#20
     slot: Option<B::Input>,
+    acker: Acker,
 }
 
 impl<T, B> BatchedHttpSink<T, B>
@@ -71,6 +74,7 @@ where
             sink,
             inner,
             slot: None,
+            acker: cx.acker(),
         }
     }
 }
@@ -85,10 +89,13 @@ where
     type SinkError = ();
 
     fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
-        if let Some(item) = self.sink.encode_event(item) {
-            if let AsyncSink::NotReady(item) = self.inner.start_send(item)? {
-                self.slot = Some(item);
+        match self.sink.encode_event(item) {
+            Some(item) => {
+                if let AsyncSink::NotReady(item) = self.inner.start_send(item)? {
+                    self.slot = Some(item);
+                }
             }
+            None => self.acker.discard(),
         }
 
         Ok(AsyncSink::Ready)
@@ -236,6 +243,8 @@ impl<B> Service<B> for HttpService<B> {
         let request = (self.request_builder)(body);
 
         debug!(message = "sending request.");
+        let bytes = request.body().len() as u64;
+        metrics::with_current(|metrics| metrics.counter("processed_bytes_total").add(bytes));
 
         let fut = self
             .inner
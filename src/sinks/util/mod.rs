@@ -2,6 +2,7 @@ pub mod batch;
 pub mod buffer;
 pub mod encoding;
 pub mod http;
+pub mod multipart;
 pub mod retries;
 #[cfg(feature = "rusoto_core")]
 pub mod rusoto;
@@ -15,17 +16,25 @@ pub mod uri;
 
 use crate::buffers::Acker;
 use crate::event::{self, Event};
+use crate::metrics::{self, BATCH_BUCKETS, DURATION_BUCKETS};
 use bytes::Bytes;
 use encoding::{EncodingConfig, EncodingConfiguration};
 use futures01::{
-    future, stream::FuturesUnordered, Async, AsyncSink, Future, Poll, Sink, StartSend, Stream,
+    future,
+    stream::{iter_ok, FuturesUnordered, IterOk},
+    Async, AsyncSink, Future, Poll, Sink, StartSend, Stream,
 };
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::hash::Hash;
+use std::option;
+use std::time::Instant;
 use tower::Service;
 
-pub use batch::{Batch, BatchBytesConfig, BatchEventsConfig, BatchSettings, BatchSink};
+pub use batch::{
+    Batch, BatchBytesConfig, BatchEventsConfig, BatchSettings, BatchSink, SharedBatch,
+    SharedRequest,
+};
 pub use buffer::json::{BoxedRawValue, JsonArrayBuffer};
 pub use buffer::metrics::{MetricBuffer, MetricEntry};
 pub use buffer::partition::{Partition, PartitionedBatchSink};
@@ -67,10 +76,28 @@ pub fn encode_event(mut event: Event, encoding: &EncodingConfig<Encoding>) -> Op
         b.push(b'\n');
         Bytes::from(b)
     })
-    .map_err(|error| error!(message = "Unable to encode.", %error))
+    .map_err(|error| {
+        error!(message = "Unable to encode.", %error);
+        metrics::with_current(|metrics| metrics.error("encode_failed"));
+    })
     .ok()
 }
 
+/// Wraps an encoder run by `with_flat_map`, finalizing the events it drops
+/// right away as they won't be acked with the others.
+pub fn finalize_dropped<T, U, E>(
+    acker: Acker,
+    mut encode: impl FnMut(T) -> Option<U>,
+) -> impl FnMut(T) -> IterOk<option::IntoIter<U>, E> {
+    move |event| {
+        let encoded = encode(event);
+        if encoded.is_none() {
+            acker.discard();
+        }
+        iter_ok(encoded)
+    }
+}
+
 pub trait SinkExt<T>
 where
     Self: Sink<SinkItem = T> + Sized,
@@ -153,6 +180,8 @@ impl<T: Sink> Sink for StreamAck<T> {
 
         if let Ok(Async::Ready(_)) = res {
             self.acker.ack(self.pending);
+            let pending = self.pending as u64;
+            metrics::with_current(|metrics| metrics.events_out().add(pending));
             self.pending = 0;
         }
 
@@ -162,15 +191,38 @@ impl<T: Sink> Sink for StreamAck<T> {
 
 pub type MetadataFuture<F, M> = future::Join<F, future::FutureResult<M, <F as Future>::Error>>;
 
+/// Resolves to the result of a request along with its metadata, so failed
+/// requests can be acked in order too.
+pub struct WithMetadata<F, M> {
+    inner: F,
+    metadata: Option<M>,
+}
+
+impl<F: Future, M> Future for WithMetadata<F, M> {
+    type Item = (Result<F::Item, F::Error>, M);
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let result = match self.inner.poll() {
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Ok(Async::Ready(response)) => Ok(response),
+            Err(error) => Err(error),
+        };
+        let metadata = self.metadata.take().expect("polled after completion");
+        Ok(Async::Ready((result, metadata)))
+    }
+}
+
 pub struct BatchServiceSink<T, S: Service<T>, B: Batch<Output = T>> {
     service: S,
-    in_flight: FuturesUnordered<MetadataFuture<S::Future, (usize, usize)>>,
+    in_flight: FuturesUnordered<WithMetadata<S::Future, (usize, usize, Instant)>>,
     _phantom: std::marker::PhantomData<(T, B)>,
 
     acker: Acker,
     seq_head: usize,
     seq_tail: usize,
-    pending_acks: HashMap<usize, usize>,
+    /// The size of each finished batch, and whether it was delivered.
+    pending_acks: HashMap<usize, (usize, bool)>,
 }
 
 impl<T, S, B> BatchServiceSink<T, S, B>
@@ -209,11 +261,16 @@ where
                     let items_in_batch = batch.num_items();
                     let seqno = self.seq_head;
                     self.seq_head += 1;
-                    self.in_flight.push(
-                        self.service
-                            .call(batch.finish())
-                            .join(future::ok((seqno, items_in_batch))),
-                    );
+                    metrics::with_current(|metrics| {
+                        metrics
+                            .histogram("batch_events", BATCH_BUCKETS)
+                            .record(items_in_batch as f64)
+                    });
+                    let metadata = (seqno, items_in_batch, Instant::now());
+                    self.in_flight.push(WithMetadata {
+                        inner: self.service.call(batch.finish()),
+                        metadata: Some(metadata),
+                    });
                     return Ok(AsyncSink::Ready);
                 }
 
@@ -239,26 +296,43 @@ where
 
                 Ok(Async::Ready(None)) => return Ok(Async::Ready(())),
 
-                Ok(Async::Ready(Some((response, (seqno, batch_size))))) => {
-                    self.pending_acks.insert(seqno, batch_size);
+                Ok(Async::Ready(Some((result, (seqno, batch_size, started))))) => {
+                    match result {
+                        Ok(response) => {
+                            self.pending_acks.insert(seqno, (batch_size, true));
+                            metrics::with_current(|metrics| {
+                                metrics
+                                    .histogram("request_duration_seconds", DURATION_BUCKETS)
+                                    .record(started.elapsed().as_secs_f64());
+                                metrics.events_out().add(batch_size as u64);
+                            });
+                            trace!(message = "request succeeded.", ?response);
+                        }
+                        Err(error) => {
+                            // Retries are done by the service, so the batch
+                            // won't be sent again.
+                            self.pending_acks.insert(seqno, (batch_size, false));
+                            let error = error.into();
+                            error!(
+                                message = "request failed.",
+                                error = tracing::field::display(&error)
+                            );
+                            metrics::with_current(|metrics| metrics.error("request_failed"));
+                        }
+                    }
 
-                    let mut num_to_ack = 0;
-                    while let Some(ack_size) = self.pending_acks.remove(&self.seq_tail) {
-                        num_to_ack += ack_size;
+                    while let Some((ack_size, delivered)) = self.pending_acks.remove(&self.seq_tail)
+                    {
+                        if delivered {
+                            self.acker.ack(ack_size);
+                        } else {
+                            self.acker.fail(ack_size);
+                        }
                         self.seq_tail += 1
                     }
-                    self.acker.ack(num_to_ack);
-
-                    trace!(message = "request succeeded.", ?response);
                 }
 
-                Err(error) => {
-                    let error = error.into();
-                    error!(
-                        message = "request failed.",
-                        error = tracing::field::display(&error)
-                    )
-                }
+                Err(()) => unreachable!(),
             }
         }
     }
@@ -361,8 +435,34 @@ mod tests {
         std::thread::sleep(std::time::Duration::from_millis(50));
         assert_eq!(15, ack_counter.load(Ordering::Relaxed));
 
+        // A failed batch is acked as well, so the buffer keeps draining.
         drop(senders.lock().unwrap().remove(0)); // 16
-        std::thread::sleep(std::time::Duration::from_millis(50));
-        assert_eq!(15, ack_counter.load(Ordering::Relaxed));
+        wait_for(|| {
+            let current = ack_counter.load(Ordering::Relaxed);
+            assert!(current == 15 || current == 63);
+            63 == current
+        });
+    }
+
+    #[test]
+    fn batch_service_sink_acking_after_failure() {
+        let mut rt = Runtime::new().unwrap();
+
+        let (service, senders) = FakeService::new();
+        let (acker, ack_counter) = Acker::new_for_testing();
+
+        let service_sink = BatchServiceSink::new(service, acker);
+        rt.spawn(
+            service_sink
+                .send_all(stream::iter_ok(vec![vec![(); 1], vec![(); 2]]))
+                .map(|_| ()),
+        );
+
+        wait_for(|| senders.lock().unwrap().len() == 2);
+        drop(senders.lock().unwrap().remove(0)); // 1
+        wait_for(|| ack_counter.load(Ordering::Relaxed) == 1);
+
+        senders.lock().unwrap().remove(0).send(()).unwrap(); // 2
+        wait_for(|| ack_counter.load(Ordering::Relaxed) == 3);
     }
 }
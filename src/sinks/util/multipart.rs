@@ -0,0 +1,587 @@
+//! A sink streaming partitioned events into objects which are uploaded in
+//! parts as they fill up, rather than being buffered whole in memory.
+//!
+//! Each partition has at most one open object. An object is finalized once
+//! it reaches the configured size or has been open for the configured time,
+//! and the next event for its partition starts a new one. Uploads run one
+//! request at a time per object, so parts are always sent in order.
+
+use super::{
+    buffer::{Buffer, PartitionInnerBuffer},
+    Batch, BatchSettings, Partition,
+};
+use crate::buffers::Acker;
+use bytes::Bytes;
+use futures01::{
+    stream::FuturesUnordered, try_ready, Async, AsyncSink, Future, Poll, Sink, StartSend, Stream,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    mem,
+    time::{Duration, Instant},
+};
+use tokio::timer::Delay;
+
+/// The smallest part size accepted by S3 for every part but the last.
+pub const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+
+// Parts queued per object before the sink applies backpressure.
+const MAX_QUEUED_PARTS: usize = 2;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct MultipartConfig {
+    pub part_size: Option<usize>,
+    pub abort_dangling_uploads: Option<bool>,
+}
+
+impl MultipartConfig {
+    pub fn part_size(&self) -> usize {
+        self.part_size.unwrap_or(MIN_PART_SIZE)
+    }
+
+    pub fn abort_dangling_uploads(&self) -> bool {
+        self.abort_dangling_uploads.unwrap_or(true)
+    }
+}
+
+pub type UploadFuture<T> = Box<dyn Future<Item = T, Error = crate::Error> + Send + 'static>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Part {
+    /// The part number, starting at one.
+    pub number: usize,
+    /// The position of the first byte of this part in the object.
+    pub offset: usize,
+    pub body: Vec<u8>,
+}
+
+/// An object store that can assemble objects from parts uploaded in order.
+pub trait MultipartUploader: Send + 'static {
+    /// The state of an upload in progress, such as its id and the parts
+    /// uploaded so far.
+    type Upload: Clone + Send + 'static;
+
+    /// Starts uploading a new object whose key starts with `prefix`.
+    fn create(&self, prefix: Bytes) -> UploadFuture<Self::Upload>;
+
+    /// Uploads a part of the object. Every part except the final one has
+    /// exactly the configured part size.
+    fn upload_part(&self, upload: Self::Upload, part: Part) -> UploadFuture<Self::Upload>;
+
+    /// Uploads the final part, which may be empty, and assembles the object.
+    fn complete(&self, upload: Self::Upload, part: Part) -> UploadFuture<()>;
+
+    /// Discards an upload along with the parts uploaded so far.
+    fn abort(&self, upload: Self::Upload) -> UploadFuture<()>;
+
+    /// Discards uploads left behind by previous runs, if the store keeps
+    /// track of them.
+    fn abort_dangling(&self) -> Option<UploadFuture<()>> {
+        None
+    }
+}
+
+enum State<U> {
+    // No request has been made yet.
+    New,
+    Busy(UploadFuture<U>),
+    Idle(U),
+    Completing(UploadFuture<()>),
+}
+
+struct Object<U: MultipartUploader> {
+    prefix: Bytes,
+    buffer: Buffer,
+    events: usize,
+    size: usize,
+    next_part: usize,
+    parts: VecDeque<Part>,
+    final_part: Option<Part>,
+    // The last known state of the upload, used to abort it on errors.
+    upload: Option<U::Upload>,
+    state: State<U::Upload>,
+    deadline: Delay,
+    // Assigned once the object is finished, or when it's aborted before that.
+    seqno: Option<usize>,
+}
+
+impl<U: MultipartUploader> Object<U> {
+    fn new(prefix: Bytes, gzip: bool, timeout: Duration) -> Self {
+        Self {
+            prefix,
+            buffer: Buffer::new(gzip),
+            events: 0,
+            size: 0,
+            next_part: 1,
+            parts: VecDeque::new(),
+            final_part: None,
+            upload: None,
+            state: State::New,
+            deadline: Delay::new(Instant::now() + timeout),
+            seqno: None,
+        }
+    }
+
+    fn push(&mut self, bytes: &[u8], part_size: usize) {
+        self.buffer.push(bytes);
+        self.events += 1;
+        while let Some(body) = self.buffer.take_output(part_size) {
+            let part = self.next_part(body);
+            self.parts.push_back(part);
+        }
+    }
+
+    fn next_part(&mut self, body: Vec<u8>) -> Part {
+        let part = Part {
+            number: self.next_part,
+            offset: self.size,
+            body,
+        };
+        self.next_part += 1;
+        self.size += part.body.len();
+        part
+    }
+
+    fn len(&self) -> usize {
+        self.size + self.buffer.size()
+    }
+
+    fn finish(&mut self) {
+        let body = mem::replace(&mut self.buffer, Buffer::new(false)).finish();
+        self.final_part = Some(self.next_part(body));
+    }
+
+    /// Sends the queued parts, resolving once the object has been completed.
+    fn poll_upload(&mut self, uploader: &U) -> Poll<(), crate::Error> {
+        loop {
+            self.state = match mem::replace(&mut self.state, State::New) {
+                State::New => {
+                    if self.parts.is_empty() && self.final_part.is_none() {
+                        return Ok(Async::NotReady);
+                    }
+                    State::Busy(uploader.create(self.prefix.clone()))
+                }
+                State::Busy(mut future) => match future.poll()? {
+                    Async::Ready(upload) => {
+                        self.upload = Some(upload.clone());
+                        State::Idle(upload)
+                    }
+                    Async::NotReady => {
+                        self.state = State::Busy(future);
+                        return Ok(Async::NotReady);
+                    }
+                },
+                State::Idle(upload) => {
+                    if let Some(part) = self.parts.pop_front() {
+                        State::Busy(uploader.upload_part(upload, part))
+                    } else if let Some(part) = self.final_part.take() {
+                        State::Completing(uploader.complete(upload, part))
+                    } else {
+                        self.state = State::Idle(upload);
+                        return Ok(Async::NotReady);
+                    }
+                }
+                State::Completing(mut future) => {
+                    let result = future.poll();
+                    self.state = State::Completing(future);
+                    return result;
+                }
+            }
+        }
+    }
+}
+
+pub struct MultipartSink<U: MultipartUploader> {
+    uploader: U,
+    part_size: usize,
+    max_object_size: usize,
+    timeout: Duration,
+    gzip: bool,
+    objects: HashMap<Bytes, Object<U>>,
+    completing: Vec<Object<U>>,
+    aborting: FuturesUnordered<UploadFuture<()>>,
+    closing: bool,
+    acker: Acker,
+    seq_head: usize,
+    seq_tail: usize,
+    pending_acks: HashMap<usize, (usize, bool)>,
+}
+
+impl<U: MultipartUploader> MultipartSink<U> {
+    /// Creates a sink uploading objects of up to `batch.size` bytes in parts
+    /// of `part_size`, finalizing them after `batch.timeout` at the latest.
+    pub fn new(
+        uploader: U,
+        part_size: usize,
+        batch: &BatchSettings,
+        gzip: bool,
+        acker: Acker,
+    ) -> Self {
+        let mut aborting = FuturesUnordered::new();
+        if let Some(future) = uploader.abort_dangling() {
+            aborting.push(future);
+        }
+
+        Self {
+            uploader,
+            part_size,
+            max_object_size: batch.size,
+            timeout: batch.timeout,
+            gzip,
+            objects: HashMap::new(),
+            completing: Vec::new(),
+            aborting,
+            closing: false,
+            acker,
+            seq_head: 0,
+            seq_tail: 0,
+            pending_acks: HashMap::new(),
+        }
+    }
+
+    fn finish_object(&mut self, mut object: Object<U>) {
+        object.finish();
+        object.seqno = Some(self.next_seqno());
+        self.completing.push(object);
+    }
+
+    fn next_seqno(&mut self) -> usize {
+        let seqno = self.seq_head;
+        self.seq_head += 1;
+        seqno
+    }
+
+    fn ack(&mut self, seqno: usize, events: usize, delivered: bool) {
+        self.pending_acks.insert(seqno, (events, delivered));
+
+        while let Some((ack_size, delivered)) = self.pending_acks.remove(&self.seq_tail) {
+            if delivered {
+                self.acker.ack(ack_size);
+            } else {
+                self.acker.fail(ack_size);
+            }
+            self.seq_tail += 1
+        }
+    }
+
+    fn abort(&mut self, object: Object<U>, error: crate::Error) {
+        error!(
+            message = "upload failed; discarding object.",
+            %error,
+            events = object.events,
+        );
+        // The events of the object are failed in order, so the ones of the
+        // objects finished after it can still be acked.
+        let seqno = match object.seqno {
+            Some(seqno) => seqno,
+            None => self.next_seqno(),
+        };
+        self.ack(seqno, object.events, false);
+        if let Some(upload) = object.upload {
+            self.aborting.push(self.uploader.abort(upload));
+        }
+    }
+}
+
+impl<U: MultipartUploader> Sink for MultipartSink<U> {
+    type SinkItem = PartitionInnerBuffer<Vec<u8>, Bytes>;
+    type SinkError = ();
+
+    fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        let backlogged = |sink: &Self, key: &Bytes| {
+            sink.objects
+                .get(key)
+                .map_or(false, |object| object.parts.len() >= MAX_QUEUED_PARTS)
+        };
+
+        let key = item.partition();
+        if backlogged(self, &key) {
+            self.poll_complete()?;
+            if backlogged(self, &key) {
+                return Ok(AsyncSink::NotReady(item));
+            }
+        }
+
+        let (bytes, _) = item.into_parts();
+        let (gzip, timeout, part_size) = (self.gzip, self.timeout, self.part_size);
+        let object = self
+            .objects
+            .entry(key.clone())
+            .or_insert_with(|| Object::new(key.clone(), gzip, timeout));
+        object.push(&bytes, part_size);
+
+        if object.len() >= self.max_object_size {
+            let object = self.objects.remove(&key).unwrap();
+            self.finish_object(object);
+        }
+
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        // Finalize the objects which have been open for too long.
+        let closing = self.closing;
+        let expired = self
+            .objects
+            .iter_mut()
+            .filter_map(|(key, object)| {
+                let elapsed = object
+                    .deadline
+                    .poll()
+                    .map_err(|error| panic!("timer error: {}; this is a bug!", error))
+                    .unwrap()
+                    .is_ready();
+                if elapsed || closing {
+                    Some(key.clone())
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        for key in expired {
+            let object = self.objects.remove(&key).unwrap();
+            self.finish_object(object);
+        }
+
+        let mut failed = Vec::new();
+        for (key, object) in self.objects.iter_mut() {
+            if let Err(error) = object.poll_upload(&self.uploader) {
+                failed.push((key.clone(), error));
+            }
+        }
+        for (key, error) in failed {
+            let object = self.objects.remove(&key).unwrap();
+            self.abort(object, error);
+        }
+
+        let mut i = 0;
+        while i < self.completing.len() {
+            match self.completing[i].poll_upload(&self.uploader) {
+                Ok(Async::NotReady) => i += 1,
+                Ok(Async::Ready(())) => {
+                    let object = self.completing.swap_remove(i);
+                    debug!(
+                        message = "object uploaded.",
+                        events = object.events,
+                        bytes = object.size
+                    );
+                    self.ack(object.seqno.unwrap(), object.events, true);
+                }
+                Err(error) => {
+                    let object = self.completing.swap_remove(i);
+                    self.abort(object, error);
+                }
+            }
+        }
+
+        loop {
+            match self.aborting.poll() {
+                Ok(Async::Ready(Some(()))) => {}
+                Ok(Async::Ready(None)) | Ok(Async::NotReady) => break,
+                Err(error) => error!(message = "failed to abort upload.", %error),
+            }
+        }
+
+        if self.objects.is_empty() && self.completing.is_empty() && self.aborting.is_empty() {
+            Ok(Async::Ready(()))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+
+    fn close(&mut self) -> Poll<(), Self::SinkError> {
+        self.closing = true;
+        try_ready!(self.poll_complete());
+        Ok(Async::Ready(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{
+        finalization::PendingFinalizers, BatchNotifier, BatchStatus, EventFinalizers,
+    };
+    use crate::runtime::Runtime;
+    use crate::test_util::wait_for;
+    use futures01::{future, stream};
+    use std::sync::{atomic::Ordering, Arc, Mutex};
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum Call {
+        Create(Bytes),
+        Part(usize, usize, usize),
+        Complete(usize, usize, usize),
+        Abort,
+    }
+
+    #[derive(Clone, Default)]
+    struct FakeUploader {
+        calls: Arc<Mutex<Vec<(usize, Call)>>>,
+        uploads: Arc<Mutex<usize>>,
+        fail_parts: bool,
+    }
+
+    impl FakeUploader {
+        fn record(&self, upload: usize, call: Call) {
+            self.calls.lock().unwrap().push((upload, call));
+        }
+
+        fn calls(&self) -> Vec<(usize, Call)> {
+            self.calls.lock().unwrap().clone()
+        }
+    }
+
+    impl MultipartUploader for FakeUploader {
+        type Upload = usize;
+
+        fn create(&self, prefix: Bytes) -> UploadFuture<usize> {
+            let mut uploads = self.uploads.lock().unwrap();
+            *uploads += 1;
+            self.record(*uploads, Call::Create(prefix));
+            Box::new(future::ok::<_, crate::Error>(*uploads))
+        }
+
+        fn upload_part(&self, upload: usize, part: Part) -> UploadFuture<usize> {
+            self.record(
+                upload,
+                Call::Part(part.number, part.offset, part.body.len()),
+            );
+            if self.fail_parts {
+                Box::new(future::err::<usize, _>("part failed".into()))
+            } else {
+                Box::new(future::ok::<_, crate::Error>(upload))
+            }
+        }
+
+        fn complete(&self, upload: usize, part: Part) -> UploadFuture<()> {
+            self.record(
+                upload,
+                Call::Complete(part.number, part.offset, part.body.len()),
+            );
+            Box::new(future::ok::<_, crate::Error>(()))
+        }
+
+        fn abort(&self, upload: usize) -> UploadFuture<()> {
+            self.record(upload, Call::Abort);
+            Box::new(future::ok::<_, crate::Error>(()))
+        }
+    }
+
+    fn items(key: &str, count: usize, size: usize) -> Vec<PartitionInnerBuffer<Vec<u8>, Bytes>> {
+        (0..count)
+            .map(|_| PartitionInnerBuffer::new(vec![b'x'; size], Bytes::from(key)))
+            .collect()
+    }
+
+    fn batch(size: usize, timeout_secs: u64) -> BatchSettings {
+        BatchSettings {
+            size,
+            timeout: Duration::from_secs(timeout_secs),
+        }
+    }
+
+    #[test]
+    fn multipart_uploads_parts_and_rolls_over() {
+        let mut rt = Runtime::new().unwrap();
+        let uploader = FakeUploader::default();
+        let (acker, ack_counter) = Acker::new_for_testing();
+
+        let sink = MultipartSink::new(uploader.clone(), 10, &batch(25, 60), false, acker);
+        let mut input = items("a/", 7, 4);
+        input.extend(items("b/", 1, 4));
+        rt.block_on(sink.send_all(stream::iter_ok(input))).unwrap();
+
+        let mut calls = uploader.calls();
+        calls.sort_by_key(|(upload, _)| *upload);
+        assert_eq!(
+            calls,
+            vec![
+                (1, Call::Create("a/".into())),
+                (1, Call::Part(1, 0, 10)),
+                (1, Call::Part(2, 10, 10)),
+                (1, Call::Complete(3, 20, 8)),
+                (2, Call::Create("b/".into())),
+                (2, Call::Complete(1, 0, 4)),
+            ]
+        );
+        assert_eq!(ack_counter.load(Ordering::Relaxed), 8);
+    }
+
+    #[test]
+    fn multipart_finalizes_after_timeout() {
+        let mut rt = Runtime::new().unwrap();
+        let uploader = FakeUploader::default();
+        let (acker, ack_counter) = Acker::new_for_testing();
+
+        let sink = MultipartSink::new(uploader.clone(), 10, &batch(1000, 1), false, acker);
+        let (tx, rx) = futures01::sync::mpsc::channel(10);
+        rt.spawn(sink.send_all(rx.map_err(|_| ())).map(|_| ()));
+
+        // Keep the sender around so the sink isn't closed.
+        let (_tx, _) = rt
+            .block_on(tx.send_all(stream::iter_ok(items("a/", 3, 4))))
+            .unwrap();
+
+        wait_for(|| ack_counter.load(Ordering::Relaxed) == 3);
+        assert_eq!(
+            uploader.calls(),
+            vec![
+                (1, Call::Create("a/".into())),
+                (1, Call::Part(1, 0, 10)),
+                (1, Call::Complete(2, 10, 2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn multipart_aborts_failed_uploads() {
+        let mut rt = Runtime::new().unwrap();
+        let uploader = FakeUploader {
+            fail_parts: true,
+            ..Default::default()
+        };
+        let (acker, ack_counter) = Acker::new_for_testing();
+
+        let (failed_batch, failed_rx) = BatchNotifier::new_with_receiver();
+        let (delivered_batch, delivered_rx) = BatchNotifier::new_with_receiver();
+        let pending = Arc::new(Mutex::new(PendingFinalizers::default()));
+        for _ in 0..3 {
+            let finalizers = EventFinalizers::new(Arc::clone(&failed_batch));
+            pending.lock().unwrap().push(finalizers);
+        }
+        pending
+            .lock()
+            .unwrap()
+            .push(EventFinalizers::new(Arc::clone(&delivered_batch)));
+        drop((failed_batch, delivered_batch));
+        let acker = Acker::Finalizing(Arc::clone(&pending), Box::new(acker));
+
+        // The first object fails before the second one is uploaded, which
+        // must still be acked.
+        let sink = MultipartSink::new(uploader.clone(), 10, &batch(1000, 60), false, acker);
+        let (sink, _) = rt
+            .block_on(sink.send_all(stream::iter_ok(items("a/", 3, 4))))
+            .unwrap();
+        rt.block_on(sink.send_all(stream::iter_ok(items("b/", 1, 4))))
+            .unwrap();
+
+        assert_eq!(
+            uploader.calls(),
+            vec![
+                (1, Call::Create("a/".into())),
+                (1, Call::Part(1, 0, 10)),
+                (1, Call::Abort),
+                (2, Call::Create("b/".into())),
+                (2, Call::Complete(1, 0, 4)),
+            ]
+        );
+        assert!(pending.lock().unwrap().is_empty());
+        assert_eq!(failed_rx.wait(), Ok(BatchStatus::Failed));
+        assert_eq!(delivered_rx.wait(), Ok(BatchStatus::Delivered));
+        // The failed object is acked in the buffer all the same.
+        assert_eq!(ack_counter.load(Ordering::Relaxed), 4);
+    }
+}
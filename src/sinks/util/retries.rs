@@ -1,5 +1,8 @@
-use crate::Error;
-use futures01::{try_ready, Async, Future, Poll};
+use crate::{metrics, Error};
+use futures01::{
+    future::{self, Either, Loop},
+    try_ready, Async, Future, Poll,
+};
 use std::{
     cmp,
     time::{Duration, Instant},
@@ -74,14 +77,49 @@ impl<L: RetryLogic> FixedRetryPolicy<L> {
         self.current_duration
     }
 
+    fn attempts_exhausted(&self) -> bool {
+        self.remaining_attempts == 0
+    }
+
+    /// Waits for the current backoff, resolving to the policy for the next attempt.
     fn build_retry(&self) -> RetryPolicyFuture<L> {
         let policy = self.advance();
         let next = Instant::now() + policy.backoff();
         let delay = Delay::new(next);
 
         debug!(message = "retrying request.", delay_ms = %self.backoff().as_millis());
+        metrics::with_current(|metrics| metrics.counter("retries_total").increment());
         RetryPolicyFuture { delay, policy }
     }
+
+    /// Runs the requests built by `request` until one succeeds, fails with an
+    /// error that can't be retried or the attempts are exhausted. This is
+    /// used for requests which aren't sent through a tower service.
+    pub(crate) fn retry_request<F, R>(
+        self,
+        request: F,
+    ) -> impl Future<Item = R::Item, Error = L::Error>
+    where
+        F: FnMut() -> R,
+        R: Future<Error = L::Error>,
+    {
+        future::loop_fn((self, request), |(policy, mut request)| {
+            request().then(move |result| match result {
+                Err(error)
+                    if !policy.attempts_exhausted() && policy.logic.is_retriable_error(&error) =>
+                {
+                    warn!("retrying after error: {}", error);
+                    Either::A(
+                        policy
+                            .build_retry()
+                            .map_err(|()| unreachable!("retry backoff can't fail"))
+                            .map(move |policy| Loop::Continue((policy, request))),
+                    )
+                }
+                result => Either::B(future::result(result.map(Loop::Break))),
+            })
+        })
+    }
 }
 
 impl<Req, Res, L> Policy<Req, Res, Error> for FixedRetryPolicy<L>
@@ -96,6 +134,7 @@ where
             Ok(response) => {
                 if self.remaining_attempts == 0 {
                     error!("retries exhausted");
+                    metrics::with_current(|metrics| metrics.error("retries_exhausted"));
                     return None;
                 }
 
@@ -116,6 +155,7 @@ where
             Err(error) => {
                 if self.remaining_attempts == 0 {
                     error!(message = "retries exhausted.", %error);
+                    metrics::with_current(|metrics| metrics.error("retries_exhausted"));
                     return None;
                 }
 
@@ -271,6 +311,46 @@ mod tests {
         });
     }
 
+    #[test]
+    fn retry_request_until_success() {
+        clock::mock(|clock| {
+            trace_init();
+
+            let policy = FixedRetryPolicy::new(
+                5,
+                Duration::from_secs(1),
+                Duration::from_secs(10),
+                SvcRetryLogic,
+            );
+
+            let mut attempts = 0;
+            let mut fut = policy.retry_request(move || {
+                attempts += 1;
+                if attempts < 3 {
+                    future::err(Error(true))
+                } else {
+                    future::ok(attempts)
+                }
+            });
+            assert_not_ready!(fut.poll());
+
+            clock.advance(Duration::from_secs(2));
+            assert_not_ready!(fut.poll());
+
+            clock.advance(Duration::from_secs(2));
+            assert_eq!(fut.wait().unwrap(), 3);
+
+            let policy = FixedRetryPolicy::new(
+                5,
+                Duration::from_secs(1),
+                Duration::from_secs(10),
+                SvcRetryLogic,
+            );
+            let mut fut = policy.retry_request(|| future::err::<(), _>(Error(false)));
+            assert_err!(fut.poll());
+        });
+    }
+
     #[test]
     fn backoff_grows_to_max() {
         let mut policy = FixedRetryPolicy::new(
@@ -102,3 +102,49 @@ pub fn client(resolver: Resolver) -> crate::Result<Client> {
 
     Ok(HttpClient::from_connector(https))
 }
+
+/// Splits records into chunks which fit the record count and size limits of
+/// a single request. A record larger than `max_bytes` gets a chunk of its own.
+pub fn chunk_records<T>(
+    records: Vec<T>,
+    max_records: usize,
+    max_bytes: usize,
+    size: impl Fn(&T) -> usize,
+) -> Vec<Vec<T>> {
+    let mut chunks = Vec::new();
+    let mut chunk = Vec::new();
+    let mut chunk_bytes = 0;
+
+    for record in records {
+        let record_bytes = size(&record);
+        if !chunk.is_empty()
+            && (chunk.len() >= max_records || chunk_bytes + record_bytes > max_bytes)
+        {
+            chunks.push(std::mem::replace(&mut chunk, Vec::new()));
+            chunk_bytes = 0;
+        }
+        chunk_bytes += record_bytes;
+        chunk.push(record);
+    }
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunk_records_by_count_and_size() {
+        let chunks = chunk_records(vec![1, 2, 3, 4, 5], 2, 100, |r| *r);
+        assert_eq!(chunks, vec![vec![1, 2], vec![3, 4], vec![5]]);
+
+        let chunks = chunk_records(vec![4, 5, 1, 9, 2], 10, 9, |r| *r);
+        assert_eq!(chunks, vec![vec![4, 5], vec![1], vec![9], vec![2]]);
+
+        assert!(chunk_records(Vec::<usize>::new(), 10, 10, |r| *r).is_empty());
+    }
+}
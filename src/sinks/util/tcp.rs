@@ -1,14 +1,12 @@
 use crate::{
     dns::Resolver,
-    sinks::util::{encode_event, encoding::EncodingConfig, Encoding, SinkExt},
+    sinks::util::{encode_event, encoding::EncodingConfig, finalize_dropped, Encoding, SinkExt},
     sinks::{Healthcheck, RouterSink},
     tls::{TlsConfig, TlsConnectorExt, TlsSettings},
     topology::config::SinkContext,
 };
 use bytes::Bytes;
-use futures01::{
-    future, stream::iter_ok, try_ready, Async, AsyncSink, Future, Poll, Sink, StartSend,
-};
+use futures01::{future, try_ready, Async, AsyncSink, Future, Poll, Sink, StartSend};
 use serde::{Deserialize, Serialize};
 use snafu::{ResultExt, Snafu};
 use std::net::SocketAddr;
@@ -273,7 +271,9 @@ pub fn raw_tcp(
     Box::new(
         TcpSink::new(host, port, cx.resolver(), tls)
             .stream_ack(cx.acker())
-            .with_flat_map(move |event| iter_ok(encode_event(event, &encoding))),
+            .with_flat_map(finalize_dropped(cx.acker(), move |event| {
+                encode_event(event, &encoding)
+            })),
     )
 }
 
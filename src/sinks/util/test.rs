@@ -5,6 +5,7 @@ use crate::{
 use futures01::{sync::mpsc, Future, Sink, Stream};
 use hyper::{service::service_fn_ok, Body, Request, Response, Server};
 use serde::Deserialize;
+use std::sync::{Arc, Mutex};
 
 pub fn load_sink<T>(config: &str) -> crate::Result<(T, SinkContext, Runtime)>
 where
@@ -23,10 +24,25 @@ pub fn build_test_server(
     mpsc::Receiver<(http::request::Parts, hyper::Chunk)>,
     stream_cancel::Trigger,
     impl Future<Item = (), Error = ()>,
+) {
+    build_test_server_with_responses(addr, Vec::new())
+}
+
+/// Like `build_test_server`, but answers requests with the given response
+/// bodies, in order. Once they run out, responses are empty.
+pub fn build_test_server_with_responses(
+    addr: &std::net::SocketAddr,
+    responses: Vec<String>,
+) -> (
+    mpsc::Receiver<(http::request::Parts, hyper::Chunk)>,
+    stream_cancel::Trigger,
+    impl Future<Item = (), Error = ()>,
 ) {
     let (tx, rx) = mpsc::channel(100);
+    let responses = Arc::new(Mutex::new(responses.into_iter()));
     let service = move || {
         let tx = tx.clone();
+        let responses = responses.clone();
         service_fn_ok(move |req: Request<Body>| {
             let (parts, body) = req.into_parts();
 
@@ -39,7 +55,10 @@ pub fn build_test_server(
                     .map_err(|e| panic!(e)),
             );
 
-            Response::new(Body::empty())
+            match responses.lock().unwrap().next() {
+                Some(response) => Response::new(Body::from(response)),
+                None => Response::new(Body::empty()),
+            }
         })
     };
 
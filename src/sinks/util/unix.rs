@@ -1,13 +1,11 @@
 use crate::sinks::util::SinkExt;
 use crate::{
-    sinks::util::{encode_event, encoding::EncodingConfig, Encoding},
+    sinks::util::{encode_event, encoding::EncodingConfig, finalize_dropped, Encoding},
     sinks::{Healthcheck, RouterSink},
     topology::config::SinkContext,
 };
 use bytes::Bytes;
-use futures01::{
-    future, stream::iter_ok, try_ready, Async, AsyncSink, Future, Poll, Sink, StartSend,
-};
+use futures01::{future, try_ready, Async, AsyncSink, Future, Poll, Sink, StartSend};
 use serde::{Deserialize, Serialize};
 use snafu::Snafu;
 use std::io;
@@ -37,7 +35,9 @@ impl UnixSinkConfig {
         let sink = Box::new(
             UnixSink::new(self.path.clone())
                 .stream_ack(cx.acker())
-                .with_flat_map(move |event| iter_ok(encode_event(event, &encoding))),
+                .with_flat_map(finalize_dropped(cx.acker(), move |event| {
+                    encode_event(event, &encoding)
+                })),
         );
         let healthcheck = unix_healthcheck(self.path.clone());
 
@@ -1,12 +1,12 @@
 use crate::{
     event::proto,
     sinks::util::tcp::TcpSink,
-    sinks::util::SinkExt,
+    sinks::util::{finalize_dropped, SinkExt},
     topology::config::{DataType, SinkConfig, SinkContext, SinkDescription},
     Event,
 };
 use bytes::{BufMut, Bytes, BytesMut};
-use futures01::{stream::iter_ok, Sink};
+use futures01::Sink;
 use prost::Message;
 use serde::{Deserialize, Serialize};
 use snafu::Snafu;
@@ -45,7 +45,9 @@ impl SinkConfig for VectorSinkConfig {
 
         let sink = TcpSink::new(host.clone(), port, cx.resolver(), None)
             .stream_ack(cx.acker())
-            .with_flat_map(move |event| iter_ok(encode_event(event)));
+            .with_flat_map(finalize_dropped(cx.acker(), move |event| {
+                encode_event(event)
+            }));
         let healthcheck = super::util::tcp::tcp_healthcheck(host, port, cx.resolver());
 
         Ok((Box::new(sink), healthcheck))
@@ -0,0 +1,96 @@
+mod poll;
+mod subscription;
+
+use crate::{
+    event::Event,
+    topology::config::{DataType, GlobalOptions, SourceConfig, SourceDescription},
+};
+use futures01::sync::mpsc;
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+// TODO: add back when serde-rs/serde#1358 is addressed
+// #[serde(deny_unknown_fields)]
+pub struct AwsCloudwatchLogsConfig {
+    #[serde(flatten)]
+    mode: Mode,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+enum Mode {
+    Poll(poll::PollConfig),
+    Subscription(subscription::SubscriptionConfig),
+}
+
+inventory::submit! {
+    SourceDescription::new_without_default::<AwsCloudwatchLogsConfig>("aws_cloudwatch_logs")
+}
+
+#[typetag::serde(name = "aws_cloudwatch_logs")]
+impl SourceConfig for AwsCloudwatchLogsConfig {
+    fn build(
+        &self,
+        name: &str,
+        globals: &GlobalOptions,
+        out: mpsc::Sender<Event>,
+    ) -> crate::Result<super::Source> {
+        match &self.mode {
+            Mode::Poll(config) => config.build(name, globals, out),
+            Mode::Subscription(config) => config.build(out),
+        }
+    }
+
+    fn output_type(&self) -> DataType {
+        DataType::Log
+    }
+
+    fn source_type(&self) -> &'static str {
+        "aws_cloudwatch_logs"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aws_cloudwatch_logs_parses_modes() {
+        let config: AwsCloudwatchLogsConfig = toml::from_str(
+            r#"
+            mode = "poll"
+            region = "us-east-1"
+            group_names = ["/aws/lambda/api"]
+            stream_prefix = "2020/"
+            "#,
+        )
+        .unwrap();
+        assert!(matches_mode(&config, "poll"));
+
+        let config: AwsCloudwatchLogsConfig = toml::from_str(
+            r#"
+            mode = "subscription"
+            address = "0.0.0.0:8443"
+            access_key = "secret"
+            "#,
+        )
+        .unwrap();
+        assert!(matches_mode(&config, "subscription"));
+
+        assert!(toml::from_str::<AwsCloudwatchLogsConfig>(
+            r#"
+            mode = "subscription"
+            region = "us-east-1"
+            address = "0.0.0.0:8443"
+            "#,
+        )
+        .is_err());
+    }
+
+    fn matches_mode(config: &AwsCloudwatchLogsConfig, mode: &str) -> bool {
+        match config.mode {
+            Mode::Poll(_) => mode == "poll",
+            Mode::Subscription(_) => mode == "subscription",
+        }
+    }
+}
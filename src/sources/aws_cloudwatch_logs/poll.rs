@@ -0,0 +1,361 @@
+use crate::{
+    event::Event, region::RegionOrEndpoint, sinks::util::rusoto::AwsCredentialsProvider,
+    sources::util::firehose::logs_event, topology::config::GlobalOptions,
+};
+use futures01::{
+    future::{self, poll_fn, Either, Loop},
+    sync::mpsc,
+    Future, Sink,
+};
+use rusoto_core::{HttpClient, Region};
+use rusoto_logs::{CloudWatchLogs, CloudWatchLogsClient, FilterLogEventsRequest, FilteredLogEvent};
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+use std::{
+    collections::BTreeMap,
+    convert::TryInto,
+    fs,
+    io::{self, ErrorKind},
+    mem,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::timer::Delay;
+
+const CHECKPOINTS_FILENAME: &str = "checkpoints.json";
+
+// How long before the latest event read each poll starts, as events can be
+// ingested a while after their timestamp.
+const LATE_EVENTS_MS: i64 = 5 * 60 * 1000;
+
+/// Polls `FilterLogEvents` for the events of each log group.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub(super) struct PollConfig {
+    group_names: Vec<String>,
+    stream_names: Option<Vec<String>>,
+    stream_prefix: Option<String>,
+    filter_pattern: Option<String>,
+    #[serde(default = "default_poll_secs")]
+    poll_secs: u64,
+    #[serde(default)]
+    start_at_beginning: bool,
+    data_dir: Option<PathBuf>,
+    #[serde(flatten)]
+    region: RegionOrEndpoint,
+    assume_role: Option<String>,
+}
+
+fn default_poll_secs() -> u64 {
+    10
+}
+
+#[derive(Debug, Snafu)]
+enum BuildError {
+    #[snafu(display("At least one log group name is required"))]
+    NoGroupNames,
+    #[snafu(display("Only one of stream_names and stream_prefix can be set"))]
+    StreamNamesWithPrefix,
+    #[snafu(display("Unable to load checkpoints from {:?}: {}", path, source))]
+    LoadCheckpoints { path: PathBuf, source: io::Error },
+}
+
+impl PollConfig {
+    pub(super) fn build(
+        &self,
+        name: &str,
+        globals: &GlobalOptions,
+        out: mpsc::Sender<Event>,
+    ) -> crate::Result<crate::sources::Source> {
+        if self.group_names.is_empty() {
+            return Err(BuildError::NoGroupNames.into());
+        }
+        if self.stream_names.is_some() && self.stream_prefix.is_some() {
+            return Err(BuildError::StreamNamesWithPrefix.into());
+        }
+
+        let data_dir = globals.resolve_and_make_data_subdir(self.data_dir.as_ref(), name)?;
+        let path = data_dir.join(CHECKPOINTS_FILENAME);
+        let checkpoints = Checkpoints::load(path.clone()).context(LoadCheckpoints { path })?;
+
+        let region: Region = self.region.clone().try_into()?;
+        let client = CloudWatchLogsClient::new_with(
+            HttpClient::new()?,
+            AwsCredentialsProvider::new(&region, self.assume_role.clone())?,
+            region,
+        );
+
+        // Without a checkpoint, start from now unless reading whole groups.
+        let start = if self.start_at_beginning {
+            0
+        } else {
+            chrono::Utc::now().timestamp_millis()
+        };
+
+        let mut pollers = Vec::new();
+        for group in &self.group_names {
+            let checkpoint = checkpoints
+                .get(group)
+                .unwrap_or_else(|| Checkpoint::new(start));
+
+            let poller = GroupPoller {
+                client: client.clone(),
+                group: group.clone(),
+                stream_names: self.stream_names.clone(),
+                stream_prefix: self.stream_prefix.clone(),
+                filter_pattern: self.filter_pattern.clone(),
+                checkpoint,
+                checkpoints: checkpoints.clone(),
+                out: out.clone(),
+            };
+            pollers.push(poller.run(Duration::from_secs(self.poll_secs)));
+        }
+
+        Ok(Box::new(future::join_all(pollers).map(|_| ())))
+    }
+}
+
+/// The latest timestamp read from a group, and the timestamps of the events
+/// read since `LATE_EVENTS_MS` before it by id. Polls start that far back to
+/// pick up the events ingested late, skipping the ones already read.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+struct Checkpoint {
+    timestamp: i64,
+    event_ids: BTreeMap<String, i64>,
+}
+
+impl Checkpoint {
+    fn new(timestamp: i64) -> Self {
+        Self {
+            timestamp,
+            event_ids: BTreeMap::new(),
+        }
+    }
+
+    /// The time the next poll starts at.
+    fn start_time(&self) -> i64 {
+        (self.timestamp - LATE_EVENTS_MS).max(0)
+    }
+
+    /// Moves the checkpoint past an event, returning `false` if it has
+    /// already been read.
+    fn advance(&mut self, timestamp: i64, id: &str) -> bool {
+        self.timestamp = self.timestamp.max(timestamp);
+        self.event_ids.insert(id.to_owned(), timestamp).is_none()
+    }
+
+    /// Forgets the events before the start of the next poll, which it
+    /// won't return again.
+    fn prune(&mut self) {
+        let start_time = self.start_time();
+        self.event_ids = mem::replace(&mut self.event_ids, BTreeMap::new())
+            .into_iter()
+            .filter(|(_, timestamp)| *timestamp >= start_time)
+            .collect();
+    }
+}
+
+/// The checkpoints of all groups, saved together in the data directory.
+#[derive(Clone)]
+struct Checkpoints {
+    path: PathBuf,
+    groups: Arc<Mutex<BTreeMap<String, Checkpoint>>>,
+}
+
+impl Checkpoints {
+    fn load(path: PathBuf) -> io::Result<Self> {
+        let groups = match fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data)
+                .map_err(|error| io::Error::new(ErrorKind::InvalidData, error))?,
+            Err(error) if error.kind() == ErrorKind::NotFound => BTreeMap::new(),
+            Err(error) => return Err(error),
+        };
+
+        Ok(Self {
+            path,
+            groups: Arc::new(Mutex::new(groups)),
+        })
+    }
+
+    fn get(&self, group: &str) -> Option<Checkpoint> {
+        self.groups.lock().unwrap().get(group).cloned()
+    }
+
+    fn set(&self, group: &str, checkpoint: Checkpoint) {
+        let mut groups = self.groups.lock().unwrap();
+        groups.insert(group.to_owned(), checkpoint);
+    }
+
+    /// Writes all checkpoints to a temporary file first, so a crash never
+    /// leaves a partially written file behind.
+    fn save(&self) -> io::Result<()> {
+        let data = serde_json::to_vec(&*self.groups.lock().unwrap())
+            .expect("Error encoding checkpoints as json.");
+        let tmp_path = self.path.with_extension("json.tmp");
+        fs::write(&tmp_path, data)?;
+        fs::rename(&tmp_path, &self.path)
+    }
+
+    /// Saves the checkpoints on a thread which may block.
+    fn save_blocking(&self) -> impl Future<Item = (), Error = io::Error> {
+        let checkpoints = self.clone();
+        poll_fn(move || tokio_threadpool::blocking(|| checkpoints.save()))
+            .map_err(|error| io::Error::new(ErrorKind::Other, error))
+            .and_then(|result| result)
+    }
+}
+
+struct GroupPoller {
+    client: CloudWatchLogsClient,
+    group: String,
+    stream_names: Option<Vec<String>>,
+    stream_prefix: Option<String>,
+    filter_pattern: Option<String>,
+    checkpoint: Checkpoint,
+    checkpoints: Checkpoints,
+    out: mpsc::Sender<Event>,
+}
+
+impl GroupPoller {
+    /// Polls the group until the pipeline is closed.
+    fn run(self, interval: Duration) -> impl Future<Item = (), Error = ()> {
+        future::loop_fn(self, move |poller| {
+            poller.poll().and_then(move |poller| {
+                Delay::new(Instant::now() + interval)
+                    .map_err(|error| error!(message = "Timer error.", %error))
+                    .map(|_| Loop::<(), _>::Continue(poller))
+            })
+        })
+    }
+
+    /// Reads all pages of events since the checkpoint. Failed requests are
+    /// logged and retried on the next poll.
+    fn poll(mut self) -> impl Future<Item = Self, Error = ()> {
+        self.checkpoint.prune();
+        let start_time = self.checkpoint.start_time();
+        future::loop_fn((self, None), move |(poller, next_token)| {
+            let request = FilterLogEventsRequest {
+                log_group_name: poller.group.clone(),
+                log_stream_names: poller.stream_names.clone(),
+                log_stream_name_prefix: poller.stream_prefix.clone(),
+                filter_pattern: poller.filter_pattern.clone(),
+                start_time: Some(start_time),
+                next_token,
+                ..Default::default()
+            };
+
+            poller
+                .client
+                .filter_log_events(request)
+                .then(move |result| match result {
+                    Ok(output) => {
+                        let next_token = output.next_token;
+                        Either::A(poller.forward(output.events.unwrap_or_default()).map(
+                            move |poller| match next_token {
+                                Some(token) => Loop::Continue((poller, Some(token))),
+                                None => Loop::Break(poller),
+                            },
+                        ))
+                    }
+                    Err(error) => {
+                        warn!(
+                            message = "Failed to fetch log events.",
+                            group = %poller.group,
+                            %error,
+                            rate_limit_secs = 30,
+                        );
+                        Either::B(future::ok(Loop::Break(poller)))
+                    }
+                })
+        })
+    }
+
+    /// Sends the events not read yet, then saves the checkpoint.
+    fn forward(mut self, events: Vec<FilteredLogEvent>) -> impl Future<Item = Self, Error = ()> {
+        let mut batch = Vec::new();
+        for event in events {
+            let (timestamp, id) = match (event.timestamp, event.event_id) {
+                (Some(timestamp), Some(id)) => (timestamp, id),
+                _ => continue,
+            };
+            if !self.checkpoint.advance(timestamp, &id) {
+                continue;
+            }
+
+            batch.push(logs_event(
+                &self.group,
+                event.log_stream_name.as_deref().unwrap_or_default(),
+                id,
+                timestamp,
+                event.message.unwrap_or_default(),
+            ));
+        }
+
+        self.out
+            .clone()
+            .send_all(futures01::stream::iter_ok(batch))
+            .map_err(|error| error!(message = "Error sending log events.", %error))
+            .and_then(move |_| {
+                self.checkpoints.set(&self.group, self.checkpoint.clone());
+                self.checkpoints.save_blocking().then(move |result| {
+                    if let Err(error) = result {
+                        error!(
+                            message = "Unable to save checkpoint.",
+                            group = %self.group,
+                            %error,
+                        );
+                    }
+                    Ok(self)
+                })
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aws_cloudwatch_logs_checkpoint_skips_read_events() {
+        let mut checkpoint = Checkpoint::new(LATE_EVENTS_MS);
+        assert!(checkpoint.advance(LATE_EVENTS_MS, "a"));
+        assert!(checkpoint.advance(LATE_EVENTS_MS + 2, "b"));
+        assert!(!checkpoint.advance(LATE_EVENTS_MS + 2, "b"));
+        assert!(!checkpoint.advance(LATE_EVENTS_MS, "a"));
+        assert_eq!(checkpoint.start_time(), 2);
+
+        // Late events before the latest one are still read.
+        assert!(checkpoint.advance(1, "late"));
+        assert_eq!(checkpoint.timestamp, LATE_EVENTS_MS + 2);
+
+        checkpoint.prune();
+        assert_eq!(
+            checkpoint.event_ids.keys().collect::<Vec<_>>(),
+            vec!["a", "b"]
+        );
+    }
+
+    #[test]
+    fn aws_cloudwatch_logs_checkpoints_persist() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CHECKPOINTS_FILENAME);
+
+        let checkpoints = Checkpoints::load(path.clone()).unwrap();
+        assert_eq!(checkpoints.get("/aws/lambda/api"), None);
+
+        let mut checkpoint = Checkpoint::new(1583020800000);
+        checkpoint.advance(
+            1583020800000,
+            "35683658089614582423604394983260738922885519999578275840",
+        );
+        checkpoints.set("/aws/lambda/api", checkpoint.clone());
+        checkpoints.set("/ecs/web", Checkpoint::new(5));
+        checkpoints.save().unwrap();
+
+        let checkpoints = Checkpoints::load(path).unwrap();
+        assert_eq!(checkpoints.get("/aws/lambda/api"), Some(checkpoint));
+        assert_eq!(checkpoints.get("/ecs/web"), Some(Checkpoint::new(5)));
+        assert!(!dir.path().join("checkpoints.json.tmp").exists());
+    }
+}
@@ -0,0 +1,130 @@
+use crate::{
+    event::Event,
+    sources::util::{firehose, ErrorMessage, HttpSource},
+    tls::TlsConfig,
+};
+use bytes::Bytes;
+use futures01::sync::mpsc;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, net::SocketAddr};
+use warp::http::{HeaderMap, Response};
+
+/// Receives the log events forwarded by subscription filters, delivered
+/// by a Kinesis Firehose stream with an HTTP endpoint destination.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub(super) struct SubscriptionConfig {
+    address: SocketAddr,
+    access_key: Option<String>,
+    tls: Option<TlsConfig>,
+}
+
+impl SubscriptionConfig {
+    pub(super) fn build(&self, out: mpsc::Sender<Event>) -> crate::Result<crate::sources::Source> {
+        let source = SubscriptionSource {
+            access_key: self.access_key.clone(),
+        };
+        source.run(self.address, "", false, &self.tls, &None, false, out)
+    }
+}
+
+#[derive(Clone)]
+struct SubscriptionSource {
+    access_key: Option<String>,
+}
+
+impl HttpSource for SubscriptionSource {
+    fn build_event(
+        &self,
+        body: Bytes,
+        header_map: HeaderMap,
+        _query_parameters: HashMap<String, String>,
+        _path: &str,
+    ) -> Result<Vec<Event>, ErrorMessage> {
+        firehose::check_access_key(self.access_key.as_deref(), &header_map)?;
+
+        let request = firehose::parse_request(&body)?;
+        let mut events = Vec::new();
+        for record in &request.records {
+            events.extend(firehose::decode_subscription(&record.decode()?)?);
+        }
+        Ok(events)
+    }
+
+    fn build_response(
+        &self,
+        header_map: &HeaderMap,
+        result: Result<(), ErrorMessage>,
+    ) -> Response<Vec<u8>> {
+        firehose::response(header_map, result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        event,
+        test_util::{self, collect_n},
+    };
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+
+    fn send(address: SocketAddr, access_key: &str, body: String) -> (u16, serde_json::Value) {
+        let response = reqwest::Client::new()
+            .post(&format!("http://{}/", address))
+            .header(firehose::REQUEST_ID_HEADER, "e17265d6-97af-4938")
+            .header(firehose::ACCESS_KEY_HEADER, access_key)
+            .body(body)
+            .send()
+            .unwrap();
+        let status = response.status().as_u16();
+        (status, serde_json::from_reader(response).unwrap())
+    }
+
+    #[test]
+    fn aws_cloudwatch_logs_subscription_receives_events() {
+        test_util::trace_init();
+        let mut rt = test_util::runtime();
+
+        let (tx, rx) = mpsc::channel(100);
+        let address = test_util::next_addr();
+        let config = SubscriptionConfig {
+            address,
+            access_key: Some("secret".to_owned()),
+            tls: None,
+        };
+        rt.spawn(config.build(tx).unwrap());
+        test_util::wait_for_tcp(address);
+
+        let payload = r#"{"messageType":"DATA_MESSAGE","owner":"111111111111","logGroup":"/aws/lambda/api","logStream":"2020/03/01/[$LATEST]abc","subscriptionFilters":["to-vector"],"logEvents":[{"id":"1","timestamp":1583020800000,"message":"hello"},{"id":"2","timestamp":1583020800001,"message":"world"}]}"#;
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(payload.as_bytes()).unwrap();
+        let data = base64::encode(&encoder.finish().unwrap());
+        let body = format!(
+            r#"{{"requestId":"e17265d6-97af-4938","timestamp":1583020801000,"records":[{{"data":"{}"}}]}}"#,
+            data
+        );
+
+        let (status, reply) = send(address, "wrong", body.clone());
+        assert_eq!(status, 401);
+        assert_eq!(reply["requestId"], "e17265d6-97af-4938");
+        assert_eq!(reply["errorMessage"], "Invalid access key");
+
+        let (status, reply) = send(address, "secret", body);
+        assert_eq!(status, 200);
+        assert_eq!(reply["requestId"], "e17265d6-97af-4938");
+        assert!(reply.get("errorMessage").is_none());
+
+        let events = rt.block_on(collect_n(rx, 2)).ok().unwrap();
+        let messages: Vec<_> = events
+            .iter()
+            .map(|event| event.as_log()[&event::log_schema().message_key()].to_string_lossy())
+            .collect();
+        assert_eq!(messages, vec!["hello", "world"]);
+        assert_eq!(
+            events[0].as_log()[&"log_group".into()],
+            "/aws/lambda/api".into()
+        );
+    }
+}
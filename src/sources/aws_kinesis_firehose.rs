@@ -0,0 +1,251 @@
+use crate::{
+    event::{self, Event},
+    sources::util::{firehose, ErrorMessage, HttpSource},
+    tls::TlsConfig,
+    topology::config::{DataType, GlobalOptions, SourceConfig, SourceDescription},
+};
+use bytes::Bytes;
+use futures01::sync::mpsc;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, net::SocketAddr};
+use warp::http::{HeaderMap, Response};
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AwsKinesisFirehoseConfig {
+    address: SocketAddr,
+    access_key: Option<String>,
+    #[serde(default)]
+    record_format: RecordFormat,
+    tls: Option<TlsConfig>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Eq, PartialEq, Clone, Copy, Derivative)]
+#[serde(rename_all = "snake_case")]
+#[derivative(Default)]
+pub enum RecordFormat {
+    #[derivative(Default)]
+    Raw,
+    CloudwatchLogsSubscription,
+}
+
+inventory::submit! {
+    SourceDescription::new_without_default::<AwsKinesisFirehoseConfig>("aws_kinesis_firehose")
+}
+
+#[typetag::serde(name = "aws_kinesis_firehose")]
+impl SourceConfig for AwsKinesisFirehoseConfig {
+    fn build(
+        &self,
+        _: &str,
+        _: &GlobalOptions,
+        out: mpsc::Sender<Event>,
+    ) -> crate::Result<super::Source> {
+        let source = FirehoseSource {
+            access_key: self.access_key.clone(),
+            record_format: self.record_format,
+        };
+        source.run(self.address, "", false, &self.tls, &None, false, out)
+    }
+
+    fn output_type(&self) -> DataType {
+        DataType::Log
+    }
+
+    fn source_type(&self) -> &'static str {
+        "aws_kinesis_firehose"
+    }
+}
+
+#[derive(Clone)]
+struct FirehoseSource {
+    access_key: Option<String>,
+    record_format: RecordFormat,
+}
+
+impl HttpSource for FirehoseSource {
+    fn build_event(
+        &self,
+        body: Bytes,
+        header_map: HeaderMap,
+        _query_parameters: HashMap<String, String>,
+        _path: &str,
+    ) -> Result<Vec<Event>, ErrorMessage> {
+        firehose::check_access_key(self.access_key.as_deref(), &header_map)?;
+
+        let request = firehose::parse_request(&body)?;
+        let source_arn = header_map
+            .get(firehose::SOURCE_ARN_HEADER)
+            .and_then(|arn| arn.to_str().ok());
+
+        let mut events = Vec::new();
+        for record in &request.records {
+            let data = record.decode()?;
+            match self.record_format {
+                RecordFormat::Raw => {
+                    let mut event = Event::from(Bytes::from(data));
+                    // Records don't have a timestamp of their own, so the
+                    // time Firehose received them is used instead.
+                    if let Some(timestamp) = firehose::timestamp(request.timestamp) {
+                        event
+                            .as_mut_log()
+                            .insert(event::log_schema().timestamp_key().clone(), timestamp);
+                    }
+                    events.push(event);
+                }
+                RecordFormat::CloudwatchLogsSubscription => {
+                    events.extend(firehose::decode_subscription(&data)?)
+                }
+            }
+        }
+
+        for event in &mut events {
+            let log = event.as_mut_log();
+            log.insert("request_id", request.request_id.clone());
+            if let Some(source_arn) = source_arn {
+                log.insert("source_arn", source_arn.to_owned());
+            }
+        }
+
+        Ok(events)
+    }
+
+    fn build_response(
+        &self,
+        header_map: &HeaderMap,
+        result: Result<(), ErrorMessage>,
+    ) -> Response<Vec<u8>> {
+        firehose::response(header_map, result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        runtime::Runtime,
+        test_util::{self, collect_n},
+    };
+    use chrono::{TimeZone, Utc};
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+
+    const REQUEST_ID: &str = "e17265d6-97af-4938-8dbe-2a7a8e4a6a44";
+    const SOURCE_ARN: &str = "arn:aws:firehose:us-east-1:111111111111:deliverystream/test";
+
+    fn source(
+        rt: &mut Runtime,
+        record_format: RecordFormat,
+    ) -> (mpsc::Receiver<Event>, SocketAddr) {
+        test_util::trace_init();
+        let (sender, recv) = mpsc::channel(100);
+        let address = test_util::next_addr();
+        let config = AwsKinesisFirehoseConfig {
+            address,
+            access_key: Some("secret".to_owned()),
+            record_format,
+            tls: None,
+        };
+        rt.spawn(
+            config
+                .build("default", &GlobalOptions::default(), sender)
+                .unwrap(),
+        );
+        test_util::wait_for_tcp(address);
+        (recv, address)
+    }
+
+    fn send(
+        address: SocketAddr,
+        access_key: &str,
+        records: &[Vec<u8>],
+    ) -> (u16, serde_json::Value) {
+        let records = records
+            .iter()
+            .map(|data| serde_json::json!({ "data": base64::encode(data) }))
+            .collect::<Vec<_>>();
+        let body = serde_json::json!({
+            "requestId": REQUEST_ID,
+            "timestamp": 1583020801000i64,
+            "records": records,
+        });
+
+        let response = reqwest::Client::new()
+            .post(&format!("http://{}/", address))
+            .header(firehose::REQUEST_ID_HEADER, REQUEST_ID)
+            .header(firehose::SOURCE_ARN_HEADER, SOURCE_ARN)
+            .header(firehose::ACCESS_KEY_HEADER, access_key)
+            .body(body.to_string())
+            .send()
+            .unwrap();
+        let status = response.status().as_u16();
+        (status, serde_json::from_reader(response).unwrap())
+    }
+
+    #[test]
+    fn aws_kinesis_firehose_raw_records() {
+        let mut rt = test_util::runtime();
+        let (rx, address) = source(&mut rt, RecordFormat::Raw);
+
+        let (status, reply) = send(
+            address,
+            "secret",
+            &[b"first record".to_vec(), b"second record".to_vec()],
+        );
+        assert_eq!(status, 200);
+        assert_eq!(reply["requestId"], REQUEST_ID);
+        assert!(reply["timestamp"].is_i64());
+
+        let events = rt.block_on(collect_n(rx, 2)).ok().unwrap();
+        let log = events[0].as_log();
+        assert_eq!(
+            log[&event::log_schema().message_key()],
+            "first record".into()
+        );
+        assert_eq!(
+            log[&event::log_schema().timestamp_key()],
+            Utc.timestamp(1583020801, 0).into()
+        );
+        assert_eq!(log[&"request_id".into()], REQUEST_ID.into());
+        assert_eq!(log[&"source_arn".into()], SOURCE_ARN.into());
+        assert_eq!(
+            events[1].as_log()[&event::log_schema().message_key()],
+            "second record".into()
+        );
+    }
+
+    #[test]
+    fn aws_kinesis_firehose_cloudwatch_logs_subscription_records() {
+        let mut rt = test_util::runtime();
+        let (rx, address) = source(&mut rt, RecordFormat::CloudwatchLogsSubscription);
+
+        let payload = r#"{"messageType":"DATA_MESSAGE","owner":"111111111111","logGroup":"/aws/lambda/api","logStream":"2020/03/01/[$LATEST]abc","subscriptionFilters":["to-vector"],"logEvents":[{"id":"1","timestamp":1583020800000,"message":"hello"},{"id":"2","timestamp":1583020800001,"message":"world"}]}"#;
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(payload.as_bytes()).unwrap();
+
+        let (status, _) = send(address, "secret", &[encoder.finish().unwrap()]);
+        assert_eq!(status, 200);
+
+        let events = rt.block_on(collect_n(rx, 2)).ok().unwrap();
+        let log = events[1].as_log();
+        assert_eq!(log[&event::log_schema().message_key()], "world".into());
+        assert_eq!(log[&"log_group".into()], "/aws/lambda/api".into());
+        assert_eq!(log[&"event_id".into()], "2".into());
+        assert_eq!(log[&"request_id".into()], REQUEST_ID.into());
+    }
+
+    #[test]
+    fn aws_kinesis_firehose_rejects_invalid_requests() {
+        let mut rt = test_util::runtime();
+        let (_rx, address) = source(&mut rt, RecordFormat::CloudwatchLogsSubscription);
+
+        let (status, reply) = send(address, "wrong", &[b"record".to_vec()]);
+        assert_eq!(status, 401);
+        assert_eq!(reply["requestId"], REQUEST_ID);
+        assert_eq!(reply["errorMessage"], "Invalid access key");
+
+        let (status, reply) = send(address, "secret", &[b"not gzipped".to_vec()]);
+        assert_eq!(status, 400);
+        assert!(reply["errorMessage"].is_string());
+    }
+}
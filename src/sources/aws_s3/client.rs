@@ -0,0 +1,225 @@
+use crate::sinks::util::rusoto::AwsCredentialsProvider;
+use futures01::{Future, Stream};
+use http::StatusCode;
+use rusoto_core::{
+    request::{DispatchSignedRequest, HttpClient, HttpDispatchError},
+    signature::SignedRequest,
+    Region,
+};
+use rusoto_credential::{CredentialsError, ProvideAwsCredentials};
+use snafu::{ResultExt, Snafu};
+use std::{io, sync::Arc};
+use xml::reader::{EventReader, XmlEvent};
+
+const API_VERSION: &str = "2012-11-05";
+
+#[derive(Debug, Snafu)]
+pub(super) enum SqsError {
+    #[snafu(display("Failed to get AWS credentials: {}", source))]
+    Credentials { source: CredentialsError },
+    #[snafu(display("Failed to send request: {}", source))]
+    Dispatch { source: HttpDispatchError },
+    #[snafu(display("Failed to read response: {}", source))]
+    ReadResponse { source: io::Error },
+    #[snafu(display("Request failed with status {}: {}", status, body))]
+    Response { status: StatusCode, body: String },
+    #[snafu(display("Invalid response: {}", source))]
+    ParseResponse { source: xml::reader::Error },
+}
+
+#[derive(Debug, PartialEq)]
+pub(super) struct Message {
+    pub(super) receipt_handle: String,
+    pub(super) body: String,
+}
+
+/// Sends the few SQS actions the source needs, signed the same way as the
+/// requests of the rusoto clients.
+pub(super) struct SqsClient {
+    client: Arc<HttpClient>,
+    credentials: AwsCredentialsProvider,
+    region: Region,
+}
+
+impl SqsClient {
+    pub(super) fn new(
+        client: HttpClient,
+        credentials: AwsCredentialsProvider,
+        region: Region,
+    ) -> Self {
+        Self {
+            client: Arc::new(client),
+            credentials,
+            region,
+        }
+    }
+
+    /// Receives up to 10 messages, waiting up to `wait_time_secs` for them.
+    pub(super) fn receive_message(
+        &self,
+        queue_url: &str,
+        wait_time_secs: u32,
+        visibility_timeout_secs: u32,
+    ) -> impl Future<Item = Vec<Message>, Error = SqsError> {
+        self.request(
+            "ReceiveMessage",
+            &[
+                ("QueueUrl", queue_url),
+                ("MaxNumberOfMessages", "10"),
+                ("WaitTimeSeconds", &wait_time_secs.to_string()),
+                ("VisibilityTimeout", &visibility_timeout_secs.to_string()),
+            ],
+        )
+        .and_then(|body| {
+            let receipt_handles = element_texts(&body, "ReceiptHandle").context(ParseResponse)?;
+            let bodies = element_texts(&body, "Body").context(ParseResponse)?;
+            Ok(receipt_handles
+                .into_iter()
+                .zip(bodies)
+                .map(|(receipt_handle, body)| Message {
+                    receipt_handle,
+                    body,
+                })
+                .collect())
+        })
+    }
+
+    pub(super) fn delete_message(
+        &self,
+        queue_url: &str,
+        receipt_handle: &str,
+    ) -> impl Future<Item = (), Error = SqsError> {
+        self.request(
+            "DeleteMessage",
+            &[("QueueUrl", queue_url), ("ReceiptHandle", receipt_handle)],
+        )
+        .map(|_| ())
+    }
+
+    pub(super) fn change_message_visibility(
+        &self,
+        queue_url: &str,
+        receipt_handle: &str,
+        visibility_timeout_secs: u32,
+    ) -> impl Future<Item = (), Error = SqsError> {
+        self.request(
+            "ChangeMessageVisibility",
+            &[
+                ("QueueUrl", queue_url),
+                ("ReceiptHandle", receipt_handle),
+                ("VisibilityTimeout", &visibility_timeout_secs.to_string()),
+            ],
+        )
+        .map(|_| ())
+    }
+
+    /// Sends an action with the query protocol, returning the body of the
+    /// response if it succeeded.
+    pub(super) fn request(
+        &self,
+        action: &str,
+        params: &[(&str, &str)],
+    ) -> impl Future<Item = Vec<u8>, Error = SqsError> {
+        let mut body = url::form_urlencoded::Serializer::new(String::new());
+        body.append_pair("Action", action)
+            .append_pair("Version", API_VERSION);
+        for (name, value) in params {
+            body.append_pair(name, value);
+        }
+
+        let mut request = SignedRequest::new("POST", "sqs", &self.region, "/");
+        request.set_content_type("application/x-www-form-urlencoded".to_owned());
+        request.set_payload(Some(body.finish()));
+
+        let client = Arc::clone(&self.client);
+        self.credentials
+            .credentials()
+            .map_err(|source| SqsError::Credentials { source })
+            .and_then(move |credentials| {
+                request.sign_with_plus(&credentials, true);
+                client
+                    .dispatch(request, None)
+                    .map_err(|source| SqsError::Dispatch { source })
+            })
+            .and_then(|response| {
+                let status = response.status;
+                response
+                    .body
+                    .fold(Vec::new(), |mut body, chunk| {
+                        body.extend_from_slice(&chunk);
+                        Ok::<_, io::Error>(body)
+                    })
+                    .map_err(|source| SqsError::ReadResponse { source })
+                    .and_then(move |body| {
+                        if status.is_success() {
+                            Ok(body)
+                        } else {
+                            Err(SqsError::Response {
+                                status,
+                                body: String::from_utf8_lossy(&body).into_owned(),
+                            })
+                        }
+                    })
+            })
+    }
+}
+
+/// The text of every element named `name` in an XML document, in order.
+pub(super) fn element_texts(
+    document: &[u8],
+    name: &str,
+) -> Result<Vec<String>, xml::reader::Error> {
+    let mut texts = Vec::new();
+    let mut text = None;
+    for event in EventReader::new(document) {
+        match event? {
+            XmlEvent::StartElement { name: element, .. } if element.local_name == name => {
+                text = Some(String::new());
+            }
+            XmlEvent::Characters(chunk) | XmlEvent::CData(chunk) => {
+                if let Some(text) = &mut text {
+                    text.push_str(&chunk);
+                }
+            }
+            XmlEvent::EndElement { name: element } if element.local_name == name => {
+                texts.extend(text.take());
+            }
+            _ => {}
+        }
+    }
+    Ok(texts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aws_s3_parses_received_messages() {
+        let response = br#"<?xml version="1.0"?>
+            <ReceiveMessageResponse xmlns="http://queue.amazonaws.com/doc/2012-11-05/">
+                <ReceiveMessageResult>
+                    <Message>
+                        <MessageId>5fea7756-0ea4-451a-a703-a558b933e274</MessageId>
+                        <ReceiptHandle>MbZj6wDWli+JvwwJaBV+3dcjk2YW2vA3+STFFljT</ReceiptHandle>
+                        <MD5OfBody>fafb00f5732ab283681e124bf8747ed1</MD5OfBody>
+                        <Body>{&quot;Records&quot;:[]}</Body>
+                    </Message>
+                    <Message>
+                        <ReceiptHandle>second</ReceiptHandle>
+                        <Body><![CDATA[a <b>]]></Body>
+                    </Message>
+                </ReceiveMessageResult>
+            </ReceiveMessageResponse>"#;
+
+        assert_eq!(
+            element_texts(response, "ReceiptHandle").unwrap(),
+            vec!["MbZj6wDWli+JvwwJaBV+3dcjk2YW2vA3+STFFljT", "second"]
+        );
+        assert_eq!(
+            element_texts(response, "Body").unwrap(),
+            vec![r#"{"Records":[]}"#, "a <b>"]
+        );
+        assert!(element_texts(b"<Body>", "Body").is_err());
+    }
+}
@@ -0,0 +1,383 @@
+use crate::{
+    event::{self, Event},
+    region::RegionOrEndpoint,
+    sinks::util::rusoto::AwsCredentialsProvider,
+    topology::config::{DataType, GlobalOptions, SourceConfig, SourceDescription},
+};
+use bytes::{Bytes, BytesMut};
+use chrono::Utc;
+use codec::BytesDelimitedCodec;
+use flate2::write::GzDecoder;
+use futures01::sync::mpsc;
+use rusoto_core::{HttpClient, Region};
+use rusoto_s3::S3Client;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::{
+    convert::TryInto,
+    io::{self, Write},
+    mem,
+};
+use tokio_codec::Decoder;
+
+mod client;
+mod sqs;
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AwsS3Config {
+    sqs: SqsConfig,
+    #[serde(default)]
+    compression: Compression,
+    #[serde(default)]
+    encoding: Encoding,
+    #[serde(flatten)]
+    region: RegionOrEndpoint,
+    assume_role: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+struct SqsConfig {
+    queue_url: String,
+    endpoint: Option<String>,
+    #[serde(default = "default_poll_secs")]
+    poll_secs: u32,
+    #[serde(default = "default_visibility_timeout_secs")]
+    visibility_timeout_secs: u32,
+    #[serde(default = "crate::serde::default_true")]
+    delete_message: bool,
+}
+
+fn default_poll_secs() -> u32 {
+    15
+}
+
+fn default_visibility_timeout_secs() -> u32 {
+    300
+}
+
+#[derive(Deserialize, Serialize, Debug, Eq, PartialEq, Clone, Copy, Derivative)]
+#[serde(rename_all = "snake_case")]
+#[derivative(Default)]
+pub enum Compression {
+    #[derivative(Default)]
+    Auto,
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Guesses the compression of an object from its first bytes.
+    fn detect(head: &[u8]) -> Self {
+        if head.starts_with(&[0x1f, 0x8b]) {
+            Compression::Gzip
+        } else if head.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Eq, PartialEq, Clone, Copy, Derivative)]
+#[serde(rename_all = "snake_case")]
+#[derivative(Default)]
+pub enum Encoding {
+    #[derivative(Default)]
+    Text,
+    Ndjson,
+}
+
+inventory::submit! {
+    SourceDescription::new_without_default::<AwsS3Config>("aws_s3")
+}
+
+#[typetag::serde(name = "aws_s3")]
+impl SourceConfig for AwsS3Config {
+    fn build(
+        &self,
+        _name: &str,
+        _globals: &GlobalOptions,
+        out: mpsc::Sender<Event>,
+    ) -> crate::Result<super::Source> {
+        let region: Region = self.region.clone().try_into()?;
+        let sqs_region = match &self.sqs.endpoint {
+            Some(endpoint) => RegionOrEndpoint::with_endpoint(endpoint.clone()).try_into()?,
+            None => region.clone(),
+        };
+
+        let ingestor = sqs::Ingestor {
+            s3: S3Client::new_with(
+                HttpClient::new()?,
+                credentials(&region, self.assume_role.clone())?,
+                region.clone(),
+            ),
+            sqs: client::SqsClient::new(
+                HttpClient::new()?,
+                credentials(&region, self.assume_role.clone())?,
+                sqs_region,
+            ),
+            queue_url: self.sqs.queue_url.clone(),
+            poll_secs: self.sqs.poll_secs,
+            visibility_timeout_secs: self.sqs.visibility_timeout_secs,
+            delete_message: self.sqs.delete_message,
+            compression: self.compression,
+            encoding: self.encoding,
+            out,
+        };
+
+        Ok(Box::new(ingestor.run()))
+    }
+
+    fn output_type(&self) -> DataType {
+        DataType::Log
+    }
+
+    fn source_type(&self) -> &'static str {
+        "aws_s3"
+    }
+}
+
+fn credentials(
+    region: &Region,
+    _assume_role: Option<String>,
+) -> crate::Result<AwsCredentialsProvider> {
+    #[cfg(not(test))]
+    let creds = AwsCredentialsProvider::new(region, _assume_role)?;
+
+    // Static credentials for the local S3 and SQS stand-ins used by tests,
+    // as rusoto doesn't pick up env vars set at runtime.
+    #[cfg(test)]
+    let creds = {
+        let _ = region;
+        AwsCredentialsProvider::new_minimal("test-access-key", "test-secret-key")
+    };
+
+    Ok(creds)
+}
+
+enum Decompressor {
+    None(Vec<u8>),
+    Gzip(GzDecoder<Vec<u8>>),
+    Zstd(zstd::stream::write::Decoder<Vec<u8>>),
+}
+
+impl Decompressor {
+    fn new(compression: Compression, head: &[u8]) -> io::Result<Self> {
+        let compression = match compression {
+            Compression::Auto => Compression::detect(head),
+            compression => compression,
+        };
+        Ok(match compression {
+            Compression::Auto | Compression::None => Decompressor::None(Vec::new()),
+            Compression::Gzip => Decompressor::Gzip(GzDecoder::new(Vec::new())),
+            Compression::Zstd => Decompressor::Zstd(zstd::stream::write::Decoder::new(Vec::new())?),
+        })
+    }
+
+    fn write(&mut self, chunk: &[u8]) -> io::Result<()> {
+        match self {
+            Decompressor::None(output) => {
+                output.extend_from_slice(chunk);
+                Ok(())
+            }
+            Decompressor::Gzip(decoder) => decoder.write_all(chunk),
+            Decompressor::Zstd(decoder) => decoder.write_all(chunk),
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self {
+            Decompressor::None(_) => Ok(()),
+            Decompressor::Gzip(decoder) => decoder.try_finish(),
+            Decompressor::Zstd(decoder) => decoder.flush(),
+        }
+    }
+
+    /// Removes the data decompressed so far.
+    fn take_output(&mut self) -> Vec<u8> {
+        let output = match self {
+            Decompressor::None(output) => output,
+            Decompressor::Gzip(decoder) => decoder.get_mut(),
+            Decompressor::Zstd(decoder) => decoder.get_mut(),
+        };
+        mem::replace(output, Vec::new())
+    }
+}
+
+/// Turns the chunks of an object into events as they are downloaded, so
+/// objects never have to be held in memory whole.
+struct ObjectDecoder {
+    compression: Compression,
+    decompressor: Option<Decompressor>,
+    encoding: Encoding,
+    lines: BytesMut,
+    codec: BytesDelimitedCodec,
+    bucket: Bytes,
+    key: Bytes,
+}
+
+impl ObjectDecoder {
+    fn new(compression: Compression, encoding: Encoding, bucket: &str, key: &str) -> Self {
+        Self {
+            compression,
+            decompressor: None,
+            encoding,
+            lines: BytesMut::new(),
+            codec: BytesDelimitedCodec::new(b'\n'),
+            bucket: bucket.into(),
+            key: key.into(),
+        }
+    }
+
+    /// Decodes the events completed by the next chunk of the object, or the
+    /// remaining ones once the object has ended and `chunk` is `None`.
+    fn decode(&mut self, chunk: Option<Bytes>) -> io::Result<Vec<Event>> {
+        if let Some(chunk) = &chunk {
+            if chunk.is_empty() {
+                return Ok(Vec::new());
+            }
+        }
+
+        if self.decompressor.is_none() {
+            let head = chunk.as_ref().map_or(&[][..], |chunk| &chunk[..]);
+            self.decompressor = Some(Decompressor::new(self.compression, head)?);
+        }
+        let decompressor = self.decompressor.as_mut().unwrap();
+        match &chunk {
+            Some(chunk) => decompressor.write(chunk)?,
+            None => decompressor.finish()?,
+        }
+        self.lines.extend_from_slice(&decompressor.take_output());
+
+        let mut events = Vec::new();
+        loop {
+            let line = match chunk {
+                Some(_) => self.codec.decode(&mut self.lines)?,
+                None => self.codec.decode_eof(&mut self.lines)?,
+            };
+            match line {
+                Some(line) => events.extend(self.event(line)),
+                None => break,
+            }
+        }
+
+        Ok(events)
+    }
+
+    fn event(&self, line: Bytes) -> Option<Event> {
+        if line.is_empty() {
+            return None;
+        }
+
+        let mut event = match self.encoding {
+            Encoding::Text => Event::from(line),
+            Encoding::Ndjson => match serde_json::from_slice(&line) {
+                Ok(JsonValue::Object(map)) => {
+                    let mut event = Event::new_empty_log();
+                    let log = event.as_mut_log();
+                    log.insert(event::log_schema().timestamp_key().clone(), Utc::now());
+                    for (key, value) in map {
+                        log.insert(key, value);
+                    }
+                    event
+                }
+                Ok(_) => {
+                    warn!(
+                        message = "Line is not a JSON object; discarding it.",
+                        rate_limit_secs = 30,
+                    );
+                    return None;
+                }
+                Err(error) => {
+                    warn!(
+                        message = "Failed to parse line as JSON; discarding it.",
+                        %error,
+                        rate_limit_secs = 30,
+                    );
+                    return None;
+                }
+            },
+        };
+
+        let log = event.as_mut_log();
+        log.insert("bucket", self.bucket.clone());
+        log.insert("object", self.key.clone());
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+
+    fn decode(decoder: &mut ObjectDecoder, body: &[u8], chunk_size: usize) -> Vec<Event> {
+        let mut events = Vec::new();
+        for chunk in body.chunks(chunk_size) {
+            events.extend(decoder.decode(Some(chunk.into())).unwrap());
+        }
+        events.extend(decoder.decode(None).unwrap());
+        events
+    }
+
+    fn messages(events: &[Event]) -> Vec<String> {
+        events
+            .iter()
+            .map(|event| event.as_log()[&event::log_schema().message_key()].to_string_lossy())
+            .collect()
+    }
+
+    const BODY: &[u8] = b"first line\nsecond line\n\nthird line";
+
+    #[test]
+    fn aws_s3_decodes_lines_across_chunks() {
+        let mut decoder = ObjectDecoder::new(Compression::Auto, Encoding::Text, "bucket", "key");
+        let events = decode(&mut decoder, BODY, 4);
+
+        assert_eq!(
+            messages(&events),
+            vec!["first line", "second line", "third line"]
+        );
+        let log = events[0].as_log();
+        assert_eq!(log[&"bucket".into()], "bucket".into());
+        assert_eq!(log[&"object".into()], "key".into());
+    }
+
+    #[test]
+    fn aws_s3_decompresses_objects() {
+        let mut gzip = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(BODY).unwrap();
+        let gzip = gzip.finish().unwrap();
+        let zstd = zstd::stream::encode_all(BODY, 0).unwrap();
+
+        for (compression, body) in vec![
+            (Compression::Auto, &gzip),
+            (Compression::Gzip, &gzip),
+            (Compression::Auto, &zstd),
+            (Compression::Zstd, &zstd),
+        ] {
+            let mut decoder = ObjectDecoder::new(compression, Encoding::Text, "bucket", "key");
+            let events = decode(&mut decoder, body, 7);
+            assert_eq!(
+                messages(&events),
+                vec!["first line", "second line", "third line"]
+            );
+        }
+    }
+
+    #[test]
+    fn aws_s3_decodes_ndjson() {
+        let body = b"{\"message\":\"one\",\"status\":200}\nnot json\n[1]\n{\"message\":\"two\"}\n";
+        let mut decoder = ObjectDecoder::new(Compression::None, Encoding::Ndjson, "b", "k");
+        let events = decode(&mut decoder, body, 5);
+
+        assert_eq!(messages(&events), vec!["one", "two"]);
+        let log = events[0].as_log();
+        assert_eq!(log[&"status".into()], 200i64.into());
+        assert!(log.get(&event::log_schema().timestamp_key()).is_some());
+        assert_eq!(log[&"object".into()], "k".into());
+    }
+}
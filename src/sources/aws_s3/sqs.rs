@@ -0,0 +1,527 @@
+use super::{
+    client::{Message, SqsClient, SqsError},
+    Compression, Encoding, ObjectDecoder,
+};
+use crate::event::{BatchNotifier, BatchStatus, Event};
+use futures01::{
+    future::{self, Either, Loop},
+    stream,
+    sync::{mpsc, oneshot},
+    Future, Sink, Stream,
+};
+use rusoto_core::RusotoError;
+use rusoto_s3::{GetObjectError, GetObjectRequest, S3Client, S3};
+use serde::Deserialize;
+use snafu::{ResultExt, Snafu};
+use std::{
+    io,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::timer::{Delay, Interval};
+use url::percent_encoding::percent_decode;
+
+// How long to wait before polling again after failing to receive messages.
+const RECEIVE_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Snafu)]
+enum IngestError {
+    #[snafu(display("Invalid bucket notification: {}", source))]
+    InvalidNotification { source: serde_json::Error },
+    #[snafu(display("Failed to fetch s3://{}/{}: {}", bucket, key, source))]
+    GetObject {
+        bucket: String,
+        key: String,
+        source: RusotoError<GetObjectError>,
+    },
+    #[snafu(display("Failed to read s3://{}/{}: {}", bucket, key, source))]
+    ReadObject {
+        bucket: String,
+        key: String,
+        source: io::Error,
+    },
+    #[snafu(display("The pipeline is shutting down"))]
+    PipelineClosed,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct S3EventNotification {
+    // Missing from the test event S3 sends when notifications are set up.
+    #[serde(default)]
+    records: Vec<S3EventRecord>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct S3EventRecord {
+    event_name: String,
+    s3: S3Message,
+}
+
+#[derive(Deserialize, Debug)]
+struct S3Message {
+    bucket: S3Bucket,
+    object: S3Object,
+}
+
+#[derive(Deserialize, Debug)]
+struct S3Bucket {
+    name: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct S3Object {
+    key: String,
+}
+
+#[derive(Debug, PartialEq)]
+struct ObjectRef {
+    bucket: String,
+    key: String,
+}
+
+/// Returns the objects created according to an S3 bucket notification.
+fn parse_notification(body: &str) -> Result<Vec<ObjectRef>, IngestError> {
+    let notification: S3EventNotification =
+        serde_json::from_str(body).context(InvalidNotification)?;
+
+    Ok(notification
+        .records
+        .into_iter()
+        .filter(|record| record.event_name.starts_with("ObjectCreated:"))
+        .map(|record| ObjectRef {
+            bucket: record.s3.bucket.name,
+            key: decode_key(&record.s3.object.key),
+        })
+        .collect())
+}
+
+// Keys are URL encoded in notifications, with spaces encoded as `+`.
+fn decode_key(key: &str) -> String {
+    let key = key.replace('+', " ");
+    percent_decode(key.as_bytes())
+        .decode_utf8_lossy()
+        .into_owned()
+}
+
+pub(super) struct Ingestor {
+    pub(super) s3: S3Client,
+    pub(super) sqs: SqsClient,
+    pub(super) queue_url: String,
+    pub(super) poll_secs: u32,
+    pub(super) visibility_timeout_secs: u32,
+    pub(super) delete_message: bool,
+    pub(super) compression: Compression,
+    pub(super) encoding: Encoding,
+    pub(super) out: mpsc::Sender<Event>,
+}
+
+type BoxedFuture<T, E> = Box<dyn Future<Item = T, Error = E> + Send>;
+
+impl Ingestor {
+    /// Polls the queue until the pipeline shuts down.
+    pub(super) fn run(self) -> impl Future<Item = (), Error = ()> {
+        let ingestor = Arc::new(self);
+        future::loop_fn((), move |()| {
+            let ingestor = Arc::clone(&ingestor);
+            ingestor.receive().then(move |result| {
+                let next: BoxedFuture<Loop<(), ()>, ()> = match result {
+                    Ok(messages) => Box::new(
+                        stream::iter_ok(messages)
+                            .for_each(move |message| Arc::clone(&ingestor).handle(message))
+                            .map(Loop::Continue),
+                    ),
+                    Err(error) => {
+                        error!(message = "failed to receive SQS messages.", %error);
+                        Box::new(
+                            Delay::new(Instant::now() + RECEIVE_RETRY_DELAY)
+                                .map_err(|error| error!(message = "timer error.", %error))
+                                .map(Loop::Continue),
+                        )
+                    }
+                };
+                next
+            })
+        })
+    }
+
+    fn receive(&self) -> impl Future<Item = Vec<Message>, Error = SqsError> {
+        self.sqs.receive_message(
+            &self.queue_url,
+            self.poll_secs,
+            self.visibility_timeout_secs,
+        )
+    }
+
+    /// Ingests the objects of a notification, deleting the message once all
+    /// of their events have been delivered by the sinks. Otherwise the
+    /// message is left to become visible again after the visibility timeout,
+    /// so the objects are retried. Only fails if the pipeline is shutting
+    /// down.
+    fn handle(self: Arc<Self>, message: Message) -> BoxedFuture<(), ()> {
+        let Message {
+            receipt_handle,
+            body,
+        } = message;
+        let objects = match parse_notification(&body) {
+            Ok(objects) => objects,
+            Err(error) => {
+                error!(message = "discarding SQS message.", %error, rate_limit_secs = 30);
+                if self.delete_message {
+                    return Box::new(self.delete(receipt_handle));
+                }
+                return Box::new(future::ok(()));
+            }
+        };
+
+        let extending = Arc::clone(&self).extend_visibility(receipt_handle.clone());
+        let (batch, delivered) = BatchNotifier::new_with_receiver();
+        let ingestor = Arc::clone(&self);
+        let fut = stream::iter_ok(objects)
+            .for_each(move |object| ingestor.ingest(object, Arc::clone(&batch)))
+            .then(move |result| match result {
+                Ok(()) => {
+                    if self.delete_message {
+                        tokio::spawn(self.delete_when_delivered(
+                            receipt_handle,
+                            delivered,
+                            extending,
+                        ));
+                    }
+                    Ok(())
+                }
+                Err(IngestError::PipelineClosed) => Err(()),
+                Err(error) => {
+                    error!(
+                        message = "failed to ingest objects; the SQS message will be retried.",
+                        %error,
+                        rate_limit_secs = 30,
+                    );
+                    Ok(())
+                }
+            });
+
+        Box::new(fut)
+    }
+
+    /// Keeps the message hidden from other consumers until the returned
+    /// sender is dropped, so objects taking longer than the visibility
+    /// timeout to ingest and deliver aren't ingested twice.
+    fn extend_visibility(self: Arc<Self>, receipt_handle: String) -> oneshot::Sender<()> {
+        let (extending, done) = oneshot::channel();
+        let period = Duration::from_secs(u64::from(self.visibility_timeout_secs / 2).max(1));
+        let ingestor = self;
+        let extend = Interval::new(Instant::now() + period, period)
+            .map_err(|error| error!(message = "timer error.", %error))
+            .for_each(move |_| {
+                ingestor
+                    .sqs
+                    .change_message_visibility(
+                        &ingestor.queue_url,
+                        &receipt_handle,
+                        ingestor.visibility_timeout_secs,
+                    )
+                    .then(|result| {
+                        if let Err(error) = result {
+                            warn!(
+                                message = "failed to extend the visibility timeout of SQS message.",
+                                %error,
+                                rate_limit_secs = 30,
+                            );
+                        }
+                        Ok(())
+                    })
+            });
+
+        tokio::spawn(extend.select2(done).then(|_| Ok(())));
+        extending
+    }
+
+    fn delete_when_delivered(
+        self: Arc<Self>,
+        receipt_handle: String,
+        delivered: oneshot::Receiver<BatchStatus>,
+        extending: oneshot::Sender<()>,
+    ) -> impl Future<Item = (), Error = ()> {
+        delivered.then(move |status| {
+            drop(extending);
+            match status {
+                Ok(BatchStatus::Delivered) => Either::A(self.delete(receipt_handle)),
+                _ => {
+                    warn!(
+                        message = "events were not delivered; the SQS message will be retried.",
+                        rate_limit_secs = 30,
+                    );
+                    Either::B(future::ok(()))
+                }
+            }
+        })
+    }
+
+    fn ingest(
+        &self,
+        object: ObjectRef,
+        batch: Arc<BatchNotifier>,
+    ) -> impl Future<Item = (), Error = IngestError> {
+        debug!(
+            message = "ingesting object.",
+            bucket = %object.bucket,
+            key = %object.key,
+        );
+
+        let ObjectRef { bucket, key } = object;
+        let mut decoder = ObjectDecoder::new(self.compression, self.encoding, &bucket, &key);
+        let out = self
+            .out
+            .clone()
+            .sink_map_err(|_| IngestError::PipelineClosed);
+
+        self.s3
+            .get_object(GetObjectRequest {
+                bucket: bucket.clone(),
+                key: key.clone(),
+                ..Default::default()
+            })
+            .map_err({
+                let (bucket, key) = (bucket.clone(), key.clone());
+                move |source| IngestError::GetObject {
+                    bucket,
+                    key,
+                    source,
+                }
+            })
+            .and_then(move |output| {
+                let body = output.body.unwrap_or_else(|| Vec::new().into());
+                let events = body
+                    .map(Some)
+                    .chain(stream::once(Ok(None)))
+                    .and_then(move |chunk| decoder.decode(chunk))
+                    .map_err(move |source| IngestError::ReadObject {
+                        bucket: bucket.clone(),
+                        key: key.clone(),
+                        source,
+                    })
+                    .map(stream::iter_ok::<_, IngestError>)
+                    .flatten()
+                    .map(move |mut event| {
+                        event.add_batch_notifier(Arc::clone(&batch));
+                        event
+                    });
+
+                out.send_all(events).map(|_| ())
+            })
+    }
+
+    fn delete(&self, receipt_handle: String) -> impl Future<Item = (), Error = ()> {
+        self.sqs
+            .delete_message(&self.queue_url, &receipt_handle)
+            .then(|result| {
+                if let Err(error) = result {
+                    error!(message = "failed to delete SQS message.", %error);
+                }
+                Ok(())
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aws_s3_parses_notifications() {
+        let body = r#"{
+            "Records": [
+                {
+                    "eventVersion": "2.1",
+                    "eventSource": "aws:s3",
+                    "awsRegion": "us-east-1",
+                    "eventName": "ObjectCreated:Put",
+                    "s3": {
+                        "bucket": { "name": "logs", "arn": "arn:aws:s3:::logs" },
+                        "object": { "key": "date%3D2020-05-01/app+logs.gz", "size": 1024 }
+                    }
+                },
+                {
+                    "eventVersion": "2.1",
+                    "eventSource": "aws:s3",
+                    "awsRegion": "us-east-1",
+                    "eventName": "ObjectRemoved:Delete",
+                    "s3": {
+                        "bucket": { "name": "logs" },
+                        "object": { "key": "old.gz" }
+                    }
+                }
+            ]
+        }"#;
+
+        assert_eq!(
+            parse_notification(body).unwrap(),
+            vec![ObjectRef {
+                bucket: "logs".into(),
+                key: "date=2020-05-01/app logs.gz".into(),
+            }]
+        );
+    }
+
+    #[test]
+    fn aws_s3_ignores_test_notifications() {
+        let body = r#"{
+            "Service": "Amazon S3",
+            "Event": "s3:TestEvent",
+            "Time": "2020-05-01T00:00:00.000Z",
+            "Bucket": "logs"
+        }"#;
+
+        assert_eq!(parse_notification(body).unwrap(), vec![]);
+        assert!(parse_notification("not json").is_err());
+    }
+}
+
+#[cfg(feature = "s3-integration-tests")]
+#[cfg(test)]
+mod integration_tests {
+    use super::super::{
+        client::{element_texts, SqsClient},
+        AwsS3Config,
+    };
+    use crate::{
+        event::{self, Event},
+        sinks::util::rusoto::AwsCredentialsProvider,
+        test_util::{collect_n, random_lines, random_string, runtime},
+        topology::config::{GlobalOptions, SourceConfig},
+    };
+    use flate2::{write::GzEncoder, Compression};
+    use futures01::sync::mpsc;
+    use rusoto_core::{HttpClient, Region};
+    use rusoto_credential::StaticProvider;
+    use rusoto_s3::{CreateBucketRequest, PutObjectRequest, S3Client, S3};
+    use std::io::Write;
+
+    const BUCKET: &str = "source-tests";
+    const S3_ENDPOINT: &str = "http://localhost:9000";
+    const SQS_ENDPOINT: &str = "http://localhost:4576";
+
+    fn region(endpoint: &str) -> Region {
+        Region::Custom {
+            name: "us-east-1".to_owned(),
+            endpoint: endpoint.to_owned(),
+        }
+    }
+
+    fn s3_client() -> S3Client {
+        let p = StaticProvider::new_minimal("test-access-key".into(), "test-secret-key".into());
+        S3Client::new_with(HttpClient::new().unwrap(), p, region(S3_ENDPOINT))
+    }
+
+    /// Sends an SQS action, returning the text of the `element`s of the
+    /// response.
+    fn sqs_request(action: &str, params: &[(&str, &str)], element: &str) -> Vec<String> {
+        let client = SqsClient::new(
+            HttpClient::new().unwrap(),
+            AwsCredentialsProvider::new_minimal("test-access-key", "test-secret-key"),
+            region(SQS_ENDPOINT),
+        );
+        let body = runtime().block_on(client.request(action, params)).unwrap();
+        element_texts(&body, element).unwrap()
+    }
+
+    fn notification(key: &str) -> String {
+        serde_json::json!({
+            "Records": [{
+                "eventName": "ObjectCreated:Put",
+                "s3": {
+                    "bucket": { "name": BUCKET },
+                    "object": { "key": key }
+                }
+            }]
+        })
+        .to_string()
+    }
+
+    fn queued_messages(queue_url: &str) -> usize {
+        sqs_request(
+            "GetQueueAttributes",
+            &[
+                ("QueueUrl", queue_url),
+                ("AttributeName.1", "ApproximateNumberOfMessages"),
+                ("AttributeName.2", "ApproximateNumberOfMessagesNotVisible"),
+            ],
+            "Value",
+        )
+        .iter()
+        .map(|count| count.parse::<usize>().unwrap())
+        .sum()
+    }
+
+    #[test]
+    fn aws_s3_ingests_notified_objects() {
+        let _ = s3_client()
+            .create_bucket(CreateBucketRequest {
+                bucket: BUCKET.to_owned(),
+                ..Default::default()
+            })
+            .sync();
+
+        let lines = random_lines(100).take(1000).collect::<Vec<_>>();
+        let mut body = GzEncoder::new(Vec::new(), Compression::default());
+        body.write_all(lines.join("\n").as_bytes()).unwrap();
+        let key = format!("{}/logs.gz", random_string(10));
+        s3_client()
+            .put_object(PutObjectRequest {
+                bucket: BUCKET.to_owned(),
+                key: key.clone(),
+                body: Some(body.finish().unwrap().into()),
+                ..Default::default()
+            })
+            .sync()
+            .unwrap();
+
+        let queue_url = sqs_request(
+            "CreateQueue",
+            &[("QueueName", &random_string(10))],
+            "QueueUrl",
+        )
+        .remove(0);
+        sqs_request(
+            "SendMessage",
+            &[
+                ("QueueUrl", &queue_url),
+                ("MessageBody", &notification(&key)),
+            ],
+            "MessageId",
+        );
+
+        let config: AwsS3Config = toml::from_str(&format!(
+            r#"
+                endpoint = "{}"
+                sqs.queue_url = "{}"
+                sqs.endpoint = "{}"
+                sqs.poll_secs = 1
+            "#,
+            S3_ENDPOINT, queue_url, SQS_ENDPOINT
+        ))
+        .unwrap();
+
+        let mut rt = runtime();
+        let (tx, rx) = mpsc::channel(10);
+        let source = config
+            .build("default", &GlobalOptions::default(), tx)
+            .unwrap();
+        rt.spawn(source);
+
+        let events: Vec<Event> = rt.block_on(collect_n(rx, lines.len())).unwrap();
+        let messages = events
+            .iter()
+            .map(|event| event.as_log()[&event::log_schema().message_key()].to_string_lossy())
+            .collect::<Vec<_>>();
+        assert_eq!(messages, lines);
+        assert_eq!(events[0].as_log()[&"object".into()], key.as_str().into());
+        // The message is deleted once the events have been delivered.
+        assert_eq!(queued_messages(&queue_url), 1);
+        drop(events);
+
+        crate::test_util::wait_for(|| queued_messages(&queue_url) == 0);
+    }
+}
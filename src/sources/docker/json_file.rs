@@ -0,0 +1,523 @@
+//! Tails the log files written by the `json-file` Docker logging driver,
+//! for hosts where the Docker API isn't accessible. Container metadata is
+//! lazily read from each container's `config.v2.json` on first sight, and
+//! forgotten once the container's directory is removed.
+
+use super::{
+    is_self, merge_partial_event, remove_slash, ContainerMetadata, DockerConfig, CONTAINER, STDERR,
+    STDOUT, STREAM,
+};
+use crate::{
+    event::{self, merge_state::LogEventMergeState, Event},
+    sources::{file::FileConfig, Source},
+    topology::config::{GlobalOptions, SourceConfig},
+};
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures01::{sync::mpsc, Future, Sink, Stream};
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use std::{env, fs, iter};
+use string_cache::DefaultAtom as Atom;
+
+/// Subdirectory of Docker data root with a directory per container.
+const CONTAINERS_DIR: &str = "containers";
+/// Container configuration, as persisted by the Docker daemon.
+const CONFIG_FILE: &str = "config.v2.json";
+/// How long before metadata that couldn't be read is read again.
+const METADATA_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+/// How often removed containers are forgotten.
+const EVICTION_INTERVAL: Duration = Duration::from_secs(60);
+
+lazy_static! {
+    static ref FILE: Atom = Atom::from("file");
+}
+
+pub fn build(
+    config: DockerConfig,
+    name: &str,
+    globals: &GlobalOptions,
+    out: mpsc::Sender<Event>,
+) -> crate::Result<Source> {
+    let containers_dir = config.docker_data_root.join(CONTAINERS_DIR);
+
+    let file_config = FileConfig {
+        include: vec![containers_dir.join("*").join("*-json.log")],
+        // Have a subdirectory for this source to avoid collision of naming its file source.
+        data_dir: Some(globals.resolve_and_make_data_subdir(None, name)?),
+        ..FileConfig::default()
+    };
+
+    let (file_send, file_recv) = mpsc::channel(1000);
+    let file_source = file_config.build("file_source", globals, file_send)?;
+
+    let mut reader = JsonFileReader::new(config, containers_dir);
+    let source = file_recv
+        .filter_map(move |event| reader.read(event))
+        .forward(out.sink_map_err(drop))
+        .map(drop)
+        .join(file_source)
+        .map(drop);
+
+    Ok(Box::new(source))
+}
+
+/// One line of a `json-file` log.
+#[derive(Deserialize)]
+struct JsonFileLine {
+    log: String,
+    stream: String,
+    time: String,
+}
+
+/// Subset of `config.v2.json` this source is interested in.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ContainerConfigFile {
+    name: String,
+    created: String,
+    config: ContainerConfigFileConfig,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ContainerConfigFileConfig {
+    image: String,
+    #[serde(default)]
+    labels: Option<HashMap<String, String>>,
+    #[serde(default)]
+    env: Option<Vec<String>>,
+}
+
+enum ContainerEntry {
+    /// Container doesn't pass the include filters.
+    Excluded,
+    /// Metadata is `None` if it couldn't be read.
+    Included(Option<ContainerMetadata>),
+}
+
+struct Container {
+    entry: ContainerEntry,
+    /// When the metadata couldn't be read, to read it again later.
+    failed_at: Option<Instant>,
+}
+
+struct JsonFileReader {
+    config: DockerConfig,
+    containers_dir: PathBuf,
+    /// It may contain shortened container id.
+    hostname: Option<String>,
+    /// True if self needs to be excluded
+    exclude_self: bool,
+    /// Lazily resolved containers, by container id.
+    containers: HashMap<String, Container>,
+    /// Partial events being merged, by container id.
+    partial_event_merge_states: HashMap<String, Option<LogEventMergeState>>,
+    last_eviction: Instant,
+}
+
+impl JsonFileReader {
+    fn new(config: DockerConfig, containers_dir: PathBuf) -> Self {
+        JsonFileReader {
+            exclude_self: config.exclude_self(),
+            config,
+            containers_dir,
+            hostname: env::var("HOSTNAME").ok(),
+            containers: HashMap::new(),
+            partial_event_merge_states: HashMap::new(),
+            last_eviction: Instant::now(),
+        }
+    }
+
+    fn read(&mut self, event: Event) -> Option<Event> {
+        let log = event.as_log();
+        let file = log.get(&FILE)?.to_string_lossy();
+        let id = container_id(Path::new(&file))?;
+
+        let line = log.get(&event::log_schema().message_key())?.as_bytes();
+        let line = match serde_json::from_slice::<JsonFileLine>(&line) {
+            Ok(line) => line,
+            Err(error) => {
+                warn!(
+                    message = "Unable to parse json-file log line.",
+                    %error,
+                    rate_limit_secs = 10
+                );
+                return None;
+            }
+        };
+
+        self.evict_removed();
+        let stale = self.containers.get(&id).map_or(true, |container| {
+            container
+                .failed_at
+                .map_or(false, |at| at.elapsed() >= METADATA_RETRY_INTERVAL)
+        });
+        if stale {
+            let container = self.load(&id);
+            self.containers.insert(id.clone(), container);
+        }
+        let metadata = match &self.containers[&id].entry {
+            ContainerEntry::Excluded => return None,
+            ContainerEntry::Included(metadata) => metadata.as_ref(),
+        };
+
+        let stream = match line.stream.as_str() {
+            "stderr" => STDERR.clone(),
+            "stdout" => STDOUT.clone(),
+            _ => return None,
+        };
+
+        // Each line is terminated by a newline, unless it's partial.
+        let mut message = line.log;
+        let is_partial = if message.ends_with('\n') {
+            message.pop();
+            false
+        } else {
+            true
+        };
+
+        let mut log_event = Event::new_empty_log().into_log();
+        log_event.insert(event::log_schema().message_key().clone(), message);
+        log_event.insert(STREAM.clone(), stream);
+        match DateTime::parse_from_rfc3339(&line.time) {
+            Ok(timestamp) => log_event.insert(
+                event::log_schema().timestamp_key().clone(),
+                timestamp.with_timezone(&Utc),
+            ),
+            Err(error) => error!(message = "Invalid timestamp in json-file log line.", %error),
+        }
+        log_event.insert(CONTAINER.clone(), Bytes::from(id.as_str()));
+        if let Some(metadata) = metadata {
+            metadata.enrich(&mut log_event);
+        }
+
+        merge_partial_event(
+            log_event,
+            is_partial,
+            self.config.partial_event_marker_field.clone(),
+            self.config.auto_partial_merge,
+            self.partial_event_merge_states.entry(id).or_insert(None),
+        )
+    }
+
+    /// Forgets the containers whose directory was removed, along with the
+    /// events they left partial.
+    fn evict_removed(&mut self) {
+        if self.last_eviction.elapsed() < EVICTION_INTERVAL {
+            return;
+        }
+        self.last_eviction = Instant::now();
+
+        let containers_dir = &self.containers_dir;
+        let exists = |id: &String| containers_dir.join(id).exists();
+        self.containers.retain(|id, _| exists(id));
+        self.partial_event_merge_states.retain(|id, _| exists(id));
+    }
+
+    fn load(&self, id: &str) -> Container {
+        let path = self.containers_dir.join(id).join(CONFIG_FILE);
+        let file = match read_config_file(&path) {
+            Ok(file) => file,
+            Err(error) => {
+                error!(message = "Unable to read container metadata.", ?path, %error);
+                // Filters can't be checked without metadata.
+                let entry = if self.has_filters() {
+                    ContainerEntry::Excluded
+                } else {
+                    ContainerEntry::Included(None)
+                };
+                return Container {
+                    entry,
+                    failed_at: Some(Instant::now()),
+                };
+            }
+        };
+
+        if !self.included(id, &file) {
+            trace!(message = "Container excluded", %id);
+            return Container {
+                entry: ContainerEntry::Excluded,
+                failed_at: None,
+            };
+        }
+
+        let metadata = ContainerMetadata::new(
+            &self.config,
+            &file.name,
+            &file.config.image,
+            &file.created,
+            file.config.labels.as_ref(),
+            file.config.env.as_ref().map(Vec::as_slice).unwrap_or(&[]),
+        );
+        let entry = match metadata {
+            Ok(metadata) => ContainerEntry::Included(Some(metadata)),
+            Err(error) => {
+                error!(message = "Metadata extraction failed", %error);
+                ContainerEntry::Included(None)
+            }
+        };
+        Container {
+            entry,
+            failed_at: None,
+        }
+    }
+
+    fn has_filters(&self) -> bool {
+        self.config.include_containers.is_some()
+            || self.config.include_labels.is_some()
+            || self.config.include_images.is_some()
+    }
+
+    fn included(&self, id: &str, file: &ContainerConfigFile) -> bool {
+        if self.exclude_self && is_self(self.hostname.as_ref(), id, file.config.image.as_str()) {
+            return false;
+        }
+
+        if !self
+            .config
+            .container_name_included(id, iter::once(remove_slash(&file.name)))
+        {
+            return false;
+        }
+
+        if let Some(images) = &self.config.include_images {
+            if !images.is_empty() && !images.iter().any(|image| image == &file.config.image) {
+                return false;
+            }
+        }
+
+        // Same as in the Docker API, all of the labels must match.
+        if let Some(include_labels) = &self.config.include_labels {
+            let labels = file.config.labels.clone().unwrap_or_default();
+            let label_included = |include: &String| {
+                let mut parts = include.splitn(2, '=');
+                let key = parts.next().unwrap_or("");
+                match (labels.get(key), parts.next()) {
+                    (Some(value), Some(expected)) => value == expected,
+                    (Some(_), None) => true,
+                    (None, _) => false,
+                }
+            };
+            if !include_labels.iter().all(label_included) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+fn read_config_file(path: &Path) -> crate::Result<ContainerConfigFile> {
+    let bytes = fs::read(path)?;
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+/// Log files are at `<data_root>/containers/<id>/<id>-json.log`.
+fn container_id(file: &Path) -> Option<String> {
+    file.parent()?.file_name()?.to_str().map(ToOwned::to_owned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::Value;
+    use tempfile::tempdir;
+
+    const ID: &str = "2b1bde3fa6fd1ec6cd3d2e8c5d2f2a3d9b3fa6b6b8a8c2d1e0f1a2b3c4d5e6f7";
+
+    fn write_config(containers_dir: &Path, id: &str, image: &str) {
+        let dir = containers_dir.join(id);
+        fs::create_dir_all(&dir).unwrap();
+        let config = serde_json::json!({
+            "ID": id,
+            "Created": "2020-03-01T12:00:00.123456789Z",
+            "Name": "/vector_test",
+            "Config": {
+                "Image": image,
+                "Labels": {
+                    "com.example.vendor": "Timber",
+                    "com.docker.compose.project": "shop",
+                    "com.docker.compose.service": "web"
+                },
+                "Env": ["PATH=/usr/bin", "SERVICE_VERSION=1.2.3"]
+            }
+        });
+        fs::write(dir.join(CONFIG_FILE), config.to_string()).unwrap();
+    }
+
+    fn line(containers_dir: &Path, id: &str, log: &str) -> Event {
+        let mut event = Event::from(
+            serde_json::json!({
+                "log": log,
+                "stream": "stderr",
+                "time": "2020-03-01T12:00:01.000000001Z"
+            })
+            .to_string(),
+        );
+        let file = containers_dir.join(id).join(format!("{}-json.log", id));
+        event
+            .as_mut_log()
+            .insert(FILE.clone(), file.to_str().unwrap().to_owned());
+        event
+    }
+
+    fn reader(config: DockerConfig, containers_dir: &Path) -> JsonFileReader {
+        let mut reader = JsonFileReader::new(config, containers_dir.to_owned());
+        reader.exclude_self = false;
+        reader
+    }
+
+    #[test]
+    fn docker_json_file_enriches() {
+        let dir = tempdir().unwrap();
+        write_config(dir.path(), ID, "busybox");
+        let config = DockerConfig {
+            include_env_vars: vec!["SERVICE_VERSION".to_owned()],
+            compose_metadata: true,
+            ..DockerConfig::default()
+        };
+        let mut reader = reader(config, dir.path());
+
+        let event = reader.read(line(dir.path(), ID, "hello\n")).unwrap();
+        let log = event.as_log();
+        assert_eq!(log[&event::log_schema().message_key()], "hello".into());
+        assert_eq!(log[&STREAM], "stderr".into());
+        assert_eq!(log[&CONTAINER], ID.into());
+        assert_eq!(log[&super::super::NAME], "vector_test".into());
+        assert_eq!(log[&super::super::IMAGE], "busybox".into());
+        assert!(log.get(&super::super::CREATED_AT).is_some());
+        assert!(log.get(&event::log_schema().timestamp_key()).is_some());
+        assert_eq!(
+            log[&"label.com.example.vendor".into()],
+            Value::from("Timber")
+        );
+        assert_eq!(log[&super::super::COMPOSE_PROJECT], "shop".into());
+        assert_eq!(log[&super::super::COMPOSE_SERVICE], "web".into());
+        assert_eq!(log[&"env.SERVICE_VERSION".into()], "1.2.3".into());
+        assert!(log.get(&"env.PATH".into()).is_none());
+    }
+
+    #[test]
+    fn docker_json_file_label_prefix_and_compose_disabled() {
+        let dir = tempdir().unwrap();
+        write_config(dir.path(), ID, "busybox");
+        let config = DockerConfig {
+            label_prefix: "docker_labels".to_owned(),
+            compose_metadata: false,
+            ..DockerConfig::default()
+        };
+        let mut reader = reader(config, dir.path());
+
+        let event = reader.read(line(dir.path(), ID, "hello\n")).unwrap();
+        let log = event.as_log();
+        assert_eq!(
+            log[&"docker_labels.com.example.vendor".into()],
+            Value::from("Timber")
+        );
+        assert!(log.get(&"label".into()).is_none());
+        assert!(log.get(&super::super::COMPOSE_PROJECT).is_none());
+    }
+
+    #[test]
+    fn docker_json_file_merges_partial() {
+        let dir = tempdir().unwrap();
+        write_config(dir.path(), ID, "busybox");
+        let mut reader = reader(DockerConfig::default(), dir.path());
+
+        assert!(reader.read(line(dir.path(), ID, "hel")).is_none());
+        let event = reader.read(line(dir.path(), ID, "lo\n")).unwrap();
+        assert_eq!(
+            event.as_log()[&event::log_schema().message_key()],
+            "hello".into()
+        );
+    }
+
+    #[test]
+    fn docker_json_file_filters() {
+        let dir = tempdir().unwrap();
+        write_config(dir.path(), ID, "busybox");
+
+        let config = DockerConfig {
+            include_images: Some(vec!["redis".to_owned()]),
+            ..DockerConfig::default()
+        };
+        assert!(reader(config, dir.path())
+            .read(line(dir.path(), ID, "hello\n"))
+            .is_none());
+
+        let config = DockerConfig {
+            include_labels: Some(vec!["com.example.vendor=Timber".to_owned()]),
+            include_containers: Some(vec!["vector_".to_owned()]),
+            ..DockerConfig::default()
+        };
+        assert!(reader(config, dir.path())
+            .read(line(dir.path(), ID, "hello\n"))
+            .is_some());
+
+        let config = DockerConfig {
+            include_labels: Some(vec!["com.example.vendor=Other".to_owned()]),
+            ..DockerConfig::default()
+        };
+        assert!(reader(config, dir.path())
+            .read(line(dir.path(), ID, "hello\n"))
+            .is_none());
+    }
+
+    #[test]
+    fn docker_json_file_missing_metadata() {
+        let dir = tempdir().unwrap();
+
+        let event = reader(DockerConfig::default(), dir.path())
+            .read(line(dir.path(), ID, "hello\n"))
+            .unwrap();
+        assert_eq!(event.as_log()[&CONTAINER], ID.into());
+        assert!(event.as_log().get(&super::super::NAME).is_none());
+
+        let config = DockerConfig {
+            include_containers: Some(vec!["vector_".to_owned()]),
+            ..DockerConfig::default()
+        };
+        assert!(reader(config, dir.path())
+            .read(line(dir.path(), ID, "hello\n"))
+            .is_none());
+    }
+
+    #[test]
+    fn docker_json_file_retries_missing_metadata() {
+        let dir = tempdir().unwrap();
+        let mut reader = reader(DockerConfig::default(), dir.path());
+        let event = reader.read(line(dir.path(), ID, "hello\n")).unwrap();
+        assert!(event.as_log().get(&super::super::NAME).is_none());
+
+        write_config(dir.path(), ID, "busybox");
+        let event = reader.read(line(dir.path(), ID, "hello\n")).unwrap();
+        assert!(event.as_log().get(&super::super::NAME).is_none());
+
+        let retry_at = Instant::now() - METADATA_RETRY_INTERVAL;
+        reader.containers.get_mut(ID).unwrap().failed_at = Some(retry_at);
+        let event = reader.read(line(dir.path(), ID, "hello\n")).unwrap();
+        assert_eq!(event.as_log()[&super::super::NAME], "vector_test".into());
+    }
+
+    #[test]
+    fn docker_json_file_forgets_removed_containers() {
+        let dir = tempdir().unwrap();
+        write_config(dir.path(), ID, "busybox");
+        let other = "0".repeat(64);
+        write_config(dir.path(), &other, "busybox");
+        let mut reader = reader(DockerConfig::default(), dir.path());
+
+        assert!(reader.read(line(dir.path(), ID, "partial")).is_none());
+        assert!(reader.containers.contains_key(ID));
+
+        fs::remove_dir_all(dir.path().join(ID)).unwrap();
+        reader.last_eviction = Instant::now() - EVICTION_INTERVAL;
+        assert!(reader.read(line(dir.path(), &other, "hello\n")).is_some());
+        assert!(!reader.containers.contains_key(ID));
+        assert!(!reader.partial_event_merge_states.contains_key(ID));
+    }
+}
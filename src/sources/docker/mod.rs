@@ -1,6 +1,8 @@
+mod json_file;
+
 use crate::{
     event::merge_state::LogEventMergeState,
-    event::{self, Event, Value},
+    event::{self, Event, LogEvent, Value},
     topology::config::{DataType, GlobalOptions, SourceConfig, SourceDescription},
 };
 use bytes::{Bytes, BytesMut};
@@ -19,6 +21,7 @@ use shiplift::{
     Docker, Error,
 };
 use std::borrow::Borrow;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::{collections::HashMap, env};
 use string_cache::DefaultAtom as Atom;
@@ -35,8 +38,14 @@ lazy_static! {
     static ref NAME: Atom = Atom::from("container_name");
     static ref STREAM: Atom = Atom::from("stream");
     static ref CONTAINER: Atom = Atom::from("container_id");
+    static ref COMPOSE_PROJECT: Atom = Atom::from("compose_project");
+    static ref COMPOSE_SERVICE: Atom = Atom::from("compose_service");
 }
 
+/// Labels set by docker-compose on the containers it manages.
+const COMPOSE_PROJECT_LABEL: &str = "com.docker.compose.project";
+const COMPOSE_SERVICE_LABEL: &str = "com.docker.compose.service";
+
 type DockerEvent = shiplift::rep::Event;
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -45,8 +54,13 @@ pub struct DockerConfig {
     include_containers: Option<Vec<String>>,
     include_labels: Option<Vec<String>>,
     include_images: Option<Vec<String>>,
+    include_env_vars: Vec<String>,
+    label_prefix: String,
+    compose_metadata: bool,
     partial_event_marker_field: Option<Atom>,
     auto_partial_merge: bool,
+    mode: Mode,
+    docker_data_root: PathBuf,
 }
 
 impl Default for DockerConfig {
@@ -55,12 +69,27 @@ impl Default for DockerConfig {
             include_containers: None,
             include_labels: None,
             include_images: None,
+            include_env_vars: Vec::new(),
+            label_prefix: "label".to_owned(),
+            compose_metadata: false,
             partial_event_marker_field: Some(event::PARTIAL.clone()),
             auto_partial_merge: true,
+            mode: Mode::Api,
+            docker_data_root: PathBuf::from("/var/lib/docker"),
         }
     }
 }
 
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    /// Logs are streamed through the Docker API.
+    Api,
+    /// Log files of the `json-file` logging driver are tailed directly,
+    /// and container metadata is read from the Docker data root.
+    JsonFile,
+}
+
 impl DockerConfig {
     fn container_name_included<'a>(
         &self,
@@ -84,6 +113,16 @@ impl DockerConfig {
         }
     }
 
+    /// True if all containers are to be logged, in which case
+    /// vector's own container needs to be excluded.
+    fn exclude_self(&self) -> bool {
+        self.include_containers
+            .clone()
+            .unwrap_or_default()
+            .is_empty()
+            && self.include_labels.clone().unwrap_or_default().is_empty()
+    }
+
     fn with_empty_partial_event_marker_field_as_none(mut self) -> Self {
         if let Some(val) = &self.partial_event_marker_field {
             if val.is_empty() {
@@ -102,16 +141,17 @@ inventory::submit! {
 impl SourceConfig for DockerConfig {
     fn build(
         &self,
-        _name: &str,
-        _globals: &GlobalOptions,
+        name: &str,
+        globals: &GlobalOptions,
         out: Sender<Event>,
     ) -> crate::Result<super::Source> {
-        DockerSource::new(
-            self.clone().with_empty_partial_event_marker_field_as_none(),
-            out,
-        )
-        .map(Box::new)
-        .map(|source| source as Box<_>)
+        let config = self.clone().with_empty_partial_event_marker_field_as_none();
+        match config.mode {
+            Mode::Api => DockerSource::new(config, out)
+                .map(Box::new)
+                .map(|source| source as Box<_>),
+            Mode::JsonFile => json_file::build(config, name, globals, out),
+        }
     }
 
     fn output_type(&self) -> DataType {
@@ -233,12 +273,7 @@ impl DockerSource {
         // exact, but probable.
         // This is to be used only if source is in state of catching everything.
         // Or in other words, if includes are used then this is not necessary.
-        let exclude_self = config
-            .include_containers
-            .clone()
-            .unwrap_or_default()
-            .is_empty()
-            && config.include_labels.clone().unwrap_or_default().is_empty();
+        let exclude_self = config.exclude_self();
 
         // Only logs created at, or after this moment are logged.
         let core = DockerSourceCore::new(config)?;
@@ -353,23 +388,26 @@ impl DockerSource {
     /// True if container with the given id and image must be excluded from logging,
     /// because it's a vector instance, probably this one.
     fn exclude_vector<'a>(&self, id: &str, image: impl Into<Option<&'a str>>) -> bool {
-        if self.exclude_self {
-            let hostname_hint = self
-                .hostname
-                .as_ref()
-                .map(|maybe_short_id| id.starts_with(maybe_short_id))
-                .unwrap_or(false);
-            let image_hint = image
-                .into()
-                .map(|image| image.starts_with(VECTOR_IMAGE_NAME))
-                .unwrap_or(false);
-            if hostname_hint || image_hint {
-                // This container is probably itself.
-                info!(message = "Detected self container", id);
-                return false;
-            }
-        }
+        !(self.exclude_self && is_self(self.hostname.as_ref(), id, image))
+    }
+}
+
+/// True if container with the given id and image is probably a vector instance,
+/// this one, judging by the hostname.
+fn is_self<'a>(hostname: Option<&String>, id: &str, image: impl Into<Option<&'a str>>) -> bool {
+    let hostname_hint = hostname
+        .map(|maybe_short_id| id.starts_with(maybe_short_id.as_str()))
+        .unwrap_or(false);
+    let image_hint = image
+        .into()
+        .map(|image| image.starts_with(VECTOR_IMAGE_NAME))
+        .unwrap_or(false);
+    if hostname_hint || image_hint {
+        // This container is probably itself.
+        info!(message = "Detected self container", id);
         true
+    } else {
+        false
     }
 }
 
@@ -513,9 +551,12 @@ impl EventStreamBuilder {
             .get(id.as_str())
             .inspect()
             .map_err(|error| error!(message="Fetching container details failed",%error))
-            .and_then(|details| {
-                ContainerMetadata::from_details(&details)
-                    .map_err(|error| error!(message="Metadata extraction failed",%error))
+            .and_then({
+                let core = Arc::clone(&self.core);
+                move |details| {
+                    ContainerMetadata::from_details(&details, &core.config)
+                        .map_err(|error| error!(message="Metadata extraction failed",%error))
+                }
             });
 
         let this = self.clone();
@@ -796,7 +837,7 @@ impl ContainerLogInfo {
         };
 
         // Prepare the log event.
-        let mut log_event = {
+        let log_event = {
             let mut log_event = Event::new_empty_log().into_log();
 
             // The log message.
@@ -816,80 +857,82 @@ impl ContainerLogInfo {
             // Container ID.
             log_event.insert(CONTAINER.clone(), self.id.0.clone());
 
-            // Labels.
-            for (key, value) in self.metadata.labels.iter() {
-                log_event.insert(key.clone(), value.clone());
-            }
-
-            // Container name.
-            log_event.insert(NAME.clone(), self.metadata.name.clone());
-
-            // Container image.
-            log_event.insert(IMAGE.clone(), self.metadata.image.clone());
-
-            // Timestamp of the container creation.
-            log_event.insert(CREATED_AT.clone(), self.metadata.created_at.clone());
+            // Container metadata.
+            self.metadata.enrich(&mut log_event);
 
             // Return the resulting log event.
             log_event
         };
 
-        // If automatic partial event merging is requested - perform the
-        // merging.
-        // Otherwise mark partial events and return all the events with no
-        // merging.
-        let log_event = if auto_partial_merge {
-            // Partial event events merging logic.
-
-            // If event is partial, stash it and return `None`.
-            if is_partial {
-                // If we already have a partial event merge state, the current
-                // message has to be merged into that existing state.
-                // Otherwise, create a new partial event merge state with the
-                // current message being the initial one.
-                if let Some(partial_event_merge_state) = partial_event_merge_state {
-                    partial_event_merge_state.merge_in_next_event(
-                        log_event,
-                        &[event::log_schema().message_key().clone()],
-                    );
-                } else {
-                    *partial_event_merge_state = Some(LogEventMergeState::new(log_event));
-                };
-                return None;
-            };
+        merge_partial_event(
+            log_event,
+            is_partial,
+            partial_event_marker_field,
+            auto_partial_merge,
+            partial_event_merge_state,
+        )
+    }
+}
 
-            // This is not a parial event. If we have a partial event merge
-            // state from before, the current event must be a final event, that
-            // would give us a merged event we can return.
-            // Otherwise it's just a regular event that we return as-is.
-            match partial_event_merge_state.take() {
-                Some(partial_event_merge_state) => partial_event_merge_state
-                    .merge_in_final_event(log_event, &[event::log_schema().message_key().clone()]),
-                None => log_event,
-            }
-        } else {
-            // If the event is partial, just set the partial event marker field.
-            if is_partial {
-                // Only add partial event marker field if it's requested.
-                if let Some(partial_event_marker_field) = partial_event_marker_field {
-                    log_event.insert(partial_event_marker_field, true);
-                }
-            }
-            // Return the log event as is, partial or not. No merging here.
-            log_event
+/// If automatic partial event merging is requested - performs the merging.
+/// Otherwise marks partial events and returns all the events with no merging.
+fn merge_partial_event(
+    mut log_event: LogEvent,
+    is_partial: bool,
+    partial_event_marker_field: Option<Atom>,
+    auto_partial_merge: bool,
+    partial_event_merge_state: &mut Option<LogEventMergeState>,
+) -> Option<Event> {
+    let log_event = if auto_partial_merge {
+        // Partial event events merging logic.
+
+        // If event is partial, stash it and return `None`.
+        if is_partial {
+            // If we already have a partial event merge state, the current
+            // message has to be merged into that existing state.
+            // Otherwise, create a new partial event merge state with the
+            // current message being the initial one.
+            if let Some(partial_event_merge_state) = partial_event_merge_state {
+                partial_event_merge_state
+                    .merge_in_next_event(log_event, &[event::log_schema().message_key().clone()]);
+            } else {
+                *partial_event_merge_state = Some(LogEventMergeState::new(log_event));
+            };
+            return None;
         };
 
-        // Partial or not partial - we return the event we got here, because all
-        // other cases were handeled earlier.
-        let event = Event::Log(log_event);
-        trace!(message = "Received one event.", ?event);
-        Some(event)
-    }
+        // This is not a parial event. If we have a partial event merge
+        // state from before, the current event must be a final event, that
+        // would give us a merged event we can return.
+        // Otherwise it's just a regular event that we return as-is.
+        match partial_event_merge_state.take() {
+            Some(partial_event_merge_state) => partial_event_merge_state
+                .merge_in_final_event(log_event, &[event::log_schema().message_key().clone()]),
+            None => log_event,
+        }
+    } else {
+        // If the event is partial, just set the partial event marker field.
+        if is_partial {
+            // Only add partial event marker field if it's requested.
+            if let Some(partial_event_marker_field) = partial_event_marker_field {
+                log_event.insert(partial_event_marker_field, true);
+            }
+        }
+        // Return the log event as is, partial or not. No merging here.
+        log_event
+    };
+
+    // Partial or not partial - we return the event we got here, because all
+    // other cases were handeled earlier.
+    let event = Event::Log(log_event);
+    trace!(message = "Received one event.", ?event);
+    Some(event)
 }
 
 struct ContainerMetadata {
-    /// label.key -> String
-    labels: Vec<(Atom, Value)>,
+    /// Configured enrichment: labels, environment variables
+    /// and compose project/service.
+    fields: Vec<(Atom, Value)>,
     /// name -> String
     name: Value,
     /// image -> String
@@ -899,27 +942,90 @@ struct ContainerMetadata {
 }
 
 impl ContainerMetadata {
-    fn from_details(details: &ContainerDetails) -> Result<Self, chrono::format::ParseError> {
-        let labels = details
-            .config
-            .labels
-            .as_ref()
-            .map(|map| {
-                map.iter()
-                    .map(|(key, value)| (("label.".to_owned() + key).into(), value.as_str().into()))
-                    .collect()
-            })
-            .unwrap_or_default();
+    fn from_details(
+        details: &ContainerDetails,
+        config: &DockerConfig,
+    ) -> Result<Self, chrono::format::ParseError> {
+        Self::new(
+            config,
+            details.name.as_str(),
+            details.config.image.as_str(),
+            details.created.as_str(),
+            details.config.labels.as_ref(),
+            details
+                .config
+                .env
+                .as_ref()
+                .map(Vec::as_slice)
+                .unwrap_or(&[]),
+        )
+    }
+
+    fn new(
+        config: &DockerConfig,
+        name: &str,
+        image: &str,
+        created: &str,
+        labels: Option<&HashMap<String, String>>,
+        env: &[String],
+    ) -> Result<Self, chrono::format::ParseError> {
+        let mut fields = Vec::new();
+
+        if let Some(labels) = labels {
+            // label_prefix.key -> String
+            if !config.label_prefix.is_empty() {
+                fields.extend(labels.iter().map(|(key, value)| {
+                    (
+                        format!("{}.{}", config.label_prefix, key).into(),
+                        value.as_str().into(),
+                    )
+                }));
+            }
+
+            if config.compose_metadata {
+                if let Some(project) = labels.get(COMPOSE_PROJECT_LABEL) {
+                    fields.push((COMPOSE_PROJECT.clone(), project.as_str().into()));
+                }
+                if let Some(service) = labels.get(COMPOSE_SERVICE_LABEL) {
+                    fields.push((COMPOSE_SERVICE.clone(), service.as_str().into()));
+                }
+            }
+        }
+
+        // env.KEY -> String, only for allowed variables
+        fields.extend(env.iter().filter_map(|var| {
+            let mut parts = var.splitn(2, '=');
+            let key = parts.next()?;
+            let value = parts.next().unwrap_or("");
+            if config.include_env_vars.iter().any(|include| include == key) {
+                Some((format!("env.{}", key).into(), value.into()))
+            } else {
+                None
+            }
+        }));
 
         Ok(ContainerMetadata {
-            labels,
-            name: remove_slash(details.name.as_str()).into(),
-            image: details.config.image.as_str().into(),
-            created_at: DateTime::parse_from_rfc3339(details.created.as_str())?
-                .with_timezone(&Utc)
-                .into(),
+            fields,
+            name: remove_slash(name).into(),
+            image: image.into(),
+            created_at: DateTime::parse_from_rfc3339(created)?.with_timezone(&Utc),
         })
     }
+
+    fn enrich(&self, log_event: &mut LogEvent) {
+        for (key, value) in self.fields.iter() {
+            log_event.insert(key.clone(), value.clone());
+        }
+
+        // Container name.
+        log_event.insert(NAME.clone(), self.name.clone());
+
+        // Container image.
+        log_event.insert(IMAGE.clone(), self.image.clone());
+
+        // Timestamp of the container creation.
+        log_event.insert(CREATED_AT.clone(), self.created_at);
+    }
 }
 
 /// Removes / at the start of str
@@ -1,18 +1,20 @@
 use crate::{
-    event::{self, Event},
-    sources::util::{ErrorMessage, HttpSource},
+    event::{self, proto, Event},
+    sources::util::{ErrorMessage, HttpSource, HttpSourceAuthConfig},
     tls::TlsConfig,
     topology::config::{DataType, GlobalOptions, SourceConfig, SourceDescription},
 };
-use bytes::{Buf, Bytes, BytesMut};
+use bytes::{Bytes, BytesMut};
 use chrono::Utc;
 use codec::{self, BytesDelimitedCodec};
 use futures01::sync::mpsc;
+use prost::Message;
 use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
+use std::collections::HashMap;
 use std::net::SocketAddr;
+use tokio::codec::LengthDelimitedCodec;
 use tokio_codec::Decoder;
-use warp::filters::body::FullBody;
 use warp::http::{HeaderMap, HeaderValue, StatusCode};
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -22,7 +24,21 @@ pub struct SimpleHttpConfig {
     encoding: Encoding,
     #[serde(default)]
     headers: Vec<String>,
+    #[serde(default)]
+    query_parameters: Vec<String>,
     tls: Option<TlsConfig>,
+    auth: Option<HttpSourceAuthConfig>,
+    #[serde(default = "default_path")]
+    path: String,
+    #[serde(default = "crate::serde::default_true")]
+    strict_path: bool,
+    path_key: Option<String>,
+    #[serde(default)]
+    acknowledgements: bool,
+}
+
+fn default_path() -> String {
+    "/".to_string()
 }
 
 inventory::submit! {
@@ -33,6 +49,8 @@ inventory::submit! {
 struct SimpleHttpSource {
     encoding: Encoding,
     headers: Vec<String>,
+    query_parameters: Vec<String>,
+    path_key: Option<String>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Eq, PartialEq, Clone, Derivative, Copy)]
@@ -43,16 +61,26 @@ pub enum Encoding {
     Text,
     Ndjson,
     Json,
+    Csv,
+    Logfmt,
+    Protobuf,
 }
 
 impl HttpSource for SimpleHttpSource {
     fn build_event(
         &self,
-        body: FullBody,
+        body: Bytes,
         header_map: HeaderMap,
+        query_parameters: HashMap<String, String>,
+        path: &str,
     ) -> Result<Vec<Event>, ErrorMessage> {
         decode_body(body, self.encoding)
             .map(|events| add_headers(events, &self.headers, header_map))
+            .map(|events| add_query_parameters(events, &self.query_parameters, query_parameters))
+            .map(|events| match &self.path_key {
+                Some(path_key) => add_path(events, path_key, path),
+                None => events,
+            })
     }
 }
 
@@ -67,8 +95,18 @@ impl SourceConfig for SimpleHttpConfig {
         let source = SimpleHttpSource {
             encoding: self.encoding,
             headers: self.headers.clone(),
+            query_parameters: self.query_parameters.clone(),
+            path_key: self.path_key.clone(),
         };
-        source.run(self.address, "", &self.tls, out)
+        source.run(
+            self.address,
+            &self.path,
+            self.strict_path,
+            &self.tls,
+            &self.auth,
+            self.acknowledgements,
+            out,
+        )
     }
 
     fn output_type(&self) -> DataType {
@@ -98,6 +136,34 @@ fn add_headers(
     events
 }
 
+fn add_query_parameters(
+    mut events: Vec<Event>,
+    query_parameters_config: &[String],
+    query_parameters: HashMap<String, String>,
+) -> Vec<Event> {
+    for query_parameter_name in query_parameters_config {
+        let value = query_parameters
+            .get(query_parameter_name)
+            .map(String::as_str)
+            .unwrap_or_default();
+        for event in events.iter_mut() {
+            event
+                .as_mut_log()
+                .insert(query_parameter_name as &str, value);
+        }
+    }
+
+    events
+}
+
+fn add_path(mut events: Vec<Event>, path_key: &str, path: &str) -> Vec<Event> {
+    for event in events.iter_mut() {
+        event.as_mut_log().insert(path_key, path);
+    }
+
+    events
+}
+
 fn body_to_lines(mut body: BytesMut) -> impl Iterator<Item = Result<Bytes, ErrorMessage>> {
     let mut decoder = BytesDelimitedCodec::new(b'\n');
     std::iter::from_fn(move || {
@@ -117,8 +183,8 @@ fn body_to_lines(mut body: BytesMut) -> impl Iterator<Item = Result<Bytes, Error
     })
 }
 
-fn decode_body(buf: FullBody, enc: Encoding) -> Result<Vec<Event>, ErrorMessage> {
-    let body = buf.collect::<BytesMut>();
+fn decode_body(body: Bytes, enc: Encoding) -> Result<Vec<Event>, ErrorMessage> {
+    let body = BytesMut::from(body);
 
     match enc {
         Encoding::Text => body_to_lines(body)
@@ -136,9 +202,78 @@ fn decode_body(buf: FullBody, enc: Encoding) -> Result<Vec<Event>, ErrorMessage>
                 .map_err(|e| json_error(format!("Error parsing Json: {:?}", e)))?;
             json_parse_array_of_object(parsed_json)
         }
+        Encoding::Csv => csv_parse_records(&body),
+        Encoding::Logfmt => body_to_lines(body)
+            .map(|line| Ok(logfmt_parse_line(&line?)))
+            .collect::<Result<_, _>>(),
+        Encoding::Protobuf => protobuf_parse_events(body),
     }
 }
 
+/// First record contains names of the fields.
+fn csv_parse_records(body: &[u8]) -> Result<Vec<Event>, ErrorMessage> {
+    let csv_error =
+        |e: csv::Error| ErrorMessage::new(StatusCode::BAD_REQUEST, format!("Bad CSV: {}", e));
+
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .from_reader(body);
+    let headers = reader.headers().map_err(csv_error)?.clone();
+
+    reader
+        .records()
+        .map(|record| {
+            let record = record.map_err(csv_error)?;
+            let mut event = Event::new_empty_log();
+            let log = event.as_mut_log();
+            log.insert(event::log_schema().timestamp_key().clone(), Utc::now()); // Add timestamp
+            for (key, value) in headers.iter().zip(record.iter()) {
+                log.insert(key, value);
+            }
+            Ok(event)
+        })
+        .collect()
+}
+
+fn logfmt_parse_line(line: &[u8]) -> Event {
+    let mut event = Event::new_empty_log();
+    let log = event.as_mut_log();
+    log.insert(event::log_schema().timestamp_key().clone(), Utc::now()); // Add timestamp
+                                                                         // Pairs without a value aren't logfmt data
+    for logfmt::Pair { key, val } in logfmt::parse(&String::from_utf8_lossy(line)) {
+        if let Some(val) = val {
+            log.insert(key, val);
+        }
+    }
+    event
+}
+
+/// Events are length delimited, same as in the `vector` sink.
+fn protobuf_parse_events(mut body: BytesMut) -> Result<Vec<Event>, ErrorMessage> {
+    let protobuf_error =
+        |e: String| ErrorMessage::new(StatusCode::BAD_REQUEST, format!("Bad protobuf: {}", e));
+
+    let mut decoder = LengthDelimitedCodec::new();
+    let mut events = Vec::new();
+    while let Some(frame) = decoder
+        .decode_eof(&mut body)
+        .map_err(|e| protobuf_error(e.to_string()))?
+    {
+        let event = proto::EventWrapper::decode(frame)
+            .map(Event::from)
+            .map_err(|e| protobuf_error(e.to_string()))?;
+        match event {
+            Event::Log(_) => events.push(event),
+            Event::Metric(_) => {
+                return Err(protobuf_error(
+                    "expected log events, got a metric".to_owned(),
+                ))
+            }
+        }
+    }
+    Ok(events)
+}
+
 fn json_parse_object(value: JsonValue) -> Result<Event, ErrorMessage> {
     let mut event = Event::new_empty_log();
     let log = event.as_mut_log();
@@ -191,10 +326,14 @@ fn json_value_to_type_string(value: &JsonValue) -> &'static str {
 #[cfg(test)]
 mod tests {
     use super::{Encoding, SimpleHttpConfig};
+    use crate::sources::util::HttpSourceAuthConfig;
+    use flate2::write::{GzEncoder, ZlibEncoder};
+    use prost::Message;
+    use std::io::Write;
     use warp::http::HeaderMap;
 
     use crate::{
-        event::{self, Event},
+        event::{self, proto, BatchStatus, Event},
         runtime::Runtime,
         test_util::{self, collect_n},
         topology::config::{GlobalOptions, SourceConfig},
@@ -209,19 +348,42 @@ mod tests {
         rt: &mut Runtime,
         encoding: Encoding,
         headers: Vec<String>,
+    ) -> (mpsc::Receiver<Event>, SocketAddr) {
+        source_with(
+            rt,
+            SimpleHttpConfig {
+                headers,
+                ..config(encoding)
+            },
+        )
+    }
+
+    fn config(encoding: Encoding) -> SimpleHttpConfig {
+        SimpleHttpConfig {
+            address: test_util::next_addr(),
+            encoding,
+            headers: vec![],
+            query_parameters: vec![],
+            tls: None,
+            auth: None,
+            path: "/".to_owned(),
+            strict_path: true,
+            path_key: None,
+            acknowledgements: false,
+        }
+    }
+
+    fn source_with(
+        rt: &mut Runtime,
+        config: SimpleHttpConfig,
     ) -> (mpsc::Receiver<Event>, SocketAddr) {
         test_util::trace_init();
         let (sender, recv) = mpsc::channel(100);
-        let address = test_util::next_addr();
+        let address = config.address;
         rt.spawn(
-            SimpleHttpConfig {
-                address,
-                encoding,
-                headers,
-                tls: None,
-            }
-            .build("default", &GlobalOptions::default(), sender)
-            .unwrap(),
+            config
+                .build("default", &GlobalOptions::default(), sender)
+                .unwrap(),
         );
         (recv, address)
     }
@@ -237,10 +399,14 @@ mod tests {
     }
 
     fn send_with_headers(address: SocketAddr, body: &str, headers: HeaderMap) -> u16 {
+        send_bytes(address, "/", body.as_bytes().to_vec(), headers)
+    }
+
+    fn send_bytes(address: SocketAddr, path: &str, body: Vec<u8>, headers: HeaderMap) -> u16 {
         reqwest::Client::new()
-            .request(Method::POST, &format!("http://{}/", address))
+            .request(Method::POST, &format!("http://{}{}", address, path))
             .headers(headers)
-            .body(body.to_owned())
+            .body(body)
             .send()
             .unwrap()
             .status()
@@ -412,4 +578,209 @@ mod tests {
             assert!(log.get(&event::log_schema().timestamp_key()).is_some());
         }
     }
+
+    #[test]
+    fn http_path_and_query_parameters() {
+        let mut rt = test_util::runtime();
+        let (rx, addr) = source_with(
+            &mut rt,
+            SimpleHttpConfig {
+                query_parameters: vec!["source".to_string(), "absent".to_string()],
+                path: "/events".to_owned(),
+                strict_path: false,
+                path_key: Some("path".to_owned()),
+                ..config(Encoding::Text)
+            },
+        );
+
+        assert_eq!(404, send(addr, "wrong path"));
+        assert_eq!(
+            200,
+            send_bytes(
+                addr,
+                "/events/github?source=github&other=1",
+                b"hello".to_vec(),
+                HeaderMap::new()
+            )
+        );
+
+        let mut events = rt.block_on(collect_n(rx, 1)).unwrap();
+        let event = events.remove(0);
+        let log = event.as_log();
+        assert_eq!(log[&event::log_schema().message_key()], "hello".into());
+        assert_eq!(log[&Atom::from("path")], "/events/github".into());
+        assert_eq!(log[&Atom::from("source")], "github".into());
+        assert_eq!(log[&Atom::from("absent")], "".into());
+        assert!(log.get(&Atom::from("other")).is_none());
+    }
+
+    #[test]
+    fn http_auth() {
+        let mut rt = test_util::runtime();
+        let (rx, addr) = source_with(
+            &mut rt,
+            SimpleHttpConfig {
+                auth: Some(HttpSourceAuthConfig::Basic {
+                    user: "user".to_owned(),
+                    password: "password".to_owned(),
+                }),
+                ..config(Encoding::Text)
+            },
+        );
+
+        assert_eq!(401, send(addr, "no auth"));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "Authorization",
+            "Basic dXNlcjpwYXNzd29yZA==".parse().unwrap(),
+        );
+        assert_eq!(200, send_with_headers(addr, "authorized", headers));
+
+        let mut events = rt.block_on(collect_n(rx, 1)).unwrap();
+        assert_eq!(
+            events.remove(0).as_log()[&event::log_schema().message_key()],
+            "authorized".into()
+        );
+    }
+
+    #[test]
+    fn http_decompression() {
+        let mut rt = test_util::runtime();
+        let (rx, addr) = source(&mut rt, Encoding::Text, vec![]);
+
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(b"gzipped").unwrap();
+        let body = encoder.finish().unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("Content-Encoding", "gzip".parse().unwrap());
+        assert_eq!(200, send_bytes(addr, "/", body, headers));
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(b"deflated").unwrap();
+        let body = zstd::encode_all(&encoder.finish().unwrap()[..], 0).unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("Content-Encoding", "deflate, zstd".parse().unwrap());
+        assert_eq!(200, send_bytes(addr, "/", body, headers));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("Content-Encoding", "br".parse().unwrap());
+        assert_eq!(415, send_bytes(addr, "/", b"br".to_vec(), headers));
+
+        let mut events = rt.block_on(collect_n(rx, 2)).unwrap();
+        assert_eq!(
+            events.remove(0).as_log()[&event::log_schema().message_key()],
+            "gzipped".into()
+        );
+        assert_eq!(
+            events.remove(0).as_log()[&event::log_schema().message_key()],
+            "deflated".into()
+        );
+    }
+
+    #[test]
+    fn http_csv() {
+        let mut rt = test_util::runtime();
+        let (rx, addr) = source(&mut rt, Encoding::Csv, vec![]);
+
+        assert_eq!(400, send(addr, "a,b\n1,2,3"));
+        assert_eq!(200, send(addr, "key,other\nvalue1,1\nvalue2,2\n"));
+
+        let mut events = rt.block_on(collect_n(rx, 2)).unwrap();
+        {
+            let event = events.remove(0);
+            let log = event.as_log();
+            assert_eq!(log[&Atom::from("key")], "value1".into());
+            assert_eq!(log[&Atom::from("other")], "1".into());
+            assert!(log.get(&event::log_schema().timestamp_key()).is_some());
+        }
+        {
+            let event = events.remove(0);
+            let log = event.as_log();
+            assert_eq!(log[&Atom::from("key")], "value2".into());
+            assert_eq!(log[&Atom::from("other")], "2".into());
+        }
+    }
+
+    #[test]
+    fn http_logfmt() {
+        let mut rt = test_util::runtime();
+        let (rx, addr) = source(&mut rt, Encoding::Logfmt, vec![]);
+
+        assert_eq!(
+            200,
+            send(addr, "key=value1 other=\"a b\"\n\nkey=value2 flag")
+        );
+
+        let mut events = rt.block_on(collect_n(rx, 2)).unwrap();
+        {
+            let event = events.remove(0);
+            let log = event.as_log();
+            assert_eq!(log[&Atom::from("key")], "value1".into());
+            assert_eq!(log[&Atom::from("other")], "a b".into());
+            assert!(log.get(&event::log_schema().timestamp_key()).is_some());
+        }
+        {
+            let event = events.remove(0);
+            let log = event.as_log();
+            assert_eq!(log[&Atom::from("key")], "value2".into());
+            assert!(log.get(&Atom::from("flag")).is_none());
+        }
+    }
+
+    #[test]
+    fn http_protobuf() {
+        let mut rt = test_util::runtime();
+        let (rx, addr) = source(&mut rt, Encoding::Protobuf, vec![]);
+
+        let mut body = Vec::new();
+        for message in &["first", "second"] {
+            let wrapper = proto::EventWrapper::from(Event::from(*message));
+            let mut buf = Vec::new();
+            wrapper.encode(&mut buf).unwrap();
+            body.extend_from_slice(&(buf.len() as u32).to_be_bytes());
+            body.extend_from_slice(&buf);
+        }
+        assert_eq!(200, send_bytes(addr, "/", body, HeaderMap::new()));
+        assert_eq!(
+            400,
+            send_bytes(addr, "/", vec![0, 0, 0, 9, 1], HeaderMap::new())
+        );
+
+        let mut events = rt.block_on(collect_n(rx, 2)).unwrap();
+        assert_eq!(
+            events.remove(0).as_log()[&event::log_schema().message_key()],
+            "first".into()
+        );
+        assert_eq!(
+            events.remove(0).as_log()[&event::log_schema().message_key()],
+            "second".into()
+        );
+    }
+
+    #[test]
+    fn http_acknowledgements_wait_for_delivery() {
+        let mut rt = test_util::runtime();
+        let (rx, addr) = source_with(
+            &mut rt,
+            SimpleHttpConfig {
+                acknowledgements: true,
+                ..config(Encoding::Text)
+            },
+        );
+
+        let (status_tx, status_rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || status_tx.send(send(addr, "first\nsecond")).unwrap());
+
+        let mut events = rt.block_on(collect_n(rx, 2)).unwrap();
+        assert!(status_rx
+            .recv_timeout(std::time::Duration::from_millis(100))
+            .is_err());
+
+        events[1]
+            .take_finalizers()
+            .update_status(BatchStatus::Failed);
+        drop(events);
+        assert_eq!(503, status_rx.recv().unwrap());
+    }
 }
@@ -0,0 +1,91 @@
+use crate::{
+    event::{self, Event},
+    topology::config::{DataType, GlobalOptions, SourceConfig, SourceDescription},
+    trace,
+};
+use futures01::{sync::mpsc, Future, Sink, Stream};
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct InternalLogsConfig {
+    pub host_key: Option<String>,
+}
+
+inventory::submit! {
+    SourceDescription::new::<InternalLogsConfig>("internal_logs")
+}
+
+#[typetag::serde(name = "internal_logs")]
+impl SourceConfig for InternalLogsConfig {
+    fn build(
+        &self,
+        _name: &str,
+        _globals: &GlobalOptions,
+        out: mpsc::Sender<Event>,
+    ) -> crate::Result<super::Source> {
+        let host_key = self
+            .host_key
+            .clone()
+            .unwrap_or_else(|| event::log_schema().host_key().to_string());
+        Ok(internal_logs(trace::subscribe(), host_key, out))
+    }
+
+    fn output_type(&self) -> DataType {
+        DataType::Log
+    }
+
+    fn source_type(&self) -> &'static str {
+        "internal_logs"
+    }
+}
+
+fn internal_logs(
+    logs: mpsc::Receiver<Event>,
+    host_key: String,
+    out: mpsc::Sender<Event>,
+) -> super::Source {
+    let hostname = hostname::get_hostname();
+
+    Box::new(
+        logs.map(move |mut event| {
+            if let Some(hostname) = &hostname {
+                event.as_mut_log().insert(&host_key, hostname.clone());
+            }
+            event
+        })
+        .forward(out.sink_map_err(|e| error!(message = "Unable to send event to out.", error = %e)))
+        .map(|_| info!("finished sending")),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{collect_n, runtime};
+    use string_cache::DefaultAtom as Atom;
+
+    #[test]
+    fn internal_logs_receives_logged_events() {
+        let mut rt = runtime();
+        let (tx, rx) = mpsc::channel(10);
+        let (mut logs_tx, logs_rx) = mpsc::channel(10);
+
+        let mut logged = Event::from("Connection closed.");
+        logged
+            .as_mut_log()
+            .insert("metadata.level", "DEBUG".to_owned());
+        logs_tx.try_send(logged).unwrap();
+        drop(logs_tx);
+
+        rt.spawn(internal_logs(logs_rx, "host".into(), tx));
+        let events = rt.block_on(collect_n(rx, 1)).unwrap();
+
+        let log = events[0].as_log();
+        assert_eq!(
+            log[&event::log_schema().message_key()],
+            "Connection closed.".into()
+        );
+        assert_eq!(log[&Atom::from("metadata.level")], "DEBUG".into());
+    }
+}
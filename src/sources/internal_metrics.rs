@@ -0,0 +1,238 @@
+use crate::{
+    event::{
+        metric::{Metric, MetricKind, MetricValue},
+        Event,
+    },
+    metrics::{self, Key, Value},
+    topology::config::{DataType, GlobalOptions, SourceConfig, SourceDescription},
+};
+use chrono::Utc;
+use futures01::{stream, sync::mpsc, Future, Sink, Stream};
+use hotmic::{
+    snapshot::{SummarizedHistogram, TypedMeasurement},
+    Controller,
+};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use tokio::timer::Interval;
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct InternalMetricsConfig {
+    #[serde(default = "default_scrape_interval_secs")]
+    pub scrape_interval_secs: u64,
+}
+
+impl Default for InternalMetricsConfig {
+    fn default() -> Self {
+        Self {
+            scrape_interval_secs: default_scrape_interval_secs(),
+        }
+    }
+}
+
+fn default_scrape_interval_secs() -> u64 {
+    2
+}
+
+inventory::submit! {
+    SourceDescription::new::<InternalMetricsConfig>("internal_metrics")
+}
+
+#[typetag::serde(name = "internal_metrics")]
+impl SourceConfig for InternalMetricsConfig {
+    fn build(
+        &self,
+        _name: &str,
+        _globals: &GlobalOptions,
+        out: mpsc::Sender<Event>,
+    ) -> crate::Result<super::Source> {
+        Ok(internal_metrics(
+            metrics::controller(),
+            self.scrape_interval_secs,
+            out,
+        ))
+    }
+
+    fn output_type(&self) -> DataType {
+        DataType::Metric
+    }
+
+    fn source_type(&self) -> &'static str {
+        "internal_metrics"
+    }
+}
+
+fn internal_metrics(
+    controller: Option<Controller>,
+    interval: u64,
+    out: mpsc::Sender<Event>,
+) -> super::Source {
+    let out = out.sink_map_err(|e| error!("error sending metric: {:?}", e));
+
+    let task = Interval::new(Instant::now(), Duration::from_secs(interval))
+        .map_err(|e| error!("timer error: {:?}", e))
+        .map(move |_| stream::iter_ok(capture(controller.as_ref())))
+        .flatten()
+        .map(Event::Metric)
+        .forward(out)
+        .map(|_| info!("finished sending"));
+
+    Box::new(task)
+}
+
+/// Converts the metrics recorded from Vector's logs, and those of its
+/// components, to metric events.
+fn capture(controller: Option<&Controller>) -> Vec<Metric> {
+    let timestamp = Utc::now();
+    let mut metrics = Vec::new();
+
+    let snapshot = controller.and_then(|controller| {
+        controller
+            .get_snapshot()
+            .map_err(|error| error!(message = "Unable to snapshot metrics.", ?error))
+            .ok()
+    });
+    if let Some(snapshot) = snapshot {
+        for measurement in snapshot.into_vec() {
+            let (name, value) = match measurement {
+                TypedMeasurement::Counter(label, value) => (
+                    label,
+                    MetricValue::Counter {
+                        value: value as f64,
+                    },
+                ),
+                TypedMeasurement::Gauge(label, value) => (
+                    label,
+                    MetricValue::Gauge {
+                        value: value as f64,
+                    },
+                ),
+                TypedMeasurement::TimingHistogram(label, summary) => {
+                    (label + "_nanoseconds", summary_value(&summary))
+                }
+                TypedMeasurement::ValueHistogram(label, summary) => {
+                    (label, summary_value(&summary))
+                }
+            };
+            metrics.push(Metric {
+                name: name.replace('.', "_"),
+                timestamp: Some(timestamp),
+                tags: None,
+                kind: MetricKind::Absolute,
+                value,
+            });
+        }
+    }
+
+    for (key, value) in metrics::registry().snapshot() {
+        metrics.push(registry_metric(key, value, timestamp));
+    }
+
+    metrics
+}
+
+fn summary_value(summary: &SummarizedHistogram) -> MetricValue {
+    let (quantiles, values) = summary
+        .measurements()
+        .iter()
+        .map(|(percentile, value)| (percentile.as_quantile(), *value as f64))
+        .unzip();
+    MetricValue::AggregatedSummary {
+        quantiles,
+        values,
+        count: summary.count() as u32,
+        sum: summary.sum() as f64,
+    }
+}
+
+fn registry_metric(key: Key, value: Value, timestamp: chrono::DateTime<Utc>) -> Metric {
+    let value = match value {
+        Value::Counter(value) => MetricValue::Counter {
+            value: value as f64,
+        },
+        Value::Gauge(value) => MetricValue::Gauge {
+            value: value as f64,
+        },
+        Value::Histogram(histogram) => {
+            // Metric events count the values up to each bucket's bound.
+            let mut total = 0;
+            let (buckets, counts) = histogram
+                .buckets
+                .iter()
+                .map(|(bound, count)| {
+                    total += count;
+                    (*bound, total as u32)
+                })
+                .unzip();
+            MetricValue::AggregatedHistogram {
+                buckets,
+                counts,
+                count: histogram.count as u32,
+                sum: histogram.sum,
+            }
+        }
+    };
+
+    Metric {
+        name: key.name.to_owned(),
+        timestamp: Some(timestamp),
+        tags: Some(
+            key.labels
+                .into_iter()
+                .map(|(label, value)| (label.to_owned(), value))
+                .collect(),
+        ),
+        kind: MetricKind::Absolute,
+        value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::ComponentMetrics;
+
+    #[test]
+    fn internal_metrics_captures_component_metrics() {
+        let component = ComponentMetrics::new("sink", "internal_metrics_test", "console");
+        component.events_in().add(3);
+        component
+            .histogram("batch_events", metrics::BATCH_BUCKETS)
+            .record(5.0);
+        component
+            .histogram("batch_events", metrics::BATCH_BUCKETS)
+            .record(50.0);
+
+        let captured = capture(None);
+        let find = |name: &str| {
+            captured
+                .iter()
+                .find(|metric| {
+                    metric.name == name
+                        && metric.tags.as_ref().unwrap()["component_name"]
+                            == "internal_metrics_test"
+                })
+                .unwrap()
+        };
+
+        let events_in = find("events_in_total");
+        assert_eq!(events_in.value, MetricValue::Counter { value: 3.0 });
+        assert_eq!(events_in.tags.as_ref().unwrap()["component_kind"], "sink");
+
+        match &find("batch_events").value {
+            MetricValue::AggregatedHistogram {
+                buckets,
+                counts,
+                count,
+                sum,
+            } => {
+                assert_eq!(buckets, &metrics::BATCH_BUCKETS.to_vec());
+                assert_eq!(counts, &vec![0, 1, 2, 2, 2, 2]);
+                assert_eq!(*count, 2);
+                assert_eq!(*sum, 55.0);
+            }
+            value => panic!("Unexpected value {:?}", value),
+        }
+    }
+}
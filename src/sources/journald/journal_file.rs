@@ -0,0 +1,1033 @@
+//! A native reader for the systemd journal file format, as described in
+//! https://www.freedesktop.org/wiki/Software/systemd/journal-files/
+//!
+//! Only the parts of the format required for sequential reading are
+//! implemented: the file header, the entry array chain, entry objects and
+//! data objects. Hash tables are never consulted, since matching is done on
+//! the decoded records.
+
+use super::Record;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use string_cache::DefaultAtom as Atom;
+
+const SIGNATURE: &[u8; 8] = b"LPKSHHRH";
+const HEADER_MIN_SIZE: usize = 208;
+const OBJECT_HEADER_SIZE: usize = 16;
+const ENTRY_OBJECT_HEADER_SIZE: usize = 64;
+const ENTRY_ARRAY_OBJECT_HEADER_SIZE: usize = 24;
+
+const INCOMPATIBLE_COMPRESSED_XZ: u32 = 1;
+const INCOMPATIBLE_COMPRESSED_LZ4: u32 = 1 << 1;
+const INCOMPATIBLE_KEYED_HASH: u32 = 1 << 2;
+const INCOMPATIBLE_COMPRESSED_ZSTD: u32 = 1 << 3;
+const INCOMPATIBLE_COMPACT: u32 = 1 << 4;
+// Files with xz-compressed data objects are opened, but refused once scanned.
+const INCOMPATIBLE_SUPPORTED: u32 = INCOMPATIBLE_COMPRESSED_XZ
+    | INCOMPATIBLE_COMPRESSED_LZ4
+    | INCOMPATIBLE_KEYED_HASH
+    | INCOMPATIBLE_COMPRESSED_ZSTD
+    | INCOMPATIBLE_COMPACT;
+
+const OBJECT_COMPRESSED_XZ: u8 = 1;
+const OBJECT_COMPRESSED_LZ4: u8 = 1 << 1;
+const OBJECT_COMPRESSED_ZSTD: u8 = 1 << 2;
+
+const OBJECT_DATA: u8 = 1;
+const OBJECT_ENTRY: u8 = 3;
+const OBJECT_ENTRY_ARRAY: u8 = 6;
+
+const STATE_ARCHIVED: u8 = 2;
+
+const JOURNAL_EXTENSION: &str = "journal";
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn le_u32(buf: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(buf[at..at + 4].try_into().unwrap())
+}
+
+fn le_u64(buf: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(buf[at..at + 8].try_into().unwrap())
+}
+
+fn id128(buf: &[u8], at: usize) -> [u8; 16] {
+    buf[at..at + 16].try_into().unwrap()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[derive(Debug)]
+struct Header {
+    incompatible_flags: u32,
+    state: u8,
+    file_id: [u8; 16],
+    seqnum_id: [u8; 16],
+    entry_array_offset: u64,
+}
+
+impl Header {
+    fn parse(buf: &[u8]) -> Result<Self, String> {
+        if buf.len() < HEADER_MIN_SIZE {
+            return Err("file is too short to hold a journal header".into());
+        }
+        if &buf[..8] != SIGNATURE {
+            return Err("invalid journal file signature".into());
+        }
+
+        let incompatible_flags = le_u32(buf, 12);
+        if incompatible_flags & !INCOMPATIBLE_SUPPORTED != 0 {
+            return Err(format!(
+                "unsupported incompatible flags {:#x}",
+                incompatible_flags
+            ));
+        }
+
+        Ok(Header {
+            incompatible_flags,
+            state: buf[16],
+            file_id: id128(buf, 24),
+            seqnum_id: id128(buf, 72),
+            entry_array_offset: le_u64(buf, 176),
+        })
+    }
+
+    fn is_compact(&self) -> bool {
+        self.incompatible_flags & INCOMPATIBLE_COMPACT != 0
+    }
+
+    fn is_xz_compressed(&self) -> bool {
+        self.incompatible_flags & INCOMPATIBLE_COMPRESSED_XZ != 0
+    }
+}
+
+/// Decompresses an LZ4 block, as written by journald: the size of the
+/// decompressed data as a little-endian u64, followed by the block itself.
+fn decompress_lz4(buf: &[u8]) -> Option<Vec<u8>> {
+    fn length(buf: &[u8], at: &mut usize, mut length: usize) -> Option<usize> {
+        if length == 15 {
+            loop {
+                let byte = *buf.get(*at)?;
+                *at += 1;
+                length += byte as usize;
+                if byte != 255 {
+                    break;
+                }
+            }
+        }
+        Some(length)
+    }
+
+    let size = le_u64(buf.get(..8)?, 0) as usize;
+    let block = &buf[8..];
+    let mut out = Vec::with_capacity(size);
+    let mut at = 0;
+    while at < block.len() {
+        let token = block[at];
+        at += 1;
+
+        let literals = length(block, &mut at, (token >> 4) as usize)?;
+        out.extend_from_slice(block.get(at..at.checked_add(literals)?)?);
+        at += literals;
+        if at == block.len() {
+            break;
+        }
+
+        let offset = u16::from_le_bytes([*block.get(at)?, *block.get(at + 1)?]) as usize;
+        at += 2;
+        let matched = length(block, &mut at, (token & 0xf) as usize)? + 4;
+        if offset == 0 || offset > out.len() || out.len() + matched > size {
+            return None;
+        }
+        // The match may overlap the bytes it produces.
+        let start = out.len() - offset;
+        for index in start..start + matched {
+            let byte = out[index];
+            out.push(byte);
+        }
+    }
+
+    if out.len() == size {
+        Some(out)
+    } else {
+        None
+    }
+}
+
+/// The fixed part of an entry object, along with the offsets of the data
+/// objects it references.
+#[derive(Debug)]
+struct Entry {
+    seqnum: u64,
+    realtime: u64,
+    monotonic: u64,
+    boot_id: [u8; 16],
+    xor_hash: u64,
+    items: Vec<u64>,
+}
+
+/// A parsed journal cursor, of the form
+/// `s=<seqnum_id>;i=<seqnum>;b=<boot_id>;m=<monotonic>;t=<realtime>;x=<xor_hash>`.
+#[derive(Debug, Default, PartialEq)]
+pub struct Cursor {
+    seqnum_id: Option<String>,
+    seqnum: Option<u64>,
+    realtime: Option<u64>,
+}
+
+impl Cursor {
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut cursor = Cursor::default();
+        for part in text.trim().split(';') {
+            let mut kv = part.splitn(2, '=');
+            let (key, value) = match (kv.next(), kv.next()) {
+                (Some(key), Some(value)) => (key, value),
+                _ => return None,
+            };
+            match key {
+                "s" => cursor.seqnum_id = Some(value.to_lowercase()),
+                "i" => cursor.seqnum = Some(u64::from_str_radix(value, 16).ok()?),
+                "t" => cursor.realtime = Some(u64::from_str_radix(value, 16).ok()?),
+                _ => {}
+            }
+        }
+        if cursor.seqnum.is_none() && cursor.realtime.is_none() {
+            None
+        } else {
+            Some(cursor)
+        }
+    }
+
+    /// Returns `true` if the given entry was already read at the time the
+    /// cursor was taken. Sequence numbers are only comparable within the
+    /// same sequence number space, otherwise fall back to the wallclock.
+    fn covers(&self, seqnum_id: &str, entry: &Entry) -> bool {
+        match (&self.seqnum_id, self.seqnum) {
+            (Some(id), Some(seqnum)) if id == seqnum_id => entry.seqnum <= seqnum,
+            _ => self
+                .realtime
+                .map_or(false, |realtime| entry.realtime <= realtime),
+        }
+    }
+}
+
+/// Where the next entry is, from the entry array being read.
+enum Slot {
+    Entry(u64),
+    /// The next array in the chain, past the end of this one.
+    Next(u64),
+    /// No more entries, for now.
+    End,
+}
+
+/// A single open journal file, read sequentially through its entry array
+/// chain.
+pub struct JournalFile {
+    path: PathBuf,
+    file: File,
+    header: Header,
+    seqnum_id: String,
+    /// Offset of the entry array holding the next unread entry, or zero if
+    /// reading has not started yet.
+    array_offset: u64,
+    /// Index of the next unread entry within `array_offset`.
+    array_index: u64,
+    /// The entry array at `array_offset`, as last read.
+    array: Vec<u8>,
+    peeked: Option<Entry>,
+    /// The sequence number and wallclock time of the last entry consumed.
+    consumed: Option<(u64, u64)>,
+}
+
+impl JournalFile {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let mut buf = vec![0; HEADER_MIN_SIZE];
+        file.read_exact(&mut buf)?;
+        let header =
+            Header::parse(&buf).map_err(|error| invalid_data(format!("{:?}: {}", path, error)))?;
+        let seqnum_id = to_hex(&header.seqnum_id);
+
+        Ok(JournalFile {
+            path: path.to_path_buf(),
+            file,
+            header,
+            seqnum_id,
+            array_offset: 0,
+            array_index: 0,
+            array: Vec::new(),
+            peeked: None,
+            consumed: None,
+        })
+    }
+
+    fn refresh_header(&mut self) -> io::Result<()> {
+        let buf = self.read_at(0, HEADER_MIN_SIZE)?;
+        self.header = Header::parse(&buf)
+            .map_err(|error| invalid_data(format!("{:?}: {}", self.path, error)))?;
+        Ok(())
+    }
+
+    fn is_archived(&self) -> bool {
+        self.header.state == STATE_ARCHIVED
+    }
+
+    fn read_at(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        let mut buf = vec![0; len];
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Reads a whole object at `offset`, checking its type.
+    fn read_object(&mut self, offset: u64, object_type: u8) -> io::Result<(u8, Vec<u8>)> {
+        let header = self.read_at(offset, OBJECT_HEADER_SIZE)?;
+        let size = le_u64(&header, 8) as usize;
+        if header[0] != object_type || size < OBJECT_HEADER_SIZE {
+            return Err(invalid_data(format!(
+                "{:?}: unexpected object at offset {}",
+                self.path, offset
+            )));
+        }
+        let flags = header[1];
+        Ok((flags, self.read_at(offset, size)?))
+    }
+
+    fn item_size(&self) -> usize {
+        if self.header.is_compact() {
+            4
+        } else {
+            8
+        }
+    }
+
+    /// Finds the offset of the next entry in the entry array chain, without
+    /// consuming it. Entry arrays are preallocated, so an empty slot marks
+    /// the current end of the file.
+    fn next_entry_offset(&mut self) -> io::Result<Option<u64>> {
+        if self.array_offset == 0 {
+            self.refresh_header()?;
+            if self.header.entry_array_offset == 0 {
+                return Ok(None);
+            }
+            self.array_offset = self.header.entry_array_offset;
+            self.array_index = 0;
+            self.read_array()?;
+        }
+
+        loop {
+            let slot = match self.array_slot() {
+                // The array may have been filled, or chained to a new one,
+                // since it was read.
+                Slot::End => {
+                    self.read_array()?;
+                    self.array_slot()
+                }
+                slot => slot,
+            };
+            match slot {
+                Slot::Entry(offset) => return Ok(Some(offset)),
+                Slot::End => return Ok(None),
+                Slot::Next(next) => {
+                    self.array_offset = next;
+                    self.array_index = 0;
+                    self.read_array()?;
+                }
+            }
+        }
+    }
+
+    fn read_array(&mut self) -> io::Result<()> {
+        let (_, array) = self.read_object(self.array_offset, OBJECT_ENTRY_ARRAY)?;
+        if array.len() < ENTRY_ARRAY_OBJECT_HEADER_SIZE {
+            return Err(invalid_data(format!(
+                "{:?}: truncated entry array at offset {}",
+                self.path, self.array_offset
+            )));
+        }
+        self.array = array;
+        Ok(())
+    }
+
+    /// Looks up `array_index` in the entry array as last read.
+    fn array_slot(&self) -> Slot {
+        let item_size = self.item_size();
+        let capacity = (self.array.len() - ENTRY_ARRAY_OBJECT_HEADER_SIZE) / item_size;
+
+        if (self.array_index as usize) < capacity {
+            let at = ENTRY_ARRAY_OBJECT_HEADER_SIZE + self.array_index as usize * item_size;
+            let offset = if item_size == 4 {
+                u64::from(le_u32(&self.array, at))
+            } else {
+                le_u64(&self.array, at)
+            };
+            if offset == 0 {
+                Slot::End
+            } else {
+                Slot::Entry(offset)
+            }
+        } else {
+            match le_u64(&self.array, OBJECT_HEADER_SIZE) {
+                0 => Slot::End,
+                next => Slot::Next(next),
+            }
+        }
+    }
+
+    fn read_entry(&mut self, offset: u64) -> io::Result<Entry> {
+        let (_, buf) = self.read_object(offset, OBJECT_ENTRY)?;
+        if buf.len() < ENTRY_OBJECT_HEADER_SIZE {
+            return Err(invalid_data(format!(
+                "{:?}: truncated entry at offset {}",
+                self.path, offset
+            )));
+        }
+
+        let items = if self.header.is_compact() {
+            buf[ENTRY_OBJECT_HEADER_SIZE..]
+                .chunks_exact(4)
+                .map(|item| u64::from(le_u32(item, 0)))
+                .collect()
+        } else {
+            buf[ENTRY_OBJECT_HEADER_SIZE..]
+                .chunks_exact(16)
+                .map(|item| le_u64(item, 0))
+                .collect()
+        };
+
+        Ok(Entry {
+            seqnum: le_u64(&buf, 16),
+            realtime: le_u64(&buf, 24),
+            monotonic: le_u64(&buf, 32),
+            boot_id: id128(&buf, 40),
+            xor_hash: le_u64(&buf, 56),
+            items,
+        })
+    }
+
+    /// Reads the `FIELD=value` payload of a data object, decompressing it if
+    /// needed. Only zstd and LZ4 compressed payloads are supported.
+    fn read_data(&mut self, offset: u64) -> io::Result<Option<(Atom, String)>> {
+        let (flags, buf) = self.read_object(offset, OBJECT_DATA)?;
+
+        let payload_offset = if self.header.is_compact() { 72 } else { 64 };
+        let payload = match buf.get(payload_offset..) {
+            Some(payload) => payload,
+            None => return Ok(None),
+        };
+        let decompressed;
+        let payload = if flags & OBJECT_COMPRESSED_ZSTD != 0 {
+            decompressed = zstd::stream::decode_all(payload).map_err(|error| {
+                invalid_data(format!(
+                    "{:?}: invalid zstd data at offset {}: {}",
+                    self.path, offset, error
+                ))
+            })?;
+            &decompressed[..]
+        } else if flags & OBJECT_COMPRESSED_LZ4 != 0 {
+            decompressed = decompress_lz4(payload).ok_or_else(|| {
+                invalid_data(format!(
+                    "{:?}: invalid LZ4 data at offset {}",
+                    self.path, offset
+                ))
+            })?;
+            &decompressed[..]
+        } else if flags & OBJECT_COMPRESSED_XZ != 0 {
+            return Err(invalid_data(format!(
+                "{:?}: xz-compressed data at offset {} is not supported",
+                self.path, offset
+            )));
+        } else {
+            payload
+        };
+        Ok(payload.iter().position(|&b| b == b'=').map(|eq| {
+            (
+                Atom::from(String::from_utf8_lossy(&payload[..eq]).as_ref()),
+                String::from_utf8_lossy(&payload[eq + 1..]).into(),
+            )
+        }))
+    }
+
+    fn peek(&mut self) -> io::Result<Option<&Entry>> {
+        if self.peeked.is_none() {
+            if let Some(offset) = self.next_entry_offset()? {
+                self.peeked = Some(self.read_entry(offset)?);
+            }
+        }
+        Ok(self.peeked.as_ref())
+    }
+
+    fn consume(&mut self) -> Option<Entry> {
+        let entry = self.peeked.take();
+        if let Some(entry) = &entry {
+            self.array_index += 1;
+            self.consumed = Some((entry.seqnum, entry.realtime));
+        }
+        entry
+    }
+
+    /// A cursor covering every entry consumed so far, if any.
+    fn position(&self) -> Option<Cursor> {
+        self.consumed.map(|(seqnum, realtime)| Cursor {
+            seqnum_id: Some(self.seqnum_id.clone()),
+            seqnum: Some(seqnum),
+            realtime: Some(realtime),
+        })
+    }
+
+    /// Skips over all entries already covered by `cursor`.
+    fn seek_past(&mut self, cursor: &Cursor) -> io::Result<()> {
+        let seqnum_id = self.seqnum_id.clone();
+        loop {
+            let covered = match self.peek()? {
+                Some(entry) => cursor.covers(&seqnum_id, entry),
+                None => false,
+            };
+            if !covered {
+                return Ok(());
+            }
+            self.consume();
+        }
+    }
+
+    fn to_record(&mut self, entry: &Entry) -> io::Result<Record> {
+        let mut record = Record::new();
+        for &offset in &entry.items {
+            if let Some((field, value)) = self.read_data(offset)? {
+                record.insert(field, value);
+            }
+        }
+
+        let boot_id = to_hex(&entry.boot_id);
+        record.insert(
+            Atom::from("__CURSOR"),
+            format!(
+                "s={};i={:x};b={};m={:x};t={:x};x={:x}",
+                self.seqnum_id,
+                entry.seqnum,
+                boot_id,
+                entry.monotonic,
+                entry.realtime,
+                entry.xor_hash
+            ),
+        );
+        record.insert(
+            Atom::from("__REALTIME_TIMESTAMP"),
+            entry.realtime.to_string(),
+        );
+        record.insert(
+            Atom::from("__MONOTONIC_TIMESTAMP"),
+            entry.monotonic.to_string(),
+        );
+        record.insert(Atom::from("_BOOT_ID"), boot_id);
+        Ok(record)
+    }
+}
+
+/// Reads all journal files found in a set of directories, interleaving their
+/// entries by wallclock time and picking up new files as they appear.
+pub struct JournalDirectory {
+    directories: Vec<PathBuf>,
+    files: Vec<JournalFile>,
+    seen: HashSet<[u8; 16]>,
+    /// Where to resume reading the files closed after an error, once they
+    /// are opened again.
+    resume: HashMap<[u8; 16], Cursor>,
+    cursor: Option<Cursor>,
+    boot_id: Option<String>,
+}
+
+impl JournalDirectory {
+    pub fn new(directories: Vec<PathBuf>, cursor: Option<Cursor>, boot_id: Option<String>) -> Self {
+        let mut journal = JournalDirectory {
+            directories,
+            files: Vec::new(),
+            seen: HashSet::new(),
+            resume: HashMap::new(),
+            cursor,
+            boot_id,
+        };
+        journal.scan();
+        journal
+    }
+
+    /// Opens any journal file not seen before. Journals are stored either
+    /// directly in the directory or in a per-machine subdirectory.
+    fn scan(&mut self) {
+        let mut paths = Vec::new();
+        for directory in &self.directories {
+            collect_journal_paths(directory, 1, &mut paths);
+        }
+
+        // Forget the files removed since, so their IDs don't pile up.
+        let mut present = self
+            .files
+            .iter()
+            .map(|file| file.header.file_id)
+            .collect::<HashSet<_>>();
+        for path in paths {
+            let mut file = match JournalFile::open(&path) {
+                Ok(file) => file,
+                Err(error) => {
+                    // Files being created or rotated may briefly be unreadable.
+                    debug!(message = "Could not open journal file.", ?path, %error);
+                    continue;
+                }
+            };
+            let file_id = file.header.file_id;
+            present.insert(file_id);
+            if !self.seen.insert(file_id) {
+                continue;
+            }
+            if file.header.is_xz_compressed() {
+                // Kept as seen, so this is only reported once per file.
+                error!(
+                    message = "Refusing xz-compressed journal file; use the journalctl reader to read it.",
+                    ?path
+                );
+                continue;
+            }
+            let cursor = self.resume.get(&file_id).or_else(|| self.cursor.as_ref());
+            if let Some(cursor) = cursor {
+                if let Err(error) = file.seek_past(cursor) {
+                    warn!(message = "Could not seek in journal file.", ?path, %error);
+                    self.seen.remove(&file_id);
+                    continue;
+                }
+            }
+            self.resume.remove(&file_id);
+            debug!(message = "Reading journal file.", ?path);
+            self.files.push(file);
+        }
+        self.seen.retain(|file_id| present.contains(file_id));
+        self.resume.retain(|file_id, _| present.contains(file_id));
+    }
+
+    /// Closes a file that could not be read, so it's opened again on the
+    /// next scan, resuming after its last entry read.
+    fn close_failed(&mut self, file: JournalFile, error: io::Error) {
+        error!(message = "Could not read journal file, closing it.", path = ?file.path, %error);
+        let file_id = file.header.file_id;
+        self.seen.remove(&file_id);
+        if let Some(cursor) = file.position() {
+            self.resume.insert(file_id, cursor);
+        }
+    }
+
+    /// Picks the file holding the oldest unread entry.
+    fn oldest(&mut self) -> Option<usize> {
+        let mut oldest: Option<(usize, u64)> = None;
+        let mut failed = Vec::new();
+
+        for (index, file) in self.files.iter_mut().enumerate() {
+            match file.peek() {
+                Ok(Some(entry)) => {
+                    if oldest.map_or(true, |(_, realtime)| entry.realtime < realtime) {
+                        oldest = Some((index, entry.realtime));
+                    }
+                }
+                Ok(None) => {}
+                Err(error) => failed.push((index, error)),
+            }
+        }
+
+        if !failed.is_empty() {
+            for (index, error) in failed.into_iter().rev() {
+                let file = self.files.remove(index);
+                self.close_failed(file, error);
+            }
+            return self.oldest();
+        }
+
+        oldest.map(|(index, _)| index)
+    }
+
+    /// Returns the next record, or `None` once every file is exhausted for
+    /// now. Calling it again later picks up any entries appended since.
+    pub fn next_record(&mut self) -> io::Result<Option<Record>> {
+        let mut rescanned = false;
+        loop {
+            let index = match self.oldest() {
+                Some(index) => index,
+                None if !rescanned => {
+                    // Archived files never grow again, so they can be closed
+                    // once they have been read completely.
+                    let files = std::mem::replace(&mut self.files, Vec::new());
+                    for mut file in files {
+                        let exhausted = file
+                            .refresh_header()
+                            .and_then(|()| file.peek().map(|entry| entry.is_none()));
+                        match exhausted {
+                            Ok(true) if file.is_archived() => {
+                                debug!(message = "Closing archived journal file.", path = ?file.path);
+                            }
+                            Ok(_) => self.files.push(file),
+                            Err(error) => self.close_failed(file, error),
+                        }
+                    }
+                    self.scan();
+                    rescanned = true;
+                    continue;
+                }
+                None => return Ok(None),
+            };
+
+            let file = &mut self.files[index];
+            let entry = file.consume().expect("Peeked entry must exist");
+            if let Some(boot_id) = &self.boot_id {
+                if to_hex(&entry.boot_id) != *boot_id {
+                    continue;
+                }
+            }
+            return file.to_record(&entry).map(Some);
+        }
+    }
+}
+
+fn collect_journal_paths(directory: &Path, depth: usize, paths: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        if path.is_dir() {
+            if depth > 0 {
+                collect_journal_paths(&path, depth - 1, paths);
+            }
+        } else if path
+            .extension()
+            .map_or(false, |ext| ext == JOURNAL_EXTENSION)
+        {
+            paths.push(path);
+        }
+    }
+}
+
+/// Reads the boot ID of the running system, in the same hex format used
+/// for `_BOOT_ID`.
+pub fn current_boot_id() -> io::Result<String> {
+    let text = fs::read_to_string("/proc/sys/kernel/random/boot_id")?;
+    Ok(text.trim().replace('-', ""))
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    const HEADER_SIZE: usize = 240;
+
+    fn align(buf: &mut Vec<u8>) {
+        while buf.len() % 8 != 0 {
+            buf.push(0);
+        }
+    }
+
+    fn object(buf: &mut Vec<u8>, object_type: u8, flags: u8, body: &[u8]) -> u64 {
+        align(buf);
+        let offset = buf.len() as u64;
+        buf.push(object_type);
+        buf.push(flags);
+        buf.extend_from_slice(&[0; 6]);
+        buf.extend_from_slice(&((OBJECT_HEADER_SIZE + body.len()) as u64).to_le_bytes());
+        buf.extend_from_slice(body);
+        offset
+    }
+
+    /// Writes a minimal uncompressed journal file holding `entries`, each
+    /// given as `(seqnum, realtime, boot_id, fields)`.
+    pub fn write_journal(path: &Path, file_id: u8, entries: &[(u64, u64, u8, &[&str])]) {
+        write_compressed_journal(path, file_id, entries, 0, 0, |field| field.to_vec());
+    }
+
+    /// Writes a journal file like `write_journal`, with its data objects
+    /// flagged as `object_flags` and compressed by `compress`.
+    fn write_compressed_journal(
+        path: &Path,
+        file_id: u8,
+        entries: &[(u64, u64, u8, &[&str])],
+        incompatible_flags: u32,
+        object_flags: u8,
+        compress: impl Fn(&[u8]) -> Vec<u8>,
+    ) {
+        let mut buf = vec![0; HEADER_SIZE];
+        let mut entry_offsets = Vec::new();
+
+        for (seqnum, realtime, boot_id, fields) in entries {
+            let mut items = Vec::new();
+            for field in fields.iter() {
+                let mut body = vec![0; 48];
+                body.extend_from_slice(&compress(field.as_bytes()));
+                items.push(object(&mut buf, OBJECT_DATA, object_flags, &body));
+            }
+
+            let mut body = Vec::new();
+            body.extend_from_slice(&seqnum.to_le_bytes());
+            body.extend_from_slice(&realtime.to_le_bytes());
+            body.extend_from_slice(&(realtime / 2).to_le_bytes());
+            body.extend_from_slice(&[*boot_id; 16]);
+            body.extend_from_slice(&0u64.to_le_bytes());
+            for item in items {
+                body.extend_from_slice(&item.to_le_bytes());
+                body.extend_from_slice(&0u64.to_le_bytes());
+            }
+            entry_offsets.push(object(&mut buf, OBJECT_ENTRY, 0, &body));
+        }
+
+        // Leave a spare slot, as journald preallocates entry arrays.
+        let mut body = 0u64.to_le_bytes().to_vec();
+        for offset in entry_offsets.iter().chain(&[0]) {
+            body.extend_from_slice(&offset.to_le_bytes());
+        }
+        let array_offset = object(&mut buf, OBJECT_ENTRY_ARRAY, 0, &body);
+
+        buf[..8].copy_from_slice(SIGNATURE);
+        buf[12..16].copy_from_slice(&incompatible_flags.to_le_bytes());
+        buf[16] = STATE_ARCHIVED;
+        buf[24..40].copy_from_slice(&[file_id; 16]);
+        buf[72..88].copy_from_slice(&[0xaa; 16]);
+        buf[88..96].copy_from_slice(&(HEADER_SIZE as u64).to_le_bytes());
+        buf[152..160].copy_from_slice(&(entries.len() as u64).to_le_bytes());
+        buf[176..184].copy_from_slice(&array_offset.to_le_bytes());
+
+        File::create(path).unwrap().write_all(&buf).unwrap();
+    }
+
+    fn read_all(journal: &mut JournalDirectory) -> Vec<Record> {
+        let mut records = Vec::new();
+        while let Some(record) = journal.next_record().unwrap() {
+            records.push(record);
+        }
+        records
+    }
+
+    fn messages(records: &[Record]) -> Vec<&str> {
+        records
+            .iter()
+            .map(|record| record[&Atom::from("MESSAGE")].as_str())
+            .collect()
+    }
+
+    #[test]
+    fn journal_file_reads_entries_in_time_order() {
+        let dir = tempdir().unwrap();
+        write_journal(
+            &dir.path().join("system.journal"),
+            1,
+            &[
+                (1, 100, 1, &["MESSAGE=one", "_SYSTEMD_UNIT=a.service"]),
+                (3, 300, 1, &["MESSAGE=three"]),
+            ],
+        );
+        write_journal(
+            &dir.path().join("user-1000.journal"),
+            2,
+            &[(2, 200, 1, &["MESSAGE=two"])],
+        );
+
+        let mut journal = JournalDirectory::new(vec![dir.path().into()], None, None);
+        let records = read_all(&mut journal);
+        assert_eq!(messages(&records), vec!["one", "two", "three"]);
+        assert_eq!(records[0][&Atom::from("_SYSTEMD_UNIT")], "a.service");
+        assert_eq!(records[0][&Atom::from("__REALTIME_TIMESTAMP")], "100");
+        assert_eq!(
+            records[0][&Atom::from("__CURSOR")],
+            format!(
+                "s={};i=1;b={};m=32;t=64;x=0",
+                "aa".repeat(16),
+                "01".repeat(16)
+            )
+        );
+    }
+
+    #[test]
+    fn journal_file_resumes_from_cursor() {
+        let dir = tempdir().unwrap();
+        write_journal(
+            &dir.path().join("system.journal"),
+            1,
+            &[
+                (1, 100, 1, &["MESSAGE=one"]),
+                (2, 200, 1, &["MESSAGE=two"]),
+                (3, 300, 1, &["MESSAGE=three"]),
+            ],
+        );
+
+        let mut journal = JournalDirectory::new(vec![dir.path().into()], None, None);
+        let records = read_all(&mut journal);
+        let cursor = Cursor::parse(&records[1][&Atom::from("__CURSOR")]).unwrap();
+
+        let mut journal = JournalDirectory::new(vec![dir.path().into()], Some(cursor), None);
+        assert_eq!(messages(&read_all(&mut journal)), vec!["three"]);
+    }
+
+    #[test]
+    fn journal_file_filters_boot_id() {
+        let dir = tempdir().unwrap();
+        write_journal(
+            &dir.path().join("system.journal"),
+            1,
+            &[
+                (1, 100, 1, &["MESSAGE=old boot"]),
+                (2, 200, 2, &["MESSAGE=new boot"]),
+            ],
+        );
+
+        let boot_id = Some("02".repeat(16));
+        let mut journal = JournalDirectory::new(vec![dir.path().into()], None, boot_id);
+        assert_eq!(messages(&read_all(&mut journal)), vec!["new boot"]);
+    }
+
+    #[test]
+    fn journal_file_picks_up_new_files() {
+        let dir = tempdir().unwrap();
+        let machine_dir = dir.path().join("c36e9ea52800a19d214cb71b53263a28");
+        fs::create_dir(&machine_dir).unwrap();
+
+        let mut journal = JournalDirectory::new(vec![dir.path().into()], None, None);
+        assert!(read_all(&mut journal).is_empty());
+
+        write_journal(
+            &machine_dir.join("system.journal"),
+            1,
+            &[(1, 100, 1, &["MESSAGE=late"])],
+        );
+        assert_eq!(messages(&read_all(&mut journal)), vec!["late"]);
+    }
+
+    #[test]
+    fn journal_file_closes_archived_files() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("system.journal");
+        write_journal(&path, 1, &[(1, 100, 1, &["MESSAGE=one"])]);
+        let set_state = |state: u8| {
+            let mut file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+            file.seek(SeekFrom::Start(16)).unwrap();
+            file.write_all(&[state]).unwrap();
+        };
+        set_state(1);
+
+        let mut journal = JournalDirectory::new(vec![dir.path().into()], None, None);
+        assert_eq!(messages(&read_all(&mut journal)), vec!["one"]);
+        assert_eq!(journal.files.len(), 1);
+
+        set_state(STATE_ARCHIVED);
+        assert!(read_all(&mut journal).is_empty());
+        assert!(journal.files.is_empty());
+        assert_eq!(journal.seen.len(), 1);
+    }
+
+    #[test]
+    fn journal_file_reopens_files_after_errors() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("system.journal");
+        let set_online = || {
+            let mut file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+            file.seek(SeekFrom::Start(16)).unwrap();
+            file.write_all(&[1]).unwrap();
+        };
+        write_journal(&path, 1, &[(1, 100, 1, &["MESSAGE=one"])]);
+        set_online();
+
+        let mut journal = JournalDirectory::new(vec![dir.path().into()], None, None);
+        assert_eq!(messages(&read_all(&mut journal)), vec!["one"]);
+
+        // A torn read of the entry array closes the file, which is then
+        // opened again, resuming after the entry last read.
+        journal.files[0].array_offset = 1;
+        write_journal(
+            &path,
+            1,
+            &[(1, 100, 1, &["MESSAGE=one"]), (2, 200, 1, &["MESSAGE=two"])],
+        );
+        set_online();
+        assert_eq!(messages(&read_all(&mut journal)), vec!["two"]);
+        assert_eq!(journal.files.len(), 1);
+        assert!(journal.resume.is_empty());
+    }
+
+    #[test]
+    fn journal_file_decompresses_fields() {
+        let message = format!("MESSAGE={}", "a".repeat(100));
+        let dir = tempdir().unwrap();
+        write_compressed_journal(
+            &dir.path().join("system.journal"),
+            1,
+            &[(1, 100, 1, &[&message])],
+            INCOMPATIBLE_COMPRESSED_ZSTD,
+            OBJECT_COMPRESSED_ZSTD,
+            |field| zstd::stream::encode_all(field, 0).unwrap(),
+        );
+        write_compressed_journal(
+            &dir.path().join("user-1000.journal"),
+            2,
+            &[(2, 200, 1, &[&message])],
+            INCOMPATIBLE_COMPRESSED_LZ4,
+            OBJECT_COMPRESSED_LZ4,
+            |field| {
+                // "MESSAGE=a", then the rest of the `a`s copied from the
+                // last byte written.
+                let mut block = (field.len() as u64).to_le_bytes().to_vec();
+                block.push(0x9f);
+                block.extend_from_slice(&field[..9]);
+                block.extend_from_slice(&[1, 0, (field.len() - 9 - 4 - 15) as u8]);
+                block
+            },
+        );
+
+        let mut journal = JournalDirectory::new(vec![dir.path().into()], None, None);
+        let records = read_all(&mut journal);
+        assert_eq!(messages(&records), vec![&message[8..], &message[8..]]);
+    }
+
+    #[test]
+    fn journal_file_refuses_xz_compression() {
+        let dir = tempdir().unwrap();
+        write_compressed_journal(
+            &dir.path().join("system.journal"),
+            1,
+            &[(1, 100, 1, &["MESSAGE=one"])],
+            INCOMPATIBLE_COMPRESSED_XZ,
+            0,
+            |field| field.to_vec(),
+        );
+
+        let mut journal = JournalDirectory::new(vec![dir.path().into()], None, None);
+        assert!(read_all(&mut journal).is_empty());
+        assert!(journal.files.is_empty());
+    }
+
+    #[test]
+    fn journal_file_rejects_garbage() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("bad.journal");
+        File::create(&path)
+            .unwrap()
+            .write_all(&[0u8; HEADER_SIZE])
+            .unwrap();
+        assert!(JournalFile::open(&path).is_err());
+    }
+
+    #[test]
+    fn journal_cursor_parses() {
+        let cursor = Cursor::parse("s=AB;i=1f;b=cd;m=2;t=ff;x=0").unwrap();
+        assert_eq!(
+            cursor,
+            Cursor {
+                seqnum_id: Some("ab".into()),
+                seqnum: Some(31),
+                realtime: Some(255),
+            }
+        );
+        assert!(Cursor::parse("garbage").is_none());
+    }
+}
@@ -20,6 +20,10 @@ use std::time;
 use string_cache::DefaultAtom as Atom;
 use tracing::{dispatcher, field};
 
+mod journal_file;
+
+use journal_file::{Cursor, JournalDirectory};
+
 const DEFAULT_BATCH_SIZE: usize = 16;
 
 lazy_static! {
@@ -29,12 +33,16 @@ lazy_static! {
     static ref SYSTEMD_UNIT: Atom = Atom::from("_SYSTEMD_UNIT");
     static ref TIMESTAMP: Atom = Atom::from("_SOURCE_REALTIME_TIMESTAMP");
     static ref JOURNALCTL: PathBuf = "journalctl".into();
+    static ref JOURNAL_DIRECTORIES: Vec<PathBuf> =
+        vec!["/var/log/journal".into(), "/run/log/journal".into()];
 }
 
 #[derive(Debug, Snafu)]
 enum BuildError {
     #[snafu(display("journalctl failed to execute: {}", source))]
     JournalctlSpawn { source: io::Error },
+    #[snafu(display("Could not read the current boot ID: {}", source))]
+    BootId { source: io::Error },
 }
 
 #[derive(Deserialize, Serialize, Debug, Default)]
@@ -42,9 +50,28 @@ enum BuildError {
 pub struct JournaldConfig {
     pub current_boot_only: Option<bool>,
     pub units: Vec<String>,
+    pub include_matches: HashMap<String, HashSet<String>>,
+    pub exclude_matches: HashMap<String, HashSet<String>>,
     pub data_dir: Option<PathBuf>,
     pub batch_size: Option<usize>,
+    pub reader: JournalReader,
     pub journalctl_path: Option<PathBuf>,
+    pub journal_directory: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JournalReader {
+    /// Spawn `journalctl` and parse its JSON output.
+    Journalctl,
+    /// Read the journal files directly.
+    Native,
+}
+
+impl Default for JournalReader {
+    fn default() -> Self {
+        JournalReader::Journalctl
+    }
 }
 
 inventory::submit! {
@@ -52,6 +79,7 @@ inventory::submit! {
 }
 
 type Record = HashMap<Atom, String>;
+type Matches = HashMap<Atom, HashSet<String>>;
 
 #[typetag::serde(name = "journald")]
 impl SourceConfig for JournaldConfig {
@@ -78,10 +106,30 @@ impl SourceConfig for JournaldConfig {
             })
             .collect::<HashSet<String>>();
 
+        let include_matches = to_matches(&self.include_matches);
+        let exclude_matches = to_matches(&self.exclude_matches);
+
         let checkpointer = Checkpointer::new(data_dir)
             .map_err(|err| format!("Unable to open checkpoint file: {}", err))?;
 
-        self.source::<Journalctl>(out, checkpointer, units, batch_size)
+        match self.reader {
+            JournalReader::Journalctl => self.source::<Journalctl>(
+                out,
+                checkpointer,
+                units,
+                include_matches,
+                exclude_matches,
+                batch_size,
+            ),
+            JournalReader::Native => self.source::<NativeJournal>(
+                out,
+                checkpointer,
+                units,
+                include_matches,
+                exclude_matches,
+                batch_size,
+            ),
+        }
     }
 
     fn output_type(&self) -> DataType {
@@ -99,6 +147,8 @@ impl JournaldConfig {
         out: mpsc::Sender<Event>,
         mut checkpointer: Checkpointer,
         units: HashSet<String>,
+        include_matches: Matches,
+        exclude_matches: Matches,
         batch_size: usize,
     ) -> crate::Result<super::Source>
     where
@@ -130,6 +180,8 @@ impl JournaldConfig {
             let journald_server = JournaldServer {
                 journal,
                 units,
+                include_matches,
+                exclude_matches,
                 channel: out,
                 shutdown: shutdown_rx,
                 checkpointer,
@@ -149,6 +201,22 @@ impl JournaldConfig {
     }
 }
 
+fn to_matches(matches: &HashMap<String, HashSet<String>>) -> Matches {
+    matches
+        .iter()
+        .map(|(field, values)| (Atom::from(field.as_str()), values.clone()))
+        .collect()
+}
+
+/// Returns `true` if any of the given field values is present in the record.
+fn matches_any(record: &Record, matches: &Matches) -> bool {
+    matches.iter().any(|(field, values)| {
+        record
+            .get(field)
+            .map_or(false, |value| values.contains(value))
+    })
+}
+
 fn create_event(record: Record) -> Event {
     let mut log = LogEvent::from_iter(record);
     // Convert some journald-specific field names into Vector standard ones.
@@ -177,10 +245,9 @@ fn create_event(record: Record) -> Event {
 }
 
 /// A `JournalSource` is a data source that works as an `Iterator`
-/// producing lines that resemble journald JSON format records. These
-/// trait functions is an addition to the standard iteration methods for
-/// initializing the source.
-trait JournalSource: Iterator<Item = Result<String, io::Error>> + Sized {
+/// producing journald records. These trait functions is an addition to
+/// the standard iteration methods for initializing the source.
+trait JournalSource: Iterator<Item = Result<Record, io::Error>> + Sized {
     fn new(config: &JournaldConfig, cursor: Option<String>) -> crate::Result<Self>;
 }
 
@@ -221,20 +288,73 @@ impl JournalSource for Journalctl {
 }
 
 impl Iterator for Journalctl {
-    type Item = Result<String, io::Error>;
+    type Item = Result<Record, io::Error>;
     fn next(&mut self) -> Option<Self::Item> {
-        let mut line = Vec::<u8>::new();
-        match self.stdout.read_until(b'\n', &mut line) {
-            Ok(0) => None,
-            Ok(_) => Some(Ok(String::from_utf8_lossy(&line).into())),
-            Err(err) => Some(Err(err)),
+        loop {
+            let mut line = Vec::<u8>::new();
+            match self.stdout.read_until(b'\n', &mut line) {
+                Ok(0) => return None,
+                Ok(_) => match serde_json::from_slice::<Record>(&line) {
+                    Ok(record) => return Some(Ok(record)),
+                    Err(error) => {
+                        // journalctl will output non-ASCII messages
+                        // using an array of integers. We don't
+                        // parse them into valid records yet but
+                        // instead just skip them.
+                        let text = String::from_utf8_lossy(&line);
+                        error!(message = "Invalid record from journald, discarding", %error, %text);
+                    }
+                },
+                Err(err) => return Some(Err(err)),
+            }
         }
     }
 }
 
+/// Reads the journal files directly, without going through `journalctl`.
+struct NativeJournal {
+    journal: JournalDirectory,
+}
+
+impl JournalSource for NativeJournal {
+    fn new(config: &JournaldConfig, cursor: Option<String>) -> crate::Result<Self> {
+        let directories = match &config.journal_directory {
+            Some(directory) => vec![directory.clone()],
+            None => JOURNAL_DIRECTORIES.clone(),
+        };
+
+        let boot_id = if config.current_boot_only.unwrap_or(true) {
+            Some(journal_file::current_boot_id().context(BootId)?)
+        } else {
+            None
+        };
+
+        let cursor = cursor.and_then(|cursor| {
+            let parsed = Cursor::parse(&cursor);
+            if parsed.is_none() {
+                warn!(message = "Ignoring invalid journald checkpoint.", %cursor);
+            }
+            parsed
+        });
+
+        Ok(NativeJournal {
+            journal: JournalDirectory::new(directories, cursor, boot_id),
+        })
+    }
+}
+
+impl Iterator for NativeJournal {
+    type Item = Result<Record, io::Error>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.journal.next_record().transpose()
+    }
+}
+
 struct JournaldServer<J, T> {
     journal: J,
     units: HashSet<String>,
+    include_matches: Matches,
+    exclude_matches: Matches,
     channel: T,
     shutdown: Receiver<()>,
     checkpointer: Checkpointer,
@@ -256,12 +376,12 @@ where
             let mut cursor: Option<String> = None;
 
             for _ in 0..self.batch_size {
-                let text = match self.journal.next() {
+                let mut record = match self.journal.next() {
                     None => {
                         at_end = true;
                         break;
                     }
-                    Some(Ok(text)) => text,
+                    Some(Ok(record)) => record,
                     Some(Err(err)) => {
                         error!(
                             message = "Could not read from journald source",
@@ -271,17 +391,6 @@ where
                     }
                 };
 
-                let mut record = match serde_json::from_str::<Record>(&text) {
-                    Ok(record) => record,
-                    Err(error) => {
-                        // journalctl will output non-ASCII messages
-                        // using an array of integers. We don't
-                        // parse them into valid records yet but
-                        // instead just skip them.
-                        error!(message = "Invalid record from journald, discarding", %error, %text);
-                        continue;
-                    }
-                };
                 if let Some(tmp) = record.remove(&CURSOR) {
                     cursor = Some(tmp);
                 }
@@ -297,6 +406,13 @@ where
                         continue;
                     }
                 }
+                if !self.include_matches.is_empty() && !matches_any(&record, &self.include_matches)
+                {
+                    continue;
+                }
+                if matches_any(&record, &self.exclude_matches) {
+                    continue;
+                }
                 match channel.send(record).wait() {
                     Ok(_) => {}
                     Err(()) => error!(message = "Could not send journald log"),
@@ -429,15 +545,12 @@ mod tests {
     }
 
     impl Iterator for FakeJournal {
-        type Item = Result<String, io::Error>;
+        type Item = Result<Record, io::Error>;
         fn next(&mut self) -> Option<Self::Item> {
             let mut line = String::new();
             match self.reader.read_line(&mut line) {
                 Ok(0) => None,
-                Ok(_) => {
-                    line.pop();
-                    Some(Ok(line))
-                }
+                Ok(_) => Some(Ok(serde_json::from_str(&line).unwrap())),
                 Err(err) => Some(Err(err)),
             }
         }
@@ -462,12 +575,20 @@ mod tests {
     }
 
     fn run_journal(units: &[&str], cursor: Option<&str>) -> Vec<Event> {
+        run_journal_matches(units, Matches::new(), Matches::new(), cursor)
+    }
+
+    fn run_journal_matches(
+        units: &[&str],
+        include_matches: Matches,
+        exclude_matches: Matches,
+        cursor: Option<&str>,
+    ) -> Vec<Event> {
         let (tx, rx) = futures01::sync::mpsc::channel(10);
         let (trigger, tripwire) = Tripwire::new();
         let tempdir = tempdir().unwrap();
         let mut checkpointer =
             Checkpointer::new(tempdir.path().to_path_buf()).expect("Creating checkpointer failed!");
-        let units = HashSet::<String>::from_iter(units.into_iter().map(|&s| s.into()));
 
         if let Some(cursor) = cursor {
             checkpointer.set(cursor).expect("Could not set checkpoint");
@@ -475,7 +596,14 @@ mod tests {
 
         let config = JournaldConfig::default();
         let source = config
-            .source::<FakeJournal>(tx, checkpointer, units, DEFAULT_BATCH_SIZE)
+            .source::<FakeJournal>(
+                tx,
+                checkpointer,
+                HashSet::from_iter(units.iter().map(|&s| s.into())),
+                include_matches,
+                exclude_matches,
+                DEFAULT_BATCH_SIZE,
+            )
             .expect("Creating journald source failed");
         let mut rt = runtime();
         rt.spawn(source.select(tripwire).map(|_| ()).map_err(|_| ()));
@@ -511,6 +639,77 @@ mod tests {
         );
     }
 
+    #[test]
+    fn journald_source_includes_matches() {
+        let mut include_matches = Matches::new();
+        include_matches.insert(
+            SYSTEMD_UNIT.clone(),
+            HashSet::from_iter(vec!["sysinit.target".into()]),
+        );
+        let received = run_journal_matches(&[], include_matches, Matches::new(), None);
+        assert_eq!(received.len(), 1);
+        assert_eq!(
+            received[0].as_log()[&event::log_schema().message_key()],
+            Value::Bytes("System Initialization".into())
+        );
+    }
+
+    #[test]
+    fn journald_source_requires_both_units_and_matches() {
+        let mut include_matches = Matches::new();
+        include_matches.insert(
+            Atom::from("MESSAGE"),
+            HashSet::from_iter(vec!["System Initialization".into()]),
+        );
+        let received =
+            run_journal_matches(&["unit.service"], include_matches, Matches::new(), None);
+        assert!(received.is_empty());
+    }
+
+    #[test]
+    fn journald_source_excludes_matches() {
+        let mut exclude_matches = Matches::new();
+        exclude_matches.insert(
+            Atom::from("MESSAGE"),
+            HashSet::from_iter(vec!["System Initialization".into()]),
+        );
+        let received = run_journal_matches(&[], Matches::new(), exclude_matches, None);
+        assert_eq!(received.len(), 1);
+        assert_eq!(
+            received[0].as_log()[&event::log_schema().message_key()],
+            Value::Bytes("unit message".into())
+        );
+    }
+
+    #[test]
+    fn journald_source_reads_native_journal() {
+        let dir = tempdir().unwrap();
+        journal_file::test::write_journal(
+            &dir.path().join("system.journal"),
+            1,
+            &[
+                (1, 100, 1, &["MESSAGE=first", "_HOSTNAME=myhost"]),
+                (2, 200, 1, &["MESSAGE=second"]),
+            ],
+        );
+        let config = JournaldConfig {
+            current_boot_only: Some(false),
+            journal_directory: Some(dir.path().into()),
+            ..JournaldConfig::default()
+        };
+
+        let mut journal = NativeJournal::new(&config, None).unwrap();
+        let first = journal.next().unwrap().unwrap();
+        assert_eq!(first[&MESSAGE], "first");
+        assert_eq!(first[&HOSTNAME], "myhost");
+        let second = NativeJournal::new(&config, first.get(&CURSOR).cloned())
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(second[&MESSAGE], "second");
+    }
+
     #[test]
     fn journald_source_handles_checkpoint() {
         let received = run_journal(&[], Some("1"));
@@ -0,0 +1,200 @@
+use snafu::Snafu;
+use std::collections::{BTreeMap, HashSet};
+
+/// Kubernetes label selector, as used by `kubectl --selector`.
+/// https://kubernetes.io/docs/concepts/overview/working-with-objects/labels/#label-selectors
+///
+/// Requirements are separated by commas and must all be satisfied.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LabelSelector {
+    requirements: Vec<Requirement>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Requirement {
+    /// `key`
+    Exists(String),
+    /// `!key`
+    DoesNotExist(String),
+    /// `key=value`, `key==value`, `key in (a,b)`
+    In(String, HashSet<String>),
+    /// `key!=value`, `key notin (a,b)`
+    NotIn(String, HashSet<String>),
+}
+
+#[derive(Debug, Snafu, PartialEq)]
+pub enum LabelSelectorError {
+    #[snafu(display("Invalid label selector requirement: {:?}", requirement))]
+    InvalidRequirement { requirement: String },
+    #[snafu(display("Empty label selector"))]
+    Empty,
+}
+
+impl LabelSelector {
+    pub fn parse(selector: &str) -> Result<Self, LabelSelectorError> {
+        let requirements = split_requirements(selector)
+            .into_iter()
+            .map(|requirement| parse_requirement(&requirement))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if requirements.is_empty() {
+            Err(LabelSelectorError::Empty)
+        } else {
+            Ok(LabelSelector { requirements })
+        }
+    }
+
+    pub fn matches(&self, labels: &BTreeMap<String, String>) -> bool {
+        self.requirements
+            .iter()
+            .all(|requirement| match requirement {
+                Requirement::Exists(key) => labels.contains_key(key),
+                Requirement::DoesNotExist(key) => !labels.contains_key(key),
+                Requirement::In(key, values) => labels
+                    .get(key)
+                    .map_or(false, |value| values.contains(value)),
+                Requirement::NotIn(key, values) => labels
+                    .get(key)
+                    .map_or(true, |value| !values.contains(value)),
+            })
+    }
+}
+
+/// Splits on commas which are not inside of a `(..)` value set.
+fn split_requirements(selector: &str) -> Vec<String> {
+    let mut requirements = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0;
+    for c in selector.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                requirements.push(current.trim().to_owned());
+                current.clear();
+                continue;
+            }
+            _ => (),
+        }
+        current.push(c);
+    }
+    if !current.trim().is_empty() || !requirements.is_empty() {
+        requirements.push(current.trim().to_owned());
+    }
+    requirements
+}
+
+fn parse_requirement(requirement: &str) -> Result<Requirement, LabelSelectorError> {
+    let invalid = || LabelSelectorError::InvalidRequirement {
+        requirement: requirement.to_owned(),
+    };
+    let key = |key: &str| {
+        let key = key.trim();
+        if key.is_empty() || key.contains(char::is_whitespace) {
+            Err(invalid())
+        } else {
+            Ok(key.to_owned())
+        }
+    };
+
+    if let Some(index) = requirement.find("!=") {
+        Ok(Requirement::NotIn(
+            key(&requirement[..index])?,
+            single(&requirement[index + 2..]),
+        ))
+    } else if let Some(index) = requirement.find("==") {
+        Ok(Requirement::In(
+            key(&requirement[..index])?,
+            single(&requirement[index + 2..]),
+        ))
+    } else if let Some(index) = requirement.find('=') {
+        Ok(Requirement::In(
+            key(&requirement[..index])?,
+            single(&requirement[index + 1..]),
+        ))
+    } else if let Some(index) = requirement.find(" notin ") {
+        let values = set(&requirement[index + 7..]).ok_or_else(invalid)?;
+        Ok(Requirement::NotIn(key(&requirement[..index])?, values))
+    } else if let Some(index) = requirement.find(" in ") {
+        let values = set(&requirement[index + 4..]).ok_or_else(invalid)?;
+        Ok(Requirement::In(key(&requirement[..index])?, values))
+    } else if requirement.starts_with('!') {
+        Ok(Requirement::DoesNotExist(key(&requirement[1..])?))
+    } else {
+        Ok(Requirement::Exists(key(requirement)?))
+    }
+}
+
+fn single(value: &str) -> HashSet<String> {
+    let mut values = HashSet::new();
+    values.insert(value.trim().to_owned());
+    values
+}
+
+fn set(values: &str) -> Option<HashSet<String>> {
+    let values = values.trim();
+    if values.starts_with('(') && values.ends_with(')') {
+        Some(
+            values[1..values.len() - 1]
+                .split(',')
+                .map(|value| value.trim().to_owned())
+                .collect(),
+        )
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn matches(selector: &str, pairs: &[(&str, &str)]) -> bool {
+        LabelSelector::parse(selector)
+            .unwrap()
+            .matches(&labels(pairs))
+    }
+
+    #[test]
+    fn label_selector_equality() {
+        assert!(matches("app=nginx", &[("app", "nginx")]));
+        assert!(matches("app==nginx", &[("app", "nginx")]));
+        assert!(!matches("app=nginx", &[("app", "redis")]));
+        assert!(!matches("app=nginx", &[]));
+        assert!(matches("app!=nginx", &[("app", "redis")]));
+        assert!(matches("app!=nginx", &[]));
+        assert!(!matches("app!=nginx", &[("app", "nginx")]));
+    }
+
+    #[test]
+    fn label_selector_sets() {
+        let pairs = &[("tier", "backend"), ("app.kubernetes.io/name", "api")];
+        assert!(matches("tier in (frontend, backend)", pairs));
+        assert!(!matches("tier notin (frontend,backend)", pairs));
+        assert!(matches("app.kubernetes.io/name", pairs));
+        assert!(!matches("!app.kubernetes.io/name", pairs));
+        assert!(matches("!release", pairs));
+    }
+
+    #[test]
+    fn label_selector_all_requirements() {
+        let pairs = &[("tier", "backend"), ("app", "api")];
+        assert!(matches("tier in (frontend,backend),app=api", pairs));
+        assert!(!matches("tier in (frontend,backend),app=web", pairs));
+    }
+
+    #[test]
+    fn label_selector_invalid() {
+        assert_eq!(LabelSelector::parse(""), Err(LabelSelectorError::Empty));
+        assert!(LabelSelector::parse("tier in frontend").is_err());
+        assert!(LabelSelector::parse("=value").is_err());
+        assert!(LabelSelector::parse("app=nginx,").is_err());
+    }
+}
@@ -3,22 +3,30 @@ pub mod test;
 
 mod applicable_transform;
 mod file_source_builder;
+mod label_selector;
 mod message_parser;
+mod pod_metadata;
 
 use self::applicable_transform::ApplicableTransform;
+use self::label_selector::{LabelSelector, LabelSelectorError};
+use self::pod_metadata::{PodCache, PodMetadataFilter};
 use crate::{
+    dns::Resolver,
     event::{self, Event, Value},
     sources::Source,
     topology::config::{DataType, GlobalOptions, SourceConfig},
     transforms::{
+        kubernetes::watch_client::ClientConfig,
         regex_parser::{RegexParser, RegexParserConfig},
         Transform,
     },
 };
 use chrono::{DateTime, Utc};
-use futures01::{sync::mpsc, Future, Sink, Stream};
+use futures01::{future, sync::mpsc, Future, Sink, Stream};
 use serde::{Deserialize, Serialize};
-use snafu::Snafu;
+use snafu::{ResultExt, Snafu};
+use std::env;
+use tokio::executor::DefaultExecutor;
 
 // ?NOTE
 // Original proposal: https://github.com/kubernetes/kubernetes/blob/release-1.5/docs/proposals/kubelet-cri-logging.md#proposed-solution
@@ -30,12 +38,21 @@ use snafu::Snafu;
 /// Location in which by Kubernetes CRI, container runtimes are to store logs.
 const LOG_DIRECTORY: &str = r"/var/log/pods/";
 
+/// Environment variable through which the name of the Node is passed down,
+/// so that only Pods on the same Node are watched.
+const NODE_NAME_ENV: &str = "VECTOR_NODE_NAME";
+
 #[derive(Debug, Snafu)]
 enum BuildError {
     #[snafu(display("To large UID: {:?}", uid))]
     UidToLarge { uid: String },
     #[snafu(display("UID contains illegal characters: {:?}", uid))]
     IllegalCharacterInUid { uid: String },
+    #[snafu(display("Invalid {}: {}", option, source))]
+    InvalidLabelSelector {
+        option: &'static str,
+        source: LabelSelectorError,
+    },
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone, Default)]
@@ -44,6 +61,29 @@ pub struct KubernetesConfig {
     include_container_names: Vec<String>,
     include_pod_uids: Vec<String>,
     include_namespaces: Vec<String>,
+    include_label_selector: Option<String>,
+    exclude_label_selector: Option<String>,
+    enrich_pod_metadata: bool,
+}
+
+impl KubernetesConfig {
+    fn label_selector(
+        selector: &Option<String>,
+        option: &'static str,
+    ) -> crate::Result<Option<LabelSelector>> {
+        Ok(selector
+            .as_ref()
+            .map(|selector| LabelSelector::parse(selector))
+            .transpose()
+            .context(InvalidLabelSelector { option })?)
+    }
+
+    /// Pod metadata is needed both for enrichment and for label selectors.
+    fn watches_pods(&self) -> bool {
+        self.enrich_pod_metadata
+            || self.include_label_selector.is_some()
+            || self.exclude_label_selector.is_some()
+    }
 }
 
 #[typetag::serde(name = "kubernetes")]
@@ -70,16 +110,56 @@ impl SourceConfig for KubernetesConfig {
         let mut transform_pod_uid = transform_pod_uid()?;
         let mut parse_message = message_parser::build_message_parser()?;
 
+        let include = Self::label_selector(&self.include_label_selector, "include_label_selector")?;
+        let exclude = Self::label_selector(&self.exclude_label_selector, "exclude_label_selector")?;
+        let cache = PodCache::default();
+        let pod_filter =
+            PodMetadataFilter::new(cache.clone(), self.enrich_pod_metadata, include, exclude);
+
+        let watch = if self.watches_pods() {
+            let node_name = env::var(NODE_NAME_ENV).ok();
+            if node_name.is_none() {
+                warn!(
+                    message = "Node name not set, watching Pods of all Nodes.",
+                    env = NODE_NAME_ENV
+                );
+            }
+            // Fail early when not running inside of a cluster.
+            env::var("KUBERNETES_SERVICE_HOST")
+                .map_err(|_| "Kubernetes Pod metadata requires running inside of a cluster")?;
+
+            let dns_servers = globals.dns_servers.clone();
+            future::Either::A(future::lazy(move || {
+                let watch = Resolver::new(dns_servers, DefaultExecutor::current())
+                    .map_err(Into::into)
+                    .and_then(|resolver| {
+                        ClientConfig::in_cluster(node_name, resolver).map_err(Into::into)
+                    })
+                    .and_then(|config| cache.watch(config));
+                match watch {
+                    Ok(watch) => future::Either::A(watch),
+                    Err(error) => {
+                        error!(message = "Unable to watch Kubernetes Pods.", %error);
+                        future::Either::B(future::ok(()))
+                    }
+                }
+            }))
+        } else {
+            future::Either::B(future::ok(()))
+        };
+
         // Kubernetes source
         let source = file_recv
             .filter_map(move |event| transform_file.transform(event))
             .filter_map(move |event| parse_message.transform(event))
             .filter_map(move |event| now.filter(event))
             .map(remove_ending_newline)
-            .filter_map(move |event| transform_pod_uid.transform(event))
+            .filter_map(move |event| transform_pod_uid.transform(event));
+        let source = pod_filter
+            .filter_stream(source)
             .forward(out.sink_map_err(drop))
             .map(drop)
-            .join(file_source)
+            .join3(file_source, watch)
             .map(drop);
 
         Ok(Box::new(source))
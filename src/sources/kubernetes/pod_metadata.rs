@@ -0,0 +1,497 @@
+use super::label_selector::LabelSelector;
+use crate::{
+    event::{Event, Value},
+    transforms::kubernetes::watch_client::{ClientConfig, PodEvent, RuntimeError, Version},
+};
+use futures01::{
+    future::{self, Either, Loop},
+    Async, Future, Poll, Stream,
+};
+use k8s_openapi::api::core::v1::Pod;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use string_cache::DefaultAtom as Atom;
+use tokio::timer::{Delay, Interval};
+
+lazy_static::lazy_static! {
+    static ref OBJECT_UID: Atom = Atom::from("object_uid");
+    static ref POD_LABELS: Atom = Atom::from("pod_labels");
+    static ref POD_ANNOTATIONS: Atom = Atom::from("pod_annotations");
+    static ref POD_NODE_NAME: Atom = Atom::from("pod_node_name");
+    static ref POD_OWNER_REFERENCES: Atom = Atom::from("pod_owner_references");
+}
+
+/// Static Pods are logged under the hash of their manifest, which the API
+/// server exposes through this annotation on the mirror Pod.
+const MIRROR_ANNOTATION: &str = "kubernetes.io/config.mirror";
+
+/// Time to wait before reconnecting a failed watch.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Time to hold the events of Pods missing from the cache, waiting for the
+/// watch to catch up, before dropping them.
+const UNKNOWN_POD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Events held at most before the source stops reading more.
+const MAX_HELD_EVENTS: usize = 1000;
+
+/// How often held events are checked against the cache again.
+const HELD_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct PodMetadata {
+    labels: BTreeMap<String, String>,
+    annotations: BTreeMap<String, String>,
+    node_name: Option<String>,
+    /// Kind and name of each owner.
+    owner_references: Vec<(String, String)>,
+}
+
+impl PodMetadata {
+    fn from_pod(pod: &Pod) -> Self {
+        let metadata = pod.metadata.as_ref();
+        PodMetadata {
+            labels: metadata
+                .and_then(|metadata| metadata.labels.clone())
+                .unwrap_or_default(),
+            annotations: metadata
+                .and_then(|metadata| metadata.annotations.clone())
+                .unwrap_or_default(),
+            node_name: pod.spec.as_ref().and_then(|spec| spec.node_name.clone()),
+            owner_references: metadata
+                .and_then(|metadata| metadata.owner_references.as_ref())
+                .map(|owners| {
+                    owners
+                        .iter()
+                        .map(|owner| (owner.kind.clone(), owner.name.clone()))
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
+    }
+
+    fn enrich(&self, event: &mut Event) {
+        let log = event.as_mut_log();
+        log.insert(POD_LABELS.clone(), to_map(&self.labels));
+        log.insert(POD_ANNOTATIONS.clone(), to_map(&self.annotations));
+        if let Some(node_name) = &self.node_name {
+            log.insert(POD_NODE_NAME.clone(), node_name.clone());
+        }
+        let owners = self
+            .owner_references
+            .iter()
+            .map(|(kind, name)| {
+                let mut owner = BTreeMap::new();
+                owner.insert(Atom::from("kind"), Value::from(kind.clone()));
+                owner.insert(Atom::from("name"), Value::from(name.clone()));
+                Value::Map(owner)
+            })
+            .collect::<Vec<_>>();
+        log.insert(POD_OWNER_REFERENCES.clone(), owners);
+    }
+}
+
+/// Inserted as a map so that keys containing dots, as is common for
+/// labels, aren't interpreted as paths.
+fn to_map(pairs: &BTreeMap<String, String>) -> BTreeMap<Atom, Value> {
+    pairs
+        .iter()
+        .map(|(key, value)| (Atom::from(key.as_str()), Value::from(value.clone())))
+        .collect()
+}
+
+/// UIDs appear both with and without dashes in log paths.
+fn normalize_uid(uid: &str) -> String {
+    uid.chars()
+        .filter(|c| *c != '-')
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn pod_keys(pod: &Pod) -> Vec<String> {
+    let metadata = match pod.metadata.as_ref() {
+        Some(metadata) => metadata,
+        None => return Vec::new(),
+    };
+    metadata
+        .uid
+        .iter()
+        .chain(
+            metadata
+                .annotations
+                .as_ref()
+                .and_then(|annotations| annotations.get(MIRROR_ANNOTATION)),
+        )
+        .map(|uid| normalize_uid(uid))
+        .collect()
+}
+
+/// Pod metadata shared between the watch and the source, keyed by Pod UID.
+#[derive(Clone, Default)]
+pub struct PodCache {
+    pods: Arc<RwLock<HashMap<String, PodMetadata>>>,
+}
+
+impl PodCache {
+    fn apply(&self, event: &PodEvent) {
+        let mut pods = self.pods.write().expect("Pod cache lock poisoned");
+        match event {
+            PodEvent::Changed(pod) => {
+                let metadata = PodMetadata::from_pod(pod);
+                for key in pod_keys(pod) {
+                    pods.insert(key, metadata.clone());
+                }
+            }
+            PodEvent::Deleted(pod) => {
+                for key in pod_keys(pod) {
+                    pods.remove(&key);
+                }
+            }
+            PodEvent::Bookmark(_) => (),
+        }
+    }
+
+    fn get(&self, uid: &str) -> Option<PodMetadata> {
+        self.pods
+            .read()
+            .expect("Pod cache lock poisoned")
+            .get(&normalize_uid(uid))
+            .cloned()
+    }
+
+    /// Keeps the cache up to date by watching Pods, reconnecting whenever
+    /// the watch fails or ends.
+    pub fn watch(self, config: ClientConfig) -> crate::Result<impl Future<Item = (), Error = ()>> {
+        let mut client = config.build()?;
+        let version: Arc<Mutex<Option<Version>>> = Arc::new(Mutex::new(None));
+
+        Ok(future::loop_fn(None, move |error: Option<RuntimeError>| {
+            let start = version.lock().expect("Version lock poisoned").clone();
+            let stream = match client.watch_metadata(start, error) {
+                Ok(stream) => stream,
+                Err(error) => {
+                    error!(message = "Unable to watch Kubernetes Pods.", %error);
+                    return Either::A(future::ok::<_, ()>(Loop::Break(())));
+                }
+            };
+
+            let cache = self.clone();
+            let version = Arc::clone(&version);
+            Either::B(
+                stream
+                    .for_each(move |event| {
+                        if let Some(new) = event.version() {
+                            *version.lock().expect("Version lock poisoned") = Some(new);
+                        }
+                        cache.apply(&event);
+                        Ok(())
+                    })
+                    .then(|result| {
+                        let error = match result {
+                            Ok(()) => RuntimeError::WatchUnexpectedlyEnded,
+                            Err(error) => error,
+                        };
+                        warn!(message = "Kubernetes Pod watch stopped, reconnecting.", %error);
+                        Delay::new(Instant::now() + RECONNECT_DELAY)
+                            .then(move |_| Ok::<_, ()>(Loop::Continue(Some(error))))
+                    }),
+            )
+        }))
+    }
+}
+
+/// Filters events by the labels of their Pod, and optionally adds the Pod
+/// metadata to them.
+pub struct PodMetadataFilter {
+    cache: PodCache,
+    enrich: bool,
+    include: Option<LabelSelector>,
+    exclude: Option<LabelSelector>,
+}
+
+impl PodMetadataFilter {
+    pub fn new(
+        cache: PodCache,
+        enrich: bool,
+        include: Option<LabelSelector>,
+        exclude: Option<LabelSelector>,
+    ) -> Self {
+        PodMetadataFilter {
+            cache,
+            enrich,
+            include,
+            exclude,
+        }
+    }
+
+    /// Filters a stream of events. Without labels it can't be known whether
+    /// a Pod is included, so the events of Pods missing from the cache are
+    /// held until the watch adds them, like at startup while the initial
+    /// list of Pods is received.
+    pub fn filter_stream<S>(self, events: S) -> FilterStream<S>
+    where
+        S: Stream<Item = Event, Error = ()>,
+    {
+        FilterStream {
+            events: Some(events),
+            filter: self,
+            held: VecDeque::new(),
+            retry: Interval::new_interval(HELD_RETRY_INTERVAL),
+        }
+    }
+
+    fn metadata(&self, event: &Event) -> Option<PodMetadata> {
+        event
+            .as_log()
+            .get(&OBJECT_UID)
+            .and_then(|uid| self.cache.get(&uid.to_string_lossy()))
+    }
+
+    /// Returns `true` if the event is of a Pod missing from the cache, while
+    /// Pods need to be known to be included.
+    fn holds(&self, event: &Event) -> bool {
+        self.include.is_some()
+            && event.as_log().contains(&OBJECT_UID)
+            && self.metadata(event).is_none()
+    }
+
+    pub fn filter(&self, mut event: Event) -> Option<Event> {
+        let metadata = self.metadata(&event);
+
+        match &metadata {
+            Some(metadata) => {
+                if let Some(include) = &self.include {
+                    if !include.matches(&metadata.labels) {
+                        return None;
+                    }
+                }
+                if let Some(exclude) = &self.exclude {
+                    if exclude.matches(&metadata.labels) {
+                        return None;
+                    }
+                }
+            }
+            // Without labels it can't be known whether the Pod is included.
+            None if self.include.is_some() => {
+                trace!(message = "Dropping event of unknown Pod.");
+                return None;
+            }
+            None => (),
+        }
+
+        if self.enrich {
+            if let Some(metadata) = metadata {
+                metadata.enrich(&mut event);
+            }
+        }
+
+        Some(event)
+    }
+}
+
+pub struct FilterStream<S> {
+    /// `None` once the stream has ended.
+    events: Option<S>,
+    filter: PodMetadataFilter,
+    /// Events of Pods missing from the cache, along with when they arrived.
+    held: VecDeque<(Instant, Event)>,
+    retry: Interval,
+}
+
+impl<S: Stream<Item = Event, Error = ()>> Stream for FilterStream<S> {
+    type Item = Event;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<Event>, ()> {
+        // Held events go first, so the events of a Pod stay in order.
+        let mut index = 0;
+        while index < self.held.len() {
+            let (since, event) = &self.held[index];
+            if since.elapsed() < UNKNOWN_POD_TIMEOUT && self.filter.holds(event) {
+                index += 1;
+                continue;
+            }
+            let (_, event) = self.held.remove(index).expect("Held event must exist");
+            if let Some(event) = self.filter.filter(event) {
+                return Ok(Async::Ready(Some(event)));
+            }
+        }
+
+        while self.held.len() < MAX_HELD_EVENTS {
+            let events = match &mut self.events {
+                Some(events) => events,
+                None => break,
+            };
+            match events.poll()? {
+                Async::Ready(Some(event)) if self.filter.holds(&event) => {
+                    self.held.push_back((Instant::now(), event));
+                }
+                Async::Ready(Some(event)) => {
+                    if let Some(event) = self.filter.filter(event) {
+                        return Ok(Async::Ready(Some(event)));
+                    }
+                }
+                Async::Ready(None) => self.events = None,
+                Async::NotReady => break,
+            }
+        }
+
+        if self.held.is_empty() {
+            return Ok(if self.events.is_none() {
+                Async::Ready(None)
+            } else {
+                Async::NotReady
+            });
+        }
+
+        // Check the held events again later.
+        while let Async::Ready(_) = self.retry.poll().map_err(|error| {
+            error!(message = "Timer error while holding events of unknown Pods.", %error);
+        })? {}
+        Ok(Async::NotReady)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        dns::Resolver,
+        test_util::{next_addr, runtime, wait_for},
+        tls::TlsSettings,
+    };
+    use http::Uri;
+    use hyper::{service::service_fn_ok, Body, Request, Response, Server};
+
+    const POD_UID: &str = "e2782fb0-ba64-4289-acd5-68c4f5b0d27e";
+
+    fn pod_json(uid: &str, labels: &str) -> String {
+        format!(
+            r#"{{"apiVersion":"v1","kind":"Pod","metadata":{{"uid":"{}","name":"busybox","namespace":"default","resourceVersion":"10","labels":{},"annotations":{{"team":"telemetry"}},"ownerReferences":[{{"apiVersion":"apps/v1","kind":"ReplicaSet","name":"busybox-5bdc7bfd99","uid":"1"}}]}},"spec":{{"containers":[],"nodeName":"node-1"}}}}"#,
+            uid, labels
+        )
+    }
+
+    fn cache_with(labels: &str) -> PodCache {
+        let pod: Pod = serde_json::from_str(&pod_json(POD_UID, labels)).unwrap();
+        let cache = PodCache::default();
+        cache.apply(&PodEvent::Changed(pod));
+        cache
+    }
+
+    fn event_for(uid: &str) -> Event {
+        let mut event = Event::from("message");
+        event.as_mut_log().insert("object_uid", uid);
+        event
+    }
+
+    #[test]
+    fn pod_metadata_enriches_events() {
+        let filter = PodMetadataFilter::new(cache_with(r#"{"app":"busybox"}"#), true, None, None);
+        let event = filter.filter(event_for(POD_UID)).unwrap();
+        let log = event.as_log();
+
+        assert_eq!(log[&"pod_labels.app".into()], "busybox".into());
+        assert_eq!(log[&"pod_annotations.team".into()], "telemetry".into());
+        assert_eq!(log[&"pod_node_name".into()], "node-1".into());
+        assert_eq!(
+            log[&"pod_owner_references[0].kind".into()],
+            "ReplicaSet".into()
+        );
+        assert_eq!(
+            log[&"pod_owner_references[0].name".into()],
+            "busybox-5bdc7bfd99".into()
+        );
+    }
+
+    #[test]
+    fn pod_metadata_matches_uid_without_dashes() {
+        let filter = PodMetadataFilter::new(cache_with("{}"), true, None, None);
+        let event = filter
+            .filter(event_for("E2782FB0BA644289ACD568C4F5B0D27E"))
+            .unwrap();
+        assert!(event.as_log().contains(&"pod_node_name".into()));
+    }
+
+    #[test]
+    fn pod_metadata_filters_by_label_selector() {
+        let cache = cache_with(r#"{"app":"busybox","tier":"backend"}"#);
+        let selector = |s| Some(LabelSelector::parse(s).unwrap());
+
+        let filter = PodMetadataFilter::new(cache.clone(), false, selector("app=busybox"), None);
+        assert!(filter.filter(event_for(POD_UID)).is_some());
+        // Pods that are still not known once held are dropped when including.
+        assert!(filter.filter(event_for("unknown")).is_none());
+
+        let filter = PodMetadataFilter::new(cache.clone(), false, selector("app=redis"), None);
+        assert!(filter.filter(event_for(POD_UID)).is_none());
+
+        let filter = PodMetadataFilter::new(cache, false, None, selector("tier=backend"));
+        assert!(filter.filter(event_for(POD_UID)).is_none());
+        assert!(filter.filter(event_for("unknown")).is_some());
+    }
+
+    #[test]
+    fn pod_metadata_holds_events_of_unknown_pods() {
+        let cache = PodCache::default();
+        let selector = Some(LabelSelector::parse("app=busybox").unwrap());
+        let filter = PodMetadataFilter::new(cache.clone(), false, selector, None);
+        let events = vec![event_for(POD_UID), event_for(POD_UID)];
+        let events = filter.filter_stream(futures01::stream::iter_ok(events));
+
+        let mut rt = runtime();
+        let pod: Pod = serde_json::from_str(&pod_json(POD_UID, r#"{"app":"busybox"}"#)).unwrap();
+        rt.spawn(
+            Delay::new(Instant::now() + Duration::from_millis(200))
+                .map(move |_| cache.apply(&PodEvent::Changed(pod)))
+                .map_err(drop),
+        );
+
+        let events = rt.block_on(events.collect()).unwrap();
+        assert_eq!(events.len(), 2);
+    }
+
+    #[test]
+    fn pod_metadata_removes_deleted_pods() {
+        let cache = cache_with("{}");
+        let pod: Pod = serde_json::from_str(&pod_json(POD_UID, "{}")).unwrap();
+        cache.apply(&PodEvent::Deleted(pod));
+        assert!(cache.get(POD_UID).is_none());
+    }
+
+    #[test]
+    fn pod_metadata_watches_api_server() {
+        let addr = next_addr();
+        let body = format!(
+            "{{\"type\":\"ADDED\",\"object\":{}}}\n",
+            pod_json(POD_UID, r#"{"app":"busybox"}"#)
+        );
+        let service = move || {
+            let body = body.clone();
+            service_fn_ok(move |_: Request<Body>| Response::new(Body::from(body.clone())))
+        };
+        let server = Server::bind(&addr)
+            .serve(service)
+            .map_err(|error| panic!("server error: {}", error));
+
+        let mut rt = runtime();
+        rt.spawn(server);
+
+        let config = ClientConfig::new(
+            Resolver::new(Vec::new(), rt.executor()).unwrap(),
+            format!("http://{}", addr).parse::<Uri>().unwrap(),
+            None,
+            TlsSettings::from_options(&None).unwrap(),
+            None,
+        );
+        let cache = PodCache::default();
+        rt.spawn(cache.clone().watch(config).unwrap());
+
+        wait_for(|| cache.get(POD_UID).is_some());
+        assert_eq!(
+            cache.get(POD_UID).unwrap().labels.get("app"),
+            Some(&"busybox".to_owned())
+        );
+    }
+}
@@ -1,12 +1,22 @@
 use futures01::Future;
 use snafu::Snafu;
 
+#[cfg(feature = "sources-aws_cloudwatch_logs")]
+pub mod aws_cloudwatch_logs;
+#[cfg(feature = "sources-aws_kinesis_firehose")]
+pub mod aws_kinesis_firehose;
+#[cfg(feature = "sources-aws_s3")]
+pub mod aws_s3;
 #[cfg(feature = "sources-docker")]
 pub mod docker;
 #[cfg(feature = "sources-file")]
 pub mod file;
 #[cfg(feature = "sources-http")]
 pub mod http;
+#[cfg(feature = "sources-internal_logs")]
+pub mod internal_logs;
+#[cfg(feature = "sources-internal_metrics")]
+pub mod internal_metrics;
 #[cfg(feature = "sources-journald")]
 pub mod journald;
 #[cfg(all(feature = "sources-kafka", feature = "rdkafka"))]
@@ -86,7 +86,7 @@ impl SourceConfig for SocketConfig {
                     .host_key
                     .clone()
                     .unwrap_or(event::log_schema().host_key().clone());
-                Ok(udp::udp(config.address, host_key, out))
+                udp::udp(config.address, host_key, out)
             }
             #[cfg(unix)]
             Mode::Unix(config) => {
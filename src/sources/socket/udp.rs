@@ -1,4 +1,5 @@
 use crate::event::Event;
+use crate::listeners;
 use crate::sources::Source;
 use bytes::Bytes;
 use codec::BytesDelimitedCodec;
@@ -6,7 +7,10 @@ use futures01::{future, sync::mpsc, Future, Sink, Stream};
 use serde::{Deserialize, Serialize};
 use std::{io, net::SocketAddr};
 use string_cache::DefaultAtom as Atom;
-use tokio::net::udp::{UdpFramed, UdpSocket};
+use tokio::{
+    net::udp::{UdpFramed, UdpSocket},
+    reactor::Handle,
+};
 
 /// UDP processes messages per packet, where messages are separated by newline.
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -25,18 +29,22 @@ impl UdpConfig {
     }
 }
 
-pub fn udp(address: SocketAddr, host_key: Atom, out: mpsc::Sender<Event>) -> Source {
+pub fn udp(address: SocketAddr, host_key: Atom, out: mpsc::Sender<Event>) -> crate::Result<Source> {
     let out = out.sink_map_err(|e| error!("error sending event: {:?}", e));
+    let mut socket_use = listeners::udp(address)?;
 
-    Box::new(
+    Ok(Box::new(
         future::lazy(move || {
-            let socket = UdpSocket::bind(&address).expect("failed to bind to udp listener socket");
+            let socket = socket_use
+                .bind_udp()
+                .and_then(|socket| UdpSocket::from_std(socket, &Handle::default()))
+                .map_err(|error| error!(message = "failed to listen on udp socket.", %error))?;
 
             info!(message = "listening.", %address);
 
-            Ok(socket)
+            Ok((socket, socket_use))
         })
-        .and_then(move |socket| {
+        .and_then(move |(socket, socket_use)| {
             let host_key = host_key.clone();
             // UDP processes messages per packet, where messages are separated by newline.
             // And stretch to end of packet.
@@ -56,6 +64,11 @@ pub fn udp(address: SocketAddr, host_key: Atom, out: mpsc::Sender<Event>) -> Sou
                 .forward(out)
                 // Done with listening and sending
                 .map(|_| ())
+                // The socket is shared until the source stops.
+                .then(move |result| {
+                    drop(socket_use);
+                    result
+                })
         }),
-    )
+    ))
 }
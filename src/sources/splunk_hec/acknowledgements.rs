@@ -0,0 +1,256 @@
+use super::ApiError;
+use crate::event::BatchStatus;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Splunk HEC indexer acknowledgements.
+/// https://docs.splunk.com/Documentation/Splunk/latest/Data/AboutHECIDXAck
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields, default)]
+pub struct AcknowledgementsConfig {
+    pub enabled: bool,
+    /// Ack IDs which are pending or not yet queried, per channel.
+    pub max_pending_acks_per_channel: u64,
+    /// Channels which weren't used for this long are forgotten.
+    pub max_idle_secs: u64,
+}
+
+impl Default for AcknowledgementsConfig {
+    fn default() -> Self {
+        AcknowledgementsConfig {
+            enabled: false,
+            max_pending_acks_per_channel: 1_000_000,
+            max_idle_secs: 600,
+        }
+    }
+}
+
+/// Tracks ack IDs of requests, per channel.
+#[derive(Clone)]
+pub struct IndexerAcknowledgements {
+    max_pending_acks_per_channel: u64,
+    max_idle: Duration,
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    channels: HashMap<String, Channel>,
+    last_cleanup: Instant,
+}
+
+struct Channel {
+    next_ack_id: u64,
+    /// Requests whose events are yet to be delivered.
+    pending: HashSet<u64>,
+    /// Requests whose events were delivered, but weren't yet queried.
+    delivered: HashSet<u64>,
+    last_used: Instant,
+}
+
+impl Channel {
+    fn new(now: Instant) -> Self {
+        Channel {
+            next_ack_id: 0,
+            pending: HashSet::new(),
+            delivered: HashSet::new(),
+            last_used: now,
+        }
+    }
+}
+
+impl IndexerAcknowledgements {
+    pub fn new(config: &AcknowledgementsConfig) -> Self {
+        IndexerAcknowledgements {
+            max_pending_acks_per_channel: config.max_pending_acks_per_channel,
+            max_idle: Duration::from_secs(config.max_idle_secs),
+            state: Arc::new(Mutex::new(State {
+                channels: HashMap::new(),
+                last_cleanup: Instant::now(),
+            })),
+        }
+    }
+
+    /// Assigns an ack ID to a request on the channel.
+    pub fn create(&self, channel: &str) -> Result<Ack, ApiError> {
+        let now = Instant::now();
+        let mut state = self.state.lock().expect("Acknowledgements lock poisoned");
+
+        // Forget channels which weren't used for a while. Those with acks
+        // left are kept, so their ack IDs are neither lost nor reused.
+        if now.duration_since(state.last_cleanup) >= self.max_idle {
+            let max_idle = self.max_idle;
+            state.channels.retain(|_, channel| {
+                now.duration_since(channel.last_used) < max_idle
+                    || !channel.pending.is_empty()
+                    || !channel.delivered.is_empty()
+            });
+            state.last_cleanup = now;
+        }
+
+        let entry = state
+            .channels
+            .entry(channel.to_owned())
+            .or_insert_with(|| Channel::new(now));
+        entry.last_used = now;
+
+        if (entry.pending.len() + entry.delivered.len()) as u64 >= self.max_pending_acks_per_channel
+        {
+            return Err(ApiError::ServerBusy);
+        }
+
+        let id = entry.next_ack_id;
+        entry.next_ack_id += 1;
+        entry.pending.insert(id);
+
+        Ok(Ack {
+            acknowledgements: self.clone(),
+            channel: channel.to_owned(),
+            id,
+        })
+    }
+
+    /// Returns the status of queried ack IDs, forgetting those which were delivered.
+    pub fn query(&self, channel: &str, ack_ids: &[u64]) -> BTreeMap<u64, bool> {
+        let mut state = self.state.lock().expect("Acknowledgements lock poisoned");
+        let mut channel = state.channels.get_mut(channel);
+
+        ack_ids
+            .iter()
+            .map(|&id| {
+                let delivered = channel
+                    .as_mut()
+                    .map(|channel| {
+                        channel.last_used = Instant::now();
+                        channel.delivered.remove(&id)
+                    })
+                    .unwrap_or(false);
+                (id, delivered)
+            })
+            .collect()
+    }
+
+    fn finish(&self, channel: &str, id: u64, delivered: bool) {
+        let mut state = self.state.lock().expect("Acknowledgements lock poisoned");
+        if let Some(channel) = state.channels.get_mut(channel) {
+            if channel.pending.remove(&id) && delivered {
+                channel.delivered.insert(id);
+            }
+        }
+    }
+}
+
+/// Ack ID of one request.
+pub struct Ack {
+    acknowledgements: IndexerAcknowledgements,
+    channel: String,
+    id: u64,
+}
+
+impl Ack {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Completes the ack with the status of the batch holding the events of the request.
+    pub fn resolve(self, status: BatchStatus) {
+        match status {
+            BatchStatus::Delivered => {
+                self.deliver();
+            }
+            BatchStatus::Failed => self.fail(),
+        }
+    }
+
+    /// Marks the events of the request as delivered.
+    pub fn deliver(self) -> u64 {
+        self.acknowledgements.finish(&self.channel, self.id, true);
+        self.id
+    }
+
+    /// Events of the request weren't delivered, so the ack ID will never be acknowledged.
+    pub fn fail(self) {
+        self.acknowledgements.finish(&self.channel, self.id, false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn acknowledgements(max_pending_acks_per_channel: u64) -> IndexerAcknowledgements {
+        IndexerAcknowledgements::new(&AcknowledgementsConfig {
+            enabled: true,
+            max_pending_acks_per_channel,
+            ..AcknowledgementsConfig::default()
+        })
+    }
+
+    #[test]
+    fn splunk_acks_per_channel() {
+        let acks = acknowledgements(10);
+
+        let first = acks.create("a").unwrap();
+        let second = acks.create("a").unwrap();
+        let other = acks.create("b").unwrap();
+        assert_eq!(first.id, 0);
+        assert_eq!(second.id, 1);
+        assert_eq!(other.id, 0);
+
+        assert_eq!(first.deliver(), 0);
+        let status = acks.query("a", &[0, 1]);
+        assert_eq!(status[&0], true);
+        assert_eq!(status[&1], false);
+
+        // Delivered acks are forgotten once queried.
+        assert_eq!(acks.query("a", &[0])[&0], false);
+
+        second.deliver();
+        assert_eq!(acks.query("a", &[1])[&1], true);
+        assert_eq!(acks.query("b", &[0])[&0], false);
+        assert_eq!(acks.query("unknown", &[0])[&0], false);
+        other.fail();
+        assert_eq!(acks.query("b", &[0])[&0], false);
+    }
+
+    #[test]
+    fn splunk_acks_keeps_idle_channels_with_acks() {
+        let acks = IndexerAcknowledgements::new(&AcknowledgementsConfig {
+            enabled: true,
+            max_idle_secs: 0,
+            ..AcknowledgementsConfig::default()
+        });
+
+        let first = acks.create("a").unwrap();
+        let idle = acks.create("b").unwrap();
+        idle.deliver();
+        acks.query("b", &[0]);
+
+        // Idle channels are forgotten once they have no acks left.
+        acks.create("c").unwrap();
+        assert_eq!(acks.create("b").unwrap().id, 0);
+
+        assert_eq!(first.deliver(), 0);
+        acks.create("c").unwrap();
+        assert_eq!(acks.create("a").unwrap().id, 1);
+        assert_eq!(acks.query("a", &[0])[&0], true);
+    }
+
+    #[test]
+    fn splunk_acks_max_pending() {
+        let acks = acknowledgements(2);
+
+        let first = acks.create("a").unwrap();
+        let _second = acks.create("a").unwrap();
+        assert!(acks.create("a").is_err());
+        assert!(acks.create("b").is_ok());
+
+        // Delivered, but not yet queried acks are still pending.
+        first.deliver();
+        assert!(acks.create("a").is_err());
+
+        acks.query("a", &[0]);
+        assert!(acks.create("a").is_ok());
+    }
+}
@@ -1,12 +1,19 @@
+mod acknowledgements;
+
+use self::acknowledgements::{Ack, AcknowledgementsConfig, IndexerAcknowledgements};
 use crate::{
-    event::{self, Event, LogEvent, Value},
+    event::{self, BatchNotifier, BatchStatus, Event, LogEvent, Value},
     tls::{MaybeTlsIncoming, TlsConfig, TlsSettings},
     topology::config::{DataType, GlobalOptions, SourceConfig},
 };
-use bytes::{Buf, Bytes};
+use bytes::Buf;
 use chrono::{DateTime, TimeZone, Utc};
 use flate2::read::GzDecoder;
-use futures01::{sync::mpsc, Async, Future, Sink, Stream};
+use futures01::{
+    future,
+    sync::{mpsc, oneshot},
+    Async, Future, Sink, Stream,
+};
 use hyper::{Body, Response, StatusCode};
 use lazy_static::lazy_static;
 use serde::{de, Deserialize, Serialize};
@@ -14,6 +21,7 @@ use serde_json::{de::IoRead, json, Deserializer, Value as JsonValue};
 use snafu::Snafu;
 use std::sync::{Arc, Mutex};
 use std::{
+    collections::HashMap,
     io::Read,
     net::{Ipv4Addr, SocketAddr},
 };
@@ -38,6 +46,9 @@ pub struct SplunkConfig {
     address: SocketAddr,
     /// Splunk HEC token
     token: Option<String>,
+    /// Splunk HEC tokens, with defaults for events sent with them
+    valid_tokens: Vec<TokenConfig>,
+    acknowledgements: AcknowledgementsConfig,
     tls: Option<TlsConfig>,
 }
 
@@ -46,11 +57,30 @@ impl Default for SplunkConfig {
         SplunkConfig {
             address: default_socket_address(),
             token: None,
+            valid_tokens: Vec::new(),
+            acknowledgements: AcknowledgementsConfig::default(),
             tls: None,
         }
     }
 }
 
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TokenConfig {
+    token: String,
+    /// Index of events which don't specify one
+    index: Option<String>,
+    /// Sourcetype of events which don't specify one
+    sourcetype: Option<String>,
+}
+
+/// Default field values for events sent with a token.
+#[derive(Clone, Debug, Default)]
+struct TokenDefaults {
+    index: Option<Value>,
+    sourcetype: Option<Value>,
+}
+
 fn default_socket_address() -> SocketAddr {
     SocketAddr::new(Ipv4Addr::new(0, 0, 0, 0).into(), 8088)
 }
@@ -69,6 +99,7 @@ impl SourceConfig for SplunkConfig {
 
         let event_service = SplunkSource::event_service(source.clone());
         let raw_service = SplunkSource::raw_service(source.clone());
+        let ack_service = SplunkSource::ack_service(source.clone());
         let health_service = SplunkSource::health_service(source);
         let options = SplunkSource::options();
 
@@ -77,6 +108,8 @@ impl SourceConfig for SplunkConfig {
                 event_service
                     .or(raw_service)
                     .unify()
+                    .or(ack_service)
+                    .unify()
                     .or(health_service)
                     .unify()
                     .or(options)
@@ -109,16 +142,38 @@ struct SplunkSource {
     /// Trigger for ending http server
     trigger: Arc<Mutex<Option<Trigger>>>,
 
-    credentials: Option<Bytes>,
+    /// Expected `Authorization` headers, if any, with defaults of their tokens
+    credentials: Arc<HashMap<String, TokenDefaults>>,
+    /// None if indexer acknowledgements are disabled
+    acknowledgements: Option<IndexerAcknowledgements>,
 }
 
 impl SplunkSource {
     fn new(config: &SplunkConfig, out: mpsc::Sender<Event>, trigger: Trigger) -> Self {
+        let mut credentials = HashMap::new();
+        if let Some(token) = config.token.as_ref() {
+            credentials.insert(format!("Splunk {}", token), TokenDefaults::default());
+        }
+        for token in config.valid_tokens.iter() {
+            credentials.insert(
+                format!("Splunk {}", token.token),
+                TokenDefaults {
+                    index: token.index.as_ref().map(|index| index.as_str().into()),
+                    sourcetype: token
+                        .sourcetype
+                        .as_ref()
+                        .map(|sourcetype| sourcetype.as_str().into()),
+                },
+            );
+        }
+
         SplunkSource {
-            credentials: config
-                .token
-                .as_ref()
-                .map(|token| format!("Splunk {}", token).into()),
+            credentials: Arc::new(credentials),
+            acknowledgements: if config.acknowledgements.enabled {
+                Some(IndexerAcknowledgements::new(&config.acknowledgements))
+            } else {
+                None
+            },
             out,
             trigger: Arc::new(Mutex::new(Some(trigger))),
         }
@@ -138,27 +193,47 @@ impl SplunkSource {
             .and(warp::body::concat())
             .and_then(
                 move |_,
-                      _,
+                      defaults: TokenDefaults,
                       channel: Option<String>,
                       host: Option<String>,
                       gzip: bool,
                       body: FullBody| {
+                    let ack = match source.ack(channel.as_ref()) {
+                        Ok(ack) => ack,
+                        Err(error) => {
+                            return Box::new(future::err::<Option<u64>, Rejection>(error.into()))
+                                as Box<dyn Future<Item = Option<u64>, Error = Rejection> + Send>
+                        }
+                    };
+
+                    let (batch, delivered) = batch_notifier(&ack);
+
                     // Construct event parser
-                    if gzip {
+                    let delivery = if gzip {
                         Box::new(
-                            EventStream::new(GzDecoder::new(body.reader()), channel, host)
-                                .forward(source.sink_with_shutdown())
-                                .map(|_| ()),
+                            EventStream::new(
+                                GzDecoder::new(body.reader()),
+                                channel,
+                                host,
+                                defaults,
+                            )
+                            .map(move |event| with_batch(event, &batch))
+                            .forward(source.sink_with_shutdown())
+                            .map(|_| ()),
                         )
                             as Box<dyn Future<Item = (), Error = Rejection> + Send>
                     } else {
                         Box::new(
-                            EventStream::new(body.reader(), channel, host)
+                            EventStream::new(body.reader(), channel, host, defaults)
+                                .map(move |event| with_batch(event, &batch))
                                 .forward(source.sink_with_shutdown())
                                 .map(|_| ()),
                         )
                             as Box<dyn Future<Item = (), Error = Rejection> + Send>
-                    }
+                    };
+
+                    Box::new(acknowledge(delivery, ack, delivered))
+                        as Box<dyn Future<Item = Option<u64>, Error = Rejection> + Send>
                 },
             )
             .map(finish_ok)
@@ -172,41 +247,73 @@ impl SplunkSource {
                     .or(path!("raw").and(warp::path::end())),
             )
             .and(source.authorization())
-            .and(
-                warp::header::optional::<String>("x-splunk-request-channel").and_then(
-                    |channel: Option<String>| {
-                        if let Some(channel) = channel {
-                            Ok(channel)
-                        } else {
-                            Err(Rejection::from(ApiError::MissingChannel))
-                        }
-                    },
-                ),
-            )
+            .and(required_channel())
             .and(warp::header::optional::<String>("host"))
             .and(source.gzip())
             .and(warp::body::concat())
             .and_then(
-                move |_, _, channel: String, host: Option<String>, gzip: bool, body: FullBody| {
+                move |_,
+                      defaults: TokenDefaults,
+                      channel: String,
+                      host: Option<String>,
+                      gzip: bool,
+                      body: FullBody| {
+                    let ack = match source.ack(Some(&channel)) {
+                        Ok(ack) => ack,
+                        Err(error) => {
+                            return future::Either::A(future::err::<Option<u64>, Rejection>(
+                                error.into(),
+                            ));
+                        }
+                    };
+
+                    let (batch, delivered) = batch_notifier(&ack);
+
                     // Construct event parser
-                    futures01::stream::once(raw_event(body, gzip, channel, host))
-                        .forward(source.sink_with_shutdown())
-                        .map(|_| ())
+                    let delivery =
+                        futures01::stream::once(raw_event(body, gzip, channel, host, &defaults))
+                            .map(move |event| with_batch(event, &batch))
+                            .forward(source.sink_with_shutdown())
+                            .map(|_| ());
+
+                    future::Either::B(acknowledge(delivery, ack, delivered))
                 },
             )
             .map(finish_ok)
             .boxed()
     }
 
+    fn ack_service(source: Arc<Self>) -> BoxedFilter<(Response<Body>,)> {
+        warp::post2()
+            .and(path!("ack").and(warp::path::end()))
+            .and(source.authorization())
+            .and(required_channel())
+            .and(warp::body::concat())
+            .and_then(move |_, channel: String, body: FullBody| {
+                let acknowledgements = source
+                    .acknowledgements
+                    .as_ref()
+                    .ok_or_else(|| Rejection::from(ApiError::AckIsDisabled))?;
+
+                let request =
+                    serde_json::from_reader::<_, AckRequest>(body.reader()).map_err(|error| {
+                        error!(message = "Malformed request body",%error);
+                        Rejection::from(ApiError::InvalidDataFormat { event: 0 })
+                    })?;
+
+                let acks = acknowledgements.query(&channel, &request.acks);
+                Ok::<_, Rejection>(response_json(StatusCode::OK, json!({ "acks": acks })))
+            })
+            .boxed()
+    }
+
     fn health_service(source: Arc<Self>) -> BoxedFilter<(Response<Body>,)> {
         let credentials = source.credentials.clone();
         let authorize =
             warp::header::optional("Authorization").and_then(move |token: Option<String>| {
-                match (token, credentials.as_ref()) {
-                    (_, None) => Ok(()),
-                    (Some(token), Some(password)) if token.as_bytes() == password.as_ref() => {
-                        Ok(())
-                    }
+                match token {
+                    _ if credentials.is_empty() => Ok(()),
+                    Some(ref token) if credentials.contains_key(token) => Ok(()),
                     _ => Err(Rejection::from(ApiError::BadRequest)),
                 }
             });
@@ -241,7 +348,8 @@ impl SplunkSource {
                     .or(path!("event").and(warp::path::end()))
                     .or(path!("event" / "1.0").and(warp::path::end()))
                     .or(path!("raw" / "1.0").and(warp::path::end()))
-                    .or(path!("raw").and(warp::path::end())),
+                    .or(path!("raw").and(warp::path::end()))
+                    .or(path!("ack").and(warp::path::end())),
             )
             .map(|_| warp::reply::with_header(warp::reply(), "Allow", "POST").into_response());
 
@@ -255,23 +363,30 @@ impl SplunkSource {
         post.or(get).unify().boxed()
     }
 
-    /// Authorize request
-    fn authorization(&self) -> BoxedFilter<((),)> {
+    /// Authorize request, and provide defaults of it's token
+    fn authorization(&self) -> BoxedFilter<(TokenDefaults,)> {
         let credentials = self.credentials.clone();
         warp::header::optional("Authorization")
-            .and_then(
-                move |token: Option<String>| match (token, credentials.as_ref()) {
-                    (_, None) => Ok(()),
-                    (Some(token), Some(password)) if token.as_bytes() == password.as_ref() => {
-                        Ok(())
-                    }
-                    (Some(_), Some(_)) => Err(Rejection::from(ApiError::InvalidAuthorization)),
-                    (None, Some(_)) => Err(Rejection::from(ApiError::MissingAuthorization)),
-                },
-            )
+            .and_then(move |token: Option<String>| match token {
+                _ if credentials.is_empty() => Ok(TokenDefaults::default()),
+                Some(token) => credentials
+                    .get(&token)
+                    .cloned()
+                    .ok_or_else(|| Rejection::from(ApiError::InvalidAuthorization)),
+                None => Err(Rejection::from(ApiError::MissingAuthorization)),
+            })
             .boxed()
     }
 
+    /// Assigns an ack ID to the request, if indexer acknowledgements are enabled
+    fn ack(&self, channel: Option<&String>) -> Result<Option<Ack>, ApiError> {
+        match (self.acknowledgements.as_ref(), channel) {
+            (None, _) => Ok(None),
+            (Some(acknowledgements), Some(channel)) => acknowledgements.create(channel).map(Some),
+            (Some(_), None) => Err(ApiError::MissingChannel),
+        }
+    }
+
     /// Is body encoded with gzip
     fn gzip(&self) -> BoxedFilter<(bool,)> {
         warp::header::optional::<String>("Content-Encoding")
@@ -302,6 +417,74 @@ impl SplunkSource {
     }
 }
 
+/// Channel is required by raw and ack endpoints
+fn required_channel() -> BoxedFilter<(String,)> {
+    warp::header::optional::<String>("x-splunk-request-channel")
+        .and_then(|channel: Option<String>| {
+            if let Some(channel) = channel {
+                Ok(channel)
+            } else {
+                Err(Rejection::from(ApiError::MissingChannel))
+            }
+        })
+        .boxed()
+}
+
+/// Batch shared by the events of a request with an ack ID
+fn batch_notifier(
+    ack: &Option<Ack>,
+) -> (
+    Option<Arc<BatchNotifier>>,
+    Option<oneshot::Receiver<BatchStatus>>,
+) {
+    match ack {
+        Some(_) => {
+            let (batch, receiver) = BatchNotifier::new_with_receiver();
+            (Some(batch), Some(receiver))
+        }
+        None => (None, None),
+    }
+}
+
+fn with_batch(mut event: Event, batch: &Option<Arc<BatchNotifier>>) -> Event {
+    if let Some(batch) = batch {
+        event.add_batch_notifier(Arc::clone(batch));
+    }
+    event
+}
+
+/// Replies with the ack ID once the events of the request are sent, and
+/// completes the ack once they have been delivered by the sinks.
+fn acknowledge(
+    delivery: impl Future<Item = (), Error = Rejection>,
+    ack: Option<Ack>,
+    delivered: Option<oneshot::Receiver<BatchStatus>>,
+) -> impl Future<Item = Option<u64>, Error = Rejection> {
+    delivery.then(move |result| match (result, ack, delivered) {
+        (Ok(()), Some(ack), Some(delivered)) => {
+            let id = ack.id();
+            tokio::spawn(delivered.then(move |status| {
+                ack.resolve(status.unwrap_or(BatchStatus::Failed));
+                Ok(())
+            }));
+            Ok(Some(id))
+        }
+        (Ok(()), _, _) => Ok(None),
+        (Err(error), ack, _) => {
+            if let Some(ack) = ack {
+                ack.fail();
+            }
+            Err(error)
+        }
+    })
+}
+
+/// Body of a request to the ack endpoint
+#[derive(Deserialize)]
+struct AckRequest {
+    acks: Vec<u64>,
+}
+
 /// Constructs one ore more events from json-s coming from reader.
 /// If errors, it's done with input.
 struct EventStream<R: Read> {
@@ -318,7 +501,12 @@ struct EventStream<R: Read> {
 }
 
 impl<R: Read> EventStream<R> {
-    fn new(data: R, channel: Option<String>, host: Option<String>) -> Self {
+    fn new(
+        data: R,
+        channel: Option<String>,
+        host: Option<String>,
+        defaults: TokenDefaults,
+    ) -> Self {
         EventStream {
             data,
             events: 0,
@@ -330,9 +518,9 @@ impl<R: Read> EventStream<R> {
                     &event::log_schema().host_key(),
                     host.map(|value| value.as_bytes().into()),
                 ),
-                DefaultExtractor::new("index", &INDEX),
+                DefaultExtractor::new_with("index", &INDEX, defaults.index),
                 DefaultExtractor::new("source", &SOURCE),
-                DefaultExtractor::new("sourcetype", &SOURCETYPE),
+                DefaultExtractor::new_with("sourcetype", &SOURCETYPE, defaults.sourcetype),
             ],
         }
     }
@@ -520,6 +708,7 @@ fn raw_event(
     gzip: bool,
     channel: String,
     host: Option<String>,
+    defaults: &TokenDefaults,
 ) -> Result<Event, Rejection> {
     // Process gzip
     let message: Value = if gzip {
@@ -551,6 +740,14 @@ fn raw_event(
         log.insert(event::log_schema().host_key().clone(), host.as_bytes());
     }
 
+    // Add defaults of the token
+    if let Some(index) = defaults.index.as_ref() {
+        log.insert(INDEX.clone(), index.clone());
+    }
+    if let Some(sourcetype) = defaults.sourcetype.as_ref() {
+        log.insert(SOURCETYPE.clone(), sourcetype.clone());
+    }
+
     // Add timestamp
     log.insert(event::log_schema().timestamp_key().clone(), Utc::now());
 
@@ -569,6 +766,8 @@ enum ApiError {
     EmptyEventField { event: usize },
     MissingEventField { event: usize },
     BadRequest,
+    ServerBusy,
+    AckIsDisabled,
 }
 
 impl From<ApiError> for Rejection {
@@ -602,11 +801,21 @@ mod splunk_response {
             json_to_bytes(json!({"text":"unsupported content encoding"}));
         pub static ref NO_CHANNEL: Bytes =
             json_to_bytes(json!({"text":"Data channel is missing","code":10}));
+        pub static ref SERVER_BUSY: Bytes =
+            json_to_bytes(json!({"text":"Server is busy","code":9}));
+        pub static ref ACK_IS_DISABLED: Bytes =
+            json_to_bytes(json!({"text":"ACK is disabled","code":14}));
     }
 }
 
-fn finish_ok(_: ()) -> Response<Body> {
-    response_json(StatusCode::OK, splunk_response::SUCCESS.as_ref())
+fn finish_ok(ack_id: Option<u64>) -> Response<Body> {
+    match ack_id {
+        Some(ack_id) => response_json(
+            StatusCode::OK,
+            json!({"text":"Success","code":0,"ackId":ack_id}),
+        ),
+        None => response_json(StatusCode::OK, splunk_response::SUCCESS.as_ref()),
+    }
 }
 
 fn finish_err(rejection: Rejection) -> Result<(Response<Body>,), Rejection> {
@@ -643,6 +852,14 @@ fn finish_err(rejection: Rejection) -> Result<(Response<Body>,), Rejection> {
                 event_error("Event field is required", 12, *event)
             }
             ApiError::BadRequest => empty_response(StatusCode::BAD_REQUEST),
+            ApiError::ServerBusy => response_json(
+                StatusCode::SERVICE_UNAVAILABLE,
+                splunk_response::SERVER_BUSY.as_ref(),
+            ),
+            ApiError::AckIsDisabled => response_json(
+                StatusCode::BAD_REQUEST,
+                splunk_response::ACK_IS_DISABLED.as_ref(),
+            ),
         },))
     } else {
         Err(rejection)
@@ -680,14 +897,14 @@ fn event_error(text: &str, code: u16, event: usize) -> Response<Body> {
     }
 }
 
-#[cfg(features = "sinks-splunk_hec")]
+#[cfg(feature = "sinks-splunk_hec")]
 #[cfg(test)]
 mod tests {
-    use super::SplunkConfig;
+    use super::{acknowledgements::AcknowledgementsConfig, SplunkConfig, TokenConfig};
     use crate::runtime::{Runtime, TaskExecutor};
     use crate::test_util::{self, collect_n};
     use crate::{
-        event::{self, Event},
+        event::{self, BatchStatus, Event},
         sinks::{
             splunk_hec::{Encoding, HecSinkConfig},
             util::{encoding::EncodingConfigWithDefault, Compression},
@@ -709,11 +926,24 @@ mod tests {
     }
 
     fn source_with(rt: &mut Runtime, token: Option<String>) -> (mpsc::Receiver<Event>, SocketAddr) {
+        source_with_config(
+            rt,
+            SplunkConfig {
+                token,
+                ..SplunkConfig::default()
+            },
+        )
+    }
+
+    fn source_with_config(
+        rt: &mut Runtime,
+        config: SplunkConfig,
+    ) -> (mpsc::Receiver<Event>, SocketAddr) {
         test_util::trace_init();
         let (sender, recv) = mpsc::channel(CHANNEL_CAPACITY);
         let address = test_util::next_addr();
         rt.spawn(
-            SplunkConfig { address, token }
+            SplunkConfig { address, ..config }
                 .build("default", &GlobalOptions::default(), sender)
                 .unwrap(),
         );
@@ -790,6 +1020,18 @@ mod tests {
             .as_u16()
     }
 
+    fn post_json(address: SocketAddr, api: &str, message: &str) -> serde_json::Value {
+        reqwest::Client::new()
+            .post(&format!("http://{}/{}", address, api))
+            .header("Authorization", format!("Splunk {}", TOKEN))
+            .header("x-splunk-request-channel", "guid")
+            .body(message.to_owned())
+            .send()
+            .unwrap()
+            .json()
+            .unwrap()
+    }
+
     #[test]
     fn no_compression_text_event() {
         let message = "gzip_text_event";
@@ -1032,4 +1274,118 @@ mod tests {
         );
         assert_eq!(events[2].as_log()[&super::SOURCE], "secondary".into());
     }
+
+    #[test]
+    fn token_defaults() {
+        let mut rt = test_util::runtime();
+        let (source, address) = source_with_config(
+            &mut rt,
+            SplunkConfig {
+                valid_tokens: vec![
+                    TokenConfig {
+                        token: TOKEN.to_owned(),
+                        index: Some("team".to_owned()),
+                        sourcetype: Some("nginx".to_owned()),
+                    },
+                    TokenConfig {
+                        token: "other".to_owned(),
+                        index: None,
+                        sourcetype: None,
+                    },
+                ],
+                ..SplunkConfig::default()
+            },
+        );
+
+        let message = r#"{"event":"first"}{"event":"second","index":"main"}"#;
+        assert_eq!(200, post(address, "services/collector/event", message));
+        assert_eq!(200, post(address, "services/collector/raw", "third"));
+
+        let events = rt.block_on(collect_n(source, 3)).unwrap();
+
+        assert_eq!(events[0].as_log()[&super::INDEX], "team".into());
+        assert_eq!(events[0].as_log()[&super::SOURCETYPE], "nginx".into());
+        assert_eq!(events[1].as_log()[&super::INDEX], "main".into());
+        assert_eq!(events[1].as_log()[&super::SOURCETYPE], "nginx".into());
+        assert_eq!(events[2].as_log()[&super::INDEX], "team".into());
+
+        assert_eq!(
+            200,
+            send_with(
+                address,
+                "services/collector/event",
+                Method::POST,
+                r#"{"event":"fourth"}"#,
+                "other"
+            )
+        );
+        assert_eq!(
+            401,
+            send_with(
+                address,
+                "services/collector/event",
+                Method::POST,
+                r#"{"event":"fifth"}"#,
+                "nope"
+            )
+        );
+    }
+
+    #[test]
+    fn indexer_acknowledgements() {
+        let mut rt = test_util::runtime();
+        let (source, address) = source_with_config(
+            &mut rt,
+            SplunkConfig {
+                token: Some(TOKEN.to_owned()),
+                acknowledgements: AcknowledgementsConfig {
+                    enabled: true,
+                    ..AcknowledgementsConfig::default()
+                },
+                ..SplunkConfig::default()
+            },
+        );
+
+        let response = post_json(address, "services/collector/event", r#"{"event":"first"}"#);
+        assert_eq!(response["ackId"], 0);
+        let response = post_json(address, "services/collector/raw", "second");
+        assert_eq!(response["ackId"], 1);
+        let response = post_json(address, "services/collector/raw", "third");
+        assert_eq!(response["ackId"], 2);
+
+        // Events which weren't delivered yet aren't acknowledged.
+        let response = post_json(address, "services/collector/ack", r#"{"acks":[0,1]}"#);
+        assert_eq!(
+            response,
+            serde_json::json!({"acks": {"0": false, "1": false}})
+        );
+
+        let mut events = rt.block_on(collect_n(source, 3)).unwrap();
+        events[2]
+            .take_finalizers()
+            .update_status(BatchStatus::Failed);
+        drop(events);
+        std::thread::sleep(std::time::Duration::from_millis(50));
+
+        let response = post_json(address, "services/collector/ack", r#"{"acks":[0,1,2,3]}"#);
+        assert_eq!(
+            response,
+            serde_json::json!({"acks": {"0": true, "1": true, "2": false, "3": false}})
+        );
+
+        // Acknowledged IDs are forgotten once queried.
+        let response = post_json(address, "services/collector/ack", r#"{"acks":[0]}"#);
+        assert_eq!(response, serde_json::json!({"acks": {"0": false}}));
+    }
+
+    #[test]
+    fn acknowledgements_disabled() {
+        let mut rt = test_util::runtime();
+        let (_source, address) = source(&mut rt);
+
+        assert_eq!(
+            400,
+            post(address, "services/collector/ack", r#"{"acks":[0]}"#)
+        );
+    }
 }
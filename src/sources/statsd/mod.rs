@@ -1,4 +1,4 @@
-use crate::{topology::config::GlobalOptions, Event};
+use crate::{listeners, topology::config::GlobalOptions, Event};
 use futures01::{future, sync::mpsc, Future, Sink, Stream};
 use parser::parse;
 use serde::{Deserialize, Serialize};
@@ -7,6 +7,7 @@ use tokio::{
     self,
     codec::BytesCodec,
     net::{UdpFramed, UdpSocket},
+    reactor::Handle,
 };
 use tracing::field;
 
@@ -25,7 +26,7 @@ impl crate::topology::config::SourceConfig for StatsdConfig {
         _globals: &GlobalOptions,
         out: mpsc::Sender<Event>,
     ) -> crate::Result<super::Source> {
-        Ok(statsd(self.address, out))
+        statsd(self.address, out)
     }
 
     fn output_type(&self) -> crate::topology::config::DataType {
@@ -37,12 +38,16 @@ impl crate::topology::config::SourceConfig for StatsdConfig {
     }
 }
 
-fn statsd(addr: SocketAddr, out: mpsc::Sender<Event>) -> super::Source {
+fn statsd(addr: SocketAddr, out: mpsc::Sender<Event>) -> crate::Result<super::Source> {
     let out = out.sink_map_err(|e| error!("error sending metric: {:?}", e));
+    let mut socket_use = listeners::udp(addr)?;
 
-    Box::new(
+    Ok(Box::new(
         future::lazy(move || {
-            let socket = UdpSocket::bind(&addr).expect("failed to bind to udp listener socket");
+            let socket = socket_use
+                .bind_udp()
+                .and_then(|socket| UdpSocket::from_std(socket, &Handle::default()))
+                .map_err(|error| error!(message = "failed to listen on udp socket.", %error))?;
 
             info!(
                 message = "listening.",
@@ -50,9 +55,9 @@ fn statsd(addr: SocketAddr, out: mpsc::Sender<Event>) -> super::Source {
                 r#type = "udp"
             );
 
-            future::ok(socket)
+            Ok((socket, socket_use))
         })
-        .and_then(|socket| {
+        .and_then(|(socket, socket_use)| {
             let metrics_in = UdpFramed::new(socket, BytesCodec::new())
                 .map(|(bytes, _sock)| {
                     let packet = String::from_utf8_lossy(bytes.as_ref());
@@ -67,9 +72,16 @@ fn statsd(addr: SocketAddr, out: mpsc::Sender<Event>) -> super::Source {
                 .flatten()
                 .map_err(|e| error!("error reading datagram: {:?}", e));
 
-            metrics_in.forward(out).map(|_| info!("finished sending"))
+            metrics_in
+                .forward(out)
+                .map(|_| info!("finished sending"))
+                // The socket is shared until the source stops.
+                .then(move |result| {
+                    drop(socket_use);
+                    result
+                })
         }),
-    )
+    ))
 }
 
 #[cfg(feature = "sinks-prometheus")]
@@ -3,6 +3,7 @@ use super::util::{SocketListenAddr, TcpSource};
 use crate::sources::util::build_unix_source;
 use crate::{
     event::{self, Event, Value},
+    listeners,
     tls::{TlsConfig, TlsSettings},
     topology::config::{DataType, GlobalOptions, SourceConfig, SourceDescription},
 };
@@ -20,6 +21,7 @@ use tokio::{
     self,
     codec::{BytesCodec, LinesCodec},
     net::{UdpFramed, UdpSocket},
+    reactor::Handle,
 };
 use tracing::field;
 
@@ -91,7 +93,7 @@ impl SourceConfig for SyslogConfig {
                 let tls = TlsSettings::from_config(&tls, true)?;
                 source.run(address, shutdown_secs, tls, out)
             }
-            Mode::Udp { address } => Ok(udp(address, self.max_length, host_key, out)),
+            Mode::Udp { address } => udp(address, self.max_length, host_key, out),
             #[cfg(unix)]
             Mode::Unix { path } => Ok(build_unix_source(
                 path,
@@ -142,12 +144,16 @@ pub fn udp(
     _max_length: usize,
     host_key: String,
     out: mpsc::Sender<Event>,
-) -> super::Source {
+) -> crate::Result<super::Source> {
     let out = out.sink_map_err(|e| error!("error sending line: {:?}", e));
+    let mut socket_use = listeners::udp(addr)?;
 
-    Box::new(
+    Ok(Box::new(
         future::lazy(move || {
-            let socket = UdpSocket::bind(&addr).expect("failed to bind to udp listener socket");
+            let socket = socket_use
+                .bind_udp()
+                .and_then(|socket| UdpSocket::from_std(socket, &Handle::default()))
+                .map_err(|error| error!(message = "failed to listen on udp socket.", %error))?;
 
             info!(
                 message = "listening.",
@@ -155,9 +161,9 @@ pub fn udp(
                 r#type = "udp"
             );
 
-            future::ok(socket)
+            Ok((socket, socket_use))
         })
-        .and_then(move |socket| {
+        .and_then(move |(socket, socket_use)| {
             let host_key = host_key.clone();
 
             let lines_in = UdpFramed::new(socket, BytesCodec::new())
@@ -171,9 +177,16 @@ pub fn udp(
                 })
                 .map_err(|e| error!("error reading line: {:?}", e));
 
-            lines_in.forward(out).map(|_| info!("finished sending"))
+            lines_in
+                .forward(out)
+                .map(|_| info!("finished sending"))
+                // The socket is shared until the source stops.
+                .then(move |result| {
+                    drop(socket_use);
+                    result
+                })
         }),
-    )
+    ))
 }
 
 /// Function used to resolve the year for syslog messages that don't include the year.
@@ -0,0 +1,266 @@
+//! Decoding of the requests Kinesis Firehose delivers to HTTP endpoints,
+//! and of the CloudWatch Logs subscription filter payloads they often carry.
+
+use super::{http::read_decompressed, ErrorMessage};
+use crate::event::{self, Event};
+use bytes::Bytes;
+use chrono::{DateTime, TimeZone, Utc};
+use flate2::read::MultiGzDecoder;
+use serde::{Deserialize, Serialize};
+use warp::http::{header, HeaderMap, HeaderValue, Response, StatusCode};
+
+pub const REQUEST_ID_HEADER: &str = "x-amz-firehose-request-id";
+pub const ACCESS_KEY_HEADER: &str = "x-amz-firehose-access-key";
+pub const SOURCE_ARN_HEADER: &str = "x-amz-firehose-source-arn";
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FirehoseRequest {
+    pub request_id: String,
+    pub timestamp: i64,
+    pub records: Vec<FirehoseRecord>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct FirehoseRecord {
+    data: String,
+}
+
+impl FirehoseRecord {
+    pub fn decode(&self) -> Result<Vec<u8>, ErrorMessage> {
+        base64::decode(&self.data).map_err(|error| {
+            ErrorMessage::new(
+                StatusCode::BAD_REQUEST,
+                format!("Invalid base64 in record data: {}", error),
+            )
+        })
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct FirehoseResponse<'a> {
+    request_id: &'a str,
+    timestamp: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_message: Option<&'a str>,
+}
+
+pub fn parse_request(body: &[u8]) -> Result<FirehoseRequest, ErrorMessage> {
+    serde_json::from_slice(body).map_err(|error| {
+        ErrorMessage::new(
+            StatusCode::BAD_REQUEST,
+            format!("Invalid Firehose request: {}", error),
+        )
+    })
+}
+
+/// Firehose only sends the access key if one is configured for the
+/// delivery stream, so requests without one are rejected if it's expected.
+pub fn check_access_key(expected: Option<&str>, headers: &HeaderMap) -> Result<(), ErrorMessage> {
+    match expected {
+        None => Ok(()),
+        Some(expected) => match headers.get(ACCESS_KEY_HEADER) {
+            Some(key) if key.as_bytes() == expected.as_bytes() => Ok(()),
+            _ => Err(ErrorMessage::new(
+                StatusCode::UNAUTHORIZED,
+                "Invalid access key".to_owned(),
+            )),
+        },
+    }
+}
+
+/// Builds the reply Firehose expects, echoing the id of the request.
+pub fn response(headers: &HeaderMap, result: Result<(), ErrorMessage>) -> Response<Vec<u8>> {
+    let request_id = headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|id| id.to_str().ok())
+        .unwrap_or_default();
+    let (status, error_message) = match &result {
+        Ok(()) => (StatusCode::OK, None),
+        Err(error) => (error.code(), Some(error.message())),
+    };
+    let body = serde_json::to_vec(&FirehoseResponse {
+        request_id,
+        timestamp: Utc::now().timestamp_millis(),
+        error_message,
+    })
+    .expect("Error encoding response as json.");
+
+    let mut response = Response::new(body);
+    *response.status_mut() = status;
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    response
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum MessageType {
+    DataMessage,
+    ControlMessage,
+}
+
+/// A batch of log events forwarded by a subscription filter.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct LogsMessage {
+    pub message_type: MessageType,
+    pub owner: String,
+    pub log_group: String,
+    pub log_stream: String,
+    pub subscription_filters: Vec<String>,
+    pub log_events: Vec<LogsEvent>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LogsEvent {
+    pub id: String,
+    pub timestamp: i64,
+    pub message: String,
+}
+
+/// Decodes the gzipped subscription filter messages in `data` into events.
+/// They may decompress to at most `MAX_DECOMPRESSED_SIZE` bytes.
+/// Control messages, sent to check the destination is reachable, are
+/// dropped.
+pub fn decode_subscription(data: &[u8]) -> Result<Vec<Event>, ErrorMessage> {
+    let json = read_decompressed(MultiGzDecoder::new(data)).map_err(|error| {
+        ErrorMessage::new(
+            StatusCode::BAD_REQUEST,
+            format!("Failed decompressing subscription payload: {}", error),
+        )
+    })?;
+
+    let mut events = Vec::new();
+    for message in serde_json::Deserializer::from_slice(&json).into_iter::<LogsMessage>() {
+        let message = message.map_err(|error| {
+            ErrorMessage::new(
+                StatusCode::BAD_REQUEST,
+                format!("Invalid subscription payload: {}", error),
+            )
+        })?;
+        if message.message_type != MessageType::DataMessage {
+            continue;
+        }
+
+        events.extend(message.log_events.into_iter().map(|log_event| {
+            logs_event(
+                &message.log_group,
+                &message.log_stream,
+                log_event.id,
+                log_event.timestamp,
+                log_event.message,
+            )
+        }));
+    }
+
+    Ok(events)
+}
+
+/// Builds the event for a CloudWatch Logs event, the same way whether it
+/// was polled or pushed by a subscription filter.
+pub fn logs_event(
+    group: &str,
+    stream: &str,
+    id: String,
+    timestamp_ms: i64,
+    message: String,
+) -> Event {
+    let mut event = Event::from(Bytes::from(message));
+    let log = event.as_mut_log();
+    log.insert(
+        event::log_schema().timestamp_key().clone(),
+        timestamp(timestamp_ms).unwrap_or_else(Utc::now),
+    );
+    log.insert("log_group", group.to_owned());
+    log.insert("log_stream", stream.to_owned());
+    log.insert("event_id", id);
+    event
+}
+
+/// Converts the milliseconds since the epoch AWS uses for timestamps.
+pub fn timestamp(ms: i64) -> Option<DateTime<Utc>> {
+    Utc.timestamp_opt(
+        ms.div_euclid(1000),
+        (ms.rem_euclid(1000) * 1_000_000) as u32,
+    )
+    .single()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn firehose_decodes_subscription_payload() {
+        let control = r#"{"messageType":"CONTROL_MESSAGE","owner":"CloudwatchLogs","logGroup":"","logStream":"","subscriptionFilters":[],"logEvents":[{"id":"","timestamp":1578090901599,"message":"CWL CONTROL MESSAGE: Checking health of destination Firehose."}]}"#;
+        let data = r#"{"messageType":"DATA_MESSAGE","owner":"071959437513","logGroup":"/jesse/test","logStream":"test","subscriptionFilters":["Destination"],"logEvents":[{"id":"35683658089614582423604394983260738922885519999578275840","timestamp":1600110569039,"message":"{\"bytes\":26780}"},{"id":"35683658089659183914001456229543810359430816722590236673","timestamp":1600110569041,"message":"second"}]}"#;
+        let payload = gzip(format!("{}{}", control, data).as_bytes());
+
+        let events = decode_subscription(&payload).unwrap();
+        assert_eq!(events.len(), 2);
+
+        let log = events[0].as_log();
+        assert_eq!(
+            log[&event::log_schema().message_key()],
+            r#"{"bytes":26780}"#.into()
+        );
+        assert_eq!(
+            log[&event::log_schema().timestamp_key()],
+            Utc.timestamp(1600110569, 39_000_000).into()
+        );
+        assert_eq!(log[&"log_group".into()], "/jesse/test".into());
+        assert_eq!(log[&"log_stream".into()], "test".into());
+        assert_eq!(
+            log[&"event_id".into()],
+            "35683658089614582423604394983260738922885519999578275840".into()
+        );
+        assert_eq!(
+            events[1].as_log()[&event::log_schema().message_key()],
+            "second".into()
+        );
+
+        assert!(decode_subscription(b"not gzip").is_err());
+    }
+
+    #[test]
+    fn firehose_checks_access_key() {
+        let mut headers = HeaderMap::new();
+        assert!(check_access_key(None, &headers).is_ok());
+        assert!(check_access_key(Some("secret"), &headers).is_err());
+        headers.insert(ACCESS_KEY_HEADER, "wrong".parse().unwrap());
+        assert!(check_access_key(Some("secret"), &headers).is_err());
+        headers.insert(ACCESS_KEY_HEADER, "secret".parse().unwrap());
+        assert!(check_access_key(Some("secret"), &headers).is_ok());
+    }
+
+    #[test]
+    fn firehose_response_echoes_request_id() {
+        let mut headers = HeaderMap::new();
+        headers.insert(REQUEST_ID_HEADER, "ed4acda5-034f".parse().unwrap());
+
+        let reply = response(&headers, Ok(()));
+        assert_eq!(reply.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(reply.body()).unwrap();
+        assert_eq!(body["requestId"], "ed4acda5-034f");
+        assert!(body["timestamp"].is_i64());
+        assert!(body.get("errorMessage").is_none());
+
+        let error = ErrorMessage::new(StatusCode::UNAUTHORIZED, "Invalid access key".into());
+        let reply = response(&headers, Err(error));
+        assert_eq!(reply.status(), StatusCode::UNAUTHORIZED);
+        let body: serde_json::Value = serde_json::from_slice(reply.body()).unwrap();
+        assert_eq!(body["errorMessage"], "Invalid access key");
+    }
+}
@@ -1,14 +1,22 @@
-use crate::event::Event;
+use crate::event::{BatchNotifier, BatchStatus, Event};
 use crate::tls::{MaybeTlsIncoming, TlsConfig, TlsSettings};
-use futures01::{sync::mpsc, Future, IntoFuture, Sink};
-use serde::Serialize;
+use bytes::{Buf, Bytes};
+use flate2::read::{MultiGzDecoder, ZlibDecoder};
+use futures01::{
+    future::{self, Either},
+    sync::mpsc,
+    Future, Sink,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::error::Error;
 use std::fmt::{self, Display};
+use std::io::{self, Read};
 use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
 use stream_cancel::Tripwire;
-use warp::filters::{body::FullBody, BoxedFilter};
-use warp::http::{HeaderMap, StatusCode};
+use warp::filters::{body::FullBody, path::FullPath};
+use warp::http::{header, HeaderMap, HeaderValue, Response, StatusCode};
 use warp::{Filter, Rejection};
 
 #[derive(Serialize, Debug)]
@@ -23,6 +31,14 @@ impl ErrorMessage {
             message,
         }
     }
+
+    pub fn code(&self) -> StatusCode {
+        StatusCode::from_u16(self.code).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
 }
 impl Error for ErrorMessage {}
 impl Display for ErrorMessage {
@@ -31,71 +47,163 @@ impl Display for ErrorMessage {
     }
 }
 
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(deny_unknown_fields, rename_all = "snake_case", tag = "strategy")]
+pub enum HttpSourceAuthConfig {
+    Basic { user: String, password: String },
+    Bearer { token: String },
+}
+
+impl HttpSourceAuthConfig {
+    /// Expected value of the `Authorization` header.
+    fn header_value(&self) -> String {
+        match self {
+            HttpSourceAuthConfig::Basic { user, password } => format!(
+                "Basic {}",
+                base64::encode(&format!("{}:{}", user, password))
+            ),
+            HttpSourceAuthConfig::Bearer { token } => format!("Bearer {}", token),
+        }
+    }
+}
+
 pub trait HttpSource: Clone + Send + Sync + 'static {
     fn build_event(
         &self,
-        body: FullBody,
+        body: Bytes,
         header_map: HeaderMap,
+        query_parameters: HashMap<String, String>,
+        path: &str,
     ) -> Result<Vec<Event>, ErrorMessage>;
 
+    /// Builds the reply to a request once its events have been forwarded,
+    /// or it failed. Errors are replied to as JSON by default.
+    fn build_response(
+        &self,
+        _header_map: &HeaderMap,
+        result: Result<(), ErrorMessage>,
+    ) -> Response<Vec<u8>> {
+        match result {
+            Ok(()) => Response::new(Vec::new()),
+            Err(error) => {
+                let body = serde_json::to_vec(&error).expect("Error encoding error as json.");
+                let mut response = Response::new(body);
+                *response.status_mut() = error.code();
+                response.headers_mut().insert(
+                    header::CONTENT_TYPE,
+                    HeaderValue::from_static("application/json"),
+                );
+                response
+            }
+        }
+    }
+
     fn run(
         self,
         address: SocketAddr,
-        path: &'static str,
+        path: &str,
+        strict_path: bool,
         tls: &Option<TlsConfig>,
+        auth: &Option<HttpSourceAuthConfig>,
+        acknowledgements: bool,
         out: mpsc::Sender<Event>,
     ) -> crate::Result<crate::sources::Source> {
         let (trigger, tripwire) = Tripwire::new();
         let trigger = Arc::new(Mutex::new(Some(trigger)));
 
-        let mut filter: BoxedFilter<()> = warp::post2().boxed();
-        if !path.is_empty() && path != "/" {
-            for s in path.split('/') {
-                filter = filter.and(warp::path(s)).boxed();
-            }
-        }
-        let svc = filter
-            .and(warp::path::end())
+        let path = normalize_path(path);
+        let authorization = auth.as_ref().map(HttpSourceAuthConfig::header_value);
+
+        let svc = warp::post2()
+            .and(warp::path::full())
             .and(warp::header::headers_cloned())
+            .and(warp::query::raw().or(warp::any().map(String::new)).unify())
             .and(warp::body::concat())
-            .and_then(move |headers: HeaderMap, body| {
-                let out = out.clone();
-                let trigger = trigger.clone();
-                info!("Handling http request: {:?}", headers);
-
-                self.build_event(body, headers)
-                    .map_err(warp::reject::custom)
-                    .into_future()
-                    .and_then(|events| {
-                        out.send_all(futures01::stream::iter_ok(events)).map_err(
-                            move |e: mpsc::SendError<Event>| {
-                                //can only fail if receiving end disconnected, so shut down and make some error logs
-                                error!("Failed to forward events, downstream is closed");
-                                error!("Tried to send the following event: {:?}", e);
-                                error!("Shutting down");
-
-                                trigger.try_lock().ok().take().map(drop); // shut down the http server if someone hasn't already
-                                warp::reject::custom("shutting down")
-                            },
-                        )
-                    })
-                    .map(|_| warp::reply())
-            });
+            .and_then(
+                move |full_path: FullPath, headers: HeaderMap, query: String, body: FullBody| {
+                    let out = out.clone();
+                    let trigger = trigger.clone();
+                    info!("Handling http request: {:?}", headers);
+
+                    let request_path = full_path.as_str();
+                    let events = if !path_matches(&path, request_path, strict_path) {
+                        Err(ErrorMessage::new(
+                            StatusCode::NOT_FOUND,
+                            format!("Path not found: {}", request_path),
+                        ))
+                    } else if !authorized(authorization.as_ref(), &headers) {
+                        Err(ErrorMessage::new(
+                            StatusCode::UNAUTHORIZED,
+                            "Invalid authorization".to_owned(),
+                        ))
+                    } else {
+                        decompress(&headers, body.bytes().into()).and_then(|body| {
+                            self.build_event(
+                                body,
+                                headers.clone(),
+                                parse_query(&query),
+                                request_path,
+                            )
+                        })
+                    };
+
+                    match events {
+                        Ok(mut events) => {
+                            // With acknowledgements, the request is replied
+                            // to once its events have been delivered.
+                            let delivered = if acknowledgements {
+                                let (batch, receiver) = BatchNotifier::new_with_receiver();
+                                for event in &mut events {
+                                    event.add_batch_notifier(Arc::clone(&batch));
+                                }
+                                Some(receiver)
+                            } else {
+                                None
+                            };
+
+                            let source = self.clone();
+                            Either::A(
+                                out.send_all(futures01::stream::iter_ok(events))
+                                    .map_err(move |e: mpsc::SendError<Event>| {
+                                        //can only fail if receiving end disconnected, so shut down and make some error logs
+                                        error!("Failed to forward events, downstream is closed");
+                                        error!("Tried to send the following event: {:?}", e);
+                                        error!("Shutting down");
+
+                                        trigger.try_lock().ok().take().map(drop); // shut down the http server if someone hasn't already
+                                        warp::reject::custom("shutting down")
+                                    })
+                                    .and_then(move |_| match delivered {
+                                        None => Either::A(future::ok(
+                                            source.build_response(&headers, Ok(())),
+                                        )),
+                                        Some(delivered) => {
+                                            Either::B(delivered.then(move |status| {
+                                                let result = match status {
+                                                    Ok(BatchStatus::Delivered) => Ok(()),
+                                                    Ok(BatchStatus::Failed) | Err(_) => {
+                                                        Err(ErrorMessage::new(
+                                                            StatusCode::SERVICE_UNAVAILABLE,
+                                                            "Events could not be delivered."
+                                                                .to_owned(),
+                                                        ))
+                                                    }
+                                                };
+                                                Ok(source.build_response(&headers, result))
+                                            }))
+                                        }
+                                    }),
+                            )
+                        }
+                        Err(error) => Either::B(future::ok::<_, Rejection>(
+                            self.build_response(&headers, Err(error)),
+                        )),
+                    }
+                },
+            );
 
         let ping = warp::get2().and(warp::path("ping")).map(|| "pong");
-        let routes = svc.or(ping).recover(|r: Rejection| {
-            if let Some(e_msg) = r.find_cause::<ErrorMessage>() {
-                let json = warp::reply::json(e_msg);
-                Ok(warp::reply::with_status(
-                    json,
-                    StatusCode::from_u16(e_msg.code).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
-                ))
-            } else {
-                //other internal error - will return 500 internal server error
-                Err(r)
-            }
-            .into_future()
-        });
+        let routes = svc.or(ping);
 
         info!(message = "building http server", addr = %address);
 
@@ -107,3 +215,162 @@ pub trait HttpSource: Clone + Send + Sync + 'static {
         Ok(Box::new(server))
     }
 }
+
+/// Ensures the path starts, but doesn't end, with `/`.
+fn normalize_path(path: &str) -> String {
+    format!("/{}", path.trim_matches('/'))
+}
+
+/// If not strict, any path under the configured one matches.
+fn path_matches(path: &str, request_path: &str, strict_path: bool) -> bool {
+    let request_path = normalize_path(request_path);
+    if strict_path {
+        request_path == path
+    } else {
+        path == "/"
+            || request_path == path
+            || (request_path.starts_with(path) && request_path[path.len()..].starts_with('/'))
+    }
+}
+
+/// The header is compared in constant time, so the expected value can't be
+/// guessed from how long requests take to be rejected.
+fn authorized(authorization: Option<&String>, headers: &HeaderMap) -> bool {
+    match authorization {
+        None => true,
+        Some(expected) => headers.get(header::AUTHORIZATION).map_or(false, |value| {
+            value.len() == expected.len()
+                && openssl::memcmp::eq(value.as_bytes(), expected.as_bytes())
+        }),
+    }
+}
+
+fn parse_query(query: &str) -> HashMap<String, String> {
+    url::form_urlencoded::parse(query.as_bytes())
+        .into_owned()
+        .collect()
+}
+
+/// Decodes the body according to the `Content-Encoding` header,
+/// in reverse order of the listed encodings.
+fn decompress(headers: &HeaderMap, mut body: Bytes) -> Result<Bytes, ErrorMessage> {
+    let encodings = match headers.get(header::CONTENT_ENCODING) {
+        Some(value) => value.to_str().map_err(|_| {
+            ErrorMessage::new(
+                StatusCode::BAD_REQUEST,
+                "Invalid Content-Encoding header".to_owned(),
+            )
+        })?,
+        None => return Ok(body),
+    };
+
+    for encoding in encodings.rsplit(',').map(str::trim) {
+        let decoded = match encoding {
+            "identity" | "" => continue,
+            "gzip" | "x-gzip" => read_decompressed(MultiGzDecoder::new(&body[..])),
+            "deflate" => read_decompressed(ZlibDecoder::new(&body[..])),
+            "zstd" => zstd::stream::read::Decoder::new(&body[..]).and_then(read_decompressed),
+            encoding => {
+                return Err(ErrorMessage::new(
+                    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    format!("Unsupported encoding {}", encoding),
+                ))
+            }
+        };
+        body = decoded
+            .map_err(|error| {
+                ErrorMessage::new(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    format!(
+                        "Failed decompressing payload with {} decoder: {}",
+                        encoding, error
+                    ),
+                )
+            })?
+            .into();
+    }
+
+    Ok(body)
+}
+
+/// Decompressed payloads larger than this are rejected, so a small
+/// compressed request can't exhaust memory.
+pub const MAX_DECOMPRESSED_SIZE: u64 = 100 * 1024 * 1024;
+
+/// Reads the output of a decoder, failing once it's larger than
+/// `MAX_DECOMPRESSED_SIZE`.
+pub fn read_decompressed(decoder: impl Read) -> io::Result<Vec<u8>> {
+    let mut decoded = Vec::new();
+    decoder
+        .take(MAX_DECOMPRESSED_SIZE + 1)
+        .read_to_end(&mut decoded)?;
+    if decoded.len() as u64 > MAX_DECOMPRESSED_SIZE {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "decompressed payload is larger than {} bytes",
+                MAX_DECOMPRESSED_SIZE
+            ),
+        ))
+    } else {
+        Ok(decoded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+
+    #[test]
+    fn http_source_path_matches() {
+        assert!(path_matches("/", "/", true));
+        assert!(path_matches("/", "", true));
+        assert!(!path_matches("/", "/events", true));
+        assert!(path_matches("/", "/events", false));
+        assert!(path_matches("/events", "/events/", true));
+        assert!(path_matches("/events", "/events/github", false));
+        assert!(!path_matches("/events", "/events/github", true));
+        assert!(!path_matches("/events", "/eventsource", false));
+    }
+
+    #[test]
+    fn http_source_auth_header() {
+        let basic = HttpSourceAuthConfig::Basic {
+            user: "user".to_owned(),
+            password: "password".to_owned(),
+        };
+        assert_eq!(basic.header_value(), "Basic dXNlcjpwYXNzd29yZA==");
+
+        let mut headers = HeaderMap::new();
+        assert!(authorized(None, &headers));
+        assert!(!authorized(Some(&basic.header_value()), &headers));
+        headers.insert(header::AUTHORIZATION, "Bearer secret".parse().unwrap());
+        let bearer = HttpSourceAuthConfig::Bearer {
+            token: "secret".to_owned(),
+        };
+        assert!(authorized(Some(&bearer.header_value()), &headers));
+        assert!(!authorized(Some(&basic.header_value()), &headers));
+        assert!(!authorized(Some(&"Bearer secreT".to_owned()), &headers));
+    }
+
+    #[test]
+    fn http_source_decompression_limit() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_ENCODING, "gzip".parse().unwrap());
+
+        let chunk = vec![0; 1024 * 1024];
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(&chunk).unwrap();
+        let body = decompress(&headers, encoder.finish().unwrap().into()).unwrap();
+        assert_eq!(body.len(), chunk.len());
+
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        for _ in 0..=MAX_DECOMPRESSED_SIZE / chunk.len() as u64 {
+            encoder.write_all(&chunk).unwrap();
+        }
+        let error = decompress(&headers, encoder.finish().unwrap().into()).unwrap_err();
+        assert_eq!(error.code(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+}
@@ -1,12 +1,25 @@
-#[cfg(feature = "sources-http")]
+#[cfg(any(
+    feature = "sources-aws_cloudwatch_logs",
+    feature = "sources-aws_kinesis_firehose"
+))]
+pub mod firehose;
+#[cfg(any(
+    feature = "sources-aws_cloudwatch_logs",
+    feature = "sources-aws_kinesis_firehose",
+    feature = "sources-http"
+))]
 mod http;
 #[cfg(feature = "sources-socket")]
 mod tcp;
 #[cfg(all(unix, feature = "sources-socket"))]
 mod unix;
 
-#[cfg(feature = "sources-http")]
-pub use self::http::{ErrorMessage, HttpSource};
+#[cfg(any(
+    feature = "sources-aws_cloudwatch_logs",
+    feature = "sources-aws_kinesis_firehose",
+    feature = "sources-http"
+))]
+pub use self::http::{ErrorMessage, HttpSource, HttpSourceAuthConfig};
 #[cfg(feature = "sources-socket")]
 pub use tcp::{SocketListenAddr, TcpSource};
 
@@ -1,4 +1,4 @@
-use crate::{tls::TlsSettings, Event};
+use crate::{listeners, metrics, tls::TlsSettings, Event};
 use bytes::Bytes;
 use futures01::{future, sync::mpsc, Future, Sink, Stream};
 use listenfd::ListenFd;
@@ -42,20 +42,32 @@ pub trait TcpSource: Clone + Send + 'static {
 
         let mut listenfd = ListenFd::from_env();
 
+        // Taken while building the source, so that a reload shares the
+        // socket of the source's previous instance.
+        let mut listener_use = match addr {
+            SocketListenAddr::SocketAddr(addr) => Some(listeners::tcp(addr)?),
+            SocketListenAddr::SystemdFd(_) => None,
+        };
+
         let source = future::lazy(move || {
-            let listener = match addr {
-                SocketListenAddr::SocketAddr(addr) => TcpListener::bind(&addr),
-                SocketListenAddr::SystemdFd(offset) => match listenfd.take_tcp_listener(offset) {
-                    Ok(Some(listener)) => TcpListener::from_std(listener, &Handle::default()),
-                    Ok(None) => {
-                        error!("Failed to take listen FD, not open or already taken");
-                        return future::Either::B(future::err(()));
-                    }
-                    Err(err) => {
-                        error!("Failed to take listen FD: {}", err);
-                        return future::Either::B(future::err(()));
+            let listener = match (addr, &mut listener_use) {
+                (_, Some(listener_use)) => listener_use
+                    .bind_tcp()
+                    .and_then(|listener| TcpListener::from_std(listener, &Handle::default())),
+                (SocketListenAddr::SystemdFd(offset), None) => {
+                    match listenfd.take_tcp_listener(offset) {
+                        Ok(Some(listener)) => TcpListener::from_std(listener, &Handle::default()),
+                        Ok(None) => {
+                            error!("Failed to take listen FD, not open or already taken");
+                            return future::Either::B(future::err(()));
+                        }
+                        Err(err) => {
+                            error!("Failed to take listen FD: {}", err);
+                            return future::Either::B(future::err(()));
+                        }
                     }
-                },
+                }
+                (SocketListenAddr::SocketAddr(_), None) => unreachable!(),
             };
             let listener = match listener {
                 Ok(listener) => listener,
@@ -126,7 +138,12 @@ pub trait TcpSource: Clone + Send + 'static {
                     });
                     Ok(())
                 })
-                .inspect(|_| trigger.cancel());
+                .inspect(|_| trigger.cancel())
+                // The socket is shared until the source stops.
+                .then(move |result| {
+                    drop(listener_use);
+                    result
+                });
             future::Either::A(future)
         });
 
@@ -155,7 +172,7 @@ fn accept_socket(
                     .map_err(|error| warn!(message = "TLS connection accept error.", %error))
                     .map(|socket| handle_stream(inner_span, socket, source, tripwire, host, out));
 
-                tokio::spawn(handler.instrument(span.clone()));
+                tokio::spawn(metrics::in_current(handler).instrument(span.clone()));
             }
         },
         None => handle_stream(span, socket, source, tripwire, host, out),
@@ -170,18 +187,34 @@ fn handle_stream(
     host: Option<Bytes>,
     out: impl Sink<SinkItem = Event, SinkError = ()> + Send + 'static,
 ) {
-    let handler = FramedRead::new(socket, source.decoder())
+    let handler = FramedRead::new(CountingRead(socket), source.decoder())
         .take_until(tripwire)
         .filter_map(move |frame| {
             let host = host.clone();
             source.build_event(frame, host)
         })
-        .map_err(|error| warn!(message = "connection error.", %error))
+        .map_err(|error| {
+            warn!(message = "connection error.", %error);
+            metrics::with_current(|metrics| metrics.error("connection_failed"));
+        })
         .forward(out)
         .map(|_| debug!("connection closed."));
-    tokio::spawn(handler.instrument(span));
+    tokio::spawn(metrics::in_current(handler).instrument(span));
 }
 
+/// Counts the bytes read from a connection in `bytes_in_total`.
+struct CountingRead<R>(R);
+
+impl<R: io::Read> io::Read for CountingRead<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.0.read(buf)?;
+        metrics::with_current(|metrics| metrics.counter("bytes_in_total").add(read as u64));
+        Ok(read)
+    }
+}
+
+impl<R: AsyncRead> AsyncRead for CountingRead<R> {}
+
 #[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
 #[serde(untagged)]
 pub enum SocketListenAddr {
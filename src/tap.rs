@@ -0,0 +1,186 @@
+use crate::runtime;
+use futures01::{future::Either, Future, Stream};
+use hyper::{header::CONTENT_TYPE, Body, Client, Request};
+use serde_json::json;
+use std::{
+    io::{self, Write},
+    net::SocketAddr,
+};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub struct Opts {
+    /// The source or transform whose output is shown.
+    component: String,
+
+    /// The address of the running Vector's API, as given to `--api-addr`.
+    #[structopt(long, default_value = "127.0.0.1:8686")]
+    api_addr: SocketAddr,
+
+    /// Show one in this many events.
+    #[structopt(long, default_value = "1")]
+    sample_rate: u64,
+
+    /// The most events shown each second.
+    #[structopt(long, default_value = "100")]
+    rate_limit: u64,
+
+    /// Only show the events matching a `check_fields` predicate, such as
+    /// `message.eq=foo`. Repeat to require more predicates.
+    #[structopt(long = "where", short = "w")]
+    predicates: Vec<String>,
+
+    /// Show events as JSON, or logs by their message.
+    ///
+    /// Options: `json`, `text`
+    #[structopt(long, default_value = "json")]
+    format: Format,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Format {
+    Json,
+    Text,
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Format::Json),
+            "text" => Ok(Format::Text),
+            s => Err(format!(
+                "{} is not a valid option, expected `json` or `text`",
+                s
+            )),
+        }
+    }
+}
+
+pub fn cmd(opts: &Opts) -> exitcode::ExitCode {
+    let body = match request_body(opts) {
+        Ok(body) => body,
+        Err(error) => {
+            eprintln!("{}", error);
+            return exitcode::USAGE;
+        }
+    };
+
+    let uri = format!("http://{}/tap/{}", opts.api_addr, opts.component);
+    let request = Request::post(uri.as_str())
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+
+    let mut rt = runtime::Runtime::new().unwrap();
+    let api_addr = opts.api_addr;
+    let tap = Client::new()
+        .request(request)
+        .map_err(move |error| {
+            eprintln!("Unable to reach Vector's API at {}: {}", api_addr, error);
+            exitcode::UNAVAILABLE
+        })
+        .and_then(|response| {
+            let status = response.status();
+            let body = response.into_body();
+            if status.is_success() {
+                // Stop once stdout is closed, like when piped to `head`.
+                // Vector drops the tap along with the connection.
+                let mut stdout = io::stdout();
+                Either::A(
+                    body.take_while(move |chunk| {
+                        Ok(stdout.write_all(chunk).and_then(|_| stdout.flush()).is_ok())
+                    })
+                    .for_each(|_| Ok(()))
+                    .then(|_| Ok(exitcode::OK)),
+                )
+            } else {
+                Either::B(body.concat2().then(move |body| {
+                    let error = body
+                        .ok()
+                        .and_then(|body| serde_json::from_slice::<serde_json::Value>(&body).ok())
+                        .and_then(|body| body["error"].as_str().map(str::to_owned))
+                        .unwrap_or_else(|| status.to_string());
+                    eprintln!("{}", error);
+                    Err(if status.is_client_error() {
+                        exitcode::USAGE
+                    } else {
+                        exitcode::UNAVAILABLE
+                    })
+                }))
+            }
+        });
+
+    rt.block_on(tap).unwrap_or_else(|code| code)
+}
+
+/// Builds the options sent to the API, turning the predicates into a
+/// `check_fields` condition.
+fn request_body(opts: &Opts) -> Result<serde_json::Value, String> {
+    let mut predicates = serde_json::Map::new();
+    for predicate in &opts.predicates {
+        let mut parts = predicate.splitn(2, '=');
+        let (target, value) = match (parts.next(), parts.next()) {
+            (Some(target), Some(value)) if !target.is_empty() => (target, value),
+            _ => {
+                return Err(format!(
+                    "{:?} is not a valid predicate, expected `<field>.<predicate>=<value>`",
+                    predicate
+                ))
+            }
+        };
+        // Values like `true` and `5` keep their type, anything else is a string.
+        let value = value
+            .parse::<serde_json::Value>()
+            .unwrap_or_else(|_| value.into());
+        predicates.insert(target.to_owned(), value);
+    }
+
+    let condition = if predicates.is_empty() {
+        serde_json::Value::Null
+    } else {
+        predicates.insert("type".into(), "check_fields".into());
+        predicates.into()
+    };
+
+    let format = match opts.format {
+        Format::Json => "json",
+        Format::Text => "text",
+    };
+
+    Ok(json!({
+        "sample_rate": opts.sample_rate,
+        "rate_limit": opts.rate_limit,
+        "format": format,
+        "condition": condition
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tap_builds_condition_from_predicates() {
+        let opts = Opts::from_iter(&[
+            "tap",
+            "parse",
+            "--where",
+            "message.eq=a=b",
+            "-w",
+            "host.exists=true",
+        ]);
+
+        let body = request_body(&opts).unwrap();
+        assert_eq!(body["format"], "json");
+        assert_eq!(
+            body["condition"],
+            json!({ "type": "check_fields", "message.eq": "a=b", "host.exists": true })
+        );
+
+        let opts = Opts::from_iter(&["tap", "parse", "--where", "message"]);
+        assert!(request_body(&opts).is_err());
+    }
+}
@@ -16,9 +16,11 @@ use std::io::{self, Read, Write};
 use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 use tokio::io::{AsyncRead, AsyncWrite};
-use tokio::net::tcp::Incoming;
 #[cfg(feature = "sources-tls")]
-use tokio::net::TcpListener;
+use tokio::{
+    net::{tcp::Incoming, TcpListener, TcpStream},
+    reactor::Handle,
+};
 use tokio_tls::TlsStream;
 
 #[derive(Debug, Snafu)]
@@ -299,16 +301,42 @@ impl<I: Stream> MaybeTlsIncoming<I> {
     }
 }
 
-impl MaybeTlsIncoming<Incoming> {
-    #[cfg(feature = "sources-tls")]
+#[cfg(feature = "sources-tls")]
+impl MaybeTlsIncoming<SharedIncoming> {
     pub fn bind(addr: &SocketAddr, tls: Option<TlsSettings>) -> crate::Result<Self> {
-        let listener = TcpListener::bind(addr)?;
-        let incoming = listener.incoming();
+        let incoming = SharedIncoming {
+            listener: crate::listeners::tcp(*addr)?,
+            incoming: None,
+        };
 
         MaybeTlsIncoming::new(incoming, tls)
     }
 }
 
+/// The connections to a listener shared with other instances of the source,
+/// bound once first polled.
+#[cfg(feature = "sources-tls")]
+#[derive(Debug)]
+pub struct SharedIncoming {
+    listener: crate::listeners::Listener,
+    incoming: Option<Incoming>,
+}
+
+#[cfg(feature = "sources-tls")]
+impl Stream for SharedIncoming {
+    type Item = TcpStream;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if self.incoming.is_none() {
+            let listener = self.listener.bind_tcp()?;
+            let listener = TcpListener::from_std(listener, &Handle::default())?;
+            self.incoming = Some(listener.incoming());
+        }
+        self.incoming.as_mut().unwrap().poll()
+    }
+}
+
 impl<I> Stream for MaybeTlsIncoming<I>
 where
     I: Stream,
@@ -0,0 +1,355 @@
+use crate::runtime;
+use futures01::{Future, Stream};
+use hyper::{Client, Uri};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    io::{self, Write},
+    net::SocketAddr,
+    thread,
+    time::{Duration, Instant},
+};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub struct Opts {
+    /// The address of the running Vector's API, as given to `--api-addr`.
+    #[structopt(long, default_value = "127.0.0.1:8686")]
+    api_addr: SocketAddr,
+
+    /// How often the table is refreshed, in milliseconds.
+    #[structopt(short, long, default_value = "1000")]
+    interval: u64,
+
+    /// The column the components are sorted by. Figures are sorted from
+    /// the highest.
+    ///
+    /// Options: `name`, `kind`, `events-in`, `events-out`, `bytes-in`,
+    /// `bytes-out`, `errors`, `buffer`
+    #[structopt(short, long, default_value = "name")]
+    sort: Sort,
+
+    /// Only show the components whose name or type contains this text.
+    #[structopt(short, long)]
+    filter: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Sort {
+    Name,
+    Kind,
+    EventsIn,
+    EventsOut,
+    BytesIn,
+    BytesOut,
+    Errors,
+    Buffer,
+}
+
+impl std::str::FromStr for Sort {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "name" => Ok(Sort::Name),
+            "kind" => Ok(Sort::Kind),
+            "events-in" => Ok(Sort::EventsIn),
+            "events-out" => Ok(Sort::EventsOut),
+            "bytes-in" => Ok(Sort::BytesIn),
+            "bytes-out" => Ok(Sort::BytesOut),
+            "errors" => Ok(Sort::Errors),
+            "buffer" => Ok(Sort::Buffer),
+            s => Err(format!("{} is not a column that can be sorted by", s)),
+        }
+    }
+}
+
+/// A component, as returned by the API's `/components` endpoint.
+#[derive(Clone, Debug, Deserialize)]
+struct Component {
+    name: String,
+    kind: String,
+    #[serde(rename = "type")]
+    component_type: String,
+    state: String,
+    metrics: HashMap<String, f64>,
+}
+
+#[derive(Deserialize)]
+struct Components {
+    components: Vec<Component>,
+}
+
+/// A line of the table. Rates are per second, and unknown until the
+/// components have been fetched twice.
+#[derive(Debug)]
+struct Row {
+    name: String,
+    kind: String,
+    component_type: String,
+    state: String,
+    events_in: Option<f64>,
+    events_out: Option<f64>,
+    bytes_in: Option<f64>,
+    bytes_out: Option<f64>,
+    errors: Option<f64>,
+    buffer: Option<f64>,
+}
+
+pub fn cmd(opts: &Opts) -> exitcode::ExitCode {
+    let mut rt = runtime::Runtime::new().unwrap();
+    let client = Client::new();
+    let uri = format!("http://{}/components", opts.api_addr)
+        .parse::<Uri>()
+        .unwrap();
+    let interval = Duration::from_millis(opts.interval.max(100));
+
+    let mut previous = None;
+    loop {
+        let fetch = client
+            .get(uri.clone())
+            .and_then(|response| response.into_body().concat2())
+            .map_err(|error| error.to_string())
+            .and_then(|body| {
+                serde_json::from_slice::<Components>(&body).map_err(|error| error.to_string())
+            });
+        let components = match rt.block_on(fetch) {
+            Ok(components) => components.components,
+            Err(error) => {
+                eprintln!(
+                    "Unable to reach Vector's API at {}: {}",
+                    opts.api_addr, error
+                );
+                return exitcode::UNAVAILABLE;
+            }
+        };
+        let now = Instant::now();
+
+        let elapsed = previous
+            .as_ref()
+            .map(|(at, previous): &(Instant, Vec<Component>)| (previous, now - *at));
+        let rows = rows(&components, elapsed, opts);
+
+        // Clear the terminal before drawing the table again.
+        let mut stdout = io::stdout();
+        let drawn =
+            write!(stdout, "\x1B[2J\x1B[H{}", render(&rows, opts)).and_then(|_| stdout.flush());
+        if drawn.is_err() {
+            return exitcode::OK;
+        }
+
+        previous = Some((now, components));
+        thread::sleep(interval);
+    }
+}
+
+fn rows(
+    components: &[Component],
+    previous: Option<(&Vec<Component>, Duration)>,
+    opts: &Opts,
+) -> Vec<Row> {
+    let mut rows = components
+        .iter()
+        .filter(|component| match &opts.filter {
+            Some(filter) => {
+                component.name.contains(filter.as_str())
+                    || component.component_type.contains(filter.as_str())
+            }
+            None => true,
+        })
+        .map(|component| {
+            let before = previous.and_then(|(previous, elapsed)| {
+                previous
+                    .iter()
+                    .find(|before| before.name == component.name)
+                    .map(|before| (before, elapsed))
+            });
+            let rate = |name: &str| {
+                let (before, elapsed) = before?;
+                let now = component.metrics.get(name)?;
+                let before = before.metrics.get(name).unwrap_or(&0.0);
+                // Counters start over when a component is reloaded.
+                if now < before {
+                    return None;
+                }
+                Some((now - before) / elapsed.as_secs_f64())
+            };
+
+            Row {
+                name: component.name.clone(),
+                kind: component.kind.clone(),
+                component_type: component.component_type.clone(),
+                state: component.state.clone(),
+                events_in: rate("events_in_total"),
+                events_out: rate("events_out_total"),
+                bytes_in: rate("bytes_in_total"),
+                bytes_out: rate("processed_bytes_total"),
+                errors: component.metrics.get("errors_total").cloned(),
+                buffer: component.metrics.get("buffer_events").cloned(),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let sorted_by = |row: &Row| match opts.sort {
+        Sort::EventsIn => row.events_in,
+        Sort::EventsOut => row.events_out,
+        Sort::BytesIn => row.bytes_in,
+        Sort::BytesOut => row.bytes_out,
+        Sort::Errors => row.errors,
+        Sort::Buffer => row.buffer,
+        Sort::Name | Sort::Kind => None,
+    };
+    rows.sort_by(|a, b| match opts.sort {
+        Sort::Name => a.name.cmp(&b.name),
+        Sort::Kind => (kind_order(&a.kind), &a.name).cmp(&(kind_order(&b.kind), &b.name)),
+        // The highest figures first, and the unknown ones last.
+        _ => sorted_by(b)
+            .unwrap_or(-1.0)
+            .partial_cmp(&sorted_by(a).unwrap_or(-1.0))
+            .unwrap()
+            .then_with(|| a.name.cmp(&b.name)),
+    });
+    rows
+}
+
+/// Sources, then transforms, then sinks, in the order events flow through.
+fn kind_order(kind: &str) -> usize {
+    match kind {
+        "source" => 0,
+        "transform" => 1,
+        _ => 2,
+    }
+}
+
+fn render(rows: &[Row], opts: &Opts) -> String {
+    let name_width = rows
+        .iter()
+        .map(|row| row.name.len())
+        .max()
+        .unwrap_or(0)
+        .max(4);
+    let type_width = rows
+        .iter()
+        .map(|row| row.component_type.len())
+        .max()
+        .unwrap_or(0)
+        .max(4);
+
+    let mut table = String::new();
+    let _ = writeln!(
+        table,
+        "Vector at {}, refreshed every {}ms. Press Ctrl-C to quit.\n",
+        opts.api_addr, opts.interval
+    );
+    let _ = writeln!(
+        table,
+        "{:<name_width$}  {:<9}  {:<type_width$}  {:<7}  {:>12}  {:>12}  {:>11}  {:>11}  {:>8}  {:>8}",
+        "NAME",
+        "KIND",
+        "TYPE",
+        "STATE",
+        "EVENTS IN/S",
+        "EVENTS OUT/S",
+        "BYTES IN/S",
+        "BYTES OUT/S",
+        "ERRORS",
+        "BUFFER",
+        name_width = name_width,
+        type_width = type_width,
+    );
+    for row in rows {
+        let _ = writeln!(
+            table,
+            "{:<name_width$}  {:<9}  {:<type_width$}  {:<7}  {:>12}  {:>12}  {:>11}  {:>11}  {:>8}  {:>8}",
+            row.name,
+            row.kind,
+            row.component_type,
+            row.state,
+            figure(row.events_in, ""),
+            figure(row.events_out, ""),
+            figure(row.bytes_in, "B"),
+            figure(row.bytes_out, "B"),
+            figure(row.errors, ""),
+            figure(row.buffer, ""),
+            name_width = name_width,
+            type_width = type_width,
+        );
+    }
+    table
+}
+
+/// Shortens large figures with a `k`, `M` or `G` suffix, showing `-` for
+/// unknown ones.
+fn figure(value: Option<f64>, unit: &str) -> String {
+    match value {
+        None => "-".to_owned(),
+        Some(value) if value >= 1e9 => format!("{:.1}G{}", value / 1e9, unit),
+        Some(value) if value >= 1e6 => format!("{:.1}M{}", value / 1e6, unit),
+        Some(value) if value >= 1e3 => format!("{:.1}k{}", value / 1e3, unit),
+        Some(value) => format!("{:.0}{}", value, unit),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn component(name: &str, kind: &str, metrics: &[(&str, f64)]) -> Component {
+        Component {
+            name: name.to_owned(),
+            kind: kind.to_owned(),
+            component_type: "stdin".to_owned(),
+            state: "running".to_owned(),
+            metrics: metrics
+                .iter()
+                .map(|(name, value)| (name.to_string(), *value))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn top_computes_rates_between_fetches() {
+        let opts = Opts::from_iter(&["top", "--sort", "events-out"]);
+        let before = vec![
+            component("in", "source", &[("events_out_total", 100.0)]),
+            component("out", "sink", &[("events_in_total", 10.0)]),
+        ];
+        let now = vec![
+            component(
+                "in",
+                "source",
+                &[("events_out_total", 300.0), ("bytes_in_total", 4000.0)],
+            ),
+            component("out", "sink", &[("events_in_total", 5.0)]),
+            component("new", "transform", &[("events_out_total", 50.0)]),
+        ];
+
+        let rows = rows(&now, Some((&before, Duration::from_secs(2))), &opts);
+        let names = rows.iter().map(|row| row.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["in", "new", "out"]);
+
+        assert_eq!(rows[0].events_out, Some(100.0));
+        assert_eq!(rows[0].bytes_in, Some(2000.0));
+        // Unknown for new and reloaded components.
+        assert_eq!(rows[1].events_out, None);
+        assert_eq!(rows[2].events_in, None);
+
+        assert_eq!(figure(rows[0].bytes_in, "B"), "2.0kB");
+        assert_eq!(figure(rows[1].events_out, ""), "-");
+    }
+
+    #[test]
+    fn top_filters_components() {
+        let opts = Opts::from_iter(&["top", "--filter", "ou"]);
+        let components = vec![
+            component("in", "source", &[]),
+            component("out", "sink", &[]),
+        ];
+
+        let rows = rows(&components, None, &opts);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].name, "out");
+    }
+}
@@ -1,16 +1,29 @@
 use super::{
-    config::{SinkContext, TransformContext},
+    config::{ConfigDiff, SinkContext, TransformContext},
     fanout::{self, Fanout},
     task::Task,
 };
-use crate::{buffers, dns::Resolver, runtime};
+use crate::{
+    buffers,
+    dns::Resolver,
+    event::finalization::PendingFinalizers,
+    metrics::{ComponentMetrics, Counter, Gauge},
+    runtime, Event,
+};
 use futures01::{
     future::{lazy, Either},
     sync::mpsc,
-    Future, Stream,
+    Future, Poll, Stream,
+};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
 };
-use std::{collections::HashMap, time::Duration};
-use stream_cancel::{Trigger, Tripwire};
+use stream_cancel::{StreamExt, Trigger, Tripwire};
 use tokio::util::FutureExt;
 
 pub struct Pieces {
@@ -20,6 +33,67 @@ pub struct Pieces {
     pub source_tasks: HashMap<String, Task>,
     pub healthchecks: HashMap<String, Task>,
     pub shutdown_triggers: HashMap<String, Trigger>,
+    pub buffers: HashMap<String, BuiltBuffer>,
+    pub detach_triggers: HashMap<String, Detach>,
+}
+
+/// The input, output and acker of a sink's buffer. The topology keeps them
+/// so that a rebuilt sink with the same buffer config can take over the
+/// buffer of its previous instance, along with the events in it.
+pub type BuiltBuffer = (
+    buffers::BufferInputCloner,
+    Arc<Mutex<Box<dyn Stream<Item = Event, Error = ()> + Send>>>,
+    buffers::Acker,
+);
+
+/// Stops a sink from reading its buffer, so that its next instance can take
+/// it over. Dropped without detaching, the sink reads its buffer until it's
+/// closed.
+pub struct Detach {
+    trigger: Option<Trigger>,
+    detached: Arc<AtomicBool>,
+}
+
+impl Detach {
+    fn new() -> (Self, Tripwire, Arc<AtomicBool>) {
+        let (trigger, tripwire) = Tripwire::new();
+        let detached = Arc::new(AtomicBool::new(false));
+        let detach = Self {
+            trigger: Some(trigger),
+            detached: Arc::clone(&detached),
+        };
+        (detach, tripwire, detached)
+    }
+
+    pub fn detach(mut self) {
+        self.detached.store(true, Ordering::Relaxed);
+        if let Some(trigger) = self.trigger.take() {
+            trigger.cancel();
+        }
+    }
+}
+
+impl Drop for Detach {
+    fn drop(&mut self) {
+        if let Some(trigger) = self.trigger.take() {
+            trigger.disable();
+        }
+    }
+}
+
+/// Reads a buffer shared between the instances of a sink, only one of which
+/// reads it at a time.
+pub(super) struct SharedReader(
+    pub(super) Arc<Mutex<Box<dyn Stream<Item = Event, Error = ()> + Send>>>,
+);
+
+impl Stream for SharedReader {
+    type Item = Event;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<Event>, ()> {
+        self.0.lock().unwrap().poll()
+    }
 }
 
 pub fn check(config: &super::Config) -> Result<Vec<String>, Vec<String>> {
@@ -88,8 +162,12 @@ pub fn check(config: &super::Config) -> Result<Vec<String>, Vec<String>> {
     }
 }
 
+/// Builds the components the diff adds or changes. Sinks given a buffer take
+/// it over instead of building their own.
 pub fn build_pieces(
     config: &super::Config,
+    diff: &ConfigDiff,
+    mut buffers: HashMap<String, BuiltBuffer>,
     exec: runtime::TaskExecutor,
 ) -> Result<(Pieces, Vec<String>), Vec<String>> {
     let mut inputs = HashMap::new();
@@ -98,6 +176,8 @@ pub fn build_pieces(
     let mut source_tasks = HashMap::new();
     let mut healthchecks = HashMap::new();
     let mut shutdown_triggers = HashMap::new();
+    let mut built_buffers = HashMap::new();
+    let mut detach_triggers = HashMap::new();
 
     let mut errors = vec![];
     let mut warnings = vec![];
@@ -113,10 +193,15 @@ pub fn build_pieces(
     let resolver = Resolver::new(config.global.dns_servers.clone(), exec.clone()).unwrap();
 
     // Build sources
-    for (name, source) in &config.sources {
+    for (name, source) in config
+        .sources
+        .iter()
+        .filter(|(name, _)| diff.sources.is_built(name))
+    {
         let (tx, rx) = mpsc::channel(1000);
 
         let typetag = source.source_type();
+        let metrics = ComponentMetrics::new("source", &name, &typetag);
 
         let server = match source.build(&name, &config.global, tx) {
             Err(error) => {
@@ -129,11 +214,15 @@ pub fn build_pieces(
         let (trigger, tripwire) = Tripwire::new();
 
         let (output, control) = Fanout::new();
-        let pump = rx.forward(output).map(|_| ());
-        let pump = Task::new(&name, &typetag, pump);
+        let events_out = metrics.events_out().clone();
+        let pump = rx
+            .inspect(move |_| events_out.increment())
+            .forward(output)
+            .map(|_| ());
+        let pump = Task::new(&name, &typetag, metrics.clone().instrument(pump));
 
         let server = server.select(tripwire.clone()).map(|_| ()).map_err(|_| ());
-        let server = Task::new(&name, &typetag, server);
+        let server = Task::new(&name, &typetag, metrics.instrument(server));
 
         outputs.insert(name.clone(), control);
         tasks.insert(name.clone(), pump);
@@ -142,10 +231,15 @@ pub fn build_pieces(
     }
 
     // Build transforms
-    for (name, transform) in &config.transforms {
+    for (name, transform) in config
+        .transforms
+        .iter()
+        .filter(|(name, _)| diff.transforms.is_built(name))
+    {
         let trans_inputs = &transform.inputs;
 
         let typetag = &transform.inner.transform_type();
+        let metrics = ComponentMetrics::new("transform", &name, &typetag);
 
         let cx = TransformContext {
             resolver: resolver.clone(),
@@ -161,15 +255,27 @@ pub fn build_pieces(
         };
 
         let (input_tx, input_rx) = futures01::sync::mpsc::channel(100);
-        let input_tx = buffers::BufferInputCloner::Memory(input_tx, buffers::WhenFull::Block);
+        let input_tx = buffers::BufferInputCloner::Memory(
+            input_tx,
+            buffers::WhenFull::Block,
+            metrics.gauge("buffer_events"),
+        );
 
         let (output, control) = Fanout::new();
 
+        let buffer_events = metrics.gauge("buffer_events");
+        let events_in = metrics.events_in().clone();
+        let input_rx = input_rx.inspect(move |_| {
+            buffer_events.decrement();
+            events_in.increment();
+        });
+        let events_out = metrics.events_out().clone();
         let transform = transform
-            .transform_stream(input_rx)
+            .transform_stream(Box::new(input_rx))
+            .inspect(move |_| events_out.increment())
             .forward(output)
             .map(|_| ());
-        let task = Task::new(&name, &typetag, transform);
+        let task = Task::new(&name, &typetag, metrics.instrument(transform));
 
         inputs.insert(name.clone(), (input_tx, trans_inputs.clone()));
         outputs.insert(name.clone(), control);
@@ -177,24 +283,35 @@ pub fn build_pieces(
     }
 
     // Build sinks
-    for (name, sink) in &config.sinks {
+    for (name, sink) in config
+        .sinks
+        .iter()
+        .filter(|(name, _)| diff.sinks.is_built(name))
+    {
         let sink_inputs = &sink.inputs;
         let enable_healthcheck = sink.healthcheck;
 
         let typetag = sink.inner.sink_type();
-
-        let buffer = sink.buffer.build(&config.global.data_dir, &name);
-        let (tx, rx, acker) = match buffer {
-            Err(error) => {
-                errors.push(format!("Sink \"{}\": {}", name, error));
-                continue;
-            }
-            Ok(buffer) => buffer,
+        let metrics = ComponentMetrics::new("sink", &name, &typetag);
+
+        let (tx, rx, acker) = match buffers.remove(name) {
+            Some(buffer) => buffer,
+            None => match sink.buffer.build(&config.global.data_dir, &name, &metrics) {
+                Err(error) => {
+                    errors.push(format!("Sink \"{}\": {}", name, error));
+                    continue;
+                }
+                Ok((tx, rx, acker)) => (tx, Arc::new(Mutex::new(rx)), acker),
+            },
         };
+        let buffer = (tx.clone(), Arc::clone(&rx), acker.clone());
 
+        // The sink acks the events it received in order, which finalizes
+        // them for their sources.
+        let pending = Arc::new(Mutex::new(PendingFinalizers::default()));
         let cx = SinkContext {
             resolver: resolver.clone(),
-            acker,
+            acker: buffers::Acker::Finalizing(Arc::clone(&pending), Box::new(acker)),
         };
 
         let (sink, healthcheck) = match sink.inner.build(cx) {
@@ -205,8 +322,42 @@ pub fn build_pieces(
             Ok((sink, healthcheck)) => (sink, healthcheck),
         };
 
-        let sink = rx.forward(sink).map(|_| ());
-        let task = Task::new(&name, &typetag, sink);
+        let (detach, tripwire, detached) = Detach::new();
+        let buffered = match &tx {
+            buffers::BufferInputCloner::Memory(_, _, buffered) => Some(buffered.clone()),
+            #[cfg(feature = "leveldb")]
+            buffers::BufferInputCloner::Disk(..) => None,
+        };
+        let dropped = metrics.counter("events_dropped_total");
+        let sink_name = name.clone();
+
+        let events_in = metrics.events_in().clone();
+        // Only a spawned sink can drop events, not one of a reload that was
+        // aborted.
+        let sink = lazy(move || {
+            let mut dropped = DroppedEvents {
+                name: sink_name,
+                pending: Arc::clone(&pending),
+                buffered,
+                detached,
+                dropped,
+                finished: false,
+            };
+
+            SharedReader(rx)
+                .take_until(tripwire)
+                .map(move |mut event| {
+                    events_in.increment();
+                    pending.lock().unwrap().push(event.take_finalizers());
+                    event
+                })
+                .forward(sink)
+                .then(move |result| {
+                    dropped.finished = true;
+                    result.map(|_| ())
+                })
+        });
+        let task = Task::new(&name, &typetag, metrics.instrument(sink));
 
         let healthcheck_task = if enable_healthcheck {
             let healthcheck_task = healthcheck
@@ -226,6 +377,8 @@ pub fn build_pieces(
         inputs.insert(name.clone(), (tx, sink_inputs.clone()));
         healthchecks.insert(name.clone(), healthcheck_task);
         tasks.insert(name.clone(), task);
+        built_buffers.insert(name.clone(), buffer);
+        detach_triggers.insert(name.clone(), detach);
     }
 
     // Warnings and errors
@@ -246,6 +399,8 @@ pub fn build_pieces(
             source_tasks,
             healthchecks,
             shutdown_triggers,
+            buffers: built_buffers,
+            detach_triggers,
         };
 
         Ok((pieces, warnings))
@@ -261,3 +416,41 @@ fn capitalize(s: &str) -> String {
     }
     s
 }
+
+/// Reports the events a sink is stopped with, like when it didn't drain in
+/// time during shutdown. Events left in a memory buffer are lost along with
+/// those the sink received but didn't acknowledge, while disk buffers keep
+/// theirs, as do buffers handed over to the sink's next instance.
+struct DroppedEvents {
+    name: String,
+    pending: Arc<Mutex<PendingFinalizers>>,
+    buffered: Option<Gauge>,
+    detached: Arc<AtomicBool>,
+    dropped: Counter,
+    finished: bool,
+}
+
+impl Drop for DroppedEvents {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+
+        let pending = self.pending.lock().unwrap().len();
+        let buffered = match &self.buffered {
+            Some(buffered) if !self.detached.load(Ordering::Relaxed) => {
+                buffered.get().max(0) as usize
+            }
+            _ => 0,
+        };
+        if pending + buffered > 0 {
+            self.dropped.add((pending + buffered) as u64);
+            error!(
+                message = "Sink stopped before delivering all of its events; dropping them.",
+                name = %self.name,
+                unacknowledged = %pending,
+                buffered = %buffered,
+            );
+        }
+    }
+}
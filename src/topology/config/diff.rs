@@ -0,0 +1,212 @@
+use super::Config;
+use indexmap::IndexMap;
+use serde::Serialize;
+use std::collections::HashSet;
+
+/// How the components of a new config differ from those of the running one.
+#[derive(Debug, Default)]
+pub struct ConfigDiff {
+    pub sources: Difference,
+    pub transforms: Difference,
+    pub sinks: Difference,
+}
+
+impl ConfigDiff {
+    /// Starting a config, all of its components are added.
+    pub fn initial(config: &Config) -> Self {
+        Self::new(&Config::empty(), config)
+    }
+
+    pub fn new(old: &Config, new: &Config) -> Self {
+        let mut diff = Self {
+            sources: Difference::new(&old.sources, &new.sources, &[]),
+            transforms: Difference::new(&old.transforms, &new.transforms, &["inputs"]),
+            sinks: Difference::new(&old.sinks, &new.sinks, &["inputs", "healthcheck"]),
+        };
+
+        // Components resolve hosts with the DNS servers they were built with,
+        // like the kubernetes source does for the API server.
+        if old.global.dns_servers != new.global.dns_servers {
+            diff.sources.change(new.sources.keys());
+            diff.transforms.change(new.transforms.keys());
+            diff.sinks.change(new.sinks.keys());
+        }
+
+        diff
+    }
+}
+
+/// The components of one kind to remove, rebuild or add. Components whose
+/// inputs are all that changed aren't rebuilt, but rewired.
+#[derive(Debug, Default)]
+pub struct Difference {
+    pub to_remove: HashSet<String>,
+    pub to_change: HashSet<String>,
+    pub to_add: HashSet<String>,
+    pub to_rewire: HashSet<String>,
+}
+
+impl Difference {
+    /// Compares the components by their serialized config, as trait objects
+    /// can't be compared otherwise. Changes to the `rewired` fields alone
+    /// don't rebuild a component.
+    fn new<C>(old: &IndexMap<String, C>, new: &IndexMap<String, C>, rewired: &[&str]) -> Self
+    where
+        C: Serialize,
+    {
+        let old_names = old.keys().cloned().collect::<HashSet<_>>();
+        let new_names = new.keys().cloned().collect::<HashSet<_>>();
+
+        let mut difference = Self {
+            to_remove: &old_names - &new_names,
+            to_add: &new_names - &old_names,
+            ..Self::default()
+        };
+
+        for name in old_names.intersection(&new_names) {
+            let old = serialize(&old[name]);
+            let new = serialize(&new[name]);
+            if old == new {
+                continue;
+            }
+
+            if without(old.clone(), rewired) != without(new.clone(), rewired) {
+                difference.to_change.insert(name.clone());
+            } else if old.get("inputs") != new.get("inputs") {
+                difference.to_rewire.insert(name.clone());
+            }
+        }
+
+        difference
+    }
+
+    /// Whether the component is built for the new config.
+    pub fn is_built(&self, name: &str) -> bool {
+        self.to_change.contains(name) || self.to_add.contains(name)
+    }
+
+    /// Rebuilds the components, unless they're added.
+    fn change<'a>(&mut self, names: impl Iterator<Item = &'a String>) {
+        for name in names {
+            if !self.to_add.contains(name) {
+                self.to_rewire.remove(name);
+                self.to_change.insert(name.clone());
+            }
+        }
+    }
+}
+
+// Json is used here over toml since toml does not support serializing `None`.
+fn serialize(config: &impl Serialize) -> serde_json::Value {
+    serde_json::to_value(config).unwrap()
+}
+
+fn without(mut config: serde_json::Value, fields: &[&str]) -> serde_json::Value {
+    if let Some(config) = config.as_object_mut() {
+        for field in fields {
+            config.remove(*field);
+        }
+    }
+    config
+}
+
+#[cfg(all(
+    test,
+    feature = "sources-socket",
+    feature = "transforms-sampler",
+    feature = "sinks-console"
+))]
+mod tests {
+    use super::*;
+
+    fn load(config: &str) -> Config {
+        Config::load(config.as_bytes()).unwrap()
+    }
+
+    fn names(names: &HashSet<String>) -> Vec<&str> {
+        let mut names = names.iter().map(String::as_str).collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn diff_rewires_components_whose_inputs_changed() {
+        let old = load(
+            r#"
+            [sources.in1]
+            type = "socket"
+            mode = "tcp"
+            address = "127.0.0.1:1235"
+
+            [sources.in2]
+            type = "socket"
+            mode = "tcp"
+            address = "127.0.0.1:1236"
+
+            [transforms.sample]
+            type = "sampler"
+            inputs = ["in1"]
+            rate = 10
+
+            [sinks.out]
+            type = "console"
+            inputs = ["sample"]
+            encoding = "text"
+
+            [sinks.gone]
+            type = "console"
+            inputs = ["in2"]
+            encoding = "text"
+            "#,
+        );
+        let new = load(
+            r#"
+            [sources.in1]
+            type = "socket"
+            mode = "tcp"
+            address = "127.0.0.1:1235"
+
+            [sources.in2]
+            type = "socket"
+            mode = "tcp"
+            address = "127.0.0.1:1237"
+
+            [transforms.sample]
+            type = "sampler"
+            inputs = ["in1", "in2"]
+            rate = 10
+
+            [sinks.out]
+            type = "console"
+            inputs = ["sample", "in1"]
+            encoding = "json"
+            healthcheck = false
+
+            [sinks.new]
+            type = "console"
+            inputs = ["in2"]
+            encoding = "text"
+            "#,
+        );
+
+        let diff = ConfigDiff::new(&old, &new);
+        assert_eq!(names(&diff.sources.to_change), vec!["in2"]);
+        assert!(diff.sources.to_rewire.is_empty());
+        assert_eq!(names(&diff.transforms.to_rewire), vec!["sample"]);
+        assert!(diff.transforms.to_change.is_empty());
+        assert_eq!(names(&diff.sinks.to_change), vec!["out"]);
+        assert_eq!(names(&diff.sinks.to_remove), vec!["gone"]);
+        assert_eq!(names(&diff.sinks.to_add), vec!["new"]);
+        assert!(diff.sinks.is_built("new"));
+        assert!(!diff.transforms.is_built("sample"));
+
+        let mut new = new;
+        new.global.dns_servers = vec!["127.0.0.1".to_owned()];
+        let diff = ConfigDiff::new(&old, &new);
+        assert_eq!(names(&diff.sources.to_change), vec!["in1", "in2"]);
+        assert_eq!(names(&diff.transforms.to_change), vec!["sample"]);
+        assert!(diff.transforms.to_rewire.is_empty());
+        assert_eq!(names(&diff.sinks.to_change), vec!["out"]);
+        assert!(!diff.sinks.is_built("gone"));
+    }
+}
@@ -0,0 +1,105 @@
+use serde::de::DeserializeOwned;
+use std::path::Path;
+
+/// The formats a config file can be written in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Format {
+    Toml,
+    Json,
+    Yaml,
+}
+
+impl Format {
+    /// The format of a config file by its extension, TOML unless it's
+    /// `.json`, `.yaml` or `.yml`.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => Format::Json,
+            Some("yaml") | Some("yml") => Format::Yaml,
+            _ => Format::Toml,
+        }
+    }
+}
+
+/// Deserializes a config in the given format. The errors of each format
+/// point at the line and column the error is at.
+pub fn deserialize<T: DeserializeOwned>(content: &str, format: Format) -> Result<T, Vec<String>> {
+    match format {
+        Format::Toml => toml::from_str(content).map_err(|e| vec![e.to_string()]),
+        Format::Json => serde_json::from_str(content).map_err(|e| vec![e.to_string()]),
+        Format::Yaml => serde_yaml::from_str(content).map_err(|e| vec![e.to_string()]),
+    }
+}
+
+#[cfg(all(test, feature = "sources-socket", feature = "sinks-console"))]
+mod tests {
+    use super::*;
+    use crate::topology::config::Config;
+
+    #[test]
+    fn format_from_path() {
+        assert_eq!(Format::from_path(Path::new("vector.toml")), Format::Toml);
+        assert_eq!(Format::from_path(Path::new("vector.json")), Format::Json);
+        assert_eq!(Format::from_path(Path::new("vector.yml")), Format::Yaml);
+        assert_eq!(Format::from_path(Path::new("vector.yaml")), Format::Yaml);
+        assert_eq!(Format::from_path(Path::new("vector")), Format::Toml);
+    }
+
+    #[test]
+    fn format_loads_the_same_config() {
+        let toml = r#"
+            data_dir = "/tmp/vector"
+
+            [sources.in]
+            type = "socket"
+            mode = "tcp"
+            address = "127.0.0.1:1235"
+
+            [sinks.out]
+            type = "console"
+            inputs = ["in"]
+            encoding = "json"
+        "#;
+        let json = r#"
+            {
+                "data_dir": "/tmp/vector",
+                "sources": {
+                    "in": {"type": "socket", "mode": "tcp", "address": "127.0.0.1:1235"}
+                },
+                "sinks": {
+                    "out": {"type": "console", "inputs": ["in"], "encoding": "json"}
+                }
+            }
+        "#;
+        let yaml = r#"
+data_dir: /tmp/vector
+sources:
+  in:
+    type: socket
+    mode: tcp
+    address: 127.0.0.1:1235
+sinks:
+  out:
+    type: console
+    inputs: [in]
+    encoding: json
+"#;
+
+        let expected = serde_json::to_value(Config::load(toml.as_bytes()).unwrap()).unwrap();
+        for (config, format) in &[(json, Format::Json), (yaml, Format::Yaml)] {
+            let config = Config::load_from(config.as_bytes(), *format).unwrap();
+            assert_eq!(serde_json::to_value(config).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn format_errors_keep_line_numbers() {
+        let yaml = "sources:\n  in:\n    type: socket\n  - out\n";
+        let errors = Config::load_from(yaml.as_bytes(), Format::Yaml).unwrap_err();
+        assert!(errors[0].contains("line 4"), "{}", errors[0]);
+
+        let json = "{\n  \"sources\": {\n    \"in\": }\n}";
+        let errors = Config::load_from(json.as_bytes(), Format::Json).unwrap_err();
+        assert!(errors[0].contains("line 3"), "{}", errors[0]);
+    }
+}
@@ -12,13 +12,18 @@ use indexmap::IndexMap; // IndexMap preserves insertion order, allowing us to ou
 use serde::{Deserialize, Serialize};
 use snafu::{ResultExt, Snafu};
 use std::fs::DirBuilder;
-use std::{collections::HashMap, path::PathBuf};
+use std::{collections::HashMap, path::PathBuf, time::Duration};
 
 pub mod component;
+mod diff;
+mod format;
 mod validation;
 mod vars;
 pub mod watcher;
 
+pub use diff::{ConfigDiff, Difference};
+pub use format::Format;
+
 #[derive(Deserialize, Serialize, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
@@ -42,6 +47,8 @@ pub struct GlobalOptions {
     pub dns_servers: Vec<String>,
     #[serde(default)]
     pub log_schema: event::LogSchema,
+    #[serde(default)]
+    pub shutdown_timeout_secs: Option<u64>,
 }
 
 pub fn default_data_dir() -> Option<PathBuf> {
@@ -93,6 +100,12 @@ impl GlobalOptions {
         Ok(data_dir)
     }
 
+    /// How long components are given to drain the events they were sent when
+    /// Vector shuts down, or when they're removed or changed by a reload.
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown_timeout_secs.unwrap_or(60))
+    }
+
     /// Resolve the `data_dir` option using
     /// `resolve_and_validate_data_dir` and then ensure a named
     /// subdirectory exists.
@@ -299,6 +312,7 @@ impl Config {
                 data_dir: None,
                 dns_servers: Vec::new(),
                 log_schema: event::LogSchema::default(),
+                shutdown_timeout_secs: None,
             },
             sources: IndexMap::new(),
             sinks: IndexMap::new(),
@@ -380,7 +394,13 @@ impl Config {
         }
     }
 
-    pub fn load(mut input: impl std::io::Read) -> Result<Self, Vec<String>> {
+    pub fn load(input: impl std::io::Read) -> Result<Self, Vec<String>> {
+        Self::load_from(input, Format::Toml)
+    }
+
+    /// Loads a config written in `format`, interpolating environment
+    /// variables first whatever the format.
+    pub fn load_from(mut input: impl std::io::Read, format: Format) -> Result<Self, Vec<String>> {
         let mut source_string = String::new();
         input
             .read_to_string(&mut source_string)
@@ -394,7 +414,7 @@ impl Config {
         }
         let with_vars = vars::interpolate(&source_string, &vars);
 
-        toml::from_str(&with_vars).map_err(|e| vec![e.to_string()])
+        format::deserialize(&with_vars, format)
     }
 
     pub fn append(&mut self, mut with: Self) -> Result<(), Vec<String>> {
@@ -409,6 +429,13 @@ impl Config {
             // we consider this an error.
             errors.push("conflicting values for 'data_dir' found".to_owned());
         }
+        if self.global.shutdown_timeout_secs.is_none() {
+            self.global.shutdown_timeout_secs = with.global.shutdown_timeout_secs;
+        } else if with.global.shutdown_timeout_secs.is_some()
+            && self.global.shutdown_timeout_secs != with.global.shutdown_timeout_secs
+        {
+            errors.push("conflicting values for 'shutdown_timeout_secs' found".to_owned());
+        }
         self.global.dns_servers.append(&mut with.global.dns_servers);
         self.global.dns_servers.sort();
         self.global.dns_servers.dedup();
@@ -1,3 +1,4 @@
+use super::tap::Tap;
 use crate::sinks::RouterSink;
 use crate::Event;
 use futures01::sync::mpsc;
@@ -7,12 +8,16 @@ pub struct Fanout {
     sinks: Vec<(String, RouterSink)>,
     i: usize,
     control_channel: mpsc::UnboundedReceiver<ControlMessage>,
+    taps: Vec<Tap>,
+    /// Whether the taps have seen the item currently being sent.
+    tapped: bool,
 }
 
 pub enum ControlMessage {
     Add(String, RouterSink),
     Remove(String),
     Replace(String, RouterSink),
+    Tap(Tap),
 }
 
 pub type ControlChannel = mpsc::UnboundedSender<ControlMessage>;
@@ -25,6 +30,8 @@ impl Fanout {
             sinks: vec![],
             i: 0,
             control_channel: control_rx,
+            taps: vec![],
+            tapped: false,
         };
 
         (fanout, control_tx)
@@ -66,6 +73,25 @@ impl Fanout {
                 ControlMessage::Add(name, sink) => self.add(name, sink),
                 ControlMessage::Remove(name) => self.remove(&name),
                 ControlMessage::Replace(name, sink) => self.replace(name, sink),
+                ControlMessage::Tap(tap) => self.taps.push(tap),
+            }
+        }
+    }
+
+    /// Shows the item to the taps once, even if sending it to the sinks is
+    /// retried, dropping the taps whose observers have gone away.
+    fn observe(&mut self, item: &Event) {
+        if self.tapped {
+            return;
+        }
+        self.tapped = true;
+
+        let mut i = 0;
+        while i < self.taps.len() {
+            if self.taps[i].observe(item) {
+                i += 1;
+            } else {
+                self.taps.swap_remove(i);
             }
         }
     }
@@ -90,8 +116,10 @@ impl Sink for Fanout {
 
     fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
         self.process_control_messages();
+        self.observe(&item);
 
         if self.sinks.is_empty() {
+            self.tapped = false;
             return Ok(AsyncSink::Ready);
         }
 
@@ -112,6 +140,7 @@ impl Sink for Fanout {
         }
 
         self.i = 0;
+        self.tapped = false;
 
         Ok(AsyncSink::Ready)
     }
@@ -142,7 +171,7 @@ impl Sink for Fanout {
 
 #[cfg(test)]
 mod tests {
-    use super::{ControlMessage, Fanout};
+    use super::{ControlMessage, Fanout, Tap};
     use crate::runtime;
     use crate::test_util::{self, CollectCurrent};
     use crate::Event;
@@ -458,4 +487,39 @@ mod tests {
             vec![rec3.clone()]
         );
     }
+
+    #[test]
+    fn fanout_taps_without_blocking() {
+        let (tx_a, rx_a) = mpsc::unbounded();
+        let tx_a = Box::new(tx_a.sink_map_err(|_| unreachable!()));
+
+        let (mut fanout, control) = Fanout::new();
+        fanout.add("a".to_string(), tx_a);
+
+        let (tap_tx, tap_rx) = mpsc::channel(0);
+        control
+            .unbounded_send(ControlMessage::Tap(Tap::new(tap_tx, None, 2, 100)))
+            .unwrap();
+
+        let recs = (1..=6)
+            .map(|i| Event::from(format!("line {}", i)))
+            .collect::<Vec<_>>();
+
+        let fanout = fanout.send(recs[0].clone()).wait().unwrap();
+        let fanout = fanout.send(recs[1].clone()).wait().unwrap();
+        // The tap's channel is full by now, so the next kept event is dropped.
+        let fanout = fanout.send(recs[2].clone()).wait().unwrap();
+        let mut fanout = fanout.send(recs[3].clone()).wait().unwrap();
+
+        let (tapped, tap_rx) = tap_rx.into_future().wait().ok().unwrap();
+        assert_eq!(tapped, Some(recs[1].clone()));
+        assert_eq!(fanout.taps.len(), 1);
+
+        drop(tap_rx);
+        fanout = fanout.send(recs[4].clone()).wait().unwrap();
+        fanout = fanout.send(recs[5].clone()).wait().unwrap();
+        assert!(fanout.taps.is_empty());
+
+        assert_eq!(CollectCurrent::new(rx_a).wait().unwrap().1, recs);
+    }
 }
@@ -1,27 +1,34 @@
 pub mod builder;
 pub mod config;
 mod fanout;
+mod status;
+mod tap;
 mod task;
 pub mod unit_test;
 
 pub use self::config::Config;
+pub use self::config::ConfigDiff;
 pub use self::config::SinkContext;
+pub use self::status::{ComponentState, ComponentStatus, HealthcheckStatus, TopologyStatus};
+pub use self::tap::Tap;
 
-use crate::topology::builder::Pieces;
+use crate::topology::builder::{BuiltBuffer, Detach, Pieces, SharedReader};
 
 use crate::buffers;
+use crate::metrics;
 use crate::runtime;
+use crate::Event;
 use futures01::{
     future,
     sync::{mpsc, oneshot},
-    Future, Stream,
+    Future, Sink, Stream,
 };
-use indexmap::IndexMap;
 use std::collections::{HashMap, HashSet};
 use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 use stream_cancel::Trigger;
-use tokio::timer;
+use tokio::{timer, util::FutureExt};
 use tracing_futures::Instrument;
 
 #[allow(dead_code)]
@@ -30,9 +37,16 @@ pub struct RunningTopology {
     outputs: HashMap<String, fanout::ControlChannel>,
     source_tasks: HashMap<String, oneshot::SpawnHandle<(), ()>>,
     tasks: HashMap<String, oneshot::SpawnHandle<(), ()>>,
+    /// Components removed or replaced by a reload, until they've drained.
+    draining: HashMap<String, oneshot::SpawnHandle<(), ()>>,
     shutdown_triggers: HashMap<String, Trigger>,
+    /// The buffers of the sinks, handed over to their next instance when
+    /// they're rebuilt with the same buffer config.
+    buffers: HashMap<String, BuiltBuffer>,
+    detach_triggers: HashMap<String, Detach>,
     config: Config,
     abort_tx: mpsc::UnboundedSender<()>,
+    status: TopologyStatus,
 }
 
 pub fn start(
@@ -59,75 +73,98 @@ pub fn start_validated(
         shutdown_triggers: HashMap::new(),
         source_tasks: HashMap::new(),
         tasks: HashMap::new(),
+        draining: HashMap::new(),
+        buffers: HashMap::new(),
+        detach_triggers: HashMap::new(),
         abort_tx,
+        status: TopologyStatus::default(),
     };
 
-    if !running_topology.run_healthchecks(&config, &mut pieces, rt, require_healthy) {
+    let diff = ConfigDiff::initial(&config);
+    if !running_topology.run_healthchecks(&diff, &config, &mut pieces, rt, require_healthy) {
         return None;
     }
 
-    running_topology.spawn_all(config, pieces, rt);
+    running_topology.spawn_all(config, &diff, pieces, rt);
     Some((running_topology, abort_rx))
 }
 
 pub fn validate(config: &Config, exec: runtime::TaskExecutor) -> Option<Pieces> {
-    match builder::build_pieces(config, exec) {
+    let diff = ConfigDiff::initial(config);
+    match build_pieces(config, &diff, HashMap::new(), exec) {
         Err(errors) => {
             for error in errors {
                 error!("Configuration error: {}", error);
             }
             None
         }
-        Ok((new_pieces, warnings)) => {
-            for warning in warnings {
-                warn!("Configuration warning: {}", warning);
-            }
-            Some(new_pieces)
-        }
+        Ok(new_pieces) => Some(new_pieces),
     }
 }
 
+fn build_pieces(
+    config: &Config,
+    diff: &ConfigDiff,
+    buffers: HashMap<String, BuiltBuffer>,
+    exec: runtime::TaskExecutor,
+) -> Result<Pieces, Vec<String>> {
+    builder::build_pieces(config, diff, buffers, exec).map(|(new_pieces, warnings)| {
+        for warning in warnings {
+            warn!("Configuration warning: {}", warning);
+        }
+        new_pieces
+    })
+}
+
 impl RunningTopology {
+    /// Shuts the topology down in order: sources stop accepting events,
+    /// transforms process the ones they were sent, and sinks flush their
+    /// batches and wait for their in-flight requests. Components still
+    /// running after the `shutdown_timeout_secs` global option are killed,
+    /// and the events they're dropped with reported.
     #[must_use]
-    pub fn stop(self) -> impl Future<Item = (), Error = ()> {
-        let mut running_tasks = self.tasks;
+    pub fn stop(mut self) -> impl Future<Item = (), Error = ()> {
+        // Stopping the sources closes their outputs once they're done, which
+        // lets each transform and sink end once it has drained its inputs.
+        for (_, trigger) in self.shutdown_triggers.drain() {
+            trigger.cancel();
+        }
+        self.inputs.clear();
+        self.outputs.clear();
+        self.buffers.clear();
+        self.detach_triggers.clear();
 
         let mut wait_handles = Vec::new();
-        let mut check_handles = HashMap::new();
-
-        for (name, task) in running_tasks.drain() {
+        let mut check_handles = Vec::new();
+
+        let running_tasks = self
+            .source_tasks
+            .drain()
+            .chain(self.tasks.drain())
+            .chain(self.draining.drain());
+        for (name, task) in running_tasks {
             let task = task
                 .or_else(|_| future::ok(())) // Consider an errored task to be shutdown
                 .shared();
 
             wait_handles.push(task.clone());
-            check_handles.insert(name, task);
+            check_handles.push((name, task));
         }
         let mut check_handles2 = check_handles.clone();
 
-        let deadline = Instant::now() + Duration::from_secs(60);
+        let deadline = Instant::now() + self.config.global.shutdown_timeout();
 
         let timeout = timer::Delay::new(deadline)
             .map(move |_| {
-                check_handles.retain(|_name, handle| {
-                    handle.poll().map(|p| p.is_not_ready()).unwrap_or(false)
-                });
-                let remaining_components = check_handles.keys().cloned().collect::<Vec<_>>();
-
                 error!(
                     "Failed to gracefully shut down in time. Killing: {}",
-                    remaining_components.join(", ")
+                    remaining_components(&mut check_handles)
                 );
             })
             .map_err(|err| panic!("Timer error: {:?}", err));
 
         let reporter = timer::Interval::new_interval(Duration::from_secs(5))
             .inspect(move |_| {
-                check_handles2.retain(|_name, handle| {
-                    handle.poll().map(|p| p.is_not_ready()).unwrap_or(false)
-                });
-                let remaining_components = check_handles2.keys().cloned().collect::<Vec<_>>();
-
                 // TODO: replace with checked_duration_since once it's stable
                 let time_remaining = if deadline > Instant::now() {
                     format!("{} seconds left", (deadline - Instant::now()).as_secs())
@@ -137,7 +174,7 @@ impl RunningTopology {
 
                 info!(
                     "Shutting down... Waiting on: {}. {}",
-                    remaining_components.join(", "),
+                    remaining_components(&mut check_handles2),
                     time_remaining
                 );
             })
@@ -159,49 +196,141 @@ impl RunningTopology {
         .map_err(|_| ())
     }
 
+    /// Stops the topology like `stop`, except that sinks stop reading their
+    /// buffers. The events left in memory buffers are returned instead, by
+    /// sink name, to hand them over to another Vector process. The streams
+    /// end once the transforms have drained, and must be read for them to.
+    /// Disk buffers keep their events.
+    pub fn hand_over(
+        mut self,
+    ) -> (
+        impl Future<Item = (), Error = ()>,
+        Vec<(String, Box<dyn Stream<Item = Event, Error = ()> + Send>)>,
+    ) {
+        let mut buffered = Vec::new();
+        for (name, detach) in self.detach_triggers.drain() {
+            detach.detach();
+            let (tx, rx, _) = self.buffers.remove(&name).unwrap();
+            if let buffers::BufferInputCloner::Memory(..) = tx {
+                let rx: Box<dyn Stream<Item = Event, Error = ()> + Send> =
+                    Box::new(SharedReader(rx));
+                buffered.push((name, rx));
+            }
+        }
+
+        (self.stop(), buffered)
+    }
+
+    /// The input of a sink, to send it events that didn't come from its
+    /// inputs, like those handed over by another Vector process.
+    pub fn sink_input(
+        &self,
+        name: &str,
+    ) -> Option<Box<dyn Sink<SinkItem = Event, SinkError = ()> + Send>> {
+        if !self.config.sinks.contains_key(name) {
+            return None;
+        }
+        self.inputs.get(name).map(|input| input.get())
+    }
+
     pub fn reload_config_and_respawn(
         &mut self,
         new_config: Config,
         rt: &mut runtime::Runtime,
         require_healthy: bool,
     ) -> bool {
+        match self.try_reload_config_and_respawn(new_config, rt, require_healthy) {
+            Ok(()) => true,
+            Err(errors) => {
+                for error in errors {
+                    error!("Configuration error: {}", error);
+                }
+                false
+            }
+        }
+    }
+
+    /// Reloads the config like `reload_config_and_respawn`, returning the
+    /// reasons it was aborted instead of logging them.
+    pub fn try_reload_config_and_respawn(
+        &mut self,
+        new_config: Config,
+        rt: &mut runtime::Runtime,
+        require_healthy: bool,
+    ) -> Result<(), Vec<String>> {
         if self.config.global.data_dir != new_config.global.data_dir {
-            error!("data_dir cannot be changed while reloading config file; reload aborted. Current value: {:?}", self.config.global.data_dir);
-            return false;
+            return Err(vec![format!("data_dir cannot be changed while reloading config file; reload aborted. Current value: {:?}", self.config.global.data_dir)]);
         }
 
-        if self.config.global.dns_servers != new_config.global.dns_servers {
-            error!("dns_servers cannot be changed while reloading config file; reload aborted. Current value: {:?}", self.config.global.dns_servers);
-            return false;
+        // The log schema is read globally, by components that aren't rebuilt
+        // as well.
+        if self.config.global.log_schema != new_config.global.log_schema {
+            return Err(vec![format!("log_schema cannot be changed while reloading config file; reload aborted. Current value: {:?}", self.config.global.log_schema)]);
         }
 
-        match validate(&new_config, rt.executor()) {
-            Some(mut new_pieces) => {
-                if !self.run_healthchecks(&new_config, &mut new_pieces, rt, require_healthy) {
-                    return false;
-                }
+        let diff = ConfigDiff::new(&self.config, &new_config);
 
-                self.spawn_all(new_config, new_pieces, rt);
-                true
-            }
+        // Rebuilt sinks with the same buffer config take over the buffer of
+        // their previous instance, rather than opening it a second time.
+        let buffers = diff
+            .sinks
+            .to_change
+            .iter()
+            .filter(|&name| {
+                serde_json::to_value(&self.config.sinks[name].buffer).unwrap()
+                    == serde_json::to_value(&new_config.sinks[name].buffer).unwrap()
+            })
+            .map(|name| (name.clone(), self.buffers[name].clone()))
+            .collect();
 
-            None => false,
+        let mut new_pieces = build_pieces(&new_config, &diff, buffers, rt.executor())?;
+        if !self.run_healthchecks(&diff, &new_config, &mut new_pieces, rt, require_healthy) {
+            return Err(vec!["Sinks unhealthy.".to_owned()]);
         }
+
+        self.spawn_all(new_config, &diff, new_pieces, rt);
+        Ok(())
+    }
+
+    /// A handle to the state of the components, kept up to date as they
+    /// run and are reloaded.
+    pub fn status(&self) -> TopologyStatus {
+        self.status.clone()
     }
 
     fn run_healthchecks(
         &mut self,
+        diff: &ConfigDiff,
         new_config: &Config,
         pieces: &mut Pieces,
         rt: &mut runtime::Runtime,
         require_healthy: bool,
     ) -> bool {
-        let (_, sinks_to_change, sinks_to_add) =
-            to_remove_change_add(&self.config.sinks, &new_config.sinks);
-
-        let healthchecks = (&sinks_to_change | &sinks_to_add)
+        let healthchecks = (&diff.sinks.to_change | &diff.sinks.to_add)
             .into_iter()
-            .map(|name| pieces.healthchecks.remove(&name).unwrap())
+            .map(|name| {
+                let healthcheck = pieces.healthchecks.remove(&name).unwrap();
+                let enabled = new_config.sinks[&name].healthcheck;
+                let status = self.status.clone();
+                let generation = status.healthcheck_started(
+                    &name,
+                    if enabled {
+                        HealthcheckStatus::Pending
+                    } else {
+                        HealthcheckStatus::Disabled
+                    },
+                );
+                healthcheck.then(move |result| {
+                    if enabled {
+                        let result = match result {
+                            Ok(()) => HealthcheckStatus::Passed,
+                            Err(()) => HealthcheckStatus::Failed,
+                        };
+                        status.healthcheck_finished(&name, generation, result);
+                    }
+                    result
+                })
+            })
             .collect::<Vec<_>>();
         let healthchecks = futures01::future::join_all(healthchecks).map(|_| ());
 
@@ -222,21 +351,27 @@ impl RunningTopology {
         }
     }
 
-    fn spawn_all(&mut self, new_config: Config, mut new_pieces: Pieces, rt: &mut runtime::Runtime) {
+    fn spawn_all(
+        &mut self,
+        new_config: Config,
+        diff: &ConfigDiff,
+        mut new_pieces: Pieces,
+        rt: &mut runtime::Runtime,
+    ) {
         // Sources
-        let (sources_to_remove, sources_to_change, sources_to_add) =
-            to_remove_change_add(&self.config.sources, &new_config.sources);
-
-        for name in sources_to_remove {
+        for name in &diff.sources.to_remove {
             info!("Removing source {:?}", name);
 
-            self.tasks.remove(&name).unwrap().forget();
+            let task = self.tasks.remove(name).unwrap();
+            self.drain(&name, task, rt);
+            metrics::registry().remove("component_name", &name);
+            self.status.remove(&name);
 
             self.remove_outputs(&name);
             self.shutdown_source(&name);
         }
 
-        for name in sources_to_change {
+        for name in &diff.sources.to_change {
             info!("Rebuilding source {:?}", name);
 
             self.remove_outputs(&name);
@@ -247,7 +382,7 @@ impl RunningTopology {
             self.spawn_source(&name, &mut new_pieces, rt);
         }
 
-        for name in sources_to_add {
+        for name in &diff.sources.to_add {
             info!("Starting source {:?}", name);
 
             self.setup_outputs(&name, &mut new_pieces);
@@ -255,13 +390,13 @@ impl RunningTopology {
         }
 
         // Transforms
-        let (transforms_to_remove, transforms_to_change, transforms_to_add) =
-            to_remove_change_add(&self.config.transforms, &new_config.transforms);
-
-        for name in transforms_to_remove {
+        for name in &diff.transforms.to_remove {
             info!("Removing transform {:?}", name);
 
-            self.tasks.remove(&name).unwrap().forget();
+            let task = self.tasks.remove(name).unwrap();
+            self.drain(&name, task, rt);
+            metrics::registry().remove("component_name", &name);
+            self.status.remove(&name);
 
             self.remove_inputs(&name);
             self.remove_outputs(&name);
@@ -269,53 +404,68 @@ impl RunningTopology {
 
         // Make sure all transform outputs are set up before another transform might try use
         // it as an input
-        for name in &transforms_to_change {
+        for name in &diff.transforms.to_change {
             self.setup_outputs(&name, &mut new_pieces);
         }
-        for name in &transforms_to_add {
+        for name in &diff.transforms.to_add {
             self.setup_outputs(&name, &mut new_pieces);
         }
 
-        for name in transforms_to_change {
+        for name in &diff.transforms.to_change {
             info!("Rebuilding transform {:?}", name);
 
             self.replace_inputs(&name, &mut new_pieces);
             self.spawn_transform(&name, &mut new_pieces, rt);
         }
 
-        for name in transforms_to_add {
+        for name in &diff.transforms.to_add {
             info!("Starting transform {:?}", name);
 
             self.setup_inputs(&name, &mut new_pieces);
             self.spawn_transform(&name, &mut new_pieces, rt);
         }
 
-        // Sinks
-        let (sinks_to_remove, sinks_to_change, sinks_to_add) =
-            to_remove_change_add(&self.config.sinks, &new_config.sinks);
+        for name in &diff.transforms.to_rewire {
+            info!("Rewiring transform {:?}", name);
+
+            self.rewire_inputs(&name, &new_config.transforms[name].inputs);
+        }
 
-        for name in sinks_to_remove {
+        // Sinks
+        for name in &diff.sinks.to_remove {
             info!("Removing sink {:?}", name);
 
-            self.tasks.remove(&name).unwrap().forget();
+            let task = self.tasks.remove(name).unwrap();
+            self.drain(&name, task, rt);
+            metrics::registry().remove("component_name", &name);
+            self.status.remove(&name);
 
             self.remove_inputs(&name);
+            self.buffers.remove(name);
+            self.detach_triggers.remove(name);
         }
 
-        for name in sinks_to_change {
+        for name in &diff.sinks.to_change {
             info!("Rebuilding sink {:?}", name);
 
             self.spawn_sink(&name, &mut new_pieces, rt);
             self.replace_inputs(&name, &mut new_pieces);
         }
 
-        for name in sinks_to_add {
+        for name in &diff.sinks.to_add {
             info!("Starting sink {:?}", name);
 
             self.setup_inputs(&name, &mut new_pieces);
             self.spawn_sink(&name, &mut new_pieces, rt);
         }
 
+        for name in &diff.sinks.to_rewire {
+            info!("Rewiring sink {:?}", name);
+
+            self.rewire_inputs(&name, &new_config.sinks[name].inputs);
+        }
+
+        self.status.set_config(&new_config);
         self.config = new_config;
     }
 
@@ -327,11 +477,33 @@ impl RunningTopology {
     ) {
         let task = new_pieces.tasks.remove(name).unwrap();
         let span = info_span!("sink", name = %task.name(), r#type = %task.typetag());
+        let generation = self.status.spawned(name, "sink", task.typetag());
         let task = handle_errors(task.instrument(span), self.abort_tx.clone());
-        let spawned = oneshot::spawn(task, &rt.executor());
+        let task = report_state(task, self.status.clone(), name, generation);
+
+        let buffer = new_pieces.buffers.remove(name).unwrap();
+        let handover = self
+            .buffers
+            .get(name)
+            .map_or(false, |previous| Arc::ptr_eq(&previous.1, &buffer.1));
+
+        let spawned = if handover {
+            // The previous instance stops reading the buffer, and finishes
+            // the events it already read before this one takes over.
+            self.detach_triggers.remove(name).unwrap().detach();
+            let previous = self.tasks.remove(name).unwrap();
+            let previous = self.drained(name, previous);
+            oneshot::spawn(previous.then(|_| task), &rt.executor())
+        } else {
+            oneshot::spawn(task, &rt.executor())
+        };
         if let Some(previous) = self.tasks.insert(name.to_string(), spawned) {
-            previous.forget();
+            self.drain(name, previous, rt);
         }
+
+        self.buffers.insert(name.to_string(), buffer);
+        let detach = new_pieces.detach_triggers.remove(name).unwrap();
+        self.detach_triggers.insert(name.to_string(), detach);
     }
 
     fn spawn_transform(
@@ -342,10 +514,12 @@ impl RunningTopology {
     ) {
         let task = new_pieces.tasks.remove(name).unwrap();
         let span = info_span!("transform", name = %task.name(), r#type = %task.typetag());
+        let generation = self.status.spawned(name, "transform", task.typetag());
         let task = handle_errors(task.instrument(span), self.abort_tx.clone());
+        let task = report_state(task, self.status.clone(), name, generation);
         let spawned = oneshot::spawn(task, &rt.executor());
         if let Some(previous) = self.tasks.insert(name.to_string(), spawned) {
-            previous.forget();
+            self.drain(name, previous, rt);
         }
     }
 
@@ -357,11 +531,13 @@ impl RunningTopology {
     ) {
         let task = new_pieces.tasks.remove(name).unwrap();
         let span = info_span!("source", name = %task.name(), r#type = %task.typetag());
+        let generation = self.status.spawned(name, "source", task.typetag());
 
         let task = handle_errors(task.instrument(span.clone()), self.abort_tx.clone());
+        let task = report_state(task, self.status.clone(), name, generation);
         let spawned = oneshot::spawn(task, &rt.executor());
         if let Some(previous) = self.tasks.insert(name.to_string(), spawned) {
-            previous.forget();
+            self.drain(name, previous, rt);
         }
 
         let shutdown_trigger = new_pieces.shutdown_triggers.remove(name).unwrap();
@@ -370,12 +546,45 @@ impl RunningTopology {
 
         let source_task = new_pieces.source_tasks.remove(name).unwrap();
         let source_task = handle_errors(source_task.instrument(span), self.abort_tx.clone());
+        let source_task = report_state(source_task, self.status.clone(), name, generation);
         self.source_tasks.insert(
             name.to_string(),
             oneshot::spawn(source_task, &rt.executor()),
         );
     }
 
+    /// Lets a removed or replaced component finish the events it was sent,
+    /// killing it if it takes longer than the shutdown timeout.
+    fn drain(&mut self, name: &str, task: oneshot::SpawnHandle<(), ()>, rt: &mut runtime::Runtime) {
+        // A component reloaded again before draining keeps its own deadline.
+        let drain = oneshot::spawn(self.drained(name, task), &rt.executor());
+        if let Some(previous) = self.draining.insert(name.to_owned(), drain) {
+            previous.forget();
+        }
+    }
+
+    /// Resolves once the task has finished, or was killed for not finishing
+    /// within the shutdown timeout.
+    fn drained(
+        &self,
+        name: &str,
+        task: oneshot::SpawnHandle<(), ()>,
+    ) -> impl Future<Item = (), Error = ()> {
+        let timeout = self.config.global.shutdown_timeout();
+        let component = name.to_owned();
+        task.timeout(timeout).then(move |result| {
+            if let Err(error) = result {
+                if error.is_elapsed() {
+                    error!(
+                        message = "Component failed to drain in time; killing it.",
+                        name = %component,
+                    );
+                }
+            }
+            Ok(())
+        })
+    }
+
     fn shutdown_source(&mut self, name: &str) {
         self.shutdown_triggers.remove(name).unwrap().cancel();
         self.source_tasks.remove(name).wait().unwrap();
@@ -383,6 +592,7 @@ impl RunningTopology {
 
     fn remove_outputs(&mut self, name: &str) {
         self.outputs.remove(name);
+        self.status.remove_output(name);
     }
 
     fn remove_inputs(&mut self, name: &str) {
@@ -429,6 +639,7 @@ impl RunningTopology {
             }
         }
 
+        self.status.set_output(name, output.clone());
         self.outputs.insert(name.to_string(), output);
     }
 
@@ -444,6 +655,36 @@ impl RunningTopology {
         self.inputs.insert(name.to_string(), tx);
     }
 
+    /// Connects a component that wasn't rebuilt to its new inputs.
+    fn rewire_inputs(&mut self, name: &str, new_inputs: &[String]) {
+        let sink_inputs = self.config.sinks.get(name).map(|s| &s.inputs);
+        let trans_inputs = self.config.transforms.get(name).map(|t| &t.inputs);
+        let old_inputs = sink_inputs
+            .or(trans_inputs)
+            .unwrap()
+            .iter()
+            .collect::<HashSet<_>>();
+
+        let new_inputs = new_inputs.iter().collect::<HashSet<_>>();
+
+        for input in &old_inputs - &new_inputs {
+            if let Some(output) = self.outputs.get(input) {
+                output
+                    .unbounded_send(fanout::ControlMessage::Remove(name.to_string()))
+                    .unwrap();
+            }
+        }
+
+        for input in &new_inputs - &old_inputs {
+            self.outputs[input]
+                .unbounded_send(fanout::ControlMessage::Add(
+                    name.to_string(),
+                    self.inputs[name].get(),
+                ))
+                .unwrap();
+        }
+    }
+
     fn replace_inputs(&mut self, name: &str, new_pieces: &mut builder::Pieces) {
         let (tx, inputs) = new_pieces.inputs.remove(name).unwrap();
 
@@ -485,33 +726,25 @@ impl RunningTopology {
     }
 }
 
-fn to_remove_change_add<C>(
-    old: &IndexMap<String, C>,
-    new: &IndexMap<String, C>,
-) -> (HashSet<String>, HashSet<String>, HashSet<String>)
+/// The names of the components whose tasks haven't finished yet.
+fn remaining_components<F>(handles: &mut Vec<(String, future::Shared<F>)>) -> String
 where
-    C: serde::Serialize + serde::Deserialize<'static>,
+    F: Future<Item = (), Error = ()>,
 {
-    let old_names = old.keys().cloned().collect::<HashSet<_>>();
-    let new_names = new.keys().cloned().collect::<HashSet<_>>();
-
-    let to_change = old_names
-        .intersection(&new_names)
-        .filter(|&n| {
-            // This is a hack around the issue of comparing two
-            // trait objects. Json is used here over toml since
-            // toml does not support serializing `None`.
-            let old_json = serde_json::to_vec(&old[n]).unwrap();
-            let new_json = serde_json::to_vec(&new[n]).unwrap();
-            old_json != new_json
-        })
-        .cloned()
-        .collect::<HashSet<_>>();
-
-    let to_remove = &old_names - &new_names;
-    let to_add = &new_names - &old_names;
-
-    (to_remove, to_change, to_add)
+    handles.retain(|(_name, handle)| {
+        handle
+            .clone()
+            .poll()
+            .map(|p| p.is_not_ready())
+            .unwrap_or(false)
+    });
+    let mut names = handles
+        .iter()
+        .map(|(name, _)| name.clone())
+        .collect::<Vec<_>>();
+    names.sort();
+    names.dedup();
+    names.join(", ")
 }
 
 fn handle_errors(
@@ -529,6 +762,23 @@ fn handle_errors(
         })
 }
 
+fn report_state(
+    task: impl Future<Item = (), Error = ()>,
+    status: TopologyStatus,
+    name: &str,
+    generation: usize,
+) -> impl Future<Item = (), Error = ()> {
+    let name = name.to_owned();
+    task.then(move |result| {
+        let state = match result {
+            Ok(()) => ComponentState::Stopped,
+            Err(()) => ComponentState::Failed,
+        };
+        status.set_state(&name, generation, state);
+        result
+    })
+}
+
 #[cfg(all(test, feature = "sinks-console", feature = "sources-socket"))]
 mod tests {
     use crate::sinks::console::{ConsoleSinkConfig, Encoding, Target};
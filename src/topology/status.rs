@@ -0,0 +1,188 @@
+use super::{
+    fanout::{ControlChannel, ControlMessage},
+    Config, Tap,
+};
+use indexmap::IndexMap;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+};
+
+/// What the running topology is doing, readable from other threads.
+#[derive(Clone, Default)]
+pub struct TopologyStatus {
+    inner: Arc<RwLock<Inner>>,
+    generations: Arc<AtomicUsize>,
+    outputs: Arc<Mutex<HashMap<String, ControlChannel>>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    config: serde_json::Value,
+    components: IndexMap<String, (usize, ComponentStatus)>,
+    healthchecks: IndexMap<String, (usize, HealthcheckStatus)>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ComponentStatus {
+    pub kind: &'static str,
+    #[serde(rename = "type")]
+    pub component_type: String,
+    pub state: ComponentState,
+    /// The result of the latest healthcheck, for sinks.
+    pub healthcheck: Option<HealthcheckStatus>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ComponentState {
+    Running,
+    Stopped,
+    Failed,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthcheckStatus {
+    Pending,
+    Passed,
+    Failed,
+    Disabled,
+}
+
+impl TopologyStatus {
+    /// The config of the running topology, serialized.
+    pub fn config(&self) -> serde_json::Value {
+        self.inner.read().unwrap().config.clone()
+    }
+
+    pub fn components(&self) -> IndexMap<String, ComponentStatus> {
+        let inner = self.inner.read().unwrap();
+        inner
+            .components
+            .iter()
+            .map(|(name, (_, status))| {
+                let mut status = status.clone();
+                status.healthcheck = inner.healthchecks.get(name).map(|(_, result)| *result);
+                (name.clone(), status)
+            })
+            .collect()
+    }
+
+    /// Attaches a tap to the output of a source or transform, returning
+    /// false if there's no such component.
+    pub fn tap(&self, name: &str, tap: Tap) -> bool {
+        self.outputs
+            .lock()
+            .unwrap()
+            .get(name)
+            .map_or(false, |output| {
+                output.unbounded_send(ControlMessage::Tap(tap)).is_ok()
+            })
+    }
+
+    pub(super) fn set_output(&self, name: &str, output: ControlChannel) {
+        self.outputs.lock().unwrap().insert(name.to_owned(), output);
+    }
+
+    pub(super) fn remove_output(&self, name: &str) {
+        self.outputs.lock().unwrap().remove(name);
+    }
+
+    pub(super) fn set_config(&self, config: &Config) {
+        self.inner.write().unwrap().config = serde_json::to_value(config).unwrap();
+    }
+
+    /// Records a component as running, returning the generation that its
+    /// task reports its state with. A task replaced by a reload can still
+    /// finish later on, so the states of older generations are ignored.
+    pub(super) fn spawned(&self, name: &str, kind: &'static str, component_type: &str) -> usize {
+        let generation = self.generations.fetch_add(1, Ordering::Relaxed);
+        let status = ComponentStatus {
+            kind,
+            component_type: component_type.to_owned(),
+            state: ComponentState::Running,
+            healthcheck: None,
+        };
+        self.inner
+            .write()
+            .unwrap()
+            .components
+            .insert(name.to_owned(), (generation, status));
+        generation
+    }
+
+    pub(super) fn set_state(&self, name: &str, generation: usize, state: ComponentState) {
+        let mut inner = self.inner.write().unwrap();
+        match inner.components.get_mut(name) {
+            // A source is run by two tasks, and either failing fails it.
+            Some((current, status))
+                if *current == generation && status.state != ComponentState::Failed =>
+            {
+                status.state = state
+            }
+            _ => (),
+        }
+    }
+
+    /// Records a sink's healthcheck as started, returning the generation its
+    /// result is reported with.
+    pub(super) fn healthcheck_started(&self, name: &str, status: HealthcheckStatus) -> usize {
+        let generation = self.generations.fetch_add(1, Ordering::Relaxed);
+        self.inner
+            .write()
+            .unwrap()
+            .healthchecks
+            .insert(name.to_owned(), (generation, status));
+        generation
+    }
+
+    pub(super) fn healthcheck_finished(
+        &self,
+        name: &str,
+        generation: usize,
+        result: HealthcheckStatus,
+    ) {
+        let mut inner = self.inner.write().unwrap();
+        match inner.healthchecks.get_mut(name) {
+            Some((current, status)) if *current == generation => *status = result,
+            _ => (),
+        }
+    }
+
+    pub(super) fn remove(&self, name: &str) {
+        let mut inner = self.inner.write().unwrap();
+        inner.components.remove(name);
+        inner.healthchecks.remove(name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn topology_status_ignores_replaced_tasks() {
+        let status = TopologyStatus::default();
+
+        let old = status.spawned("out", "sink", "console");
+        let check = status.healthcheck_started("out", HealthcheckStatus::Pending);
+        let new = status.spawned("out", "sink", "console");
+        status.set_state("out", old, ComponentState::Stopped);
+        status.healthcheck_finished("out", check, HealthcheckStatus::Passed);
+
+        let out = status.components()["out"].clone();
+        assert_eq!(out.state, ComponentState::Running);
+        assert_eq!(out.healthcheck, Some(HealthcheckStatus::Passed));
+
+        status.set_state("out", new, ComponentState::Failed);
+        assert_eq!(status.components()["out"].state, ComponentState::Failed);
+
+        status.remove("out");
+        assert!(status.components().is_empty());
+    }
+}
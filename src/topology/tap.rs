@@ -0,0 +1,73 @@
+use crate::{conditions::Condition, Event};
+use futures01::sync::mpsc;
+use std::time::{Duration, Instant};
+
+/// Copies the events sent through a component's output to an observer.
+///
+/// A tap never waits on its observer: events are dropped when its channel is
+/// full, and the tap is dropped along with the channel.
+pub struct Tap {
+    tx: mpsc::Sender<Event>,
+    condition: Option<Box<dyn Condition>>,
+    sample_rate: u64,
+    rate_limit: u64,
+    seen: u64,
+    window_start: Instant,
+    sent_in_window: u64,
+}
+
+impl Tap {
+    /// Keeps one in `sample_rate` of the events passing `condition`, sending
+    /// no more than `rate_limit` of them each second.
+    pub fn new(
+        tx: mpsc::Sender<Event>,
+        condition: Option<Box<dyn Condition>>,
+        sample_rate: u64,
+        rate_limit: u64,
+    ) -> Self {
+        Self {
+            tx,
+            condition,
+            sample_rate: sample_rate.max(1),
+            rate_limit,
+            seen: 0,
+            window_start: Instant::now(),
+            sent_in_window: 0,
+        }
+    }
+
+    /// Sends a copy of the event if it's kept, returning false once the
+    /// observer has gone away.
+    pub(super) fn observe(&mut self, event: &Event) -> bool {
+        if let Some(condition) = &self.condition {
+            if !condition.check(event) {
+                return true;
+            }
+        }
+
+        self.seen += 1;
+        if self.seen % self.sample_rate != 0 {
+            return true;
+        }
+
+        let now = Instant::now();
+        if now.duration_since(self.window_start) >= Duration::from_secs(1) {
+            self.window_start = now;
+            self.sent_in_window = 0;
+        }
+        if self.sent_in_window >= self.rate_limit {
+            return true;
+        }
+
+        // The copy doesn't hold up the acknowledgement of the event.
+        let mut event = event.clone();
+        event.take_finalizers();
+        match self.tx.try_send(event) {
+            Ok(()) => {
+                self.sent_in_window += 1;
+                true
+            }
+            Err(error) => !error.is_disconnected(),
+        }
+    }
+}
@@ -1,12 +1,24 @@
-use crate::metrics;
+use crate::{
+    event::{self, Event, LogEvent},
+    metrics,
+};
+use chrono::Utc;
+use futures01::sync::mpsc;
+use lazy_static::lazy_static;
+use std::{fmt, sync::Mutex};
 use tracing::{
     dispatcher::{set_global_default, Dispatch},
+    field::{Field, Visit},
     span::Span,
+    Subscriber,
 };
 use tracing_limit::Limit;
 use tracing_log::LogTracer;
 use tracing_metrics::MetricsSubscriber;
-use tracing_subscriber::{layer::SubscriberExt, FmtSubscriber};
+use tracing_subscriber::{
+    layer::{Context, Layer, SubscriberExt},
+    FmtSubscriber,
+};
 
 pub use tracing_futures::Instrument;
 pub use tracing_tower::{InstrumentableService, InstrumentedService};
@@ -18,7 +30,8 @@ pub fn init(color: bool, json: bool, levels: &str, metrics: Option<metrics::Sink
             .json()
             .flatten_event(true)
             .finish()
-            .with(Limit::default());
+            .with(Limit::default())
+            .with(InternalLogs);
 
         if let Some(sink) = metrics {
             Dispatch::new(MetricsSubscriber::new(subscriber, sink))
@@ -30,7 +43,8 @@ pub fn init(color: bool, json: bool, levels: &str, metrics: Option<metrics::Sink
             .with_ansi(color)
             .with_env_filter(levels)
             .finish()
-            .with(Limit::default());
+            .with(Limit::default())
+            .with(InternalLogs);
 
         if let Some(sink) = metrics {
             Dispatch::new(MetricsSubscriber::new(subscriber, sink))
@@ -46,3 +60,91 @@ pub fn init(color: bool, json: bool, levels: &str, metrics: Option<metrics::Sink
 pub fn current_span() -> Span {
     Span::current()
 }
+
+lazy_static! {
+    static ref SUBSCRIBERS: Mutex<Vec<mpsc::Sender<Event>>> = Mutex::new(Vec::new());
+}
+
+/// Receives the events Vector logs from now on, as log events.
+pub fn subscribe() -> mpsc::Receiver<Event> {
+    let (tx, rx) = mpsc::channel(1000);
+    SUBSCRIBERS.lock().unwrap().push(tx);
+    rx
+}
+
+/// Sends the events that pass the level filter to the subscribers.
+struct InternalLogs;
+
+impl<S: Subscriber> Layer<S> for InternalLogs {
+    fn on_event(&self, event: &tracing::Event, _ctx: Context<S>) {
+        let mut subscribers = SUBSCRIBERS.lock().unwrap();
+        if subscribers.is_empty() {
+            return;
+        }
+
+        let log = to_log(event);
+        // Logging can't wait for a subscriber, so events are dropped while
+        // its channel is full.
+        *subscribers = subscribers
+            .drain(..)
+            .filter_map(|mut tx| match tx.try_send(log.clone()) {
+                Err(error) if error.is_disconnected() => None,
+                _ => Some(tx),
+            })
+            .collect();
+    }
+}
+
+fn to_log(event: &tracing::Event) -> Event {
+    let metadata = event.metadata();
+    let mut log = Event::new_empty_log();
+    {
+        let log = log.as_mut_log();
+        log.insert(event::log_schema().timestamp_key().clone(), Utc::now());
+        log.insert("metadata.level", metadata.level().to_string());
+        log.insert("metadata.target", metadata.target());
+        if let Some(module_path) = metadata.module_path() {
+            log.insert("metadata.module_path", module_path);
+        }
+        event.record(&mut LogVisitor(log));
+    }
+    log
+}
+
+struct LogVisitor<'a>(&'a mut LogEvent);
+
+impl<'a> LogVisitor<'a> {
+    fn insert(&mut self, field: &Field, value: impl Into<event::Value>) {
+        match field.name() {
+            "message" => self
+                .0
+                .insert(event::log_schema().message_key().clone(), value),
+            // Set by `tracing_log` for records of the `log` crate, and by the
+            // rate limiter.
+            name if name.starts_with("log.") || name == "rate_limit_secs" => (),
+            name => self.0.insert(name, value),
+        }
+    }
+}
+
+impl<'a> Visit for LogVisitor<'a> {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, value);
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, value as i64);
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, value);
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert(field, value);
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.insert(field, format!("{:?}", value));
+    }
+}
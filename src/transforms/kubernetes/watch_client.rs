@@ -1,4 +1,8 @@
-use crate::{dns::Resolver, sinks::util::http::https_client, tls::TlsSettings};
+use crate::{
+    dns::Resolver,
+    sinks::util::http::https_client,
+    tls::{TlsOptions, TlsSettings},
+};
 use futures01::{future::Future, stream::Stream};
 use http::{header, status::StatusCode, uri, Request, Uri};
 use hyper::{client::HttpConnector, Body, Chunk};
@@ -9,6 +13,15 @@ use k8s_openapi::{
     RequestError, Response, ResponseError, WatchOptional,
 };
 use snafu::{futures01::future::FutureExt, ResultExt, Snafu};
+use std::{env, fs, str::FromStr};
+
+/// Environment variables set by Kubernetes in every container.
+const SERVICE_HOST: &str = "KUBERNETES_SERVICE_HOST";
+const SERVICE_PORT: &str = "KUBERNETES_SERVICE_PORT";
+
+/// Service account credentials mounted by Kubernetes in every container.
+const SERVICE_ACCOUNT_TOKEN: &str = "/var/run/secrets/kubernetes.io/serviceaccount/token";
+const SERVICE_ACCOUNT_CA: &str = "/var/run/secrets/kubernetes.io/serviceaccount/ca.crt";
 
 /// Config which could be loaded from kubeconfig or local kubernetes cluster.
 /// Used to build WatchClient which is in turn used to build Stream of metadata.
@@ -22,6 +35,51 @@ pub struct ClientConfig {
 }
 
 impl ClientConfig {
+    pub fn new(
+        resolver: Resolver,
+        server: Uri,
+        token: Option<String>,
+        tls_settings: TlsSettings,
+        node_name: Option<String>,
+    ) -> Self {
+        ClientConfig {
+            resolver,
+            token,
+            server,
+            tls_settings,
+            node_name,
+        }
+    }
+
+    /// Loads configuration from the service account of the Pod in which
+    /// Vector is running.
+    pub fn in_cluster(node_name: Option<String>, resolver: Resolver) -> Result<Self, BuildError> {
+        let host = env::var(SERVICE_HOST).context(NotInCluster)?;
+        let port = env::var(SERVICE_PORT).context(NotInCluster)?;
+        // IPv6 addresses need to be bracketed.
+        let host = if host.contains(':') {
+            format!("[{}]", host)
+        } else {
+            host
+        };
+        let server = Uri::from_str(&format!("https://{}:{}", host, port)).context(InvalidUri)?;
+
+        let token = fs::read_to_string(SERVICE_ACCOUNT_TOKEN).context(TokenReadError)?;
+        let tls_settings = TlsSettings::from_options(&Some(TlsOptions {
+            ca_path: Some(SERVICE_ACCOUNT_CA.into()),
+            ..TlsOptions::default()
+        }))
+        .context(TlsError)?;
+
+        Ok(ClientConfig::new(
+            resolver,
+            server,
+            Some(token.trim().to_owned()),
+            tls_settings,
+            node_name,
+        ))
+    }
+
     pub fn build(&self) -> Result<WatchClient, BuildError> {
         let client =
             https_client(self.resolver.clone(), self.tls_settings.clone()).context(HttpError)?;
@@ -88,7 +146,7 @@ impl WatchClient {
         &mut self,
         mut version: Option<Version>,
         error: Option<RuntimeError>,
-    ) -> Result<impl Stream<Item = Pod, Error = RuntimeError>, BuildError> {
+    ) -> Result<impl Stream<Item = PodEvent, Error = RuntimeError>, BuildError> {
         match error {
             None => (),
             Some(RuntimeError::WatchEventError { status }) if status.code == Some(410) => {
@@ -111,7 +169,7 @@ impl WatchClient {
     fn build_watch_stream(
         &self,
         request: Request<Body>,
-    ) -> impl Stream<Item = Pod, Error = RuntimeError> {
+    ) -> impl Stream<Item = PodEvent, Error = RuntimeError> {
         let mut decoder = Decoder::default();
 
         self.client
@@ -146,10 +204,9 @@ impl WatchClient {
             })
             // Extracts Pod metadata from event
             .and_then(|event| match event {
-                WatchEvent::Added(data)
-                | WatchEvent::Modified(data)
-                | WatchEvent::Bookmark(data)
-                | WatchEvent::Deleted(data) => Ok(data),
+                WatchEvent::Added(data) | WatchEvent::Modified(data) => Ok(PodEvent::Changed(data)),
+                WatchEvent::Deleted(data) => Ok(PodEvent::Deleted(data)),
+                WatchEvent::Bookmark(data) => Ok(PodEvent::Bookmark(data)),
                 WatchEvent::ErrorStatus(status) => Err(RuntimeError::WatchEventError { status }),
                 WatchEvent::ErrorOther(other) => {
                     Err(RuntimeError::UnknownWatchEventError { other })
@@ -158,6 +215,33 @@ impl WatchClient {
     }
 }
 
+/// Change of a Pod, as seen by the watch.
+#[derive(Debug)]
+pub enum PodEvent {
+    /// Pod was added or modified.
+    Changed(Pod),
+    Deleted(Pod),
+    /// Carries only the latest resource version.
+    Bookmark(Pod),
+}
+
+impl PodEvent {
+    pub fn pod(&self) -> &Pod {
+        match self {
+            PodEvent::Changed(pod) | PodEvent::Deleted(pod) | PodEvent::Bookmark(pod) => pod,
+        }
+    }
+
+    /// Resource version from which the watch can be resumed after this event.
+    pub fn version(&self) -> Option<Version> {
+        self.pod()
+            .metadata
+            .as_ref()
+            .and_then(|metadata| metadata.resource_version.clone())
+            .map(Version)
+    }
+}
+
 /// Decodes responses from incoming Chunks
 #[derive(Debug, Default)]
 struct Decoder {
@@ -223,6 +307,12 @@ pub enum BuildError {
     InvalidUriParts { source: uri::InvalidUriParts },
     #[snafu(display("Authorization token is invalid: {}.", source))]
     InvalidToken { source: header::InvalidHeaderValue },
+    #[snafu(display("Not running inside a Kubernetes cluster: {}.", source))]
+    NotInCluster { source: env::VarError },
+    #[snafu(display("Unable to read service account token: {}.", source))]
+    TokenReadError { source: std::io::Error },
+    #[snafu(display("TLS construction errored {}.", source))]
+    TlsError { source: crate::Error },
 }
 
 #[derive(Debug, Snafu)]
@@ -48,7 +48,7 @@ pub mod swimlanes;
 #[cfg(feature = "transforms-tokenizer")]
 pub mod tokenizer;
 
-use futures01::{sync::mpsc::Receiver, Stream};
+use futures01::Stream;
 
 pub trait Transform: Send {
     fn transform(&mut self, event: Event) -> Option<Event>;
@@ -61,7 +61,7 @@ pub trait Transform: Send {
 
     fn transform_stream(
         self: Box<Self>,
-        input_rx: Receiver<Event>,
+        input_rx: Box<dyn Stream<Item = Event, Error = ()> + Send>,
     ) -> Box<dyn Stream<Item = Event, Error = ()> + Send>
     where
         Self: 'static,
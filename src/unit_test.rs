@@ -1,6 +1,9 @@
 use crate::{
     config_paths, event,
-    topology::{config::Config, unit_test::UnitTest},
+    topology::{
+        config::{Config, Format},
+        unit_test::UnitTest,
+    },
 };
 use colored::*;
 use std::{fs::File, path::PathBuf};
@@ -9,12 +12,25 @@ use structopt::StructOpt;
 #[derive(StructOpt, Debug)]
 #[structopt(rename_all = "kebab-case")]
 pub struct Opts {
-    /// Any number of Vector config files to test. If none are specified the
-    /// default config path `/etc/vector/vector.toml` will be targeted.
+    /// Any number of Vector config files to test, in the format of their
+    /// extension. If none are specified the default config path
+    /// `/etc/vector/vector.toml` will be targeted.
     paths: Vec<PathBuf>,
+
+    /// Any number of Vector config files to test as TOML.
+    #[structopt(name = "config-toml", long)]
+    paths_toml: Vec<PathBuf>,
+
+    /// Any number of Vector config files to test as JSON.
+    #[structopt(name = "config-json", long)]
+    paths_json: Vec<PathBuf>,
+
+    /// Any number of Vector config files to test as YAML.
+    #[structopt(name = "config-yaml", long)]
+    paths_yaml: Vec<PathBuf>,
 }
 
-fn build_tests(i: usize, path: &PathBuf) -> Result<Vec<UnitTest>, Vec<String>> {
+fn build_tests(i: usize, path: &PathBuf, format: Format) -> Result<Vec<UnitTest>, Vec<String>> {
     let file = match File::open(path) {
         Ok(f) => f,
         Err(error) => {
@@ -33,7 +49,7 @@ fn build_tests(i: usize, path: &PathBuf) -> Result<Vec<UnitTest>, Vec<String>> {
         }
     };
 
-    let mut config = match Config::load(file) {
+    let mut config = match Config::load_from(file, format) {
         Err(load_errs) => {
             return Err(load_errs);
         }
@@ -52,17 +68,23 @@ pub fn cmd(opts: &Opts) -> exitcode::ExitCode {
     let mut failed_files: Vec<(String, Vec<(String, Vec<String>)>)> = Vec::new();
     let mut inspected_files: Vec<(String, Vec<(String, Vec<String>)>)> = Vec::new();
 
-    let paths = config_paths::expand(opts.paths.clone()).unwrap_or_else(|| {
+    let paths = config_paths::with_formats(&[
+        (&opts.paths, None),
+        (&opts.paths_toml, Some(Format::Toml)),
+        (&opts.paths_json, Some(Format::Json)),
+        (&opts.paths_yaml, Some(Format::Yaml)),
+    ]);
+    let paths = config_paths::expand(paths).unwrap_or_else(|| {
         std::process::exit(exitcode::CONFIG);
     });
 
-    for (i, p) in paths.iter().enumerate() {
+    for (i, (p, format)) in paths.iter().enumerate() {
         let path_str = p.to_str().unwrap_or("");
         if i > 0 {
             println!();
         }
         println!("Running {} tests", path_str);
-        match build_tests(i, p) {
+        match build_tests(i, p, *format) {
             Ok(mut tests) => {
                 let mut aggregated_test_errors = Vec::new();
                 let mut aggregated_test_inspections = Vec::new();
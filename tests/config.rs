@@ -1,9 +1,13 @@
-use vector::topology::{self, Config};
+use std::collections::HashMap;
+use vector::topology::{self, Config, ConfigDiff};
 
 fn load(config: &str) -> Result<Vec<String>, Vec<String>> {
     let rt = vector::runtime::Runtime::single_threaded().unwrap();
     Config::load(config.as_bytes())
-        .and_then(|c| topology::builder::build_pieces(&c, rt.executor()))
+        .and_then(|c| {
+            let diff = ConfigDiff::initial(&c);
+            topology::builder::build_pieces(&c, &diff, HashMap::new(), rt.executor())
+        })
         .map(|(_topology, warnings)| warnings)
 }
 
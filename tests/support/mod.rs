@@ -22,6 +22,14 @@ pub fn sink() -> (Receiver<Event>, MockSinkConfig) {
     (rx, sink)
 }
 
+/// A sink whose config differs from other sinks by its data, so that it's
+/// rebuilt on reload.
+pub fn sink_with_data(data: &str) -> (Receiver<Event>, MockSinkConfig) {
+    let (tx, rx) = futures01::sync::mpsc::channel(10);
+    let sink = MockSinkConfig::new_with_data(tx, true, data);
+    (rx, sink)
+}
+
 pub fn sink_failing_healthcheck() -> (Receiver<Event>, MockSinkConfig) {
     let (tx, rx) = futures01::sync::mpsc::channel(10);
     let sink = MockSinkConfig::new(tx, false);
@@ -177,6 +185,8 @@ pub struct MockSinkConfig {
     sender: Option<Sender<Event>>,
     #[serde(skip)]
     healthy: bool,
+    // Serialized, unlike the other fields.
+    data: Option<String>,
 }
 
 impl MockSinkConfig {
@@ -184,6 +194,14 @@ impl MockSinkConfig {
         Self {
             sender: Some(sender),
             healthy,
+            data: None,
+        }
+    }
+
+    pub fn new_with_data(sender: Sender<Event>, healthy: bool, data: &str) -> Self {
+        Self {
+            data: Some(data.to_owned()),
+            ..Self::new(sender, healthy)
         }
     }
 }
@@ -375,3 +375,80 @@ fn healthcheck() {
 
     assert!(bad_healthcheck.wait().is_err());
 }
+
+#[test]
+fn validate_doesnt_bind() {
+    let in_addr = next_addr();
+    let out_addr = next_addr();
+
+    let mut config = config::Config::empty();
+    config.add_source(
+        "in",
+        sources::socket::SocketConfig::make_tcp_config(in_addr),
+    );
+    config.add_sink(
+        "out",
+        &["in"],
+        sinks::socket::SocketSinkConfig::make_basic_tcp_config(out_addr.to_string()),
+    );
+
+    let rt = runtime();
+    // Sources only bind their address once they run, so a dry run doesn't.
+    let pieces = topology::validate(&config, rt.executor()).unwrap();
+    assert!(std::net::TcpListener::bind(in_addr).is_ok());
+    drop(pieces);
+}
+
+#[test]
+fn reload_keeps_listening() {
+    let num_lines: usize = 1000;
+
+    let in_addr = next_addr();
+    let out_addr = next_addr();
+
+    let make_config = |max_length| {
+        let mut source = sources::socket::SocketConfig::make_tcp_config(in_addr);
+        if let sources::socket::Mode::Tcp(tcp) = &mut source.mode {
+            tcp.max_length = max_length;
+        }
+
+        let mut config = config::Config::empty();
+        config.add_source("in", source);
+        config.add_sink(
+            "out",
+            &["in"],
+            sinks::socket::SocketSinkConfig::make_basic_tcp_config(out_addr.to_string()),
+        );
+        config
+    };
+
+    let mut rt = runtime();
+
+    let output_lines = receive(&out_addr);
+
+    let (mut topology, _crash) = topology::start(make_config(1024), &mut rt, false).unwrap();
+    // Wait for server to accept traffic
+    wait_for_tcp(in_addr);
+
+    let input_lines1 = random_lines(100).take(num_lines).collect::<Vec<_>>();
+    let send = send_lines(in_addr, input_lines1.clone().into_iter());
+    rt.block_on(send).unwrap();
+
+    // The rebuilt source takes over the socket of the previous one, rather
+    // than failing to bind its address.
+    assert!(topology.reload_config_and_respawn(make_config(2048), &mut rt, false));
+
+    let input_lines2 = random_lines(100).take(num_lines).collect::<Vec<_>>();
+    let send = send_lines(in_addr, input_lines2.clone().into_iter());
+    rt.block_on(send).unwrap();
+
+    // Shut down server
+    block_on(topology.stop()).unwrap();
+    shutdown_on_idle(rt);
+
+    let mut output_lines = output_lines.wait();
+    let mut input_lines = [input_lines1, input_lines2].concat();
+    output_lines.sort();
+    input_lines.sort();
+    assert_eq!(input_lines, output_lines);
+}
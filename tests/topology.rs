@@ -3,7 +3,7 @@ extern crate tracing;
 
 pub mod support;
 
-use crate::support::{sink, sink_failing_healthcheck, source, transform};
+use crate::support::{sink, sink_failing_healthcheck, sink_with_data, source, transform};
 use futures01::{
     future, future::Future, sink::Sink, stream::iter_ok, stream::Stream, sync::oneshot,
 };
@@ -12,10 +12,12 @@ use std::sync::{
     atomic::{AtomicBool, AtomicUsize, Ordering},
     Arc,
 };
+use std::time::{Duration, Instant};
 use vector::event::{self, Event};
-use vector::test_util::{runtime, shutdown_on_idle, trace_init};
-use vector::topology;
+use vector::metrics::{self, Value};
+use vector::test_util::{runtime, shutdown_on_idle, trace_init, wait_for};
 use vector::topology::config::Config;
+use vector::topology::{self, ComponentState, HealthcheckStatus};
 
 fn basic_config() -> Config {
     let mut config = Config::empty();
@@ -31,6 +33,24 @@ fn basic_config_with_sink_failing_healthcheck() -> Config {
     config
 }
 
+fn dropped_events(name: &str) -> u64 {
+    metrics::registry()
+        .snapshot()
+        .into_iter()
+        .filter(|(key, _)| {
+            key.name == "events_dropped_total"
+                && key
+                    .labels
+                    .iter()
+                    .any(|(label, value)| *label == "component_name" && value == name)
+        })
+        .map(|(_, value)| match value {
+            Value::Counter(count) => count,
+            _ => 0,
+        })
+        .sum()
+}
+
 fn into_message(event: Event) -> String {
     event
         .as_log()
@@ -156,7 +176,7 @@ fn topology_remove_one_source() {
     let mut rt = runtime();
     let (in1, source1) = source();
     let (in2, source2) = source();
-    let (_out1, sink1) = sink();
+    let (out1, sink1) = sink();
 
     let mut config = Config::empty();
     config.add_source("in1", source1);
@@ -165,11 +185,10 @@ fn topology_remove_one_source() {
 
     let (mut topology, _crash) = topology::start(config, &mut rt, false).unwrap();
 
-    let (out1, sink1) = sink();
-
+    // Only the inputs of the sink change, so it isn't rebuilt.
     let mut config = Config::empty();
     config.add_source("in1", source().1);
-    config.add_sink("out1", &["in1"], sink1);
+    config.add_sink("out1", &["in1"], sink().1);
 
     assert!(topology.reload_config_and_respawn(config, &mut rt, false));
 
@@ -259,38 +278,35 @@ fn topology_remove_one_transform() {
 fn topology_swap_source() {
     let mut rt = runtime();
     let (in1, source1) = source();
-    let (out1v1, sink1v1) = sink();
+    let (out1, sink1) = sink();
 
     let mut config = Config::empty();
     config.add_source("in1", source1);
-    config.add_sink("out1", &["in1"], sink1v1);
+    config.add_sink("out1", &["in1"], sink1);
 
     let (mut topology, _crash) = topology::start(config, &mut rt, false).unwrap();
 
     let (in2, source2) = source();
-    let (out1v2, sink1v2) = sink();
 
+    // The sink is rewired to the new source rather than rebuilt.
     let mut config = Config::empty();
     config.add_source("in2", source2);
-    config.add_sink("out1", &["in2"], sink1v2);
+    config.add_sink("out1", &["in2"], sink().1);
 
     assert!(topology.reload_config_and_respawn(config, &mut rt, false));
 
     let event1 = Event::from("this");
     let event2 = Event::from("that");
 
-    let h_out1v1 = oneshot::spawn(out1v1.collect(), &rt.executor());
-    let h_out1v2 = oneshot::spawn(out1v2.collect(), &rt.executor());
+    let h_out1 = oneshot::spawn(out1.collect(), &rt.executor());
     let h_in1 = oneshot::spawn(in1.send(event1.clone()), &rt.executor());
     let h_in2 = oneshot::spawn(in2.send(event2.clone()), &rt.executor());
     rt.block_on(h_in1).unwrap_err();
     rt.block_on(h_in2).unwrap();
     rt.block_on(topology.stop()).unwrap();
-    let res1v1 = rt.block_on(h_out1v1).unwrap();
-    let res1v2 = rt.block_on(h_out1v2).unwrap();
+    let res1 = rt.block_on(h_out1).unwrap();
     shutdown_on_idle(rt);
-    assert_eq!(Vec::<Event>::new(), res1v1);
-    assert_eq!(vec![event2], res1v2);
+    assert_eq!(vec![event2], res1);
 }
 
 #[test]
@@ -332,36 +348,32 @@ fn topology_swap_transform() {
     let mut rt = runtime();
     let (in1, source1) = source();
     let transform1 = transform(" transformed", 0.0);
-    let (out1v1, sink1v1) = sink();
+    let (out1, sink1) = sink();
 
     let mut config = Config::empty();
     config.add_source("in1", source1);
     config.add_transform("t1", &["in1"], transform1);
-    config.add_sink("out1", &["t1"], sink1v1);
+    config.add_sink("out1", &["t1"], sink1);
 
     let (mut topology, _crash) = topology::start(config, &mut rt, false).unwrap();
 
     let transform2 = transform(" replaced", 0.0);
-    let (out1v2, sink1v2) = sink();
 
     let mut config = Config::empty();
     config.add_source("in1", source().1);
     config.add_transform("t2", &["in1"], transform2);
-    config.add_sink("out1", &["t2"], sink1v2);
+    config.add_sink("out1", &["t2"], sink().1);
 
     assert!(topology.reload_config_and_respawn(config, &mut rt, false));
 
     let event = Event::from("this");
-    let h_out1v1 = oneshot::spawn(out1v1.map(into_message).collect(), &rt.executor());
-    let h_out1v2 = oneshot::spawn(out1v2.map(into_message).collect(), &rt.executor());
+    let h_out1 = oneshot::spawn(out1.map(into_message).collect(), &rt.executor());
     let h_in1 = oneshot::spawn(in1.send(event.clone()), &rt.executor());
     rt.block_on(h_in1).unwrap();
     rt.block_on(topology.stop()).unwrap();
-    let res1v1 = rt.block_on(h_out1v1).unwrap();
-    let res1v2 = rt.block_on(h_out1v2).unwrap();
+    let res1 = rt.block_on(h_out1).unwrap();
     shutdown_on_idle(rt);
-    assert_eq!(Vec::<String>::new(), res1v1);
-    assert_eq!(vec!["this replaced"], res1v2);
+    assert_eq!(vec!["this replaced"], res1);
 }
 
 #[test]
@@ -466,3 +478,173 @@ fn topology_healthcheck_run_for_changes_on_reload() {
     config.add_sink("out2", &["in1"], sink_failing_healthcheck().1);
     assert!(topology.reload_config_and_respawn(config, &mut rt, true) == false);
 }
+
+#[test]
+fn topology_reports_component_status() {
+    let mut rt = runtime();
+    let config = basic_config_with_sink_failing_healthcheck();
+    let (mut topology, _crash) = topology::start(config, &mut rt, false).unwrap();
+    let status = topology.status();
+
+    let components = status.components();
+    assert_eq!(components["in1"].kind, "source");
+    assert_eq!(components["in1"].state, ComponentState::Running);
+    assert_eq!(components["in1"].healthcheck, None);
+    wait_for(|| status.components()["out1"].healthcheck == Some(HealthcheckStatus::Failed));
+
+    let mut config = Config::empty();
+    config.add_source("in1", source().1);
+    config.add_sink("out2", &[], sink().1);
+    let errors = topology
+        .try_reload_config_and_respawn(config, &mut rt, false)
+        .unwrap_err();
+    assert!(errors.contains(&"Sink \"out2\" has no inputs".to_owned()));
+    assert!(status.components().contains_key("out1"));
+
+    let mut config = Config::empty();
+    config.add_source("in1", source().1);
+    config.add_sink("out2", &["in1"], sink().1);
+    assert!(topology
+        .try_reload_config_and_respawn(config, &mut rt, false)
+        .is_ok());
+    let components = status.components();
+    assert!(!components.contains_key("out1"));
+    assert_eq!(components["out2"].state, ComponentState::Running);
+}
+
+#[test]
+fn topology_stop_drains_sinks() {
+    let mut rt = runtime();
+    let (in1, source1) = source();
+    let (out1, sink1) = sink();
+
+    let mut config = Config::empty();
+    config.add_source("in1", source1);
+    config.add_sink("out1", &["in1"], sink1);
+
+    let (topology, _crash) = topology::start(config, &mut rt, false).unwrap();
+
+    // More events than the sink can take before it's read from.
+    let events = (0..50)
+        .map(|i| Event::from(format!("event {}", i)))
+        .collect::<Vec<_>>();
+    rt.block_on(
+        in1.sink_map_err(|e| panic!("{:?}", e))
+            .send_all(iter_ok::<_, ()>(events.clone())),
+    )
+    .unwrap();
+
+    let stop = oneshot::spawn(topology.stop(), &rt.executor());
+    let res = rt.block_on(out1.collect()).unwrap();
+    rt.block_on(stop).unwrap();
+
+    shutdown_on_idle(rt);
+    assert_eq!(events, res);
+}
+
+#[test]
+fn topology_stop_kills_sinks_after_shutdown_timeout() {
+    let mut rt = runtime();
+    let (in1, source1) = source();
+    let (_out1, sink1) = sink();
+
+    let mut config = Config::empty();
+    config.global.shutdown_timeout_secs = Some(1);
+    config.add_source("in1", source1);
+    config.add_sink("stuck_out", &["in1"], sink1);
+
+    let (topology, _crash) = topology::start(config, &mut rt, false).unwrap();
+
+    let events = (0..50).map(|i| Event::from(format!("event {}", i)));
+    rt.block_on(
+        in1.sink_map_err(|e| panic!("{:?}", e))
+            .send_all(iter_ok::<_, ()>(events)),
+    )
+    .unwrap();
+
+    let started = Instant::now();
+    rt.block_on(topology.stop()).unwrap();
+    assert!(started.elapsed() < Duration::from_secs(10));
+
+    // The events the sink couldn't send are reported as dropped.
+    wait_for(|| dropped_events("stuck_out") > 0);
+    assert!(dropped_events("stuck_out") <= 50);
+}
+
+#[test]
+fn topology_reload_drains_removed_sink() {
+    let mut rt = runtime();
+    let (in1, source1) = source();
+    let (out1, sink1) = sink();
+
+    let mut config = Config::empty();
+    config.add_source("in1", source1);
+    config.add_sink("out1", &["in1"], sink1);
+
+    let (mut topology, _crash) = topology::start(config, &mut rt, false).unwrap();
+
+    let events = (0..50)
+        .map(|i| Event::from(format!("event {}", i)))
+        .collect::<Vec<_>>();
+    rt.block_on(
+        in1.sink_map_err(|e| panic!("{:?}", e))
+            .send_all(iter_ok::<_, ()>(events.clone())),
+    )
+    .unwrap();
+
+    let (out2, sink2) = sink();
+    let mut config = Config::empty();
+    config.add_source("in1", source().1);
+    config.add_sink("out2", &["in1"], sink2);
+    assert!(topology.reload_config_and_respawn(config, &mut rt, false));
+
+    // The removed sink still delivers the events it was sent.
+    let res1 = rt.block_on(out1.collect()).unwrap();
+    let h_out2 = oneshot::spawn(out2.collect(), &rt.executor());
+    rt.block_on(topology.stop()).unwrap();
+    let res2 = rt.block_on(h_out2).unwrap();
+
+    shutdown_on_idle(rt);
+    assert_eq!(events, res1);
+    assert_eq!(Vec::<Event>::new(), res2);
+}
+
+#[test]
+fn topology_reload_hands_buffer_over_to_rebuilt_sink() {
+    let mut rt = runtime();
+    let (in1, source1) = source();
+    let (out1v1, sink1v1) = sink_with_data("v1");
+
+    let mut config = Config::empty();
+    config.add_source("in1", source1);
+    config.add_sink("out1", &["in1"], sink1v1);
+
+    let (mut topology, _crash) = topology::start(config, &mut rt, false).unwrap();
+
+    // Most of the events wait in the buffer, as the sink isn't read from.
+    let events = (0..50)
+        .map(|i| Event::from(format!("event {}", i)))
+        .collect::<Vec<_>>();
+    rt.block_on(
+        in1.sink_map_err(|e| panic!("{:?}", e))
+            .send_all(iter_ok::<_, ()>(events.clone())),
+    )
+    .unwrap();
+
+    let (out1v2, sink1v2) = sink_with_data("v2");
+    let mut config = Config::empty();
+    config.add_source("in1", source().1);
+    config.add_sink("out1", &["in1"], sink1v2);
+    assert!(topology.reload_config_and_respawn(config, &mut rt, false));
+
+    // The previous instance delivers the events it read, and the rebuilt one
+    // the rest.
+    let res1v1 = rt.block_on(out1v1.collect()).unwrap();
+    let h_out1v2 = oneshot::spawn(out1v2.collect(), &rt.executor());
+    rt.block_on(topology.stop()).unwrap();
+    let res1v2 = rt.block_on(h_out1v2).unwrap();
+
+    shutdown_on_idle(rt);
+    assert!(!res1v2.is_empty());
+    assert_eq!(events, [res1v1, res1v2].concat());
+}